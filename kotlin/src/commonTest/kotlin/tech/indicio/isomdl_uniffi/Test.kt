@@ -15,6 +15,7 @@ import uniffi.isomdl_uniffi.AuthenticationStatus
 import uniffi.isomdl_uniffi.MdlPresentationSession
 import uniffi.isomdl_uniffi.Mdoc
 import uniffi.isomdl_uniffi.P256KeyPair
+import uniffi.isomdl_uniffi.ResponseIssueKind
 import uniffi.isomdl_uniffi.establishSession
 import uniffi.isomdl_uniffi.generateTestMdl
 import uniffi.isomdl_uniffi.handleResponse
@@ -22,7 +23,6 @@ import uniffi.isomdl_uniffi.iso1801351AamvaFromJson
 import uniffi.isomdl_uniffi.iso1801351FromJson
 import kotlin.test.Test
 import kotlin.test.assertEquals
-import kotlin.test.assertNull
 
 class CommonGreetingTest {
     @Test
@@ -82,7 +82,7 @@ class CommonGreetingTest {
             }
 
             // Holder generates unsigned response
-            val unsignedResponse = presentationSession.generateResponse(permittedItems)
+            val unsignedResponse = presentationSession.generateResponse(permittedItems).payload
 
             // Holder signs response with key that matches mdoc public key
             val signedResponse = key.sign(unsignedResponse)
@@ -99,14 +99,22 @@ class CommonGreetingTest {
                         "response" to readerResult.verifiedResponse,
                         "issuer_auth" to readerResult.issuerAuthentication,
                         "device_auth" to readerResult.deviceAuthentication,
-                        "errors" to readerResult.errors
+                        "issues" to readerResult.issues
                     )
                 }"
             )
 
             assertEquals(AuthenticationStatus.VALID, readerResult.issuerAuthentication)
             assertEquals(AuthenticationStatus.VALID, readerResult.deviceAuthentication)
-            assertNull(readerResult.errors)
+            // Only the elements to be retained were permitted
+            assertEquals(
+                listOf("family_name"),
+                readerResult.issues.map { it.element }
+            )
+            assertEquals(
+                listOf(ResponseIssueKind.MISSING_ELEMENT),
+                readerResult.issues.map { it.kind }
+            )
         }
     }
 
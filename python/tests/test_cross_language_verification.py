@@ -56,13 +56,20 @@ class TestCrossLanguageVerification:
 
         # Verify authentication status
         if verified_data.issuer_authentication != mdl_module.AuthenticationStatus.VALID:
-            print(f"Issuer Authentication failed. Errors: {verified_data.errors}")
+            print(f"Issuer Authentication failed. Issues: {verified_data.issues}")
         if verified_data.device_authentication != mdl_module.AuthenticationStatus.VALID:
-            print(f"Device Authentication failed. Errors: {verified_data.errors}")
+            print(f"Device Authentication failed. Issues: {verified_data.issues}")
 
         assert verified_data.issuer_authentication == mdl_module.AuthenticationStatus.VALID
         assert verified_data.device_authentication == mdl_module.AuthenticationStatus.VALID
 
+        # The single document is also returned on its own
+        assert len(verified_data.documents) == 1
+        document = verified_data.documents[0]
+        assert document.doc_type == verified_data.doc_type
+        assert document.issuer_authentication == verified_data.issuer_authentication
+        assert document.device_authentication == verified_data.device_authentication
+
         # Check data
         namespaces = verified_data.verified_response
         assert "org.iso.18013.5.1" in namespaces
@@ -102,7 +102,7 @@ class TestNamespaceValidation:
             "org.iso.18013.5.1.mDL": {"org.iso.18013.5.1": ["given_name", "family_name"]}
         }
 
-        unsigned_response = presentation_session.generate_response(permitted_items)
+        unsigned_response = presentation_session.generate_response(permitted_items).payload
         signed_response = key_pair.sign(unsigned_response)
         final_response = presentation_session.submit_response(signed_response)
 
@@ -215,7 +215,7 @@ class TestAttributeValidation:
         }
 
         try:
-            unsigned_response = presentation_session.generate_response(permitted)
+            unsigned_response = presentation_session.generate_response(permitted).payload
             # If it succeeds, response should be valid but may have no attributes
             assert isinstance(unsigned_response, bytes)
 
@@ -301,7 +301,7 @@ class TestAttributeValues:
             "org.iso.18013.5.1.mDL": {"org.iso.18013.5.1": ["given_name", "family_name"]}
         }
 
-        unsigned = presentation_session.generate_response(correct_permitted)
+        unsigned = presentation_session.generate_response(correct_permitted).payload
         signed = key_pair.sign(unsigned)
         final = presentation_session.submit_response(signed)
 
@@ -328,7 +328,7 @@ class TestAttributeValues:
 
         # Implementation may accept dict or reject it
         try:
-            unsigned = presentation_session.generate_response(wrong_permitted)
+            unsigned = presentation_session.generate_response(wrong_permitted).payload
             # If accepted, it handled the conversion
             assert isinstance(unsigned, bytes)
         except (TypeError, ValueError, RuntimeError):
@@ -130,7 +130,7 @@ class TestErrorRecovery:
             "org.iso.18013.5.1.mDL": {"org.iso.18013.5.1": ["given_name", "family_name"]}
         }
 
-        unsigned_response = presentation_session.generate_response(permitted_items)
+        unsigned_response = presentation_session.generate_response(permitted_items).payload
 
         # Create an invalid signature (just random bytes)
         invalid_signature = b"invalid_signature_data_here_" + unsigned_response[:32]
@@ -288,7 +288,7 @@ class TestEdgeCases:
 
         # Should handle gracefully (might return empty or partial response)
         try:
-            unsigned_response = presentation_session.generate_response(mismatched_permitted)
+            unsigned_response = presentation_session.generate_response(mismatched_permitted).payload
             assert isinstance(unsigned_response, bytes)
 
             # Try to complete the flow
@@ -201,7 +201,7 @@ class TestOID4VPVerification:
             # Should fail either at trust anchor parsing or CBOR parsing
             error_msg = str(e)
             assert (
-                "Invalid trust anchor JSON" in error_msg
+                "Invalid trust anchor" in error_msg
                 or "Unable to parse DeviceResponse" in error_msg
             )
 
@@ -110,12 +110,18 @@ def test_generate_response(mdl_module, key_pair, test_mdl):
         "org.iso.18013.5.1.mDL": {"org.iso.18013.5.1": ["family_name", "given_name"]}
     }
 
-    unsigned_response = presentation_session.generate_response(permitted_items)
+    generated = presentation_session.generate_response(permitted_items)
+    unsigned_response = generated.payload
 
     assert isinstance(unsigned_response, bytes), "Response should be bytes"
     assert len(unsigned_response) > 0, "Response should not be empty"
     assert len(unsigned_response) > 10, f"Response too short: {len(unsigned_response)}"
 
+    summary = generated.consent_summary
+    shared = {item.element_identifier for item in summary.shared}
+    assert shared == {"family_name", "given_name"}
+    assert summary.missing == [] and summary.denied == []
+
 
 def test_complete_presentation_workflow(mdl_module, key_pair, test_mdl):
     """Test complete presentation workflow from session to verified response."""
@@ -139,7 +145,7 @@ def test_complete_presentation_workflow(mdl_module, key_pair, test_mdl):
         "org.iso.18013.5.1.mDL": {"org.iso.18013.5.1": ["family_name", "given_name"]}
     }
 
-    unsigned_response = presentation_session.generate_response(permitted_items)
+    unsigned_response = presentation_session.generate_response(permitted_items).payload
     signed_response = key_pair.sign(unsigned_response)
 
     assert isinstance(signed_response, bytes), "Signed response should be bytes"
@@ -59,7 +59,7 @@ class TestMessageFormats:
 
         permitted_items = {"org.iso.18013.5.1.mDL": {"org.iso.18013.5.1": ["given_name"]}}
 
-        unsigned_response = presentation_session.generate_response(permitted_items)
+        unsigned_response = presentation_session.generate_response(permitted_items).payload
         signed_response = key_pair.sign(unsigned_response)
         final_response = presentation_session.submit_response(signed_response)
 
@@ -82,7 +82,7 @@ class TestMessageFormats:
             "org.iso.18013.5.1.mDL": {"org.iso.18013.5.1": ["given_name", "family_name"]}
         }
 
-        unsigned_response = presentation_session.generate_response(permitted_items)
+        unsigned_response = presentation_session.generate_response(permitted_items).payload
         signed_response = key_pair.sign(unsigned_response)
         final_response = presentation_session.submit_response(signed_response)
 
@@ -95,6 +95,13 @@ class TestMessageFormats:
         # Authentication status should be present
         assert result.device_authentication is not None
 
+        # The intent to retain of each requested element is reported back
+        retained = {
+            element.element_id: element.intent_to_retain
+            for element in result.requested_elements["org.iso.18013.5.1"]
+        }
+        assert retained == {"given_name": True, "family_name": True}
+
 
 class TestEncodingCompliance:
     """Test CBOR and JSON encoding compliance."""
@@ -304,7 +311,7 @@ class TestDataStructures:
             "org.iso.18013.5.1.mDL": {"org.iso.18013.5.1": ["given_name", "family_name"]}
         }
 
-        unsigned = presentation_session.generate_response(permitted_items)
+        unsigned = presentation_session.generate_response(permitted_items).payload
         assert isinstance(unsigned, bytes)
 
     def test_authentication_status_enum(self, mdl_module):
@@ -344,7 +351,7 @@ class TestProtocolWorkflows:
         }
 
         # 6. Holder generates unsigned response
-        unsigned_response = presentation_session.generate_response(permitted_items)
+        unsigned_response = presentation_session.generate_response(permitted_items).payload
 
         # 7. Holder signs response
         signed_response = key_pair.sign(unsigned_response)
@@ -386,7 +393,7 @@ class TestProtocolWorkflows:
             }
         }
 
-        unsigned_response = presentation_session.generate_response(permitted_items)
+        unsigned_response = presentation_session.generate_response(permitted_items).payload
         signed_response = key_pair.sign(unsigned_response)
         final_response = presentation_session.submit_response(signed_response)
 
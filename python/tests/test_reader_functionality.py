@@ -63,6 +63,10 @@ def test_reader_session_uuid_format(mdl_module, test_mdl):
     except ValueError:
         raise AssertionError(f"Session UUID is not valid: {session_data.uuid}")
 
+    # The holder offers central client mode only
+    assert session_data.central_client_uuid == session_data.uuid
+    assert session_data.peripheral_server is None
+
 
 def test_reader_request_data_structure(mdl_module, test_mdl):
     """Validate reader request data structure."""
@@ -164,7 +168,7 @@ def test_complete_reader_workflow(mdl_module, key_pair, test_mdl):
         }
     }
 
-    unsigned_response = presentation_session.generate_response(permitted_items)
+    unsigned_response = presentation_session.generate_response(permitted_items).payload
     assert isinstance(unsigned_response, bytes), "Unsigned response should be bytes"
     assert len(unsigned_response) > 0, "Unsigned response should not be empty"
 
@@ -35,7 +35,7 @@ class TestSignatureVerification:
             "org.iso.18013.5.1.mDL": {"org.iso.18013.5.1": ["given_name", "family_name"]}
         }
 
-        unsigned_response = presentation_session.generate_response(permitted_items)
+        unsigned_response = presentation_session.generate_response(permitted_items).payload
         signed_response = key_pair.sign(unsigned_response)
         final_response = presentation_session.submit_response(signed_response)
 
@@ -75,7 +75,7 @@ class TestSignatureVerification:
 
         permitted_items = {"org.iso.18013.5.1.mDL": {"org.iso.18013.5.1": ["given_name"]}}
 
-        unsigned_response = presentation_session.generate_response(permitted_items)
+        unsigned_response = presentation_session.generate_response(permitted_items).payload
 
         # Sign with WRONG key
         wrong_signed_response = key_pair2.sign(unsigned_response)
@@ -103,7 +103,7 @@ class TestSignatureVerification:
 
         permitted_items = {"org.iso.18013.5.1.mDL": {"org.iso.18013.5.1": ["given_name"]}}
 
-        unsigned_response = presentation_session.generate_response(permitted_items)
+        unsigned_response = presentation_session.generate_response(permitted_items).payload
 
         # Try to submit unsigned response directly (should fail with SignatureError)
         with pytest.raises((ValueError, RuntimeError, Exception)):
@@ -123,7 +123,7 @@ class TestSignatureVerification:
 
         permitted_items = {"org.iso.18013.5.1.mDL": {"org.iso.18013.5.1": ["given_name"]}}
 
-        unsigned_response = presentation_session.generate_response(permitted_items)
+        unsigned_response = presentation_session.generate_response(permitted_items).payload
         signed_response = key_pair.sign(unsigned_response)
 
         # Try to malleate signature (modify signature bytes)
@@ -164,7 +164,7 @@ class TestAuthentication:
             "org.iso.18013.5.1.mDL": {"org.iso.18013.5.1": ["given_name", "family_name"]}
         }
 
-        unsigned_response = presentation_session.generate_response(permitted_items)
+        unsigned_response = presentation_session.generate_response(permitted_items).payload
         signed_response = key_pair.sign(unsigned_response)
         final_response = presentation_session.submit_response(signed_response)
 
@@ -197,7 +197,7 @@ class TestAuthentication:
 
         permitted_items = {"org.iso.18013.5.1.mDL": {"org.iso.18013.5.1": ["given_name"]}}
 
-        unsigned_response = presentation_session.generate_response(permitted_items)
+        unsigned_response = presentation_session.generate_response(permitted_items).payload
         signed_response = key_pair.sign(unsigned_response)
         final_response = presentation_session.submit_response(signed_response)
 
@@ -247,8 +247,8 @@ class TestAttackPrevention:
         permitted_items = {"org.iso.18013.5.1.mDL": {"org.iso.18013.5.1": ["given_name"]}}
 
         # Generate responses
-        unsigned1 = session1.generate_response(permitted_items)
-        unsigned2 = session2.generate_response(permitted_items)
+        unsigned1 = session1.generate_response(permitted_items).payload
+        unsigned2 = session2.generate_response(permitted_items).payload
 
         # Sign both
         signed1 = key_pair.sign(unsigned1)
@@ -317,7 +317,7 @@ class TestAttackPrevention:
         # Generate response with correct MDL
         permitted_items = {"org.iso.18013.5.1.mDL": {"org.iso.18013.5.1": ["given_name"]}}
 
-        unsigned_response = session1.generate_response(permitted_items)
+        unsigned_response = session1.generate_response(permitted_items).payload
         signed_response = key_pair.sign(unsigned_response)
         final_response = session1.submit_response(signed_response)
 
@@ -353,7 +353,7 @@ class TestAttackPrevention:
         permitted_items = {"org.iso.18013.5.1.mDL": {"org.iso.18013.5.1": ["given_name"]}}
 
         # Holder generates response
-        unsigned = holder_session.generate_response(permitted_items)
+        unsigned = holder_session.generate_response(permitted_items).payload
         signed = key_pair.sign(unsigned)
         holder_response = holder_session.submit_response(signed)
 
@@ -386,7 +386,7 @@ class TestAttackPrevention:
             "org.iso.18013.5.1.mDL": {"org.iso.18013.5.1": ["given_name", "family_name"]}
         }
 
-        unsigned_response = presentation_session.generate_response(permitted_items)
+        unsigned_response = presentation_session.generate_response(permitted_items).payload
         signed_response = key_pair.sign(unsigned_response)
         final_response = presentation_session.submit_response(signed_response)
 
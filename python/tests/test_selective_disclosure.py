@@ -67,7 +67,7 @@ def test_basic_selective_disclosure(mdl_module):
         "org.iso.18013.5.1.mDL": {"org.iso.18013.5.1": ["given_name", "family_name"]}
     }
 
-    unsigned_response = presentation_session.generate_response(permitted_items)
+    unsigned_response = presentation_session.generate_response(permitted_items).payload
     signed_response = key_pair.sign(unsigned_response)
     response = presentation_session.submit_response(signed_response)
 
@@ -131,7 +131,7 @@ def test_age_verification_attributes(mdl_module):
         "org.iso.18013.5.1.mDL": {"org.iso.18013.5.1": ["age_over_18", "age_over_21"]}
     }
 
-    unsigned_response = presentation_session.generate_response(age_permitted_items)
+    unsigned_response = presentation_session.generate_response(age_permitted_items).payload
     signed_response = holder_key.sign(unsigned_response)
     response = presentation_session.submit_response(signed_response)
 
@@ -197,7 +197,7 @@ def test_minimal_disclosure(mdl_module):
     # Generate minimal response
     minimal_permitted = {"org.iso.18013.5.1.mDL": {"org.iso.18013.5.1": ["document_number"]}}
 
-    unsigned_response = presentation_session.generate_response(minimal_permitted)
+    unsigned_response = presentation_session.generate_response(minimal_permitted).payload
     signed_response = holder_key.sign(unsigned_response)
     response = presentation_session.submit_response(signed_response)
 
@@ -244,7 +244,7 @@ def test_namespace_filtering(mdl_module):
         "org.iso.18013.5.1.mDL": {"org.iso.18013.5.1": ["given_name", "family_name"]}
     }
 
-    unsigned_response = presentation_session.generate_response(namespace_permitted)
+    unsigned_response = presentation_session.generate_response(namespace_permitted).payload
     signed_response = holder_key.sign(unsigned_response)
     response = presentation_session.submit_response(signed_response)
 
@@ -0,0 +1,365 @@
+// Copyright (c) 2025 Indicio
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+//
+// This software may be modified and distributed under the terms
+// of either the Apache License, Version 2.0 or the MIT license.
+// See the LICENSE-APACHE and LICENSE-MIT files for details.
+
+//! The data elements of the AAMVA mDL namespace, `org.iso.18013.5.1.aamva`, defined by
+//! the AAMVA mDL Implementation Guidelines for US driver's licenses: their identifiers
+//! for requests, and typed values of a response.
+
+use std::collections::HashMap;
+
+use super::namespaces::{AAMVA_NAMESPACE, code_label};
+use super::reader::MDocItem;
+
+pub const DOMESTIC_DRIVING_PRIVILEGES: &str = "domestic_driving_privileges";
+pub const NAME_SUFFIX: &str = "name_suffix";
+pub const ORGAN_DONOR: &str = "organ_donor";
+pub const VETERAN: &str = "veteran";
+pub const FAMILY_NAME_TRUNCATION: &str = "family_name_truncation";
+pub const GIVEN_NAME_TRUNCATION: &str = "given_name_truncation";
+pub const AKA_FAMILY_NAME: &str = "aka_family_name.v2";
+pub const AKA_GIVEN_NAME: &str = "aka_given_name.v2";
+pub const AKA_SUFFIX: &str = "aka_suffix";
+pub const WEIGHT_RANGE: &str = "weight_range";
+pub const RACE_ETHNICITY: &str = "race_ethnicity";
+pub const DHS_COMPLIANCE: &str = "DHS_compliance";
+pub const DHS_TEMPORARY_LAWFUL_STATUS: &str = "DHS_temporary_lawful_status";
+pub const EDL_CREDENTIAL: &str = "EDL_credential";
+pub const RESIDENT_COUNTY: &str = "resident_county";
+pub const HAZMAT_ENDORSEMENT_EXPIRATION_DATE: &str = "hazmat_endorsement_expiration_date";
+pub const SEX: &str = "sex";
+pub const CDL_INDICATOR: &str = "CDL_indicator";
+pub const DHS_COMPLIANCE_TEXT: &str = "DHS_compliance_text";
+pub const AAMVA_VERSION: &str = "aamva_version";
+
+/// A data element of the AAMVA namespace, to request without typing its identifier.
+#[derive(uniffi::Enum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AamvaElement {
+    DomesticDrivingPrivileges,
+    NameSuffix,
+    OrganDonor,
+    Veteran,
+    FamilyNameTruncation,
+    GivenNameTruncation,
+    AkaFamilyName,
+    AkaGivenName,
+    AkaSuffix,
+    WeightRange,
+    RaceEthnicity,
+    DhsCompliance,
+    DhsTemporaryLawfulStatus,
+    EdlCredential,
+    ResidentCounty,
+    HazmatEndorsementExpirationDate,
+    Sex,
+    CdlIndicator,
+    DhsComplianceText,
+    AamvaVersion,
+}
+
+impl AamvaElement {
+    pub fn identifier(self) -> &'static str {
+        match self {
+            Self::DomesticDrivingPrivileges => DOMESTIC_DRIVING_PRIVILEGES,
+            Self::NameSuffix => NAME_SUFFIX,
+            Self::OrganDonor => ORGAN_DONOR,
+            Self::Veteran => VETERAN,
+            Self::FamilyNameTruncation => FAMILY_NAME_TRUNCATION,
+            Self::GivenNameTruncation => GIVEN_NAME_TRUNCATION,
+            Self::AkaFamilyName => AKA_FAMILY_NAME,
+            Self::AkaGivenName => AKA_GIVEN_NAME,
+            Self::AkaSuffix => AKA_SUFFIX,
+            Self::WeightRange => WEIGHT_RANGE,
+            Self::RaceEthnicity => RACE_ETHNICITY,
+            Self::DhsCompliance => DHS_COMPLIANCE,
+            Self::DhsTemporaryLawfulStatus => DHS_TEMPORARY_LAWFUL_STATUS,
+            Self::EdlCredential => EDL_CREDENTIAL,
+            Self::ResidentCounty => RESIDENT_COUNTY,
+            Self::HazmatEndorsementExpirationDate => HAZMAT_ENDORSEMENT_EXPIRATION_DATE,
+            Self::Sex => SEX,
+            Self::CdlIndicator => CDL_INDICATOR,
+            Self::DhsComplianceText => DHS_COMPLIANCE_TEXT,
+            Self::AamvaVersion => AAMVA_VERSION,
+        }
+    }
+}
+
+/// The namespace of the AAMVA data elements.
+#[uniffi::export]
+pub fn aamva_namespace() -> String {
+    AAMVA_NAMESPACE.to_string()
+}
+
+/// The identifier of an AAMVA data element.
+#[uniffi::export]
+pub fn aamva_element_identifier(element: AamvaElement) -> String {
+    element.identifier().to_string()
+}
+
+/// Whether a name was truncated to fit the credential.
+#[derive(uniffi::Enum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NameTruncation {
+    Truncated,
+    NotTruncated,
+    Unknown,
+}
+
+/// The REAL ID compliance of the credential.
+#[derive(uniffi::Enum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DhsCompliance {
+    FullyCompliant,
+    NonCompliant,
+}
+
+/// The kind of enhanced credential, valid for land and sea border crossings.
+#[derive(uniffi::Enum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdlCredential {
+    DriversLicense,
+    IdentificationCard,
+}
+
+#[derive(uniffi::Enum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RaceEthnicity {
+    AlaskanOrAmericanIndian,
+    AsianOrPacificIslander,
+    Black,
+    HispanicOrigin,
+    NonHispanic,
+    Unknown,
+    White,
+}
+
+/// Sex, coded as in ISO/IEC 5218.
+#[derive(uniffi::Enum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sex {
+    NotKnown,
+    Male,
+    Female,
+    NotApplicable,
+}
+
+/// The AAMVA elements of a response with their values typed. An element is `None` if
+/// it was not returned or its value is outside its domain. The indicators are true
+/// when returned, as AAMVA defines only the value 1 for them.
+#[derive(uniffi::Record, Debug, Clone, Default, PartialEq)]
+pub struct AamvaElements {
+    pub name_suffix: Option<String>,
+    pub organ_donor: Option<bool>,
+    pub veteran: Option<bool>,
+    pub family_name_truncation: Option<NameTruncation>,
+    pub given_name_truncation: Option<NameTruncation>,
+    pub aka_family_name: Option<String>,
+    pub aka_given_name: Option<String>,
+    pub aka_suffix: Option<String>,
+    /// The weight range code, from 0 for up to 31 kg to 9 for 146 kg or more.
+    pub weight_range: Option<u8>,
+    pub race_ethnicity: Option<RaceEthnicity>,
+    pub dhs_compliance: Option<DhsCompliance>,
+    pub dhs_temporary_lawful_status: Option<bool>,
+    pub edl_credential: Option<EdlCredential>,
+    pub resident_county: Option<String>,
+    /// A full-date, `YYYY-MM-DD`.
+    pub hazmat_endorsement_expiration_date: Option<String>,
+    pub sex: Option<Sex>,
+    pub cdl_indicator: Option<bool>,
+    pub dhs_compliance_text: Option<String>,
+    pub aamva_version: Option<u64>,
+}
+
+/// Reads the AAMVA elements of a verified response, by namespace. The coded values are
+/// typed by their meaning in [code_label], which defines the domain of each element.
+#[uniffi::export]
+pub fn aamva_elements(namespaces: HashMap<String, HashMap<String, MDocItem>>) -> AamvaElements {
+    let Some(elements) = namespaces.get(AAMVA_NAMESPACE) else {
+        return AamvaElements::default();
+    };
+    let text = |identifier: &str| match elements.get(identifier) {
+        Some(MDocItem::Text(text)) => Some(text.clone()),
+        _ => None,
+    };
+    let integer = |identifier: &str| match elements.get(identifier) {
+        Some(MDocItem::Integer(integer)) => u64::try_from(*integer).ok(),
+        _ => None,
+    };
+    let coded = |identifier: &str| {
+        let code = match elements.get(identifier)? {
+            MDocItem::Text(text) => text.clone(),
+            MDocItem::Integer(integer) => integer.to_string(),
+            _ => return None,
+        };
+        code_label(AAMVA_NAMESPACE, identifier, &code)
+    };
+    let indicator = |identifier: &str| coded(identifier).map(|_| true);
+    let truncation = |identifier: &str| match coded(identifier)? {
+        "Truncated" => Some(NameTruncation::Truncated),
+        "Not truncated" => Some(NameTruncation::NotTruncated),
+        "Unknown" => Some(NameTruncation::Unknown),
+        _ => None,
+    };
+
+    AamvaElements {
+        name_suffix: text(NAME_SUFFIX),
+        organ_donor: indicator(ORGAN_DONOR),
+        veteran: indicator(VETERAN),
+        family_name_truncation: truncation(FAMILY_NAME_TRUNCATION),
+        given_name_truncation: truncation(GIVEN_NAME_TRUNCATION),
+        aka_family_name: text(AKA_FAMILY_NAME),
+        aka_given_name: text(AKA_GIVEN_NAME),
+        aka_suffix: text(AKA_SUFFIX),
+        weight_range: coded(WEIGHT_RANGE)
+            .and_then(|_| integer(WEIGHT_RANGE))
+            .and_then(|range| u8::try_from(range).ok()),
+        race_ethnicity: coded(RACE_ETHNICITY).and_then(|label| match label {
+            "Alaskan or American Indian" => Some(RaceEthnicity::AlaskanOrAmericanIndian),
+            "Asian or Pacific Islander" => Some(RaceEthnicity::AsianOrPacificIslander),
+            "Black" => Some(RaceEthnicity::Black),
+            "Hispanic origin" => Some(RaceEthnicity::HispanicOrigin),
+            "Non-Hispanic" => Some(RaceEthnicity::NonHispanic),
+            "Unknown" => Some(RaceEthnicity::Unknown),
+            "White" => Some(RaceEthnicity::White),
+            _ => None,
+        }),
+        dhs_compliance: coded(DHS_COMPLIANCE).and_then(|label| match label {
+            "Fully compliant" => Some(DhsCompliance::FullyCompliant),
+            "Non-compliant" => Some(DhsCompliance::NonCompliant),
+            _ => None,
+        }),
+        dhs_temporary_lawful_status: indicator(DHS_TEMPORARY_LAWFUL_STATUS),
+        edl_credential: coded(EDL_CREDENTIAL).and_then(|label| match label {
+            "Enhanced driver's license" => Some(EdlCredential::DriversLicense),
+            "Enhanced identification card" => Some(EdlCredential::IdentificationCard),
+            _ => None,
+        }),
+        resident_county: text(RESIDENT_COUNTY),
+        hazmat_endorsement_expiration_date: match elements.get(HAZMAT_ENDORSEMENT_EXPIRATION_DATE) {
+            Some(MDocItem::Date(date) | MDocItem::Text(date)) => Some(date.clone()),
+            _ => None,
+        },
+        sex: coded(SEX).and_then(|label| match label {
+            "Not known" => Some(Sex::NotKnown),
+            "Male" => Some(Sex::Male),
+            "Female" => Some(Sex::Female),
+            "Not applicable" => Some(Sex::NotApplicable),
+            _ => None,
+        }),
+        cdl_indicator: indicator(CDL_INDICATOR),
+        dhs_compliance_text: text(DHS_COMPLIANCE_TEXT),
+        aamva_version: integer(AAMVA_VERSION),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mdl::namespaces::{AAMVA, definition};
+
+    #[test]
+    fn test_element_identifiers() {
+        // Every identifier is an element of the AAMVA namespace definition
+        let elements = [
+            AamvaElement::DomesticDrivingPrivileges,
+            AamvaElement::NameSuffix,
+            AamvaElement::OrganDonor,
+            AamvaElement::Veteran,
+            AamvaElement::FamilyNameTruncation,
+            AamvaElement::GivenNameTruncation,
+            AamvaElement::AkaFamilyName,
+            AamvaElement::AkaGivenName,
+            AamvaElement::AkaSuffix,
+            AamvaElement::WeightRange,
+            AamvaElement::RaceEthnicity,
+            AamvaElement::DhsCompliance,
+            AamvaElement::DhsTemporaryLawfulStatus,
+            AamvaElement::EdlCredential,
+            AamvaElement::ResidentCounty,
+            AamvaElement::HazmatEndorsementExpirationDate,
+            AamvaElement::Sex,
+            AamvaElement::CdlIndicator,
+            AamvaElement::DhsComplianceText,
+            AamvaElement::AamvaVersion,
+        ];
+        assert_eq!(elements.len(), AAMVA.elements.len());
+        let definition = definition(AAMVA_NAMESPACE).unwrap();
+        for element in elements {
+            assert!(definition.element_type(element.identifier()).is_some());
+        }
+    }
+
+    #[test]
+    fn test_aamva_elements() {
+        let namespaces = HashMap::from([(
+            AAMVA_NAMESPACE.to_string(),
+            HashMap::from([
+                (DHS_COMPLIANCE.to_string(), MDocItem::Text("F".to_string())),
+                (EDL_CREDENTIAL.to_string(), MDocItem::Integer(2)),
+                (ORGAN_DONOR.to_string(), MDocItem::Integer(1)),
+                (SEX.to_string(), MDocItem::Integer(9)),
+                (
+                    FAMILY_NAME_TRUNCATION.to_string(),
+                    MDocItem::Text("T".to_string()),
+                ),
+                // Outside the domain of the element
+                (WEIGHT_RANGE.to_string(), MDocItem::Integer(12)),
+            ]),
+        )]);
+
+        let elements = aamva_elements(namespaces);
+        assert_eq!(elements.dhs_compliance, Some(DhsCompliance::FullyCompliant));
+        assert_eq!(
+            elements.edl_credential,
+            Some(EdlCredential::IdentificationCard)
+        );
+        assert_eq!(elements.organ_donor, Some(true));
+        assert_eq!(elements.veteran, None);
+        assert_eq!(elements.sex, Some(Sex::NotApplicable));
+        assert_eq!(
+            elements.family_name_truncation,
+            Some(NameTruncation::Truncated)
+        );
+        assert_eq!(elements.weight_range, None);
+        assert_eq!(aamva_elements(HashMap::new()), AamvaElements::default());
+
+        // Every code labelled in the namespace definition is typed
+        let typed = |identifier: &str, values: Vec<MDocItem>| {
+            values
+                .into_iter()
+                .map(|value| {
+                    aamva_elements(HashMap::from([(
+                        AAMVA_NAMESPACE.to_string(),
+                        HashMap::from([(identifier.to_string(), value)]),
+                    )]))
+                })
+                .collect::<Vec<_>>()
+        };
+        let race_ethnicity = typed(
+            RACE_ETHNICITY,
+            ["AI", "AP", "BK", "H", "O", "U", "W"]
+                .map(|code| MDocItem::Text(code.to_string()))
+                .to_vec(),
+        );
+        assert!(race_ethnicity.iter().all(|e| e.race_ethnicity.is_some()));
+        let sex = typed(SEX, [0, 1, 2, 9].map(MDocItem::Integer).to_vec());
+        assert!(sex.iter().all(|e| e.sex.is_some()));
+        let weight_range = typed(WEIGHT_RANGE, (0..=9).map(MDocItem::Integer).collect());
+        assert!(weight_range.iter().all(|e| e.weight_range.is_some()));
+        let dhs_compliance = typed(
+            DHS_COMPLIANCE,
+            ["F", "N"]
+                .map(|code| MDocItem::Text(code.to_string()))
+                .to_vec(),
+        );
+        assert!(dhs_compliance.iter().all(|e| e.dhs_compliance.is_some()));
+        let edl_credential = typed(EDL_CREDENTIAL, [1, 2].map(MDocItem::Integer).to_vec());
+        assert!(edl_credential.iter().all(|e| e.edl_credential.is_some()));
+        let truncation = typed(
+            GIVEN_NAME_TRUNCATION,
+            ["T", "N", "U"]
+                .map(|code| MDocItem::Text(code.to_string()))
+                .to_vec(),
+        );
+        assert!(truncation.iter().all(|e| e.given_name_truncation.is_some()));
+    }
+}
@@ -0,0 +1,195 @@
+// Copyright (c) 2025 Indicio
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+//
+// This software may be modified and distributed under the terms
+// of either the Apache License, Version 2.0 or the MIT license.
+// See the LICENSE-APACHE and LICENSE-MIT files for details.
+
+//! Framing of session messages sent over the BLE GATT characteristics, per ISO 18013-5
+//! 8.3.3.1.1.6. Every characteristic value starts with a byte telling whether more of
+//! the message follows, and carries at most ATT_MTU - 3 bytes.
+
+use std::sync::Mutex;
+
+/// First byte of a characteristic value that is followed by more of the message.
+const MORE_TO_COME: u8 = 0x01;
+/// First byte of the characteristic value ending the message.
+const LAST_PART: u8 = 0x00;
+/// Size of the ATT header of a write or notification.
+const ATT_HEADER_SIZE: u16 = 3;
+/// Default limit on the size of a reassembled message, well above that of a response
+/// with a portrait.
+const DEFAULT_MAX_MESSAGE_SIZE: u64 = 4 * 1024 * 1024;
+
+/// Splits a session message into the characteristic values to write or notify, in
+/// order, for a connection with the given ATT_MTU.
+#[uniffi::export]
+pub fn ble_segment_message(message: Vec<u8>, mtu: u16) -> Result<Vec<Vec<u8>>, BleFramingError> {
+    let chunk_size = mtu
+        .checked_sub(ATT_HEADER_SIZE + 1)
+        .filter(|size| *size > 0)
+        .ok_or(BleFramingError::MtuTooSmall { mtu })? as usize;
+    if message.is_empty() {
+        return Ok(vec![vec![LAST_PART]]);
+    }
+
+    let chunk_count = message.len().div_ceil(chunk_size);
+    Ok(message
+        .chunks(chunk_size)
+        .enumerate()
+        .map(|(index, chunk)| {
+            let header = if index + 1 < chunk_count {
+                MORE_TO_COME
+            } else {
+                LAST_PART
+            };
+            let mut value = Vec::with_capacity(chunk.len() + 1);
+            value.push(header);
+            value.extend_from_slice(chunk);
+            value
+        })
+        .collect())
+}
+
+/// Reassembles session messages from the characteristic values received, in order.
+#[derive(uniffi::Object)]
+pub struct BleMessageReassembler {
+    buffer: Mutex<Vec<u8>>,
+    max_message_size: u64,
+}
+
+impl Default for BleMessageReassembler {
+    fn default() -> Self {
+        Self {
+            buffer: Mutex::default(),
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+        }
+    }
+}
+
+#[uniffi::export]
+impl BleMessageReassembler {
+    /// A reassembler of messages up to `max_message_size` bytes, 4 MiB by default, so
+    /// that a peer cannot make it buffer without bound.
+    #[uniffi::constructor(default(max_message_size = None))]
+    pub fn new(max_message_size: Option<u64>) -> BleMessageReassembler {
+        Self {
+            max_message_size: max_message_size.unwrap_or(DEFAULT_MAX_MESSAGE_SIZE),
+            ..Self::default()
+        }
+    }
+
+    /// Adds a received characteristic value. Returns the complete message once its
+    /// last part was received, or `None` while more of it is expected.
+    ///
+    /// A value with an unknown first byte, or that makes the message larger than the
+    /// maximum size, discards the partial message.
+    pub fn push(&self, value: Vec<u8>) -> Result<Option<Vec<u8>>, BleFramingError> {
+        let mut buffer = self.buffer.lock().map_err(|_| BleFramingError::Generic {
+            value: "Could not lock mutex".to_string(),
+        })?;
+        let Some((&header, data)) = value.split_first() else {
+            return Err(BleFramingError::EmptyValue);
+        };
+        if (buffer.len() + data.len()) as u64 > self.max_message_size {
+            buffer.clear();
+            return Err(BleFramingError::MessageTooLarge {
+                max_message_size: self.max_message_size,
+            });
+        }
+        match header {
+            MORE_TO_COME => {
+                buffer.extend_from_slice(data);
+                Ok(None)
+            }
+            LAST_PART => {
+                buffer.extend_from_slice(data);
+                Ok(Some(std::mem::take(&mut *buffer)))
+            }
+            header => {
+                buffer.clear();
+                Err(BleFramingError::InvalidHeader { header })
+            }
+        }
+    }
+
+    /// Discards the partial message, e.g. after the connection was lost.
+    pub fn reset(&self) {
+        if let Ok(mut buffer) = self.buffer.lock() {
+            buffer.clear();
+        }
+    }
+}
+
+#[derive(thiserror::Error, uniffi::Error, Debug)]
+pub enum BleFramingError {
+    #[error("ATT_MTU {mtu} leaves no room for message data")]
+    MtuTooSmall { mtu: u16 },
+    #[error("received an empty characteristic value")]
+    EmptyValue,
+    #[error("invalid first byte {header:#04x} of a characteristic value")]
+    InvalidHeader { header: u8 },
+    #[error("the message is larger than {max_message_size} bytes")]
+    MessageTooLarge { max_message_size: u64 },
+    #[error("{value}")]
+    Generic { value: String },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ble_framing() {
+        let message: Vec<u8> = (0..=255).collect();
+
+        // 1. Values carry at most ATT_MTU - 4 bytes of the message after the header
+        let values = ble_segment_message(message.clone(), 23).unwrap();
+        assert_eq!(values.len(), 14);
+        assert!(values.iter().all(|value| value.len() <= 20));
+        assert!(values[..13].iter().all(|value| value[0] == MORE_TO_COME));
+        assert_eq!(values[13][0], LAST_PART);
+
+        // 2. Reassembly returns the message with its last part
+        let reassembler = BleMessageReassembler::new(None);
+        let (last, parts) = values.split_last().unwrap();
+        for value in parts {
+            assert_eq!(reassembler.push(value.clone()).unwrap(), None);
+        }
+        assert_eq!(reassembler.push(last.clone()).unwrap(), Some(message));
+        assert_eq!(
+            reassembler.push(vec![LAST_PART, 1, 2]).unwrap(),
+            Some(vec![1, 2])
+        );
+
+        // 3. Malformed input is rejected
+        assert!(matches!(
+            ble_segment_message(vec![1], 4),
+            Err(BleFramingError::MtuTooSmall { mtu: 4 })
+        ));
+        assert!(matches!(
+            reassembler.push(vec![]),
+            Err(BleFramingError::EmptyValue)
+        ));
+        reassembler.push(vec![MORE_TO_COME, 1]).unwrap();
+        assert!(matches!(
+            reassembler.push(vec![0x02, 2]),
+            Err(BleFramingError::InvalidHeader { header: 0x02 })
+        ));
+        assert_eq!(reassembler.push(vec![LAST_PART, 3]).unwrap(), Some(vec![3]));
+
+        // 4. Messages larger than the maximum size are discarded
+        let reassembler = BleMessageReassembler::new(Some(4));
+        reassembler.push(vec![MORE_TO_COME, 1, 2, 3]).unwrap();
+        assert!(matches!(
+            reassembler.push(vec![LAST_PART, 4, 5]),
+            Err(BleFramingError::MessageTooLarge {
+                max_message_size: 4
+            })
+        ));
+        assert_eq!(
+            reassembler.push(vec![LAST_PART, 1, 2, 3, 4]).unwrap(),
+            Some(vec![1, 2, 3, 4])
+        );
+    }
+}
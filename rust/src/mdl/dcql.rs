@@ -0,0 +1,311 @@
+// Copyright (c) 2025 Indicio
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+//
+// This software may be modified and distributed under the terms
+// of either the Apache License, Version 2.0 or the MIT license.
+// See the LICENSE-APACHE and LICENSE-MIT files for details.
+
+//! Matching of the documents of a response against the DCQL query of the OpenID4VP
+//! request, for the `mso_mdoc` credential queries it holds.
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use super::reader::MDLReaderDocument;
+
+/// Why a claim of the query is not satisfied by the response.
+#[derive(uniffi::Enum, Debug, Clone, PartialEq, Eq)]
+pub enum UnmetClaimReason {
+    /// No document of the requested docType was returned.
+    MissingDocument,
+    /// The document was returned without the element.
+    MissingElement,
+    /// The element was returned with none of the values the query allows.
+    ValueMismatch,
+}
+
+/// A claim of a DCQL query that the returned documents do not satisfy.
+#[derive(uniffi::Record, Debug, Clone, PartialEq, Eq)]
+pub struct UnmetClaim {
+    /// The id of the credential query.
+    pub credential_query_id: String,
+    /// The id of the claim query, if it has one.
+    pub claim_id: Option<String>,
+    /// The namespace and element identifier of the claim, empty for a missing document.
+    pub path: Vec<String>,
+    pub reason: UnmetClaimReason,
+}
+
+#[derive(Deserialize)]
+struct Query {
+    credentials: Vec<CredentialQuery>,
+    credential_sets: Option<Vec<CredentialSetQuery>>,
+}
+
+#[derive(Deserialize)]
+struct CredentialQuery {
+    id: String,
+    format: String,
+    #[serde(default)]
+    meta: Meta,
+    claims: Option<Vec<ClaimQuery>>,
+    claim_sets: Option<Vec<Vec<String>>>,
+}
+
+#[derive(Deserialize, Default)]
+struct Meta {
+    doctype_value: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ClaimQuery {
+    id: Option<String>,
+    path: Vec<String>,
+    values: Option<Vec<Value>>,
+}
+
+#[derive(Deserialize)]
+struct CredentialSetQuery {
+    options: Vec<Vec<String>>,
+    #[serde(default = "required")]
+    required: bool,
+}
+
+fn required() -> bool {
+    true
+}
+
+/// Returns the claims of the DCQL query that the documents do not satisfy, empty when
+/// the response answers the query.
+///
+/// Every `mso_mdoc` credential query is required, unless the query has
+/// `credential_sets`: then each required set must have an option whose credentials are
+/// all satisfied, and the claims of the first option are reported otherwise. Likewise,
+/// a credential query with `claim_sets` is satisfied by any of its sets, and the claims
+/// of its first set are reported when none is. Only the content of the documents is
+/// matched, their authentication is reported by the verification itself.
+pub(crate) fn unmet_claims(
+    query: &str,
+    documents: &[MDLReaderDocument],
+) -> Result<Vec<UnmetClaim>, String> {
+    let query: Query =
+        serde_json::from_str(query).map_err(|e| format!("Invalid DCQL query: {e}"))?;
+    let unmet = |id: &str| -> Vec<UnmetClaim> {
+        query
+            .credentials
+            .iter()
+            .find(|credential| credential.id == id)
+            .map(|credential| credential_unmet(credential, documents))
+            .unwrap_or_default()
+    };
+    let mdoc_queries = query
+        .credentials
+        .iter()
+        .filter(|credential| credential.format == "mso_mdoc");
+    let Some(credential_sets) = &query.credential_sets else {
+        return Ok(mdoc_queries
+            .flat_map(|credential| credential_unmet(credential, documents))
+            .collect());
+    };
+    Ok(credential_sets
+        .iter()
+        .filter(|set| set.required)
+        .flat_map(|set| {
+            let mut options = set
+                .options
+                .iter()
+                .map(|option| option.iter().flat_map(|id| unmet(id)).collect::<Vec<_>>());
+            let first = options.next().unwrap_or_default();
+            if first.is_empty() || options.any(|unmet| unmet.is_empty()) {
+                vec![]
+            } else {
+                first
+            }
+        })
+        .collect())
+}
+
+/// The unmet claims of a credential query, by the first document of its docType unless
+/// another satisfies it.
+fn credential_unmet(
+    credential: &CredentialQuery,
+    documents: &[MDLReaderDocument],
+) -> Vec<UnmetClaim> {
+    if credential.format != "mso_mdoc" {
+        return vec![];
+    }
+    let mut candidates = documents.iter().filter(|document| {
+        credential
+            .meta
+            .doctype_value
+            .as_ref()
+            .is_none_or(|doc_type| &document.doc_type == doc_type)
+    });
+    let Some(first) = candidates.next() else {
+        return vec![UnmetClaim {
+            credential_query_id: credential.id.clone(),
+            claim_id: None,
+            path: vec![],
+            reason: UnmetClaimReason::MissingDocument,
+        }];
+    };
+    let unmet = document_unmet(credential, first);
+    if unmet.is_empty()
+        || candidates.any(|document| document_unmet(credential, document).is_empty())
+    {
+        return vec![];
+    }
+    unmet
+}
+
+fn document_unmet(credential: &CredentialQuery, document: &MDLReaderDocument) -> Vec<UnmetClaim> {
+    let Some(claims) = &credential.claims else {
+        return vec![];
+    };
+    let claim_unmet = |claim: &ClaimQuery| {
+        let element = match claim.path.as_slice() {
+            [namespace, identifier] => document
+                .namespaces
+                .get(namespace)
+                .and_then(|elements| elements.get(identifier)),
+            _ => None,
+        };
+        let reason = match (element, &claim.values) {
+            (None, _) => UnmetClaimReason::MissingElement,
+            (Some(element), Some(values)) if !values.contains(&element.into()) => {
+                UnmetClaimReason::ValueMismatch
+            }
+            _ => return None,
+        };
+        Some(UnmetClaim {
+            credential_query_id: credential.id.clone(),
+            claim_id: claim.id.clone(),
+            path: claim.path.clone(),
+            reason,
+        })
+    };
+    let Some(claim_sets) = &credential.claim_sets else {
+        return claims.iter().filter_map(claim_unmet).collect();
+    };
+    let mut sets = claim_sets.iter().map(|set| {
+        claims
+            .iter()
+            .filter(|claim| claim.id.as_ref().is_some_and(|id| set.contains(id)))
+            .filter_map(claim_unmet)
+            .collect::<Vec<_>>()
+    });
+    let first = sets.next().unwrap_or_default();
+    if first.is_empty() || sets.any(|unmet| unmet.is_empty()) {
+        return vec![];
+    }
+    first
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::mdl::reader::{AuthenticationStatus, MDocItem};
+
+    fn document(doc_type: &str, elements: &[(&str, MDocItem)]) -> MDLReaderDocument {
+        MDLReaderDocument {
+            doc_type: doc_type.to_string(),
+            namespaces: HashMap::from([(
+                "org.iso.18013.5.1".to_string(),
+                elements
+                    .iter()
+                    .map(|(id, value)| (id.to_string(), value.clone()))
+                    .collect(),
+            )]),
+            issuer_authentication: AuthenticationStatus::Valid,
+            device_authentication: AuthenticationStatus::Valid,
+            issues: vec![],
+            validity: None,
+            issuer_certificate: None,
+        }
+    }
+
+    #[test]
+    fn test_unmet_claims() {
+        let mdl = [document(
+            "org.iso.18013.5.1.mDL",
+            &[
+                ("family_name", MDocItem::Text("Smith".into())),
+                ("age_over_21", MDocItem::Bool(true)),
+            ],
+        )];
+        let query = |claims: serde_json::Value, claim_sets: Option<serde_json::Value>| {
+            let mut credential = json!({
+                "id": "mdl",
+                "format": "mso_mdoc",
+                "meta": { "doctype_value": "org.iso.18013.5.1.mDL" },
+                "claims": claims,
+            });
+            if let Some(claim_sets) = claim_sets {
+                credential["claim_sets"] = claim_sets;
+            }
+            json!({ "credentials": [credential] }).to_string()
+        };
+        let claim =
+            |id: &str, element: &str| json!({ "id": id, "path": ["org.iso.18013.5.1", element] });
+
+        // 1. The returned elements satisfy the query
+        let satisfied = query(
+            json!([claim("a", "family_name"), claim("b", "age_over_21")]),
+            None,
+        );
+        assert_eq!(unmet_claims(&satisfied, &mdl).unwrap(), vec![]);
+
+        // 2. Missing elements and values outside those allowed are reported
+        let mut over_18 = claim("c", "age_over_21");
+        over_18["values"] = json!([false]);
+        let unmet = unmet_claims(
+            &query(json!([claim("a", "given_name"), over_18]), None),
+            &mdl,
+        )
+        .unwrap();
+        assert_eq!(
+            unmet
+                .iter()
+                .map(|claim| (claim.claim_id.as_deref(), claim.reason.clone()))
+                .collect::<Vec<_>>(),
+            vec![
+                (Some("a"), UnmetClaimReason::MissingElement),
+                (Some("c"), UnmetClaimReason::ValueMismatch),
+            ]
+        );
+        assert_eq!(unmet[0].path, vec!["org.iso.18013.5.1", "given_name"]);
+
+        // 3. Any claim set satisfies the query, else the first is reported
+        let claims = json!([
+            claim("a", "given_name"),
+            claim("b", "family_name"),
+            claim("c", "portrait")
+        ]);
+        let sets = query(claims.clone(), Some(json!([["a"], ["b"]])));
+        assert_eq!(unmet_claims(&sets, &mdl).unwrap(), vec![]);
+        let sets = query(claims, Some(json!([["a"], ["c"]])));
+        let unmet = unmet_claims(&sets, &mdl).unwrap();
+        assert_eq!(unmet.len(), 1);
+        assert_eq!(unmet[0].claim_id.as_deref(), Some("a"));
+
+        // 4. A missing document fails the whole credential query
+        let unmet = unmet_claims(&satisfied, &[document("org.example.pid", &[])]).unwrap();
+        assert_eq!(unmet[0].reason, UnmetClaimReason::MissingDocument);
+        assert!(unmet[0].path.is_empty());
+
+        // 5. Optional credential sets and other formats are not required
+        let optional = json!({
+            "credentials": [
+                { "id": "pid", "format": "mso_mdoc", "meta": { "doctype_value": "org.example.pid" } },
+                { "id": "sd", "format": "dc+sd-jwt" }
+            ],
+            "credential_sets": [{ "options": [["pid"]], "required": false }]
+        });
+        assert_eq!(unmet_claims(&optional.to_string(), &mdl).unwrap(), vec![]);
+        assert!(unmet_claims("{}", &mdl).is_err());
+    }
+}
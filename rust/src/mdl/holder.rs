@@ -9,38 +9,164 @@
 // This project contains code from Spruce Systems, Inc.
 // https://github.com/spruceid/sprucekit-mobile
 
-use isomdl::definitions::x509::trust_anchor::TrustAnchorRegistry;
+use isomdl::definitions::x509::{
+    X5Chain,
+    trust_anchor::{PemTrustAnchor, TrustAnchorRegistry, TrustPurpose},
+    validation::ValidationRuleset,
+};
 use isomdl::{
     definitions::{
-        BleOptions, DeviceRetrievalMethod, SessionEstablishment,
-        device_engagement::{CentralClientMode, DeviceRetrievalMethods},
-        helpers::NonEmptyMap,
+        BleOptions, DeviceEngagement, DeviceRetrievalMethod, NfcOptions, PeripheralServerMode,
+        WifiOptions,
+        device_engagement::{
+            CentralClientMode, DeviceRetrievalMethods, Security, ServerRetrievalMethods,
+            ServerRetrievalOption,
+        },
+        device_request::DeviceRequest,
+        helpers::{NonEmptyMap, NonEmptyVec, Tag24},
         session,
     },
-    presentation::device::{self, SessionManagerInit},
+    presentation::device,
 };
 
-use std::ops::DerefMut;
+use base64::prelude::*;
+use ciborium::Value;
+use coset::AsCborValue;
+use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
-    sync::{Arc, Mutex},
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet, HashMap},
+    sync::{Arc, Mutex, PoisonError},
+    time::{SystemTime, UNIX_EPOCH},
 };
 use uuid::Uuid;
 
-use super::mdoc::Mdoc;
+use super::jwe;
+use super::mdoc::{KeyAlias, Mdoc};
+use super::namespaces::{MDL_NAMESPACE, age_over_nn};
+use super::nfc;
+use super::reader::{AuthenticationStatus, OID4VPSessionTranscript};
+use super::session_encryption::{self, DeviceSession};
+use super::util::{
+    cbor_diagnostic, common_name, jwk_thumbprint, verify_reader_auth_signature, x5chain_header,
+};
 
+/// The holder side of an ISO 18013-5 presentation.
+///
+/// The session is encrypted with keys agreed between the EDeviceKey generated for the
+/// engagement and the reader's EReaderKey. Responses are device authenticated with a
+/// deviceSignature, or with a deviceMac if the wallet responds with
+/// [MdlPresentationSession::respond_with_device_mac].
 #[derive(uniffi::Object)]
 pub struct MdlPresentationSession {
-    engaged: Mutex<device::SessionManagerEngaged>,
+    /// The engagement, dropped with its EDeviceKey once expired and used up by the
+    /// request establishing the session.
+    engaged: Mutex<Option<Engagement>>,
+    /// When the engagement was generated, in seconds since the Unix epoch.
+    engaged_at: u64,
+    /// How long the engagement stays valid without a request, in seconds.
+    engagement_ttl: Mutex<Option<u64>>,
     in_process: Mutex<Option<InProcessRecord>>,
+    /// The presented mdoc, shared with the caller rather than copied, its
+    /// IssuerSignedItemBytes selected by reference for each response.
+    mdoc: Arc<Mdoc>,
     pub qr_code_uri: String,
     pub ble_ident: Vec<u8>,
+    retrieval_methods: Vec<RetrievalMethod>,
+    selected_retrieval_method: Mutex<Option<RetrievalMethod>>,
+    listener: Mutex<Option<Arc<dyn SessionListener>>>,
+    /// The `age_over_NN` elements of the mdoc, by `NN`.
+    age_attestations: BTreeMap<u8, bool>,
+    /// The docType of the mdoc.
+    doc_type: String,
+    /// The identifiers of the elements of the mdoc, by namespace.
+    elements: BTreeMap<String, BTreeSet<String>>,
+    /// The signature algorithm of the device key of the mdoc.
+    device_key_algorithm: DeviceKeyAlgorithm,
+}
+
+/// The engagement of a session waiting for the reader's request.
+#[derive(Clone, Serialize, Deserialize)]
+struct Engagement {
+    /// The scalar of the EDeviceKey.
+    #[serde(with = "serde_bytes")]
+    e_device_key: Vec<u8>,
+    /// The NFC handover the session transcript is bound to, `None` for the QR handover.
+    nfc_handover: Option<NfcHandover>,
+}
+
+/// The NDEF messages of an NFC handover.
+#[derive(Clone, Serialize, Deserialize)]
+struct NfcHandover {
+    #[serde(with = "serde_bytes")]
+    handover_select: Vec<u8>,
+    /// The reader's Handover Request, in negotiated handover.
+    #[serde(with = "serde_bytes")]
+    handover_request: Option<Vec<u8>>,
+}
+
+impl Engagement {
+    /// The Handover of the SessionTranscript per ISO 18013-5 9.1.5.1: the Handover
+    /// Select and Handover Request messages of NFC handover, or null for the QR handover.
+    fn handover(&self) -> Value {
+        match &self.nfc_handover {
+            None => Value::Null,
+            Some(handover) => Value::Array(vec![
+                Value::Bytes(handover.handover_select.clone()),
+                handover
+                    .handover_request
+                    .clone()
+                    .map_or(Value::Null, Value::Bytes),
+            ]),
+        }
+    }
 }
 
-#[derive(uniffi::Object, Clone)]
+#[derive(uniffi::Object, Clone, Serialize, Deserialize)]
 struct InProcessRecord {
-    session: device::SessionManager,
+    session: DeviceSession,
+    /// The CBOR encoded DeviceRequest being answered, as decrypted.
+    #[serde(with = "serde_bytes")]
+    device_request: Vec<u8>,
     items_request: device::RequestedItems,
+    /// The response prepared by [MdlPresentationSession::generate_response], until its
+    /// signature is submitted.
+    response: Option<PreparedResponse>,
+}
+
+/// The elements a response discloses, prepared from the request and the items the user
+/// permitted to share.
+#[derive(Clone, Serialize, Deserialize)]
+struct PreparedResponse {
+    /// The permitted elements of the mdoc, by namespace, empty if it is not returned.
+    permitted: BTreeMap<String, Vec<String>>,
+    /// The requested docTypes reported in documentErrors.
+    declined: Vec<String>,
+}
+
+/// The state of a [MdlPresentationSession] as written by
+/// [MdlPresentationSession::serialize]. The document is borrowed for serialization
+/// rather than cloned.
+#[derive(Serialize, Deserialize)]
+struct SessionState<'a> {
+    engaged: Option<Engagement>,
+    #[serde(default)]
+    engaged_at: u64,
+    #[serde(default)]
+    engagement_ttl: Option<u64>,
+    in_process: Option<InProcessRecord>,
+    document: Cow<'a, device::Document>,
+    key_alias: KeyAlias,
+    qr_code_uri: String,
+    #[serde(with = "serde_bytes")]
+    ble_ident: Vec<u8>,
+    retrieval_methods: Vec<RetrievalMethod>,
+    selected_retrieval_method: Option<RetrievalMethod>,
+    age_attestations: BTreeMap<u8, bool>,
+    doc_type: String,
+    elements: BTreeMap<String, BTreeSet<String>>,
+    #[serde(default)]
+    device_key_algorithm: DeviceKeyAlgorithm,
 }
 
 #[uniffi::export]
@@ -62,37 +188,161 @@ impl MdlPresentationSession {
     ///
     #[uniffi::constructor]
     pub fn new(mdoc: Arc<Mdoc>, uuid: String) -> Result<MdlPresentationSession, SessionError> {
-        let uuid_parsed = Uuid::parse_str(&uuid).map_err(|e| SessionError::Generic {
-            value: format!("Invalid UUID: {}", e),
-        })?;
+        Self::new_with_retrieval_methods(mdoc, vec![RetrievalMethod::BleCentralClient { uuid }])
+    }
 
-        let drms = DeviceRetrievalMethods::new(DeviceRetrievalMethod::BLE(BleOptions {
-            peripheral_server_mode: None,
-            central_client_mode: Some(CentralClientMode { uuid: uuid_parsed }),
-        }));
-        let session = SessionManagerInit::initialise(
-            NonEmptyMap::new("org.iso.18013.5.1.mDL".into(), mdoc.document().clone()),
-            Some(drms),
-            None,
-        )
-        .map_err(|e| SessionError::Generic {
-            value: format!("Could not initialize session: {e:?}"),
-        })?;
-        let ble_ident = session
-            .ble_ident()
-            .map_err(|e| SessionError::Generic {
-                value: format!("Couldn't get BLE identification: {e:?}").to_string(),
-            })?
-            .to_vec();
-        let (engaged_state, qr_code_uri) =
-            session.qr_engagement().map_err(|e| SessionError::Generic {
-                value: format!("Could not generate qr engagement: {e:?}"),
+    /// Begin the mDL presentation process, advertising each of the given device
+    /// retrieval methods in the DeviceEngagement so the reader can pick the transport it
+    /// supports. BLE central client and peripheral server modes are combined into one
+    /// BLE retrieval method.
+    ///
+    /// The BLE ident is empty if no BLE mode is advertised.
+    #[uniffi::constructor]
+    pub fn new_with_retrieval_methods(
+        mdoc: Arc<Mdoc>,
+        retrieval_methods: Vec<RetrievalMethod>,
+    ) -> Result<MdlPresentationSession, SessionError> {
+        Self::new_with_server_retrieval(mdoc, retrieval_methods, None)
+    }
+
+    /// Same as [MdlPresentationSession::new_with_retrieval_methods], also advertising
+    /// the server retrieval options in the DeviceEngagement, so the reader can fall back
+    /// to retrieving the mdoc from the issuer if the proximity transfer fails. No device
+    /// retrieval method is needed if server retrieval is advertised.
+    #[uniffi::constructor]
+    pub fn new_with_server_retrieval(
+        mdoc: Arc<Mdoc>,
+        retrieval_methods: Vec<RetrievalMethod>,
+        server_retrieval: Option<ServerRetrieval>,
+    ) -> Result<MdlPresentationSession, SessionError> {
+        let device_key_algorithm =
+            DeviceKeyAlgorithm::of(&mdoc).ok_or_else(|| SessionError::Generic {
+                value: "Unsupported device key".to_string(),
             })?;
+        let drms = if retrieval_methods.is_empty() && server_retrieval.is_some() {
+            None
+        } else {
+            Some(device_retrieval_methods(&retrieval_methods)?)
+        };
+        let age_attestations = age_attestations(mdoc.document());
+        let doc_type = mdoc.doctype();
+        let elements = element_identifiers(mdoc.document());
+
+        let error = |value: String| SessionError::Generic {
+            value: format!("Could not generate the device engagement: {value}"),
+        };
+        let e_device_key = session_encryption::e_device_key();
+        let e_device_key_bytes = session_encryption::cose_key(&e_device_key.public_key())
+            .map_err(|e| error(format!("{e:?}")))
+            .and_then(|key| Tag24::new(key).map_err(|e| error(format!("{e:?}"))))?;
+        let ble_ident = if retrieval_methods.iter().any(RetrievalMethod::is_ble) {
+            let ble_ident_error = |value: String| SessionError::Generic {
+                value: format!("Couldn't get BLE identification: {value}"),
+            };
+            let bytes = isomdl::cbor::to_vec(&e_device_key_bytes)
+                .map_err(|e| ble_ident_error(format!("{e:?}")))?;
+            session_encryption::ble_ident(&bytes)
+                .map_err(|e| ble_ident_error(format!("{e:?}")))?
+                .to_vec()
+        } else {
+            Vec::new()
+        };
+        let device_engagement = Tag24::new(DeviceEngagement {
+            version: "1.0".to_string(),
+            security: Security(1, e_device_key_bytes),
+            device_retrieval_methods: drms,
+            server_retrieval_methods: server_retrieval.map(Into::into),
+            protocol_info: None,
+        })
+        .map_err(|e| error(format!("{e:?}")))?;
+        let qr_code_uri = format!(
+            "mdoc:{}",
+            BASE64_URL_SAFE_NO_PAD.encode(&device_engagement.inner_bytes)
+        );
         Ok(MdlPresentationSession {
-            engaged: Mutex::new(engaged_state),
+            engaged: Mutex::new(Some(Engagement {
+                e_device_key: e_device_key.to_bytes().to_vec(),
+                nfc_handover: None,
+            })),
+            engaged_at: unix_time(),
+            engagement_ttl: Mutex::new(None),
             in_process: Mutex::new(None),
+            mdoc,
             qr_code_uri,
             ble_ident,
+            retrieval_methods,
+            selected_retrieval_method: Mutex::new(None),
+            listener: Mutex::new(None),
+            age_attestations,
+            doc_type,
+            elements,
+            device_key_algorithm,
+        })
+    }
+
+    /// Restore a presentation session from the bytes returned by
+    /// [MdlPresentationSession::serialize], so a presentation can be resumed after the
+    /// app was stopped, whether or not a request had already been received.
+    #[uniffi::constructor]
+    pub fn restore(bytes: Vec<u8>) -> Result<MdlPresentationSession, SessionError> {
+        let state: SessionState =
+            isomdl::cbor::from_slice(&bytes).map_err(|e| SessionError::Generic {
+                value: format!("Could not deserialize session state: {e:?}"),
+            })?;
+        Ok(MdlPresentationSession {
+            engaged: Mutex::new(state.engaged),
+            engaged_at: state.engaged_at,
+            engagement_ttl: Mutex::new(state.engagement_ttl),
+            in_process: Mutex::new(state.in_process),
+            mdoc: Arc::new(Mdoc::new_from_parts(
+                state.document.into_owned(),
+                state.key_alias,
+            )),
+            qr_code_uri: state.qr_code_uri,
+            ble_ident: state.ble_ident,
+            retrieval_methods: state.retrieval_methods,
+            selected_retrieval_method: Mutex::new(state.selected_retrieval_method),
+            listener: Mutex::new(None),
+            age_attestations: state.age_attestations,
+            doc_type: state.doc_type,
+            elements: state.elements,
+            device_key_algorithm: state.device_key_algorithm,
+        })
+    }
+
+    /// Serialize the session state, including the session keys and the request being
+    /// answered if any, to be restored with [MdlPresentationSession::restore].
+    ///
+    /// The bytes contain the session encryption keys and should be kept in secure
+    /// storage.
+    pub fn serialize(&self) -> Result<Vec<u8>, SessionError> {
+        fn lock_error<T>(_: T) -> SessionError {
+            SessionError::Generic {
+                value: "Could not lock mutex".to_string(),
+            }
+        }
+        let state = SessionState {
+            engaged: self.engaged.lock().map_err(lock_error)?.clone(),
+            engaged_at: self.engaged_at,
+            engagement_ttl: *self.engagement_ttl.lock().map_err(lock_error)?,
+            in_process: self.in_process.lock().map_err(lock_error)?.clone(),
+            document: Cow::Borrowed(self.mdoc.document()),
+            key_alias: self.mdoc.key_alias(),
+            qr_code_uri: self.qr_code_uri.clone(),
+            ble_ident: self.ble_ident.clone(),
+            retrieval_methods: self.retrieval_methods.clone(),
+            selected_retrieval_method: self
+                .selected_retrieval_method
+                .lock()
+                .map_err(lock_error)?
+                .clone(),
+            age_attestations: self.age_attestations.clone(),
+            doc_type: self.doc_type.clone(),
+            elements: self.elements.clone(),
+            device_key_algorithm: self.device_key_algorithm,
+        };
+        isomdl::cbor::to_vec(&state).map_err(|e| SessionError::Generic {
+            value: format!("Could not serialize session state: {e:?}"),
         })
     }
 
@@ -102,50 +352,152 @@ impl MdlPresentationSession {
     /// technology. Returns a Vector of information items requested by the reader, or an
     /// error.
     pub fn handle_request(&self, request: Vec<u8>) -> Result<Vec<ItemsRequest>, RequestError> {
-        let (session_manager, items_requests) = {
-            let session_establishment: SessionEstablishment = isomdl::cbor::from_slice(&request)
+        self.handle_request_with_reader_trust_anchors(request, Vec::new())
+    }
+
+    /// Same as [MdlPresentationSession::handle_request], verifying the reader
+    /// authentication of the request against the given PEM encoded reader CA
+    /// certificates. The outcome and the reader's common name are reported in each
+    /// [ItemsRequest], so the wallet can show the user who is asking before consent.
+    /// Reader authentication is unchecked if no trust anchors are given.
+    pub fn handle_request_with_reader_trust_anchors(
+        &self,
+        request: Vec<u8>,
+        reader_trust_anchors: Vec<String>,
+    ) -> Result<Vec<ItemsRequest>, RequestError> {
+        let items_requests = self.process_request(request, reader_trust_anchors);
+        self.notify(items_requests, |listener, items_requests| {
+            listener.on_request_received(items_requests.clone())
+        })
+    }
+
+    /// Same as [MdlPresentationSession::handle_request_with_reader_trust_anchors], with
+    /// the requests for the docType of the presented mdoc apart from the docTypes it
+    /// cannot answer, so the wallet can tell the user before asking for consent.
+    pub fn handle_request_matching_doc_type(
+        &self,
+        request: Vec<u8>,
+        reader_trust_anchors: Vec<String>,
+    ) -> Result<MatchedItemsRequests, RequestError> {
+        let items_requests =
+            self.handle_request_with_reader_trust_anchors(request, reader_trust_anchors)?;
+        Ok(match_doc_type(&self.doc_type, items_requests))
+    }
+
+    /// Same as [MdlPresentationSession::handle_request_with_reader_trust_anchors], also
+    /// returning the DeviceRequest exactly as it was decrypted from the
+    /// SessionEstablishment, to diagnose interop failures with readers. Not meant to be
+    /// used outside of development.
+    pub fn handle_request_debug(
+        &self,
+        request: Vec<u8>,
+        reader_trust_anchors: Vec<String>,
+    ) -> Result<DebugItemsRequests, RequestError> {
+        let items_requests =
+            self.handle_request_with_reader_trust_anchors(request, reader_trust_anchors)?;
+        let device_request_cbor = {
+            let in_process = self.in_process.lock().map_err(|_| RequestError::Generic {
+                value: "Could not lock mutex".to_string(),
+            })?;
+            let in_process = in_process.as_ref().ok_or(RequestError::Generic {
+                value: "No session has been established".to_string(),
+            })?;
+            in_process.device_request.clone()
+        };
+        let device_request_diagnostic =
+            ciborium::from_reader::<ciborium::Value, _>(device_request_cbor.as_slice())
+                .map(|value| cbor_diagnostic(&value))
                 .map_err(|e| RequestError::Generic {
-                    value: format!("Could not deserialize request: {e:?}"),
+                    value: format!("Could not decode the device request: {e:?}"),
                 })?;
-            self.engaged
-                .lock()
-                .map_err(|_| RequestError::Generic {
-                    value: "Could not lock mutex".to_string(),
-                })?
-                .clone()
-                .process_session_establishment(
-                    session_establishment,
-                    TrustAnchorRegistry::default(),
-                )
-                .map_err(|e| RequestError::Generic {
-                    value: format!("Could not process process session establishment: {e:?}"),
-                })?
-        };
+        Ok(DebugItemsRequests {
+            items_requests,
+            device_request_cbor,
+            device_request_diagnostic,
+        })
+    }
+
+    /// Handle a SessionData message received from the reader after the first request.
+    ///
+    /// Returns the further request it carries, to be answered like the first one, or
+    /// the status the reader sent instead: the end of the session or an error.
+    pub fn handle_session_data(&self, bytes: Vec<u8>) -> Result<SessionDataMessage, RequestError> {
+        let message = self.decode_session_data(bytes);
+        self.notify(message, |listener, message| match message {
+            SessionDataMessage::Request { items_requests } => {
+                listener.on_request_received(items_requests.clone())
+            }
+            SessionDataMessage::Terminated => listener.on_terminated(),
+            SessionDataMessage::SessionEncryptionError => {
+                listener.on_error("The reader could not decrypt the session data".to_string())
+            }
+            SessionDataMessage::CborDecodingError => {
+                listener.on_error("The reader could not decode the session data".to_string())
+            }
+        })
+    }
 
+    /// For every requested `age_over_NN` element the mdoc does not have, adds the
+    /// nearest `age_over_NN` element it does have to the request, as allowed by ISO
+    /// 18013-5 7.2.5: the smallest greater `NN` that is true, or else the greatest
+    /// smaller `NN` that is false. Only issuer signed elements are used, nothing is
+    /// derived from the birth date.
+    ///
+    /// Wallets whose policy allows it call this after handling the request, and add the
+    /// substitutes the user consents to to the permitted items. The returned report also
+    /// lists the requested elements that cannot be satisfied.
+    pub fn substitute_age_over_elements(&self) -> Result<Vec<AgeOverSubstitution>, RequestError> {
         let mut in_process = self.in_process.lock().map_err(|_| RequestError::Generic {
             value: "Could not lock mutex".to_string(),
         })?;
-        *in_process = Some(InProcessRecord {
-            session: session_manager,
-            items_request: items_requests.items_request.clone(),
-        });
+        let in_process = in_process.as_mut().ok_or(RequestError::Generic {
+            value: "No request has been received".to_string(),
+        })?;
 
-        Ok(items_requests
-            .items_request
-            .into_iter()
-            .map(|req| ItemsRequest {
-                doc_type: req.doc_type,
-                namespaces: req
-                    .namespaces
-                    .into_inner()
-                    .into_iter()
-                    .map(|(ns, es)| {
-                        let items_request = es.into_inner().into_iter().collect();
-                        (ns, items_request)
-                    })
-                    .collect(),
-            })
-            .collect())
+        let mut substitutions = Vec::new();
+        for request in in_process.items_request.iter_mut() {
+            let Some(elements) = request.namespaces.get(MDL_NAMESPACE) else {
+                continue;
+            };
+            let mut elements = elements.clone().into_inner();
+            let missing: Vec<(u8, bool)> = elements
+                .iter()
+                .filter_map(|(identifier, intent_to_retain)| {
+                    let nn = age_over_nn(identifier)?;
+                    (!self.age_attestations.contains_key(&nn)).then_some((nn, *intent_to_retain))
+                })
+                .collect();
+            if missing.is_empty() {
+                continue;
+            }
+
+            for (nn, intent_to_retain) in missing {
+                let substitute = age_over_substitute(&self.age_attestations, nn)
+                    .map(|substitute| format!("age_over_{substitute:02}"));
+                if let Some(substitute) = &substitute {
+                    elements
+                        .entry(substitute.clone())
+                        .or_insert(intent_to_retain);
+                }
+                substitutions.push(AgeOverSubstitution {
+                    doc_type: request.doc_type.clone(),
+                    requested: format!("age_over_{nn:02}"),
+                    substitute,
+                });
+            }
+            let mut namespaces = request.namespaces.clone().into_inner();
+            namespaces.insert(
+                MDL_NAMESPACE.to_string(),
+                NonEmptyMap::maybe_new(elements).ok_or(RequestError::Generic {
+                    value: "Requested elements are empty".to_string(),
+                })?,
+            );
+            request.namespaces =
+                NonEmptyMap::maybe_new(namespaces).ok_or(RequestError::Generic {
+                    value: "Requested namespaces are empty".to_string(),
+                })?;
+        }
+        Ok(substitutions)
     }
 
     /// Constructs the response to be sent from the holder to the reader containing
@@ -153,60 +505,180 @@ impl MdlPresentationSession {
     ///
     /// Takes a HashMap of items the user has authorized the app to share, as well
     /// as the id of a key stored in the key manager to be used to sign the response.
-    /// Returns a byte array containing the signed response to be returned to the
-    /// reader.
+    /// Returns the payload to be signed for the response to be returned to the
+    /// reader, with a [ConsentSummary] of the requested elements for the consent screen
+    /// and the receipt of what was shared.
     pub fn generate_response(
         &self,
         permitted_items: HashMap<String, HashMap<String, Vec<String>>>,
-    ) -> Result<Vec<u8>, SignatureError> {
-        let permitted = permitted_items
-            .into_iter()
-            .map(|(doc_type, namespaces)| {
-                let ns = namespaces.into_iter().collect();
-                (doc_type, ns)
-            })
-            .collect();
-        if let Some(in_process) = self.in_process.lock().unwrap().deref_mut() {
-            in_process
-                .session
-                .prepare_response(&in_process.items_request, permitted);
-            Ok(in_process
-                .session
-                .get_next_signature_payload()
-                .map(|(_, payload)| payload)
+    ) -> Result<GeneratedResponse, SignatureError> {
+        self.with_in_process(|in_process| {
+            let consent_summary = consent_summary(
+                &self.doc_type,
+                &self.elements,
+                &in_process.items_request,
+                &permitted_items,
+            );
+            let prepared = self.prepare_response(in_process, &permitted_items);
+            let payload = self
+                .signature_payload(&in_process.session, &prepared)?
                 .ok_or(SignatureError::Generic {
                     value: "Failed to get next signature payload".to_string(),
-                })?
-                .to_vec())
-        } else {
-            Err(SignatureError::Generic {
-                value: "Could not get lock on session".to_string(),
+                })?;
+            in_process.response = Some(prepared);
+            Ok(GeneratedResponse {
+                payload,
+                consent_summary,
             })
-        }
+        })
     }
 
+    /// Submits the signature over the payload returned by
+    /// [MdlPresentationSession::generate_response] and returns the response to be sent
+    /// to the reader.
+    ///
+    /// Fails with [SignatureError::TooManyDocuments] if more than one document has to be
+    /// signed, use [MdlPresentationSession::submit_signature] for those responses.
     pub fn submit_response(&self, signature: Vec<u8>) -> Result<Vec<u8>, SignatureError> {
-        let signature = p256::ecdsa::Signature::from_slice(&signature).map_err(|e| {
-            SignatureError::InvalidSignature {
-                value: e.to_string(),
+        self.submit_signature(signature)?
+            .ok_or(SignatureError::TooManyDocuments)
+    }
+
+    /// Returns the next payload to be signed with the device key, or `None` once every
+    /// document of the response has been signed.
+    pub fn next_signature_payload(&self) -> Result<Option<Vec<u8>>, SignatureError> {
+        self.with_in_process(|in_process| match &in_process.response {
+            Some(prepared) => self.signature_payload(&in_process.session, prepared),
+            None => Ok(None),
+        })
+    }
+
+    /// Submits the signature over the current payload. Returns the response to be sent
+    /// to the reader once every document has been signed, or `None` if
+    /// [MdlPresentationSession::next_signature_payload] has another payload to sign.
+    pub fn submit_signature(&self, signature: Vec<u8>) -> Result<Option<Vec<u8>>, SignatureError> {
+        let response =
+            device_signature(self.device_key_algorithm, &signature).and_then(|signature| {
+                self.with_in_process(|in_process| {
+                    let prepared = in_process.response.take().ok_or(SignatureError::Generic {
+                        value: "No response has been generated".to_string(),
+                    })?;
+                    self.session_response(
+                        in_process,
+                        &prepared,
+                        Some(DeviceAuthenticator::Signature(signature)),
+                    )
+                    .map(Some)
+                })
+            });
+        self.notify(response, |listener, response| {
+            if let Some(response) = response {
+                listener.on_response_ready(response.clone())
             }
-        })?;
-        if let Some(in_process) = self.in_process.lock().unwrap().deref_mut() {
-            in_process
-                .session
-                .submit_next_signature(signature.to_bytes().to_vec())
-                .map_err(|e| SignatureError::Generic {
-                    value: format!("Could not submit next signature: {e:?}"),
-                })?;
-            in_process
-                .session
-                .retrieve_response()
-                .ok_or(SignatureError::TooManyDocuments)
-        } else {
-            Err(SignatureError::Generic {
-                value: "Could not get lock on session".to_string(),
-            })
-        }
+        })
+    }
+
+    /// Constructs and signs the response containing the items the user has consented
+    /// to share, like [MdlPresentationSession::generate_response] followed by
+    /// [MdlPresentationSession::submit_response], but with the session asking the
+    /// signer for every signature it needs, one per document.
+    ///
+    /// Returns a byte array containing the signed response to be returned to the
+    /// reader.
+    pub fn respond(
+        &self,
+        permitted_items: HashMap<String, HashMap<String, Vec<String>>>,
+        signer: Box<dyn DeviceSigner>,
+    ) -> Result<Vec<u8>, SignatureError> {
+        let response = self.with_in_process(|in_process| {
+            let prepared = self.prepare_response(in_process, &permitted_items);
+            let authenticator = self
+                .signature_payload(&in_process.session, &prepared)?
+                .map(|payload| {
+                    let signature = signer.sign(payload)?;
+                    device_signature(self.device_key_algorithm, &signature)
+                        .map(DeviceAuthenticator::Signature)
+                })
+                .transpose()?;
+            in_process.response = None;
+            self.session_response(in_process, &prepared, authenticator)
+        });
+        self.notify(response, |listener, response| {
+            listener.on_response_ready(response.clone())
+        })
+    }
+
+    /// Constructs the response containing the items the user has consented to share,
+    /// like [MdlPresentationSession::respond], but device authenticated with a deviceMac
+    /// instead of a deviceSignature, per ISO 18013-5 9.1.3.5. The wallet chooses it for
+    /// a session by responding with this method, which needs no signature and so spares
+    /// the user a biometric prompt of the device key.
+    ///
+    /// The deviceMac is keyed with the EMacKey derived from the key agreement of the
+    /// device key of the mdoc, which must be a P-256 key, with the EReaderKey of the
+    /// session.
+    pub fn respond_with_device_mac(
+        &self,
+        permitted_items: HashMap<String, HashMap<String, Vec<String>>>,
+        key_agreement: Box<dyn DeviceKeyAgreement>,
+    ) -> Result<Vec<u8>, SignatureError> {
+        let response = self.with_in_process(|in_process| {
+            if self.device_key_algorithm != DeviceKeyAlgorithm::Es256 {
+                return Err(SignatureError::Generic {
+                    value: "A deviceMac needs a P-256 device key".to_string(),
+                });
+            }
+            let prepared = self.prepare_response(in_process, &permitted_items);
+            in_process.response = None;
+            self.session_response(
+                in_process,
+                &prepared,
+                Some(DeviceAuthenticator::Mac(key_agreement.as_ref())),
+            )
+        });
+        self.notify(response, |listener, response| {
+            listener.on_response_ready(response.clone())
+        })
+    }
+
+    /// Declines the request being answered with the given status instead of a response
+    /// with data.
+    ///
+    /// [ErrorResponseStatus::DataNotReturned] is sent in an encrypted DeviceResponse and
+    /// leaves the session open for further requests, while the SessionData error
+    /// statuses end the session.
+    pub fn generate_error_response(
+        &self,
+        status: ErrorResponseStatus,
+    ) -> Result<Vec<u8>, ResponseError> {
+        let status = match status {
+            ErrorResponseStatus::DataNotReturned => {
+                let response = self
+                    .with_in_process(|in_process| {
+                        // Without permitted items every requested document is reported
+                        // in documentErrors, leaving nothing to sign
+                        let prepared = self.prepare_response(in_process, &HashMap::new());
+                        in_process.response = None;
+                        self.session_response(in_process, &prepared, None)
+                    })
+                    .map_err(|e| ResponseError::Generic {
+                        value: e.to_string(),
+                    });
+                return self.notify(response, |listener, response| {
+                    listener.on_response_ready(response.clone())
+                });
+            }
+            ErrorResponseStatus::SessionEncryptionError => session::Status::SessionEncryptionError,
+            ErrorResponseStatus::CborDecodingError => session::Status::CborDecodingError,
+        };
+        let msg = session::SessionData {
+            data: None,
+            status: Some(status),
+        };
+        let msg_bytes = isomdl::cbor::to_vec(&msg).map_err(|e| ResponseError::Generic {
+            value: format!("Could not serialize message bytes: {e:?}"),
+        });
+        self.notify(msg_bytes, |listener, _| listener.on_terminated())
     }
 
     /// Terminates the mDL exchange session.
@@ -219,8 +691,75 @@ impl MdlPresentationSession {
         };
         let msg_bytes = isomdl::cbor::to_vec(&msg).map_err(|e| TerminationError::Generic {
             value: format!("Could not serialize message bytes: {e:?}"),
-        })?;
-        Ok(msg_bytes)
+        });
+        self.notify(msg_bytes, |listener, _| listener.on_terminated())
+    }
+
+    /// Register a listener for the events of this session, replacing any previous one,
+    /// or remove it with `None`. If no request has been received yet, the listener is
+    /// told right away that the session is engaged.
+    ///
+    /// The listener is not part of the state written by
+    /// [MdlPresentationSession::serialize] and has to be registered again after a
+    /// restore.
+    pub fn set_listener(&self, listener: Option<Box<dyn SessionListener>>) {
+        let listener: Option<Arc<dyn SessionListener>> = listener.map(Arc::from);
+        if let Ok(mut current) = self.listener.lock() {
+            current.clone_from(&listener);
+        }
+        let engaged = self
+            .in_process
+            .lock()
+            .is_ok_and(|in_process| in_process.is_none());
+        if let Some(listener) = listener.filter(|_| engaged && !self.is_expired()) {
+            listener.on_engaged(self.qr_code_uri.clone());
+        }
+    }
+
+    /// Discard the request being answered, returning the session to waiting for a
+    /// request, and recover the session from a call that failed while holding its
+    /// state, which otherwise fails every later call with a lock error.
+    ///
+    /// The listener and the selected retrieval method are kept. The engagement is kept
+    /// until a request is received, which uses it up, so a session reset after that is
+    /// expired and has to be engaged again.
+    pub fn reset(&self) {
+        self.engaged.clear_poison();
+        self.engagement_ttl.clear_poison();
+        self.selected_retrieval_method.clear_poison();
+        self.listener.clear_poison();
+        self.in_process.clear_poison();
+        *self
+            .in_process
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = None;
+    }
+
+    /// Set how long the engagement stays valid if no request is received, or `None`
+    /// for no limit, the default. An expired session drops its ephemeral device key and
+    /// rejects session establishment, a new session has to be engaged instead.
+    pub fn set_engagement_ttl(&self, ttl_seconds: Option<u64>) {
+        if let Ok(mut ttl) = self.engagement_ttl.lock() {
+            *ttl = ttl_seconds;
+        }
+    }
+
+    /// Whether the engagement expired before a request was received, see
+    /// [MdlPresentationSession::set_engagement_ttl].
+    pub fn is_expired(&self) -> bool {
+        let awaiting_request = self
+            .in_process
+            .lock()
+            .is_ok_and(|in_process| in_process.is_none());
+        let ttl = self.engagement_ttl.lock().ok().and_then(|ttl| *ttl);
+        let Ok(mut engaged) = self.engaged.lock() else {
+            return false;
+        };
+        let stale = ttl.is_some_and(|ttl| unix_time() >= self.engaged_at.saturating_add(ttl));
+        if awaiting_request && stale {
+            *engaged = None;
+        }
+        awaiting_request && engaged.is_none()
     }
 
     /// Returns the generated QR code
@@ -228,62 +767,2720 @@ impl MdlPresentationSession {
         self.qr_code_uri.clone()
     }
 
+    /// Returns the DeviceEngagement CBOR encoded in the QR code URI, to transmit over
+    /// NFC or other channels.
+    ///
+    /// The session transcript is bound to the QR handover unless an NFC Handover Select
+    /// message was served, which the reader then passes to
+    /// `establish_session_with_device_engagement`.
+    pub fn get_device_engagement(&self) -> Result<Vec<u8>, SessionError> {
+        self.qr_code_uri
+            .strip_prefix("mdoc:")
+            .and_then(|engagement| BASE64_URL_SAFE_NO_PAD.decode(engagement).ok())
+            .ok_or_else(|| SessionError::Generic {
+                value: "Invalid QR code URI".to_string(),
+            })
+    }
+
     /// Returns the BLE identification
     pub fn get_ble_ident(&self) -> Vec<u8> {
         self.ble_ident.clone()
     }
-}
 
-#[derive(thiserror::Error, uniffi::Error, Debug)]
-pub enum SessionError {
-    #[error("{value}")]
-    Generic { value: String },
-}
+    /// Returns the device retrieval methods advertised in the DeviceEngagement.
+    pub fn get_retrieval_methods(&self) -> Vec<RetrievalMethod> {
+        self.retrieval_methods.clone()
+    }
 
-#[derive(thiserror::Error, uniffi::Error, Debug)]
-pub enum RequestError {
-    #[error("{value}")]
-    Generic { value: String },
-}
+    /// Record the retrieval method the reader connected over, as observed by the
+    /// transport layer. It must be one of the advertised methods.
+    pub fn select_retrieval_method(&self, method: RetrievalMethod) -> Result<(), SessionError> {
+        if !self.retrieval_methods.contains(&method) {
+            return Err(SessionError::Generic {
+                value: "Retrieval method was not advertised in the device engagement".to_string(),
+            });
+        }
+        *self
+            .selected_retrieval_method
+            .lock()
+            .map_err(|_| SessionError::Generic {
+                value: "Could not lock mutex".to_string(),
+            })? = Some(method);
+        Ok(())
+    }
 
-#[derive(uniffi::Record, Clone)]
-pub struct ItemsRequest {
-    doc_type: String,
-    namespaces: HashMap<String, HashMap<String, bool>>,
-}
+    /// Returns the retrieval method the reader selected, with its BLE mode and UUID. It
+    /// is known once recorded with [MdlPresentationSession::select_retrieval_method], or
+    /// from the reader's Handover Request in NFC negotiated handover, and once the
+    /// session is established if a single retrieval method was advertised.
+    ///
+    /// isomdl's BLE options carry no L2CAP PSM, so none is reported.
+    pub fn get_selected_retrieval_method(&self) -> Option<RetrievalMethod> {
+        self.selected_retrieval_method.lock().ok()?.clone()
+    }
 
-#[derive(thiserror::Error, uniffi::Error, Debug)]
-pub enum ResponseError {
-    #[error("no signature payload received from session manager")]
-    MissingSignature,
-    #[error("{value}")]
-    Generic { value: String },
-}
+    /// Returns the NDEF Handover Select message of NFC static handover, to serve from
+    /// the NDEF file of the HCE service: the DeviceEngagement with an alternative
+    /// carrier for every advertised BLE retrieval method.
+    ///
+    /// The session transcript is bound to the NFC handover from then on, with this
+    /// message and no Handover Request, so the session can no longer be engaged by QR
+    /// code.
+    pub fn nfc_static_handover_select(&self) -> Result<Vec<u8>, SessionError> {
+        let carriers: Vec<nfc::BleCarrier> = self
+            .ble_carriers()?
+            .into_iter()
+            .map(|(_, carrier)| carrier)
+            .collect();
+        let handover_select = nfc::handover_select(&self.get_device_engagement()?, &carriers)
+            .map_err(|value| SessionError::Generic { value })?;
+        self.bind_nfc_handover(handover_select.clone(), None)?;
+        Ok(handover_select)
+    }
 
-#[derive(thiserror::Error, uniffi::Error, Debug)]
-pub enum SignatureError {
-    #[error("Invalid DER signature: {value}")]
-    InvalidSignature { value: String },
-    #[error("there were more documents to sign, but we only expected to sign 1!")]
-    TooManyDocuments,
-    #[error("{value}")]
-    Generic { value: String },
+    /// Returns the NDEF Handover Select message answering the Handover Request message
+    /// of NFC negotiated handover, received once the app selected the TNEP handover
+    /// service. BLE is the only carrier selected, so the request must offer it.
+    ///
+    /// The first advertised BLE mode the reader's LE role allows is selected, and
+    /// reported by [MdlPresentationSession::get_selected_retrieval_method]. The session
+    /// transcript is bound to the NFC handover of the request and the returned message.
+    pub fn nfc_negotiated_handover_select(
+        &self,
+        handover_request: Vec<u8>,
+    ) -> Result<Vec<u8>, SessionError> {
+        let reader_roles =
+            nfc::requested_ble_roles(&handover_request).map_err(|value| SessionError::Generic {
+                value: format!("Invalid handover request: {value}"),
+            })?;
+        if reader_roles.is_empty() {
+            return Err(SessionError::Generic {
+                value: "The handover request offers no BLE carrier".to_string(),
+            });
+        }
+        let (method, carrier) = self
+            .ble_carriers()?
+            .into_iter()
+            .find(|(_, carrier)| reader_roles.iter().any(|role| carrier.accepts(*role)))
+            .ok_or_else(|| SessionError::Generic {
+                value: "The reader's LE role allows none of the advertised BLE modes".to_string(),
+            })?;
+        let handover_select = nfc::handover_select(&self.get_device_engagement()?, &[carrier])
+            .map_err(|value| SessionError::Generic { value })?;
+        self.bind_nfc_handover(handover_select.clone(), Some(handover_request))?;
+        self.select_retrieval_method(method.clone())?;
+        Ok(handover_select)
+    }
 }
 
-#[derive(thiserror::Error, uniffi::Error, Debug)]
-pub enum TerminationError {
-    #[error("{value}")]
-    Generic { value: String },
+#[cfg(feature = "qr-code")]
+#[uniffi::export]
+impl MdlPresentationSession {
+    /// Renders the engagement QR code as a PNG image, at least `size` pixels wide
+    /// including the quiet zone.
+    pub fn qr_code_png(&self, size: u32) -> Result<Vec<u8>, QrCodeError> {
+        qr_code_png(&self.qr_code_uri, size)
+    }
+
+    /// Renders the engagement QR code as an SVG document.
+    pub fn qr_code_svg(&self) -> Result<String, QrCodeError> {
+        qr_code_svg(&self.qr_code_uri)
+    }
 }
 
-#[derive(thiserror::Error, uniffi::Error, Debug)]
-pub enum KeyTransformationError {
-    #[error("{value}")]
-    ToPKCS8 { value: String },
-    #[error("{value}")]
-    FromPKCS8 { value: String },
-    #[error("{value}")]
-    FromSEC1 { value: String },
-    #[error("{value}")]
-    ToSEC1 { value: String },
+impl MdlPresentationSession {
+    /// The alternative carriers of the advertised BLE retrieval methods.
+    fn ble_carriers(&self) -> Result<Vec<(&RetrievalMethod, nfc::BleCarrier)>, SessionError> {
+        let parse_uuid = |uuid: &str| {
+            Uuid::parse_str(uuid).map_err(|e| SessionError::Generic {
+                value: format!("Invalid UUID: {}", e),
+            })
+        };
+        let mut carriers = Vec::new();
+        for method in &self.retrieval_methods {
+            match method {
+                RetrievalMethod::BleCentralClient { uuid } => carriers.push((
+                    method,
+                    nfc::BleCarrier {
+                        uuid: parse_uuid(uuid)?,
+                        peripheral_server: false,
+                        device_address: None,
+                    },
+                )),
+                RetrievalMethod::BlePeripheralServer {
+                    uuid,
+                    ble_device_address,
+                } => carriers.push((
+                    method,
+                    nfc::BleCarrier {
+                        uuid: parse_uuid(uuid)?,
+                        peripheral_server: true,
+                        device_address: ble_device_address.clone(),
+                    },
+                )),
+                RetrievalMethod::Nfc { .. } | RetrievalMethod::WifiAware { .. } => {}
+            }
+        }
+        if carriers.is_empty() {
+            return Err(SessionError::Generic {
+                value: "No BLE retrieval method was advertised".to_string(),
+            });
+        }
+        Ok(carriers)
+    }
+
+    /// Binds the session transcript to the NFC handover of the engagement rather than the
+    /// QR handover.
+    fn bind_nfc_handover(
+        &self,
+        handover_select: Vec<u8>,
+        handover_request: Option<Vec<u8>>,
+    ) -> Result<(), SessionError> {
+        let mut engaged = self.engaged.lock().map_err(|_| SessionError::Generic {
+            value: "Could not lock mutex".to_string(),
+        })?;
+        let engagement = engaged.as_mut().ok_or_else(|| SessionError::Generic {
+            value: "The session has expired".to_string(),
+        })?;
+        engagement.nfc_handover = Some(NfcHandover {
+            handover_select,
+            handover_request,
+        });
+        Ok(())
+    }
+
+    /// Processes the SessionEstablishment message starting the session.
+    fn process_request(
+        &self,
+        request: Vec<u8>,
+        reader_trust_anchors: Vec<String>,
+    ) -> Result<Vec<ItemsRequest>, RequestError> {
+        if self.is_expired() {
+            return Err(RequestError::Generic {
+                value: "The session has expired".to_string(),
+            });
+        }
+        let registry = if reader_trust_anchors.is_empty() {
+            None
+        } else {
+            Some(
+                TrustAnchorRegistry::from_pem_certificates(
+                    reader_trust_anchors
+                        .into_iter()
+                        .map(|certificate_pem| PemTrustAnchor {
+                            certificate_pem,
+                            purpose: TrustPurpose::ReaderCa,
+                        })
+                        .collect(),
+                )
+                .map_err(|e| RequestError::Generic {
+                    value: format!("Could not parse reader trust anchors: {e:?}"),
+                })?,
+            )
+        };
+        let device_engagement =
+            self.get_device_engagement()
+                .map_err(|e| RequestError::Generic {
+                    value: e.to_string(),
+                })?;
+
+        let (session, device_request) = {
+            let mut engaged = self.engaged.lock().map_err(|_| RequestError::Generic {
+                value: "Could not lock mutex".to_string(),
+            })?;
+            let engagement = engaged.as_ref().ok_or(RequestError::Generic {
+                value: "The session has expired or already received a request".to_string(),
+            })?;
+            let e_device_key =
+                p256::SecretKey::from_slice(&engagement.e_device_key).map_err(|e| {
+                    RequestError::Generic {
+                        value: format!("Invalid EDeviceKey: {e}"),
+                    }
+                })?;
+            let accepted = session_encryption::accept(
+                &e_device_key,
+                &device_engagement,
+                engagement.handover(),
+                &request,
+            )
+            .map_err(|e| RequestError::Generic {
+                value: format!("Could not process session establishment: {e:#}"),
+            })?;
+            // The engagement is used up by the establishment
+            *engaged = None;
+            accepted
+        };
+        let (items_request, items_requests) =
+            decode_device_request(&device_request, &session.session_transcript, &registry)?;
+
+        let mut in_process = self.in_process.lock().map_err(|_| RequestError::Generic {
+            value: "Could not lock mutex".to_string(),
+        })?;
+        *in_process = Some(InProcessRecord {
+            session,
+            device_request,
+            items_request,
+            response: None,
+        });
+
+        // The reader could only have connected over the one advertised method
+        if let [method] = self.retrieval_methods.as_slice()
+            && let Ok(mut selected) = self.selected_retrieval_method.lock()
+        {
+            selected.get_or_insert_with(|| method.clone());
+        }
+
+        Ok(items_requests)
+    }
+
+    /// Decodes a SessionData message received after the session was established.
+    fn decode_session_data(&self, bytes: Vec<u8>) -> Result<SessionDataMessage, RequestError> {
+        let session_data: session::SessionData =
+            isomdl::cbor::from_slice(&bytes).map_err(|e| RequestError::Generic {
+                value: format!("Could not deserialize session data: {e:?}"),
+            })?;
+        let Some(data) = session_data.data else {
+            return match session_data.status {
+                Some(session::Status::SessionTermination) => Ok(SessionDataMessage::Terminated),
+                Some(session::Status::SessionEncryptionError) => {
+                    Ok(SessionDataMessage::SessionEncryptionError)
+                }
+                Some(session::Status::CborDecodingError) => {
+                    Ok(SessionDataMessage::CborDecodingError)
+                }
+                None => Err(RequestError::Generic {
+                    value: "Session data has neither data nor status".to_string(),
+                }),
+            };
+        };
+
+        let mut in_process = self.in_process.lock().map_err(|_| RequestError::Generic {
+            value: "Could not lock mutex".to_string(),
+        })?;
+        let in_process = in_process.as_mut().ok_or(RequestError::Generic {
+            value: "No session has been established".to_string(),
+        })?;
+        let device_request = in_process
+            .session
+            .keys
+            .decrypt_reader_data(data.as_ref())
+            .map_err(|e| RequestError::Generic {
+                value: format!("Could not decrypt the request: {e:#}"),
+            })?;
+        // The reader trust anchors are given with the first request only
+        let (items_request, items_requests) = decode_device_request(
+            &device_request,
+            &in_process.session.session_transcript,
+            &None,
+        )?;
+        in_process.device_request = device_request;
+        in_process.items_request = items_request;
+        in_process.response = None;
+
+        Ok(SessionDataMessage::Request { items_requests })
+    }
+
+    /// Reports the outcome of an operation to the listener, if one is registered:
+    /// errors through [SessionListener::on_error], and successes through `on_ok`.
+    fn notify<T, E: std::fmt::Display>(
+        &self,
+        result: Result<T, E>,
+        on_ok: impl FnOnce(&dyn SessionListener, &T),
+    ) -> Result<T, E> {
+        let listener = self
+            .listener
+            .lock()
+            .ok()
+            .and_then(|listener| listener.clone());
+        if let Some(listener) = listener {
+            match &result {
+                Ok(value) => on_ok(listener.as_ref(), value),
+                Err(e) => listener.on_error(e.to_string()),
+            }
+        }
+        result
+    }
+
+    /// Runs `f` on the session of the request being answered.
+    fn with_in_process<T>(
+        &self,
+        f: impl FnOnce(&mut InProcessRecord) -> Result<T, SignatureError>,
+    ) -> Result<T, SignatureError> {
+        let mut in_process = self
+            .in_process
+            .lock()
+            .map_err(|_| SignatureError::Generic {
+                value: "Could not get lock on session".to_string(),
+            })?;
+        let in_process = in_process.as_mut().ok_or(SignatureError::Generic {
+            value: "No request has been received".to_string(),
+        })?;
+        f(in_process)
+    }
+
+    /// The elements of the mdoc a response discloses: those requested for its docType
+    /// that the user permitted and the mdoc has. The other requested docTypes, and that of
+    /// the mdoc if none of its elements are disclosed, are reported in documentErrors.
+    fn prepare_response(
+        &self,
+        in_process: &InProcessRecord,
+        permitted_items: &HashMap<String, HashMap<String, Vec<String>>>,
+    ) -> PreparedResponse {
+        let mut permitted: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        let requests = in_process
+            .items_request
+            .iter()
+            .filter(|request| request.doc_type == self.doc_type);
+        for request in requests {
+            for (namespace, requested) in request.namespaces.iter() {
+                let allowed = permitted_items
+                    .get(&self.doc_type)
+                    .and_then(|namespaces| namespaces.get(namespace));
+                let (Some(allowed), Some(present)) = (allowed, self.elements.get(namespace)) else {
+                    continue;
+                };
+                let elements = permitted.entry(namespace.clone()).or_default();
+                for element in requested.keys() {
+                    if allowed.contains(element)
+                        && present.contains(element)
+                        && !elements.contains(element)
+                    {
+                        elements.push(element.clone());
+                    }
+                }
+            }
+        }
+        permitted.retain(|_, elements| !elements.is_empty());
+        let mut declined: Vec<String> = in_process
+            .items_request
+            .iter()
+            .map(|request| request.doc_type.clone())
+            .filter(|doc_type| *doc_type != self.doc_type || permitted.is_empty())
+            .collect();
+        declined.sort();
+        declined.dedup();
+        PreparedResponse {
+            permitted,
+            declined,
+        }
+    }
+
+    /// The ToBeSigned structure of the deviceSignature of a prepared response, `None` if
+    /// it returns no document.
+    fn signature_payload(
+        &self,
+        session: &DeviceSession,
+        prepared: &PreparedResponse,
+    ) -> Result<Option<Vec<u8>>, SignatureError> {
+        if prepared.permitted.is_empty() {
+            return Ok(None);
+        }
+        let (_, device_authentication_bytes) =
+            device_authentication(&self.doc_type, session_transcript(session)?)
+                .map_err(response_error)?;
+        Ok(Some(
+            unsigned_device_signature(self.device_key_algorithm)
+                .tbs_detached_data(&device_authentication_bytes, &[]),
+        ))
+    }
+
+    /// The SessionData message of a prepared response, the DeviceResponse encrypted with
+    /// the session keys. The document, if returned, is device authenticated with
+    /// `authenticator`.
+    fn session_response(
+        &self,
+        in_process: &mut InProcessRecord,
+        prepared: &PreparedResponse,
+        authenticator: Option<DeviceAuthenticator>,
+    ) -> Result<Vec<u8>, SignatureError> {
+        let mut documents = Vec::new();
+        if !prepared.permitted.is_empty() {
+            let session = &in_process.session;
+            let (device_namespaces_bytes, device_authentication_bytes) =
+                device_authentication(&self.doc_type, session_transcript(session)?)
+                    .map_err(response_error)?;
+            let device_auth = match authenticator.ok_or(SignatureError::Generic {
+                value: "The document is not device authenticated".to_string(),
+            })? {
+                DeviceAuthenticator::Signature(signature) => {
+                    let mut device_signature = unsigned_device_signature(self.device_key_algorithm);
+                    device_signature.signature = signature;
+                    let device_signature =
+                        device_signature
+                            .to_cbor_value()
+                            .map_err(|e| SignatureError::Generic {
+                                value: format!("Could not encode the device signature: {e:?}"),
+                            })?;
+                    ("deviceSignature", device_signature)
+                }
+                DeviceAuthenticator::Mac(key_agreement) => {
+                    let shared_secret = key_agreement.agree(session.e_reader_key.clone())?;
+                    if shared_secret.len() != 32 {
+                        return Err(SignatureError::Generic {
+                            value: "The shared secret is not the 32 byte x-coordinate".to_string(),
+                        });
+                    }
+                    let e_mac_key = session_encryption::device_e_mac_key(
+                        &shared_secret,
+                        &session.session_transcript,
+                    )
+                    .map_err(|e| SignatureError::Generic {
+                        value: format!("Could not derive the EMacKey: {e:#}"),
+                    })?;
+                    let device_mac = device_mac(&e_mac_key, &device_authentication_bytes)
+                        .map_err(response_error)?;
+                    ("deviceMac", device_mac)
+                }
+            };
+            let issuer_signed =
+                issuer_signed(self.mdoc.document(), &prepared.permitted).map_err(response_error)?;
+            documents.push(document(
+                &self.doc_type,
+                issuer_signed,
+                device_namespaces_bytes,
+                device_auth,
+            ));
+        }
+        let device_response =
+            device_response_cbor(documents, &prepared.declined).map_err(response_error)?;
+        let data = in_process
+            .session
+            .keys
+            .encrypt_device_data(&device_response)
+            .map_err(|e| SignatureError::Generic {
+                value: format!("Could not encrypt the response: {e:#}"),
+            })?;
+        isomdl::cbor::to_vec(&session::SessionData {
+            data: Some(data.into()),
+            status: None,
+        })
+        .map_err(|e| SignatureError::Generic {
+            value: format!("Could not serialize message bytes: {e:?}"),
+        })
+    }
+}
+
+/// How the document of a session response is device authenticated.
+enum DeviceAuthenticator<'a> {
+    /// With a deviceSignature, the COSE encoded signature of its payload.
+    Signature(Vec<u8>),
+    /// With a deviceMac, keyed with the EMacKey agreed by the device key.
+    Mac(&'a dyn DeviceKeyAgreement),
+}
+
+/// The SessionTranscript of a session, decoded.
+fn session_transcript(session: &DeviceSession) -> Result<Value, SignatureError> {
+    ciborium::from_reader(session.session_transcript.as_slice()).map_err(|e| {
+        SignatureError::Generic {
+            value: format!("Invalid session transcript: {e}"),
+        }
+    })
+}
+
+fn response_error(e: ResponseError) -> SignatureError {
+    SignatureError::Generic {
+        value: e.to_string(),
+    }
+}
+
+#[cfg(feature = "qr-code")]
+fn qr_code_png(uri: &str, size: u32) -> Result<Vec<u8>, QrCodeError> {
+    let image = qr_code(uri)?
+        .render::<image::Luma<u8>>()
+        .min_dimensions(size, size)
+        .build();
+    let mut png = std::io::Cursor::new(Vec::new());
+    image
+        .write_to(&mut png, image::ImageFormat::Png)
+        .map_err(|e| QrCodeError::Generic {
+            value: format!("Could not encode PNG: {e}"),
+        })?;
+    Ok(png.into_inner())
+}
+
+#[cfg(feature = "qr-code")]
+fn qr_code_svg(uri: &str) -> Result<String, QrCodeError> {
+    Ok(qr_code(uri)?.render::<qrcode::render::svg::Color>().build())
+}
+
+#[cfg(feature = "qr-code")]
+fn qr_code(uri: &str) -> Result<qrcode::QrCode, QrCodeError> {
+    qrcode::QrCode::new(uri).map_err(|e| QrCodeError::Generic {
+        value: format!("Could not encode QR code: {e}"),
+    })
+}
+
+/// Signs device authentication payloads with the device key of the presented mdoc,
+/// for example with a Secure Enclave or StrongBox key that never leaves the device.
+#[uniffi::export(callback_interface)]
+pub trait DeviceSigner: Send + Sync {
+    /// Sign the payload with the device key: ECDSA with SHA-256, SHA-384 or SHA-512 for
+    /// P-256, P-384 and P-521 keys respectively, or Ed25519. ECDSA signatures may be DER
+    /// encoded, as returned by platform key stores, or the raw `r || s` encoding.
+    fn sign(&self, payload: Vec<u8>) -> Result<Vec<u8>, SignatureError>;
+}
+
+/// Performs the key agreement of the device key of the presented mdoc, a P-256 key, for
+/// responses device authenticated with a deviceMac, see
+/// [MdlPresentationSession::respond_with_device_mac].
+#[uniffi::export(callback_interface)]
+pub trait DeviceKeyAgreement: Send + Sync {
+    /// The ECDH shared secret with the reader's SEC1 encoded EReaderKey, the 32 byte
+    /// x-coordinate of the shared point.
+    fn agree(&self, reader_key: Vec<u8>) -> Result<Vec<u8>, SignatureError>;
+}
+
+/// The signature algorithm of a device key, which sets the alg header of the
+/// deviceSignature and the encoding of the signatures it expects.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+enum DeviceKeyAlgorithm {
+    /// Sessions serialized before other device keys were supported used P-256 keys.
+    #[default]
+    Es256,
+    Es384,
+    Es512,
+    EdDsa,
+}
+
+impl DeviceKeyAlgorithm {
+    fn of(mdoc: &Mdoc) -> Option<Self> {
+        match mdoc
+            .document()
+            .mso
+            .device_key_info
+            .device_key
+            .signature_algorithm()?
+        {
+            coset::iana::Algorithm::ES256 => Some(Self::Es256),
+            coset::iana::Algorithm::ES384 => Some(Self::Es384),
+            coset::iana::Algorithm::ES512 => Some(Self::Es512),
+            coset::iana::Algorithm::EdDSA => Some(Self::EdDsa),
+            _ => None,
+        }
+    }
+
+    fn cose_algorithm(self) -> coset::iana::Algorithm {
+        match self {
+            Self::Es256 => coset::iana::Algorithm::ES256,
+            Self::Es384 => coset::iana::Algorithm::ES384,
+            Self::Es512 => coset::iana::Algorithm::ES512,
+            Self::EdDsa => coset::iana::Algorithm::EdDSA,
+        }
+    }
+}
+
+/// The COSE encoding of a device signature, raw `r || s` for ECDSA signatures given
+/// either raw or DER encoded.
+fn device_signature(
+    algorithm: DeviceKeyAlgorithm,
+    signature: &[u8],
+) -> Result<Vec<u8>, SignatureError> {
+    let invalid = |e: p256::ecdsa::Error| SignatureError::InvalidSignature {
+        value: e.to_string(),
+    };
+    match algorithm {
+        DeviceKeyAlgorithm::Es256 => match signature.len() {
+            64 => p256::ecdsa::Signature::from_slice(signature),
+            _ => p256::ecdsa::Signature::from_der(signature),
+        }
+        .map(|signature| signature.to_bytes().to_vec())
+        .map_err(invalid),
+        DeviceKeyAlgorithm::Es384 => match signature.len() {
+            96 => p384::ecdsa::Signature::from_slice(signature),
+            _ => p384::ecdsa::Signature::from_der(signature),
+        }
+        .map(|signature| signature.to_bytes().to_vec())
+        .map_err(invalid),
+        DeviceKeyAlgorithm::Es512 => match signature.len() {
+            132 => p521::ecdsa::Signature::from_slice(signature),
+            _ => p521::ecdsa::Signature::from_der(signature),
+        }
+        .map(|signature| signature.to_bytes().to_vec())
+        .map_err(invalid),
+        DeviceKeyAlgorithm::EdDsa => ed25519_dalek::Signature::from_slice(signature)
+            .map(|signature| signature.to_bytes().to_vec())
+            .map_err(invalid),
+    }
+}
+
+/// Splits the [ItemsRequest]s into those for `doc_type` and the other docTypes.
+fn match_doc_type(doc_type: &str, items_requests: Vec<ItemsRequest>) -> MatchedItemsRequests {
+    let (matched, unmatched): (Vec<_>, Vec<_>) = items_requests
+        .into_iter()
+        .partition(|request| request.doc_type == doc_type);
+    let mut unmatched_doc_types: Vec<String> = unmatched
+        .into_iter()
+        .map(|request| request.doc_type)
+        .collect();
+    unmatched_doc_types.sort();
+    unmatched_doc_types.dedup();
+    MatchedItemsRequests {
+        matched,
+        unmatched_doc_types,
+    }
+}
+
+/// The current time, in seconds since the Unix epoch.
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+/// Decodes the DeviceRequest of a session, returning its ItemsRequests and the
+/// [ItemsRequest]s reported to the wallet, each with the outcome of the reader
+/// authentication of its DocRequest.
+fn decode_device_request(
+    device_request: &[u8],
+    session_transcript: &[u8],
+    registry: &Option<TrustAnchorRegistry>,
+) -> Result<(device::RequestedItems, Vec<ItemsRequest>), RequestError> {
+    let device_request: DeviceRequest =
+        isomdl::cbor::from_slice(device_request).map_err(|e| RequestError::Generic {
+            value: format!("Could not decode the device request: {e:?}"),
+        })?;
+    let session_transcript: Value =
+        ciborium::from_reader(session_transcript).map_err(|e| RequestError::Generic {
+            value: format!("Invalid session transcript: {e}"),
+        })?;
+
+    let mut items_request = Vec::new();
+    let mut items_requests = Vec::new();
+    for doc_request in device_request.doc_requests.into_inner() {
+        let (reader_authentication, reader_common_name, errors) =
+            match (&doc_request.reader_auth, registry) {
+                (Some(reader_auth), Some(registry)) => {
+                    match reader_authentication(
+                        &reader_auth.inner,
+                        session_transcript.clone(),
+                        &doc_request.items_request.inner_bytes,
+                        registry,
+                    ) {
+                        Ok(common_name) => (AuthenticationStatus::Valid, common_name, None),
+                        Err(e) => (AuthenticationStatus::Invalid, None, Some(e)),
+                    }
+                }
+                _ => (AuthenticationStatus::Unchecked, None, None),
+            };
+        let request = doc_request.items_request.into_inner();
+        items_requests.push(ItemsRequest {
+            doc_type: request.doc_type.clone(),
+            namespaces: request
+                .namespaces
+                .iter()
+                .map(|(namespace, elements)| {
+                    let elements = elements
+                        .iter()
+                        .map(|(element, intent_to_retain)| (element.clone(), *intent_to_retain))
+                        .collect();
+                    (namespace.clone(), elements)
+                })
+                .collect(),
+            reader_common_name,
+            reader_authentication,
+            reader_authentication_errors: errors
+                .map(|e| serde_json::json!({ "reader_auth_errors": [e] }).to_string()),
+        });
+        items_request.push(request);
+    }
+    Ok((items_request, items_requests))
+}
+
+/// Authenticates the reader of a DocRequest: the ReaderAuth signature by the reader
+/// certificate, and its chain to the trust anchors. Returns the common name of the
+/// reader certificate.
+fn reader_authentication(
+    reader_auth: &coset::CoseSign1,
+    session_transcript: Value,
+    items_request_bytes: &[u8],
+    registry: &TrustAnchorRegistry,
+) -> Result<Option<String>, String> {
+    let certificate =
+        verify_reader_auth_signature(reader_auth, session_transcript, items_request_bytes)?;
+    let x5chain = x5chain_header(reader_auth)
+        .map(|x5chain| X5Chain::from_cbor(x5chain.clone()))
+        .transpose()
+        .map_err(|e| format!("Invalid x5chain: {e:?}"))?
+        .ok_or("The ReaderAuth has no x5chain")?;
+    let errors = ValidationRuleset::MdlReaderOneStep
+        .validate(&x5chain, registry)
+        .errors;
+    if !errors.is_empty() {
+        return Err(errors
+            .iter()
+            .map(|e| format!("{e:?}"))
+            .collect::<Vec<_>>()
+            .join(", "));
+    }
+    Ok(common_name(&certificate.tbs_certificate.subject))
+}
+
+/// Receives the lifecycle events of a [MdlPresentationSession], registered with
+/// [MdlPresentationSession::set_listener], to drive the app's presentation UI.
+#[uniffi::export(callback_interface)]
+pub trait SessionListener: Send + Sync {
+    /// The session is engaged and waiting for the reader, with the QR code URI to show.
+    fn on_engaged(&self, qr_code_uri: String);
+    /// A request was received from the reader and awaits the user's consent.
+    fn on_request_received(&self, items_requests: Vec<ItemsRequest>);
+    /// The signed response is ready to be sent to the reader.
+    fn on_response_ready(&self, response: Vec<u8>);
+    /// The session was terminated, by either side.
+    fn on_terminated(&self);
+    /// Handling a message or building the response failed, or the reader reported an
+    /// error.
+    fn on_error(&self, error: String);
+}
+
+/// The identifiers of the elements of a document, by namespace.
+fn element_identifiers(document: &device::Document) -> BTreeMap<String, BTreeSet<String>> {
+    document
+        .namespaces
+        .iter()
+        .map(|(namespace, elements)| (namespace.clone(), elements.keys().cloned().collect()))
+        .collect()
+}
+
+/// Builds the vp_token of an OpenID4VP presentation per ISO 18013-7 Annex B: a
+/// DeviceResponse disclosing the permitted elements of the mdoc, by namespace, with
+/// device authentication over the OID4VPHandover SessionTranscript of the request.
+///
+/// The signer is asked for one signature with the device key of the mdoc. Returns the
+/// base64url encoded DeviceResponse.
+#[uniffi::export]
+pub fn generate_oid4vp_response(
+    mdoc: Arc<Mdoc>,
+    permitted_items: HashMap<String, Vec<String>>,
+    client_id: String,
+    response_uri: String,
+    nonce: String,
+    signer: Box<dyn DeviceSigner>,
+) -> Result<String, ResponseError> {
+    let session_transcript = OID4VPSessionTranscript::new(&client_id, &nonce, None, &response_uri)
+        .map_err(|e| ResponseError::Generic {
+            value: format!("Could not encode the handover info: {e:?}"),
+        })?;
+    let device_response = device_response(
+        &mdoc,
+        &permitted_items,
+        &session_transcript,
+        signer.as_ref(),
+    )?;
+    Ok(BASE64_URL_SAFE_NO_PAD.encode(device_response))
+}
+
+/// Builds the encrypted authorization response for verifiers requesting
+/// `response_mode=direct_post.jwt`: the JWE to post as the `response` parameter,
+/// encrypted with ECDH-ES and A128GCM to the verifier's P-256 key, given as a JWK.
+///
+/// The plaintext holds the vp_token built like [generate_oid4vp_response], with the
+/// thumbprint of the verifier key bound into the SessionTranscript, and the `state` of
+/// the request if any. The nonce is the JWE `apv`, and `apu` the mdoc generated nonce
+/// if the verifier expects one.
+#[uniffi::export]
+#[allow(clippy::too_many_arguments)]
+pub fn generate_encrypted_oid4vp_response(
+    mdoc: Arc<Mdoc>,
+    permitted_items: HashMap<String, Vec<String>>,
+    client_id: String,
+    response_uri: String,
+    nonce: String,
+    state: Option<String>,
+    verifier_jwk: String,
+    apu: Option<Vec<u8>>,
+    signer: Box<dyn DeviceSigner>,
+) -> Result<String, ResponseError> {
+    let jwk_thumbprint = serde_json::from_str(&verifier_jwk)
+        .ok()
+        .and_then(|jwk| jwk_thumbprint(&jwk))
+        .ok_or(ResponseError::Generic {
+            value: "Invalid verifier JWK".to_string(),
+        })?;
+    let session_transcript =
+        OID4VPSessionTranscript::new(&client_id, &nonce, Some(jwk_thumbprint), &response_uri)
+            .map_err(|e| ResponseError::Generic {
+                value: format!("Could not encode the handover info: {e:?}"),
+            })?;
+    let device_response = device_response(
+        &mdoc,
+        &permitted_items,
+        &session_transcript,
+        signer.as_ref(),
+    )?;
+
+    let mut response = serde_json::json!({
+        "vp_token": BASE64_URL_SAFE_NO_PAD.encode(device_response),
+    });
+    if let Some(state) = state {
+        response["state"] = state.into();
+    }
+    jwe::encrypt(
+        response.to_string().as_bytes(),
+        &verifier_jwk,
+        apu.as_deref(),
+        Some(nonce.as_bytes()),
+    )
+    .map_err(|e| ResponseError::Generic {
+        value: format!("Could not encrypt the response: {e:#}"),
+    })
+}
+
+/// Builds the response to an OpenID4VP request made through the Digital Credentials
+/// API, for browsers and the Android Credential Manager: a DeviceResponse disclosing the
+/// permitted elements of the mdoc, by namespace, with device authentication over the
+/// OpenID4VPDCAPIHandover SessionTranscript of the request's origin and nonce.
+///
+/// Returns the JSON response to hand to the platform API,
+/// `{"vp_token": {"<credential_query_id>": ["<base64url DeviceResponse>"]}}`. If the
+/// app encrypts that response for the verifier, `jwk_thumbprint` is the SHA-256 JWK
+/// thumbprint of the verifier's encryption key, bound into the SessionTranscript.
+#[uniffi::export]
+pub fn generate_dc_api_response(
+    mdoc: Arc<Mdoc>,
+    permitted_items: HashMap<String, Vec<String>>,
+    origin: String,
+    nonce: String,
+    jwk_thumbprint: Option<Vec<u8>>,
+    credential_query_id: String,
+    signer: Box<dyn DeviceSigner>,
+) -> Result<String, ResponseError> {
+    let session_transcript = OID4VPSessionTranscript::dc_api(&origin, &nonce, jwk_thumbprint)
+        .map_err(|e| ResponseError::Generic {
+            value: format!("Could not encode the handover info: {e:?}"),
+        })?;
+    let device_response = device_response(
+        &mdoc,
+        &permitted_items,
+        &session_transcript,
+        signer.as_ref(),
+    )?;
+    Ok(serde_json::json!({
+        "vp_token": {
+            credential_query_id: [BASE64_URL_SAFE_NO_PAD.encode(device_response)],
+        },
+    })
+    .to_string())
+}
+
+/// A DeviceResponse with the permitted elements of the mdoc, device authenticated with
+/// a deviceSignature over the given SessionTranscript.
+fn device_response(
+    mdoc: &Mdoc,
+    permitted_items: &HashMap<String, Vec<String>>,
+    session_transcript: &impl Serialize,
+    signer: &dyn DeviceSigner,
+) -> Result<Vec<u8>, ResponseError> {
+    let algorithm = DeviceKeyAlgorithm::of(mdoc).ok_or_else(|| ResponseError::Generic {
+        value: "Unsupported device key".to_string(),
+    })?;
+    let doc_type = mdoc.doctype();
+    let (device_namespaces_bytes, device_authentication_bytes) =
+        device_authentication(&doc_type, cbor_value(session_transcript)?)?;
+
+    let mut device_signature_sign1 = unsigned_device_signature(algorithm);
+    let tbs = device_signature_sign1.tbs_detached_data(&device_authentication_bytes, &[]);
+    device_signature_sign1.signature = signer
+        .sign(tbs)
+        .and_then(|signature| device_signature(algorithm, &signature))
+        .map_err(|e| ResponseError::Generic {
+            value: format!("Could not sign the response: {e}"),
+        })?;
+    let device_signature_value =
+        device_signature_sign1
+            .to_cbor_value()
+            .map_err(|e| ResponseError::Generic {
+                value: format!("Could not encode the device signature: {e:?}"),
+            })?;
+
+    let document = document(
+        &doc_type,
+        issuer_signed(mdoc.document(), permitted_items)?,
+        device_namespaces_bytes,
+        ("deviceSignature", device_signature_value),
+    );
+    device_response_cbor(vec![document], &[])
+}
+
+/// The IssuerSigned of the permitted elements of a document, by namespace. The
+/// IssuerSignedItemBytes are selected by reference and encoded as stored, without
+/// decoding or copying the items.
+fn issuer_signed<'a>(
+    document: &device::Document,
+    permitted_items: impl IntoIterator<Item = (&'a String, &'a Vec<String>)>,
+) -> Result<Value, ResponseError> {
+    let text = |text: &str| Value::Text(text.to_string());
+    let mut namespaces = Vec::new();
+    for (namespace, identifiers) in permitted_items {
+        let Some(elements) = document.namespaces.get(namespace) else {
+            continue;
+        };
+        let items: Vec<Value> = identifiers
+            .iter()
+            .filter_map(|identifier| elements.get(identifier))
+            .map(|item| Value::Tag(24, Box::new(Value::Bytes(item.inner_bytes.clone()))))
+            .collect();
+        if !items.is_empty() {
+            namespaces.push((text(namespace), Value::Array(items)));
+        }
+    }
+    namespaces.sort_by(|(a, _), (b, _)| a.as_text().cmp(&b.as_text()));
+    let mut issuer_signed = Vec::new();
+    if !namespaces.is_empty() {
+        issuer_signed.push((text("nameSpaces"), Value::Map(namespaces)));
+    }
+    issuer_signed.push((text("issuerAuth"), cbor_value(&document.issuer_auth)?));
+    Ok(Value::Map(issuer_signed))
+}
+
+/// The DeviceNameSpacesBytes of a document, and the tagged CBOR encoded
+/// DeviceAuthentication over it that the document is device authenticated with.
+fn device_authentication(
+    doc_type: &str,
+    session_transcript: Value,
+) -> Result<(Value, Vec<u8>), ResponseError> {
+    // No elements are device signed
+    let device_namespaces_bytes =
+        Value::Tag(24, Box::new(Value::Bytes(cbor(&Value::Map(vec![]))?)));
+    let device_authentication = Value::Array(vec![
+        Value::Text("DeviceAuthentication".to_string()),
+        session_transcript,
+        Value::Text(doc_type.to_string()),
+        device_namespaces_bytes.clone(),
+    ]);
+    let device_authentication_bytes = cbor(&Value::Tag(
+        24,
+        Box::new(Value::Bytes(cbor(&device_authentication)?)),
+    ))?;
+    Ok((device_namespaces_bytes, device_authentication_bytes))
+}
+
+/// A deviceSignature with the alg of the device key, to be signed over the detached
+/// DeviceAuthentication.
+fn unsigned_device_signature(algorithm: DeviceKeyAlgorithm) -> coset::CoseSign1 {
+    coset::CoseSign1Builder::new()
+        .protected(
+            coset::HeaderBuilder::new()
+                .algorithm(algorithm.cose_algorithm())
+                .build(),
+        )
+        .build()
+}
+
+/// A deviceMac per ISO 18013-5 9.1.3.5: a COSE_Mac0 with HMAC 256/256 keyed with the
+/// EMacKey, over the detached DeviceAuthentication.
+fn device_mac(
+    e_mac_key: &[u8],
+    device_authentication_bytes: &[u8],
+) -> Result<Value, ResponseError> {
+    use hmac::{Hmac, Mac};
+
+    let mut device_mac = coset::CoseMac0Builder::new()
+        .protected(
+            coset::HeaderBuilder::new()
+                .algorithm(coset::iana::Algorithm::HMAC_256_256)
+                .build(),
+        )
+        .build();
+    let tbm = coset::mac_structure_data(
+        coset::MacContext::CoseMac0,
+        device_mac.protected.clone(),
+        b"",
+        device_authentication_bytes,
+    );
+    let mut mac =
+        Hmac::<sha2::Sha256>::new_from_slice(e_mac_key).map_err(|e| ResponseError::Generic {
+            value: format!("Invalid EMacKey: {e}"),
+        })?;
+    mac.update(&tbm);
+    device_mac.tag = mac.finalize().into_bytes().to_vec();
+    device_mac
+        .to_cbor_value()
+        .map_err(|e| ResponseError::Generic {
+            value: format!("Could not encode the device MAC: {e:?}"),
+        })
+}
+
+/// A Document of a DeviceResponse, with its deviceAuth entry.
+fn document(
+    doc_type: &str,
+    issuer_signed: Value,
+    device_namespaces_bytes: Value,
+    (device_auth_kind, device_auth): (&str, Value),
+) -> Value {
+    let text = |text: &str| Value::Text(text.to_string());
+    Value::Map(vec![
+        (text("docType"), text(doc_type)),
+        (text("issuerSigned"), issuer_signed),
+        (
+            text("deviceSigned"),
+            Value::Map(vec![
+                (text("nameSpaces"), device_namespaces_bytes),
+                (
+                    text("deviceAuth"),
+                    Value::Map(vec![(text(device_auth_kind), device_auth)]),
+                ),
+            ]),
+        ),
+    ])
+}
+
+/// The CBOR encoded DeviceResponse of the documents, with the declined docTypes in
+/// documentErrors with the error code 0, data not returned.
+fn device_response_cbor(
+    documents: Vec<Value>,
+    declined: &[String],
+) -> Result<Vec<u8>, ResponseError> {
+    let text = |text: &str| Value::Text(text.to_string());
+    let mut device_response = vec![(text("version"), text("1.0"))];
+    if !documents.is_empty() {
+        device_response.push((text("documents"), Value::Array(documents)));
+    }
+    if !declined.is_empty() {
+        let document_errors = declined
+            .iter()
+            .map(|doc_type| Value::Map(vec![(text(doc_type), Value::Integer(0.into()))]))
+            .collect();
+        device_response.push((text("documentErrors"), Value::Array(document_errors)));
+    }
+    device_response.push((text("status"), Value::Integer(0.into())));
+    cbor(&Value::Map(device_response))
+}
+
+/// A CBOR encodable structure as a [Value], keeping the bytes of embedded CBOR.
+fn cbor_value(value: &impl Serialize) -> Result<Value, ResponseError> {
+    let bytes = isomdl::cbor::to_vec(value).map_err(|e| ResponseError::Generic {
+        value: format!("Could not encode the response: {e:?}"),
+    })?;
+    ciborium::from_reader(bytes.as_slice()).map_err(|e| ResponseError::Generic {
+        value: format!("Could not encode the response: {e:?}"),
+    })
+}
+
+fn cbor(value: &Value) -> Result<Vec<u8>, ResponseError> {
+    let mut bytes = Vec::new();
+    ciborium::into_writer(value, &mut bytes).map_err(|e| ResponseError::Generic {
+        value: format!("Could not encode the response: {e:?}"),
+    })?;
+    Ok(bytes)
+}
+
+/// Sorts the requested elements into those the credential does not contain, those the
+/// user did not permit and those that will be shared.
+fn consent_summary(
+    doc_type: &str,
+    elements: &BTreeMap<String, BTreeSet<String>>,
+    requests: &device::RequestedItems,
+    permitted_items: &HashMap<String, HashMap<String, Vec<String>>>,
+) -> ConsentSummary {
+    let mut summary = ConsentSummary {
+        missing: Vec::new(),
+        denied: Vec::new(),
+        shared: Vec::new(),
+    };
+    for request in requests {
+        for (namespace, requested) in request.namespaces.iter() {
+            for element_identifier in requested.keys() {
+                let present = request.doc_type == doc_type
+                    && elements
+                        .get(namespace)
+                        .is_some_and(|elements| elements.contains(element_identifier));
+                let permitted = permitted_items
+                    .get(&request.doc_type)
+                    .and_then(|namespaces| namespaces.get(namespace))
+                    .is_some_and(|permitted| permitted.contains(element_identifier));
+                let item = ConsentItem {
+                    doc_type: request.doc_type.clone(),
+                    namespace: namespace.clone(),
+                    element_identifier: element_identifier.clone(),
+                };
+                match (present, permitted) {
+                    (false, _) => summary.missing.push(item),
+                    (true, false) => summary.denied.push(item),
+                    (true, true) => summary.shared.push(item),
+                }
+            }
+        }
+    }
+    summary
+}
+
+/// The `age_over_NN` elements of a document, by `NN`.
+fn age_attestations(document: &device::Document) -> BTreeMap<u8, bool> {
+    document
+        .namespaces
+        .get(MDL_NAMESPACE)
+        .into_iter()
+        .flat_map(|elements| elements.iter())
+        .filter_map(|(identifier, item)| {
+            match (age_over_nn(identifier), &item.as_ref().element_value) {
+                (Some(nn), ciborium::Value::Bool(value)) => Some((nn, *value)),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// The `NN` of the `age_over_NN` element to return instead of a missing `requested`
+/// one, per ISO 18013-5 7.2.5.
+fn age_over_substitute(age_attestations: &BTreeMap<u8, bool>, requested: u8) -> Option<u8> {
+    age_attestations
+        .range(requested..)
+        .find(|(_, value)| **value)
+        .or_else(|| {
+            age_attestations
+                .range(..requested)
+                .rev()
+                .find(|(_, value)| !**value)
+        })
+        .map(|(nn, _)| *nn)
+}
+
+/// A device retrieval method the holder can advertise in the DeviceEngagement.
+#[derive(uniffi::Enum, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RetrievalMethod {
+    /// BLE with the mdoc in central client mode, the reader advertising the service.
+    BleCentralClient { uuid: String },
+    /// BLE with the mdoc in peripheral server mode, advertising the service itself.
+    BlePeripheralServer {
+        uuid: String,
+        ble_device_address: Option<Vec<u8>>,
+    },
+    /// NFC, with the maximum lengths of the command and response data fields.
+    Nfc {
+        max_len_command_data_field: u64,
+        max_len_response_data_field: u64,
+    },
+    /// Wi-Fi Aware.
+    WifiAware {
+        pass_phrase: Option<String>,
+        channel_info_operating_class: Option<u64>,
+        channel_info_channel_number: Option<u64>,
+        band_info: Option<Vec<u8>>,
+    },
+}
+
+/// The server retrieval options of a DeviceEngagement, per ISO 18013-5 8.2.1.2.
+#[derive(uniffi::Record, Debug, Clone, PartialEq, Eq)]
+pub struct ServerRetrieval {
+    pub web_api: Option<ServerRetrievalInfo>,
+    pub oidc: Option<ServerRetrievalInfo>,
+}
+
+/// How to retrieve the mdoc from the issuer with WebAPI or OIDC.
+#[derive(uniffi::Record, Debug, Clone, PartialEq, Eq)]
+pub struct ServerRetrievalInfo {
+    pub version: u64,
+    pub issuer_url: String,
+    pub server_retrieval_token: String,
+}
+
+impl From<ServerRetrieval> for ServerRetrievalMethods {
+    fn from(server_retrieval: ServerRetrieval) -> Self {
+        let option = |info: ServerRetrievalInfo| {
+            ServerRetrievalOption(info.version, info.issuer_url, info.server_retrieval_token)
+        };
+        ServerRetrievalMethods {
+            web_api: server_retrieval.web_api.map(option),
+            oidc: server_retrieval.oidc.map(option),
+        }
+    }
+}
+
+impl From<ServerRetrievalMethods> for ServerRetrieval {
+    fn from(methods: ServerRetrievalMethods) -> Self {
+        let info = |ServerRetrievalOption(version, issuer_url, server_retrieval_token)| {
+            ServerRetrievalInfo {
+                version,
+                issuer_url,
+                server_retrieval_token,
+            }
+        };
+        ServerRetrieval {
+            web_api: methods.web_api.map(info),
+            oidc: methods.oidc.map(info),
+        }
+    }
+}
+
+impl RetrievalMethod {
+    fn is_ble(&self) -> bool {
+        matches!(
+            self,
+            Self::BleCentralClient { .. } | Self::BlePeripheralServer { .. }
+        )
+    }
+}
+
+/// The DeviceRetrievalMethods of the engagement, with both BLE modes in one BLE method
+/// placed where the first of them was given.
+fn device_retrieval_methods(
+    methods: &[RetrievalMethod],
+) -> Result<DeviceRetrievalMethods, SessionError> {
+    let parse_uuid = |uuid: &str| {
+        Uuid::parse_str(uuid).map_err(|e| SessionError::Generic {
+            value: format!("Invalid UUID: {}", e),
+        })
+    };
+
+    let mut ble = BleOptions {
+        peripheral_server_mode: None,
+        central_client_mode: None,
+    };
+    let mut drms = Vec::new();
+    let mut ble_position = None;
+    for method in methods {
+        match method {
+            RetrievalMethod::BleCentralClient { uuid } => {
+                ble.central_client_mode = Some(CentralClientMode {
+                    uuid: parse_uuid(uuid)?,
+                });
+            }
+            RetrievalMethod::BlePeripheralServer {
+                uuid,
+                ble_device_address,
+            } => {
+                ble.peripheral_server_mode = Some(PeripheralServerMode {
+                    uuid: parse_uuid(uuid)?,
+                    ble_device_address: ble_device_address.clone().map(Into::into),
+                });
+            }
+            RetrievalMethod::Nfc {
+                max_len_command_data_field,
+                max_len_response_data_field,
+            } => drms.push(DeviceRetrievalMethod::NFC(NfcOptions {
+                max_len_command_data_field: *max_len_command_data_field,
+                max_len_response_data_field: *max_len_response_data_field,
+            })),
+            RetrievalMethod::WifiAware {
+                pass_phrase,
+                channel_info_operating_class,
+                channel_info_channel_number,
+                band_info,
+            } => drms.push(DeviceRetrievalMethod::WIFI(WifiOptions {
+                pass_phrase: pass_phrase.clone(),
+                channel_info_operating_class: *channel_info_operating_class,
+                channel_info_channel_number: *channel_info_channel_number,
+                band_info: band_info.clone().map(Into::into),
+            })),
+        }
+        if method.is_ble() && ble_position.is_none() {
+            ble_position = Some(drms.len());
+        }
+    }
+    if let Some(position) = ble_position {
+        drms.insert(position, DeviceRetrievalMethod::BLE(ble));
+    }
+
+    NonEmptyVec::maybe_new(drms).ok_or_else(|| SessionError::Generic {
+        value: "At least one device retrieval method is required".to_string(),
+    })
+}
+
+#[derive(thiserror::Error, uniffi::Error, Debug)]
+pub enum SessionError {
+    #[error("{value}")]
+    Generic { value: String },
+}
+
+#[derive(thiserror::Error, uniffi::Error, Debug)]
+pub enum RequestError {
+    #[error("{value}")]
+    Generic { value: String },
+}
+
+#[derive(uniffi::Record, Clone)]
+pub struct ItemsRequest {
+    pub(crate) doc_type: String,
+    pub(crate) namespaces: HashMap<String, HashMap<String, bool>>,
+    /// Common name of the reader certificate, if the request carried reader
+    /// authentication.
+    pub(crate) reader_common_name: Option<String>,
+    /// Outcome of reader authentication against the trust anchors given to
+    /// [MdlPresentationSession::handle_request_with_reader_trust_anchors].
+    pub(crate) reader_authentication: AuthenticationStatus,
+    /// JSON encoded reader authentication errors, if any.
+    pub(crate) reader_authentication_errors: Option<String>,
+}
+
+/// The result of [MdlPresentationSession::generate_response].
+#[derive(uniffi::Record)]
+pub struct GeneratedResponse {
+    /// The payload to be signed with the device key and passed to
+    /// [MdlPresentationSession::submit_response].
+    pub(crate) payload: Vec<u8>,
+    consent_summary: ConsentSummary,
+}
+
+/// The requested elements, sorted by what happens to them in the response.
+#[derive(uniffi::Record, Debug, PartialEq)]
+pub struct ConsentSummary {
+    /// Requested elements the credential does not contain.
+    missing: Vec<ConsentItem>,
+    /// Requested elements the user did not permit to share.
+    denied: Vec<ConsentItem>,
+    /// Requested elements that will be shared.
+    shared: Vec<ConsentItem>,
+}
+
+#[derive(uniffi::Record, Debug, PartialEq)]
+pub struct ConsentItem {
+    doc_type: String,
+    namespace: String,
+    element_identifier: String,
+}
+
+/// A requested `age_over_NN` element the mdoc does not have, see
+/// [MdlPresentationSession::substitute_age_over_elements].
+#[derive(uniffi::Record, Debug, PartialEq)]
+pub struct AgeOverSubstitution {
+    doc_type: String,
+    requested: String,
+    /// The element added to the request in its place, `None` if the request cannot be
+    /// satisfied.
+    substitute: Option<String>,
+}
+
+/// The result of [MdlPresentationSession::handle_request_debug].
+#[derive(uniffi::Record)]
+pub struct DebugItemsRequests {
+    items_requests: Vec<ItemsRequest>,
+    /// The CBOR encoded DeviceRequest, as decrypted from the SessionEstablishment.
+    device_request_cbor: Vec<u8>,
+    /// `device_request_cbor` in CBOR diagnostic notation.
+    device_request_diagnostic: String,
+}
+
+/// The result of [MdlPresentationSession::handle_request_matching_doc_type].
+#[derive(uniffi::Record)]
+pub struct MatchedItemsRequests {
+    /// The requests for the docType of the presented mdoc.
+    pub matched: Vec<ItemsRequest>,
+    /// The requested docTypes other than that of the presented mdoc, which the response
+    /// cannot include.
+    pub unmatched_doc_types: Vec<String>,
+}
+
+/// A SessionData message from the reader, see
+/// [MdlPresentationSession::handle_session_data].
+#[derive(uniffi::Enum)]
+pub enum SessionDataMessage {
+    /// A further request from the reader.
+    Request { items_requests: Vec<ItemsRequest> },
+    /// The reader ended the session.
+    Terminated,
+    /// The reader could not decrypt the last message.
+    SessionEncryptionError,
+    /// The reader could not decode the last message.
+    CborDecodingError,
+}
+
+/// The status of a response declining a request, see
+/// [MdlPresentationSession::generate_error_response].
+#[derive(uniffi::Enum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorResponseStatus {
+    /// A DeviceResponse with the documentErrors code 0, data not returned, for every
+    /// requested document.
+    DataNotReturned,
+    /// The SessionData status 10, the request could not be decrypted.
+    SessionEncryptionError,
+    /// The SessionData status 11, the request could not be decoded.
+    CborDecodingError,
+}
+
+#[derive(thiserror::Error, uniffi::Error, Debug)]
+pub enum ResponseError {
+    #[error("no signature payload received from session manager")]
+    MissingSignature,
+    #[error("{value}")]
+    Generic { value: String },
+}
+
+#[derive(thiserror::Error, uniffi::Error, Debug)]
+pub enum SignatureError {
+    #[error("Invalid DER signature: {value}")]
+    InvalidSignature { value: String },
+    #[error("there were more documents to sign, but we only expected to sign 1!")]
+    TooManyDocuments,
+    #[error("{value}")]
+    Generic { value: String },
+}
+
+impl From<uniffi::UnexpectedUniFFICallbackError> for SignatureError {
+    fn from(e: uniffi::UnexpectedUniFFICallbackError) -> Self {
+        Self::Generic { value: e.reason }
+    }
+}
+
+#[cfg(feature = "qr-code")]
+#[derive(thiserror::Error, uniffi::Error, Debug)]
+pub enum QrCodeError {
+    #[error("{value}")]
+    Generic { value: String },
+}
+
+#[derive(thiserror::Error, uniffi::Error, Debug)]
+pub enum TerminationError {
+    #[error("{value}")]
+    Generic { value: String },
+}
+
+#[derive(thiserror::Error, uniffi::Error, Debug)]
+pub enum KeyTransformationError {
+    #[error("{value}")]
+    ToPKCS8 { value: String },
+    #[error("{value}")]
+    FromPKCS8 { value: String },
+    #[error("{value}")]
+    FromSEC1 { value: String },
+    #[error("{value}")]
+    ToSEC1 { value: String },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mdl::util::{P256KeyPair, generate_test_mdl};
+
+    #[test]
+    fn test_device_signature() {
+        use p256::ecdsa::{SigningKey, signature::Signer};
+
+        let key = SigningKey::random(&mut p256::elliptic_curve::rand_core::OsRng);
+        let signature: p256::ecdsa::Signature = key.sign(b"payload");
+        let raw = signature.to_bytes().to_vec();
+
+        let es256 = DeviceKeyAlgorithm::Es256;
+        assert_eq!(device_signature(es256, &raw).unwrap(), raw);
+        assert_eq!(
+            device_signature(es256, signature.to_der().as_bytes()).unwrap(),
+            raw
+        );
+        assert!(matches!(
+            device_signature(es256, &[0; 10]),
+            Err(SignatureError::InvalidSignature { .. })
+        ));
+
+        // P-384 signatures are 96 bytes raw
+        let key = p384::ecdsa::SigningKey::random(&mut p256::elliptic_curve::rand_core::OsRng);
+        let signature: p384::ecdsa::Signature = key.sign(b"payload");
+        let raw = signature.to_bytes().to_vec();
+        let es384 = DeviceKeyAlgorithm::Es384;
+        assert_eq!(raw.len(), 96);
+        assert_eq!(device_signature(es384, &raw).unwrap(), raw);
+        assert_eq!(
+            device_signature(es384, signature.to_der().as_bytes()).unwrap(),
+            raw
+        );
+        assert!(device_signature(es256, &raw).is_err());
+    }
+
+    #[test]
+    fn test_session_serialization() {
+        let mdoc = generate_test_mdl(Arc::new(P256KeyPair::new())).unwrap();
+        let session = MdlPresentationSession::new_with_retrieval_methods(
+            Arc::new(mdoc),
+            vec![RetrievalMethod::BleCentralClient {
+                uuid: Uuid::new_v4().to_string(),
+            }],
+        )
+        .unwrap();
+        session
+            .select_retrieval_method(session.get_retrieval_methods()[0].clone())
+            .unwrap();
+
+        let restored = MdlPresentationSession::restore(session.serialize().unwrap()).unwrap();
+        assert_eq!(restored.get_qr_code_uri(), session.get_qr_code_uri());
+        assert_eq!(restored.get_ble_ident(), session.get_ble_ident());
+        let device_engagement = session.get_device_engagement().unwrap();
+        assert_eq!(restored.get_device_engagement().unwrap(), device_engagement);
+        let device_engagement: Value = ciborium::from_reader(device_engagement.as_slice()).unwrap();
+        assert_eq!(
+            device_engagement.as_map().unwrap()[0],
+            (Value::Integer(0.into()), Value::Text("1.0".to_string()))
+        );
+        assert_eq!(
+            restored.get_selected_retrieval_method(),
+            session.get_selected_retrieval_method()
+        );
+        assert_eq!(restored.serialize().unwrap(), session.serialize().unwrap());
+
+        assert!(MdlPresentationSession::restore(vec![0xff]).is_err());
+    }
+
+    #[test]
+    fn test_nfc_handover() {
+        use crate::mdl::reader::{establish_session, establish_session_with_device_engagement};
+
+        let mdoc = Arc::new(generate_test_mdl(Arc::new(P256KeyPair::new())).unwrap());
+        let peripheral_server = |ble_device_address| {
+            MdlPresentationSession::new_with_retrieval_methods(
+                mdoc.clone(),
+                vec![RetrievalMethod::BlePeripheralServer {
+                    uuid: Uuid::new_v4().to_string(),
+                    ble_device_address,
+                }],
+            )
+            .unwrap()
+        };
+        let handover = |session: &MdlPresentationSession| {
+            let engaged = session.engaged.lock().unwrap();
+            engaged.as_ref().unwrap().handover()
+        };
+        let nfc_handover = |select: &[u8], request: Option<&[u8]>| {
+            Value::Array(vec![
+                Value::Bytes(select.to_vec()),
+                request.map_or(Value::Null, |request| Value::Bytes(request.to_vec())),
+            ])
+        };
+
+        // 1. Static handover binds the transcript to the Handover Select message alone
+        let session = peripheral_server(Some(vec![1, 2, 3, 4, 5, 6]));
+        let select = session.nfc_static_handover_select().unwrap();
+        assert_eq!(handover(&session), nfc_handover(&select, None));
+
+        // 2. Negotiated handover binds it to the Handover Request and Select messages
+        let mut request = vec![0x91, 2, 1, b'H', b'r', 0x15];
+        request.extend_from_slice(&[0x5a, 32, 3, 1]);
+        request.extend_from_slice(b"application/vnd.bluetooth.le.oob0");
+        request.extend_from_slice(&[2, 0x1c, 0x01]);
+        let session = peripheral_server(None);
+        let select = session
+            .nfc_negotiated_handover_select(request.clone())
+            .unwrap();
+        assert_eq!(handover(&session), nfc_handover(&select, Some(&request)));
+
+        // 3. The holder only accepts a reader that bound its transcript to the same
+        // handover
+        let requested_items = || {
+            HashMap::from([(
+                MDL_NAMESPACE.to_string(),
+                HashMap::from([("family_name".to_string(), false)]),
+            )])
+        };
+        let qr_reader = establish_session(
+            session.get_qr_code_uri(),
+            requested_items(),
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+        assert!(session.handle_request(qr_reader.request).is_err());
+        let nfc_reader = establish_session_with_device_engagement(
+            session.get_device_engagement().unwrap(),
+            requested_items(),
+            None,
+            Some(select),
+            Some(request),
+            None,
+            None,
+        )
+        .unwrap();
+        assert_eq!(session.handle_request(nfc_reader.request).unwrap().len(), 1);
+
+        // 4. A device address of the wrong length is rejected
+        let session = peripheral_server(Some(vec![1, 2, 3]));
+        assert!(session.nfc_static_handover_select().is_err());
+    }
+
+    #[test]
+    fn test_server_retrieval() {
+        use crate::mdl::reader::server_retrieval_from_uri;
+
+        let mdoc = Arc::new(generate_test_mdl(Arc::new(P256KeyPair::new())).unwrap());
+        let server_retrieval = ServerRetrieval {
+            web_api: Some(ServerRetrievalInfo {
+                version: 1,
+                issuer_url: "https://issuer.example.com/mdoc".to_string(),
+                server_retrieval_token: "token".to_string(),
+            }),
+            oidc: None,
+        };
+
+        // 1. The reader reads the options from the engagement, even without BLE
+        let session = MdlPresentationSession::new_with_server_retrieval(
+            mdoc.clone(),
+            vec![],
+            Some(server_retrieval.clone()),
+        )
+        .unwrap();
+        assert!(session.get_ble_ident().is_empty());
+        assert_eq!(
+            server_retrieval_from_uri(session.get_qr_code_uri()).unwrap(),
+            Some(server_retrieval)
+        );
+
+        // 2. Engagements without server retrieval need a device retrieval method
+        let session =
+            MdlPresentationSession::new(mdoc.clone(), Uuid::new_v4().to_string()).unwrap();
+        assert_eq!(
+            server_retrieval_from_uri(session.get_qr_code_uri()).unwrap(),
+            None
+        );
+        assert!(MdlPresentationSession::new_with_server_retrieval(mdoc, vec![], None).is_err());
+        assert!(server_retrieval_from_uri("https://example.com".to_string()).is_err());
+    }
+
+    #[test]
+    fn test_session_reset() {
+        let mdoc = generate_test_mdl(Arc::new(P256KeyPair::new())).unwrap();
+        let session =
+            MdlPresentationSession::new(Arc::new(mdoc), Uuid::new_v4().to_string()).unwrap();
+
+        // A panic while holding the state fails later calls instead of panicking
+        let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _in_process = session.in_process.lock().unwrap();
+            panic!("failed while holding the session state");
+        }));
+        assert!(matches!(
+            session.generate_response(HashMap::new()),
+            Err(SignatureError::Generic { value }) if value == "Could not get lock on session"
+        ));
+        assert!(session.serialize().is_err());
+
+        session.reset();
+        assert!(matches!(
+            session.generate_response(HashMap::new()),
+            Err(SignatureError::Generic { value }) if value == "No request has been received"
+        ));
+        assert!(session.serialize().is_ok());
+    }
+
+    #[test]
+    fn test_session_expiry() {
+        let mdoc = generate_test_mdl(Arc::new(P256KeyPair::new())).unwrap();
+        let session =
+            MdlPresentationSession::new(Arc::new(mdoc), Uuid::new_v4().to_string()).unwrap();
+
+        // 1. Sessions do not expire by default
+        assert!(!session.is_expired());
+        session.set_engagement_ttl(Some(60));
+        assert!(!session.is_expired());
+
+        // 2. A stale session drops the engagement, also once restored
+        let stale = session.serialize().unwrap();
+        session.set_engagement_ttl(Some(0));
+        assert!(session.is_expired());
+        session.set_engagement_ttl(None);
+        assert!(session.is_expired());
+        assert!(matches!(
+            session.handle_request(vec![0xa0]),
+            Err(RequestError::Generic { value }) if value == "The session has expired"
+        ));
+
+        let restored = MdlPresentationSession::restore(stale).unwrap();
+        assert!(!restored.is_expired());
+        restored.set_engagement_ttl(Some(0));
+        assert!(restored.is_expired());
+    }
+
+    #[test]
+    fn test_handle_session_data() {
+        let mdoc = generate_test_mdl(Arc::new(P256KeyPair::new())).unwrap();
+        let session =
+            MdlPresentationSession::new(Arc::new(mdoc), Uuid::new_v4().to_string()).unwrap();
+        let session_data =
+            |status| isomdl::cbor::to_vec(&session::SessionData { data: None, status }).unwrap();
+
+        assert!(matches!(
+            session.handle_session_data(session.terminate_session().unwrap()),
+            Ok(SessionDataMessage::Terminated)
+        ));
+        assert!(matches!(
+            session.handle_session_data(session_data(Some(session::Status::CborDecodingError))),
+            Ok(SessionDataMessage::CborDecodingError)
+        ));
+        assert!(session.handle_session_data(session_data(None)).is_err());
+        assert!(session.handle_session_data(vec![0xff]).is_err());
+
+        // Error responses carry the SessionData status, data needs a request
+        let error_response = session
+            .generate_error_response(ErrorResponseStatus::SessionEncryptionError)
+            .unwrap();
+        assert!(matches!(
+            session.handle_session_data(error_response),
+            Ok(SessionDataMessage::SessionEncryptionError)
+        ));
+        assert!(
+            session
+                .generate_error_response(ErrorResponseStatus::DataNotReturned)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_generate_error_response() {
+        use crate::mdl::reader::{establish_session, handle_response};
+
+        let mdoc = generate_test_mdl(Arc::new(P256KeyPair::new())).unwrap();
+        let session =
+            MdlPresentationSession::new(Arc::new(mdoc), Uuid::new_v4().to_string()).unwrap();
+        let requested_items = HashMap::from([(
+            MDL_NAMESPACE.to_string(),
+            HashMap::from([
+                ("family_name".to_string(), false),
+                ("birth_date".to_string(), false),
+            ]),
+        )]);
+        let reader =
+            establish_session(session.get_qr_code_uri(), requested_items, None, None, None)
+                .unwrap();
+        session.handle_request(reader.request.clone()).unwrap();
+
+        // The engagement is used up by the request, without expiring the session
+        assert!(session.engaged.lock().unwrap().is_none());
+        assert!(!session.is_expired());
+        assert!(session.handle_request(reader.request).is_err());
+
+        // The reader decrypts the declining DeviceResponse and finds no data in it, every
+        // requested element being refused
+        let response = session
+            .generate_error_response(ErrorResponseStatus::DataNotReturned)
+            .unwrap();
+        let data = handle_response(reader.state, response, false).unwrap();
+        assert!(
+            data.documents
+                .iter()
+                .all(|document| document.namespaces.is_empty())
+        );
+        assert!(data.element_diff.granted.is_empty());
+        let mut refused = data.element_diff.refused[MDL_NAMESPACE].clone();
+        refused.sort();
+        assert_eq!(refused, vec!["birth_date", "family_name"]);
+        assert!(
+            !data
+                .issues
+                .iter()
+                .any(|issue| issue.kind == crate::mdl::reader::ResponseIssueKind::Decryption)
+        );
+    }
+
+    #[test]
+    fn test_session_response_issuer_details() {
+        use crate::mdl::reader::{establish_session, handle_response};
+
+        let key_pair = Arc::new(P256KeyPair::new());
+        let mdoc = generate_test_mdl(key_pair.clone()).unwrap();
+        let session =
+            MdlPresentationSession::new(Arc::new(mdoc), Uuid::new_v4().to_string()).unwrap();
+        let requested_items = HashMap::from([(
+            MDL_NAMESPACE.to_string(),
+            HashMap::from([("family_name".to_string(), false)]),
+        )]);
+        let reader =
+            establish_session(session.get_qr_code_uri(), requested_items, None, None, None)
+                .unwrap();
+        session.handle_request(reader.request).unwrap();
+        let permitted = HashMap::from([(
+            "org.iso.18013.5.1.mDL".to_string(),
+            HashMap::from([(MDL_NAMESPACE.to_string(), vec!["family_name".to_string()])]),
+        )]);
+        let payload = session.generate_response(permitted).unwrap().payload;
+        let response = session.submit_response(key_pair.sign(&payload)).unwrap();
+
+        // The MSO validity and the document signer certificate of a session response are
+        // reported as for OpenID4VP, for the response and each of its documents
+        let data = handle_response(reader.state, response, false).unwrap();
+        let validity = data.validity.unwrap();
+        assert!(!validity.expired && !validity.not_yet_valid);
+        let certificate = data.issuer_certificate.unwrap();
+        assert_eq!(
+            certificate.common_name.as_deref(),
+            Some("SpruceID Test Certificate Root")
+        );
+        assert_eq!(certificate.country.as_deref(), Some("US"));
+        assert_eq!(data.documents.len(), 1);
+        assert!(data.documents[0].validity.is_some());
+        assert!(data.documents[0].issuer_certificate.is_some());
+    }
+
+    #[test]
+    fn test_issuer_signed_by_reference() {
+        let mdoc = Arc::new(generate_test_mdl(Arc::new(P256KeyPair::new())).unwrap());
+        let session =
+            MdlPresentationSession::new(mdoc.clone(), Uuid::new_v4().to_string()).unwrap();
+
+        // 1. The session shares the mdoc of the caller instead of copying its document
+        assert!(Arc::ptr_eq(&session.mdoc, &mdoc));
+
+        // 2. The permitted elements are the IssuerSignedItemBytes as stored, and only them
+        let permitted = BTreeMap::from([(
+            MDL_NAMESPACE.to_string(),
+            vec!["family_name".to_string(), "not_an_element".to_string()],
+        )]);
+        let disclosed = issuer_signed(mdoc.document(), &permitted).unwrap();
+        let stored = &mdoc.document().namespaces[MDL_NAMESPACE]["family_name"];
+        let namespaces = disclosed.as_map().unwrap()[0].1.as_map().unwrap();
+        assert_eq!(
+            namespaces[0],
+            (
+                Value::Text(MDL_NAMESPACE.to_string()),
+                Value::Array(vec![Value::Tag(
+                    24,
+                    Box::new(Value::Bytes(stored.inner_bytes.clone()))
+                )])
+            )
+        );
+
+        // 3. A restored session holds the same document
+        let restored = MdlPresentationSession::restore(session.serialize().unwrap()).unwrap();
+        assert_eq!(restored.mdoc.key_alias(), mdoc.key_alias());
+        assert_eq!(
+            issuer_signed(restored.mdoc.document(), &permitted).unwrap(),
+            disclosed
+        );
+    }
+
+    #[test]
+    fn test_handle_request_debug() {
+        use crate::mdl::reader::establish_session;
+
+        let mdoc = generate_test_mdl(Arc::new(P256KeyPair::new())).unwrap();
+        let session =
+            MdlPresentationSession::new(Arc::new(mdoc), Uuid::new_v4().to_string()).unwrap();
+        let requested_items = HashMap::from([(
+            MDL_NAMESPACE.to_string(),
+            HashMap::from([("family_name".to_string(), false)]),
+        )]);
+        let reader =
+            establish_session(session.get_qr_code_uri(), requested_items, None, None, None)
+                .unwrap();
+
+        // The DeviceRequest is returned as decrypted, not encoded again from its requests
+        let debug = session
+            .handle_request_debug(reader.request, vec![])
+            .unwrap();
+        assert_eq!(debug.items_requests.len(), 1);
+        let device_request: Value =
+            ciborium::from_reader(debug.device_request_cbor.as_slice()).unwrap();
+        let fields: Vec<_> = device_request
+            .as_map()
+            .unwrap()
+            .iter()
+            .filter_map(|(key, _)| key.as_text())
+            .collect();
+        assert_eq!(fields, ["version", "docRequests"]);
+        assert_eq!(
+            debug.device_request_diagnostic,
+            cbor_diagnostic(&device_request)
+        );
+    }
+
+    #[test]
+    fn test_respond_with_device_mac() {
+        use crate::mdl::reader::{AuthenticationStatus, establish_session, handle_response};
+
+        /// The key agreement of the device key of a test mdoc.
+        struct KeyAgreement(Arc<P256KeyPair>);
+
+        impl DeviceKeyAgreement for KeyAgreement {
+            fn agree(&self, reader_key: Vec<u8>) -> Result<Vec<u8>, SignatureError> {
+                let reader_key = p256::PublicKey::from_sec1_bytes(&reader_key).unwrap();
+                let device_key = self.0.secret_key().unwrap();
+                let shared_secret = p256::ecdh::diffie_hellman(
+                    device_key.as_nonzero_scalar(),
+                    reader_key.as_affine(),
+                );
+                Ok(shared_secret.raw_secret_bytes().to_vec())
+            }
+        }
+
+        let key_pair = Arc::new(P256KeyPair::new());
+        let mdoc = Arc::new(generate_test_mdl(key_pair.clone()).unwrap());
+        let respond = |key_agreement: KeyAgreement| {
+            let session =
+                MdlPresentationSession::new(mdoc.clone(), Uuid::new_v4().to_string()).unwrap();
+            let requested_items = HashMap::from([(
+                MDL_NAMESPACE.to_string(),
+                HashMap::from([("family_name".to_string(), false)]),
+            )]);
+            let reader =
+                establish_session(session.get_qr_code_uri(), requested_items, None, None, None)
+                    .unwrap();
+            session.handle_request(reader.request).unwrap();
+            let permitted = HashMap::from([(
+                "org.iso.18013.5.1.mDL".to_string(),
+                HashMap::from([(MDL_NAMESPACE.to_string(), vec!["family_name".to_string()])]),
+            )]);
+            let response = session
+                .respond_with_device_mac(permitted, Box::new(key_agreement))
+                .unwrap();
+            handle_response(reader.state, response, false).unwrap()
+        };
+
+        // 1. The reader verifies the deviceMac with the EMacKey it agrees with the device
+        // key of the MSO
+        let data = respond(KeyAgreement(key_pair.clone()));
+        assert_eq!(data.device_authentication, AuthenticationStatus::Valid);
+        assert_eq!(data.documents.len(), 1);
+
+        // 2. A deviceMac keyed by the agreement of another key does not authenticate
+        let data = respond(KeyAgreement(Arc::new(P256KeyPair::new())));
+        assert_eq!(data.device_authentication, AuthenticationStatus::Invalid);
+    }
+
+    #[test]
+    fn test_session_listener() {
+        struct Events(Arc<Mutex<Vec<String>>>);
+
+        impl SessionListener for Events {
+            fn on_engaged(&self, _: String) {
+                self.0.lock().unwrap().push("engaged".to_string());
+            }
+            fn on_request_received(&self, _: Vec<ItemsRequest>) {
+                self.0.lock().unwrap().push("request".to_string());
+            }
+            fn on_response_ready(&self, _: Vec<u8>) {
+                self.0.lock().unwrap().push("response".to_string());
+            }
+            fn on_terminated(&self) {
+                self.0.lock().unwrap().push("terminated".to_string());
+            }
+            fn on_error(&self, _: String) {
+                self.0.lock().unwrap().push("error".to_string());
+            }
+        }
+
+        let mdoc = generate_test_mdl(Arc::new(P256KeyPair::new())).unwrap();
+        let session =
+            MdlPresentationSession::new(Arc::new(mdoc), Uuid::new_v4().to_string()).unwrap();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        session.set_listener(Some(Box::new(Events(events.clone()))));
+
+        assert!(session.handle_request(vec![0xff]).is_err());
+        let termination = session.terminate_session().unwrap();
+        session.handle_session_data(termination).unwrap();
+        assert_eq!(
+            *events.lock().unwrap(),
+            ["engaged", "error", "terminated", "terminated"]
+        );
+
+        // Nothing is reported once the listener is removed
+        session.set_listener(None);
+        session.terminate_session().unwrap();
+        assert_eq!(events.lock().unwrap().len(), 4);
+    }
+
+    #[cfg(feature = "qr-code")]
+    #[test]
+    fn test_qr_code_rendering() {
+        let uri = "mdoc:owBjMS4wAYIB2BhYS6QBAiABIVgg";
+
+        let png = qr_code_png(uri, 256).unwrap();
+        assert!(png.starts_with(b"\x89PNG\r\n\x1a\n"));
+
+        let svg = qr_code_svg(uri).unwrap();
+        assert!(svg.contains("<svg"));
+    }
+
+    #[test]
+    fn test_generate_oid4vp_response() {
+        use crate::mdl::reader::HandoverVariant;
+        use std::time::Duration;
+
+        struct KeyPairSigner(Arc<P256KeyPair>);
+
+        impl DeviceSigner for KeyPairSigner {
+            fn sign(&self, payload: Vec<u8>) -> Result<Vec<u8>, SignatureError> {
+                Ok(self.0.sign(&payload))
+            }
+        }
+
+        let key_pair = Arc::new(P256KeyPair::new());
+        let mdoc = generate_test_mdl(key_pair.clone()).unwrap();
+        let vp_token = generate_oid4vp_response(
+            Arc::new(mdoc),
+            HashMap::from([(MDL_NAMESPACE.to_string(), vec!["family_name".to_string()])]),
+            "x509_san_dns:verifier.example.com".to_string(),
+            "https://verifier.example.com/response".to_string(),
+            "nonce".to_string(),
+            Box::new(KeyPairSigner(key_pair)),
+        )
+        .unwrap();
+
+        let verify_with = |validation_time, handover_variants| {
+            crate::mdl::reader::verify_oid4vp_response(
+                BASE64_URL_SAFE_NO_PAD.decode(&vp_token).unwrap(),
+                "nonce".to_string(),
+                "x509_san_dns:verifier.example.com".to_string(),
+                "https://verifier.example.com/response".to_string(),
+                None,
+                false,
+                validation_time,
+                None,
+                handover_variants,
+                None,
+            )
+            .unwrap()
+        };
+        let verify = |validation_time| verify_with(validation_time, None);
+        let verified = verify(None);
+        assert_eq!(verified.device_authentication, AuthenticationStatus::Valid);
+        let elements = &verified.verified_response[MDL_NAMESPACE];
+        assert!(elements.contains_key("family_name"));
+        assert!(!elements.contains_key("given_name"));
+
+        // The document signer certificate is described for display
+        let certificate = verified.issuer_certificate.clone().unwrap();
+        assert_eq!(
+            certificate.common_name.as_deref(),
+            Some("SpruceID Test Certificate Root")
+        );
+        assert_eq!(certificate.country.as_deref(), Some("US"));
+        assert_eq!(certificate.state_or_province.as_deref(), Some("NY"));
+        assert!(certificate.not_before < certificate.not_after);
+
+        // The MSO validity is checked as of the validation time
+        let validity = verified.validity.unwrap();
+        assert!(!validity.expired && !validity.not_yet_valid);
+        let later = validity.valid_until + Duration::from_secs(1);
+        assert!(verify(Some(later)).validity.unwrap().expired);
+        let earlier = validity.valid_from - Duration::from_secs(1);
+        assert!(verify(Some(earlier)).validity.unwrap().not_yet_valid);
+
+        // The response authenticates against the OpenID4VP 1.0 handover only, also when
+        // tried after the draft 20 handover
+        let draft_20 = HandoverVariant::Draft20 {
+            mdoc_generated_nonce: "mdoc nonce".to_string(),
+        };
+        let verified = verify_with(None, Some(vec![draft_20.clone()]));
+        assert_eq!(
+            verified.device_authentication,
+            AuthenticationStatus::Invalid
+        );
+        let verified = verify_with(None, Some(vec![draft_20, HandoverVariant::OpenId4Vp]));
+        assert_eq!(verified.device_authentication, AuthenticationStatus::Valid);
+    }
+
+    #[test]
+    fn test_generate_p384_oid4vp_response() {
+        use p384::ecdsa::signature::Signer;
+
+        struct P384Signer(p384::ecdsa::SigningKey);
+
+        impl DeviceSigner for P384Signer {
+            fn sign(&self, payload: Vec<u8>) -> Result<Vec<u8>, SignatureError> {
+                let signature: p384::ecdsa::Signature = self.0.sign(&payload);
+                Ok(signature.to_vec())
+            }
+        }
+
+        let holder_key =
+            p384::ecdsa::SigningKey::random(&mut p256::elliptic_curve::rand_core::OsRng);
+        let holder_jwk = p384::PublicKey::from(holder_key.verifying_key()).to_jwk_string();
+        let mdl_items = serde_json::json!({
+            "family_name": "Doe",
+            "given_name": "John",
+            "birth_date": "1990-01-01",
+            "issue_date": "2023-01-01",
+            "expiry_date": "2028-01-01",
+            "issuing_country": "US",
+            "issuing_authority": "DMV",
+            "document_number": "123456789",
+            "portrait": "SGVsbG8gV29ybGQ=",
+            "driving_privileges": [],
+            "un_distinguishing_sign": "USA"
+        })
+        .to_string();
+        let mdoc = Mdoc::create_and_sign_mdl(
+            mdl_items,
+            None,
+            holder_jwk,
+            include_str!("../../tests/res/mdl/utrecht-certificate.pem").to_string(),
+            include_str!("../../tests/res/mdl/utrecht-key.pem").to_string(),
+        )
+        .unwrap();
+        let vp_token = generate_oid4vp_response(
+            mdoc,
+            HashMap::from([(MDL_NAMESPACE.to_string(), vec!["family_name".to_string()])]),
+            "x509_san_dns:verifier.example.com".to_string(),
+            "https://verifier.example.com/response".to_string(),
+            "nonce".to_string(),
+            Box::new(P384Signer(holder_key)),
+        )
+        .unwrap();
+
+        // The ES384 DeviceAuth of the holder authenticates at the reader, and not for
+        // another nonce
+        let verify = |nonce: &str| {
+            crate::mdl::reader::verify_oid4vp_response(
+                BASE64_URL_SAFE_NO_PAD.decode(&vp_token).unwrap(),
+                nonce.to_string(),
+                "x509_san_dns:verifier.example.com".to_string(),
+                "https://verifier.example.com/response".to_string(),
+                None,
+                false,
+                None,
+                None,
+                None,
+                None,
+            )
+            .unwrap()
+        };
+        assert_eq!(
+            verify("nonce").device_authentication,
+            AuthenticationStatus::Valid
+        );
+        assert_eq!(
+            verify("other nonce").device_authentication,
+            AuthenticationStatus::Invalid
+        );
+    }
+
+    #[test]
+    fn test_verify_encrypted_oid4vp_response() {
+        use p256::elliptic_curve::{JwkEcKey, rand_core::OsRng};
+
+        struct KeyPairSigner(Arc<P256KeyPair>);
+
+        impl DeviceSigner for KeyPairSigner {
+            fn sign(&self, payload: Vec<u8>) -> Result<Vec<u8>, SignatureError> {
+                Ok(self.0.sign(&payload))
+            }
+        }
+
+        let verifier_key = p256::SecretKey::random(&mut OsRng);
+        let key_pair = Arc::new(P256KeyPair::new());
+        let mdoc = generate_test_mdl(key_pair.clone()).unwrap();
+        let response = generate_encrypted_oid4vp_response(
+            Arc::new(mdoc),
+            HashMap::from([(MDL_NAMESPACE.to_string(), vec!["family_name".to_string()])]),
+            "x509_san_dns:verifier.example.com".to_string(),
+            "https://verifier.example.com/response".to_string(),
+            "nonce".to_string(),
+            Some("state".to_string()),
+            JwkEcKey::from(verifier_key.public_key()).to_string(),
+            Some(b"mdoc nonce".to_vec()),
+            Box::new(KeyPairSigner(key_pair)),
+        )
+        .unwrap();
+
+        let verify = |state: &str| {
+            crate::mdl::reader::verify_encrypted_oid4vp_response(
+                response.clone(),
+                verifier_key.to_jwk_string().to_string(),
+                "nonce".to_string(),
+                "x509_san_dns:verifier.example.com".to_string(),
+                "https://verifier.example.com/response".to_string(),
+                Some(state.to_string()),
+                None,
+                false,
+                None,
+                None,
+            )
+        };
+
+        // 1. The device signature covers the thumbprint of the verifier key
+        let verified = verify("state").unwrap();
+        assert_eq!(verified.device_authentication, AuthenticationStatus::Valid);
+        assert!(verified.verified_response[MDL_NAMESPACE].contains_key("family_name"));
+
+        // 2. The state must be that of the request
+        assert!(matches!(
+            verify("other state"),
+            Err(crate::mdl::reader::MDLReaderSessionError::TranscriptMismatch { .. })
+        ));
+
+        // 3. A verifier holding the key verifies its responses likewise
+        let verifier = crate::mdl::reader::Oid4vpVerifier::new(
+            "x509_san_dns:verifier.example.com".to_string(),
+            "https://verifier.example.com/response".to_string(),
+            None,
+            false,
+            None,
+            None,
+            Some(verifier_key.to_jwk_string().to_string()),
+        )
+        .unwrap();
+        let verified = verifier
+            .verify_encrypted(
+                response.clone(),
+                "nonce".to_string(),
+                Some("state".to_string()),
+                None,
+                None,
+            )
+            .unwrap();
+        assert_eq!(verified.device_authentication, AuthenticationStatus::Valid);
+    }
+
+    #[test]
+    fn test_verify_dc_api_response() {
+        struct KeyPairSigner(Arc<P256KeyPair>);
+
+        impl DeviceSigner for KeyPairSigner {
+            fn sign(&self, payload: Vec<u8>) -> Result<Vec<u8>, SignatureError> {
+                Ok(self.0.sign(&payload))
+            }
+        }
+
+        let key_pair = Arc::new(P256KeyPair::new());
+        let mdoc = generate_test_mdl(key_pair.clone()).unwrap();
+        let response = generate_dc_api_response(
+            Arc::new(mdoc),
+            HashMap::from([(MDL_NAMESPACE.to_string(), vec!["family_name".to_string()])]),
+            "https://verifier.example.com".to_string(),
+            "nonce".to_string(),
+            None,
+            "mdl".to_string(),
+            Box::new(KeyPairSigner(key_pair)),
+        )
+        .unwrap();
+        let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+        let device_response = BASE64_URL_SAFE_NO_PAD
+            .decode(response["vp_token"]["mdl"][0].as_str().unwrap())
+            .unwrap();
+
+        let verify = |origin: &str| {
+            crate::mdl::reader::verify_dc_api_response(
+                device_response.clone(),
+                origin.to_string(),
+                "nonce".to_string(),
+                None,
+                None,
+                false,
+                None,
+                None,
+            )
+            .unwrap()
+        };
+
+        // 1. The device signature covers the handover of the request's origin
+        let verified = verify("https://verifier.example.com");
+        assert_eq!(verified.device_authentication, AuthenticationStatus::Valid);
+        assert!(verified.verified_response[MDL_NAMESPACE].contains_key("family_name"));
+
+        // 2. A response made for another origin does not authenticate
+        let verified = verify("https://attacker.example.com");
+        assert_eq!(
+            verified.device_authentication,
+            AuthenticationStatus::Invalid
+        );
+    }
+
+    #[test]
+    fn test_verify_oid4vp_response_documents() {
+        use ciborium::Value;
+
+        struct KeyPairSigner(Arc<P256KeyPair>);
+
+        impl DeviceSigner for KeyPairSigner {
+            fn sign(&self, payload: Vec<u8>) -> Result<Vec<u8>, SignatureError> {
+                Ok(self.0.sign(&payload))
+            }
+        }
+
+        let vp_token = |element: &str| {
+            let key_pair = Arc::new(P256KeyPair::new());
+            let mdoc = generate_test_mdl(key_pair.clone()).unwrap();
+            let vp_token = generate_oid4vp_response(
+                Arc::new(mdoc),
+                HashMap::from([(MDL_NAMESPACE.to_string(), vec![element.to_string()])]),
+                "x509_san_dns:verifier.example.com".to_string(),
+                "https://verifier.example.com/response".to_string(),
+                "nonce".to_string(),
+                Box::new(KeyPairSigner(key_pair)),
+            )
+            .unwrap();
+            let device_response = BASE64_URL_SAFE_NO_PAD.decode(vp_token).unwrap();
+            ciborium::from_reader::<Value, _>(device_response.as_slice()).unwrap()
+        };
+        fn documents(device_response: &mut Value) -> &mut Vec<Value> {
+            device_response
+                .as_map_mut()
+                .unwrap()
+                .iter_mut()
+                .find(|(key, _)| key.as_text() == Some("documents"))
+                .and_then(|(_, documents)| documents.as_array_mut())
+                .unwrap()
+        }
+
+        // A response with the documents of two mdocs, each disclosing one element
+        let mut device_response = vp_token("family_name");
+        let second = documents(&mut vp_token("given_name")).remove(0);
+        documents(&mut device_response).push(second);
+        let mut response = Vec::new();
+        ciborium::into_writer(&device_response, &mut response).unwrap();
+
+        // Each element is requested by its own credential query, answered by either document
+        let credentials = ["family_name", "given_name"].map(|element| {
+            serde_json::json!({
+                "id": element,
+                "format": "mso_mdoc",
+                "meta": { "doctype_value": "org.iso.18013.5.1.mDL" },
+                "claims": [{ "path": ["org.iso.18013.5.1", element] }],
+            })
+        });
+        let dcql_query = serde_json::json!({ "credentials": credentials });
+        let verified = crate::mdl::reader::verify_oid4vp_response(
+            response,
+            "nonce".to_string(),
+            "x509_san_dns:verifier.example.com".to_string(),
+            "https://verifier.example.com/response".to_string(),
+            None,
+            false,
+            None,
+            None,
+            None,
+            Some(dcql_query.to_string()),
+        )
+        .unwrap();
+        assert!(verified.unmet_claims.is_empty());
+        assert_eq!(verified.documents.len(), 2);
+        for (document, element) in verified.documents.iter().zip(["family_name", "given_name"]) {
+            assert_eq!(document.doc_type, "org.iso.18013.5.1.mDL");
+            assert_eq!(document.device_authentication, AuthenticationStatus::Valid);
+            let elements = &document.namespaces[MDL_NAMESPACE];
+            assert_eq!(elements.keys().collect::<Vec<_>>(), vec![element]);
+        }
+        assert!(verified.verified_response[MDL_NAMESPACE].contains_key("family_name"));
+
+        // A document that cannot be parsed, its x5chain removed, is reported with its
+        // errors without failing the other
+        fn field<'a>(value: &'a mut Value, name: &str) -> &'a mut Value {
+            value
+                .as_map_mut()
+                .unwrap()
+                .iter_mut()
+                .find(|(key, _)| key.as_text() == Some(name))
+                .map(|(_, value)| value)
+                .unwrap()
+        }
+        fn issuer_auth_headers(document: &mut Value) -> &mut Vec<(Value, Value)> {
+            let issuer_auth = field(field(document, "issuerSigned"), "issuerAuth");
+            issuer_auth.as_array_mut().unwrap()[1].as_map_mut().unwrap()
+        }
+        issuer_auth_headers(&mut documents(&mut device_response)[1]).clear();
+        let mut response = Vec::new();
+        ciborium::into_writer(&device_response, &mut response).unwrap();
+        let verify = |response: Vec<u8>| {
+            crate::mdl::reader::verify_oid4vp_response(
+                response,
+                "nonce".to_string(),
+                "x509_san_dns:verifier.example.com".to_string(),
+                "https://verifier.example.com/response".to_string(),
+                None,
+                false,
+                None,
+                None,
+                None,
+                None,
+            )
+        };
+        let verified = verify(response).unwrap();
+        assert_eq!(verified.documents.len(), 2);
+        assert_eq!(
+            verified.documents[0].device_authentication,
+            AuthenticationStatus::Valid
+        );
+        let failed = &verified.documents[1];
+        assert_eq!(failed.doc_type, "org.iso.18013.5.1.mDL");
+        assert!(failed.namespaces.is_empty());
+        assert_eq!(failed.issues.len(), 1);
+        assert_eq!(
+            failed.issues[0].kind,
+            crate::mdl::reader::ResponseIssueKind::Parsing
+        );
+
+        // Only a response without any valid document fails
+        issuer_auth_headers(&mut documents(&mut device_response)[0]).clear();
+        let mut response = Vec::new();
+        ciborium::into_writer(&device_response, &mut response).unwrap();
+        assert!(verify(response).is_err());
+    }
+
+    #[test]
+    fn test_verify_oid4vp_response_digests() {
+        use ciborium::Value;
+
+        struct KeyPairSigner(Arc<P256KeyPair>);
+
+        impl DeviceSigner for KeyPairSigner {
+            fn sign(&self, payload: Vec<u8>) -> Result<Vec<u8>, SignatureError> {
+                Ok(self.0.sign(&payload))
+            }
+        }
+
+        fn field<'a>(value: &'a mut Value, name: &str) -> &'a mut Value {
+            value
+                .as_map_mut()
+                .unwrap()
+                .iter_mut()
+                .find(|(key, _)| key.as_text() == Some(name))
+                .map(|(_, value)| value)
+                .unwrap()
+        }
+
+        let key_pair = Arc::new(P256KeyPair::new());
+        let mdoc = generate_test_mdl(key_pair.clone()).unwrap();
+        let vp_token = generate_oid4vp_response(
+            Arc::new(mdoc),
+            HashMap::from([(
+                MDL_NAMESPACE.to_string(),
+                vec!["family_name".to_string(), "given_name".to_string()],
+            )]),
+            "x509_san_dns:verifier.example.com".to_string(),
+            "https://verifier.example.com/response".to_string(),
+            "nonce".to_string(),
+            Box::new(KeyPairSigner(key_pair)),
+        )
+        .unwrap();
+        let device_response = BASE64_URL_SAFE_NO_PAD.decode(vp_token).unwrap();
+        let mut device_response: Value = ciborium::from_reader(device_response.as_slice()).unwrap();
+
+        // The value of the family name is changed after issuance
+        let document = &mut field(&mut device_response, "documents")
+            .as_array_mut()
+            .unwrap()[0];
+        let items = field(
+            field(field(document, "issuerSigned"), "nameSpaces"),
+            MDL_NAMESPACE,
+        );
+        for item in items.as_array_mut().unwrap() {
+            let Value::Tag(24, bytes) = item else {
+                panic!("IssuerSignedItemBytes is not tagged");
+            };
+            let mut element: Value =
+                ciborium::from_reader(bytes.as_bytes().unwrap().as_slice()).unwrap();
+            if field(&mut element, "elementIdentifier").as_text() == Some("family_name") {
+                *field(&mut element, "elementValue") = Value::Text("Tampered".to_string());
+                let mut encoded = Vec::new();
+                ciborium::into_writer(&element, &mut encoded).unwrap();
+                **bytes = Value::Bytes(encoded);
+            }
+        }
+        let mut response = Vec::new();
+        ciborium::into_writer(&device_response, &mut response).unwrap();
+
+        // The element is withheld and reported, failing the issuer authentication of the
+        // document, while the others are returned
+        let verified = crate::mdl::reader::verify_oid4vp_response(
+            response,
+            "nonce".to_string(),
+            "x509_san_dns:verifier.example.com".to_string(),
+            "https://verifier.example.com/response".to_string(),
+            None,
+            false,
+            None,
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+        let document = &verified.documents[0];
+        assert_eq!(
+            document.issuer_authentication,
+            AuthenticationStatus::Invalid
+        );
+        let elements = &document.namespaces[MDL_NAMESPACE];
+        assert!(!elements.contains_key("family_name"));
+        assert!(elements.contains_key("given_name"));
+        let issue = document
+            .issues
+            .iter()
+            .find(|issue| issue.element.as_deref() == Some("family_name"))
+            .unwrap();
+        assert_eq!(issue.namespace.as_deref(), Some(MDL_NAMESPACE));
+        assert_eq!(
+            issue.kind,
+            crate::mdl::reader::ResponseIssueKind::IssuerAuthentication
+        );
+    }
+
+    #[test]
+    fn test_match_doc_type() {
+        let request = |doc_type: &str| ItemsRequest {
+            doc_type: doc_type.to_string(),
+            namespaces: HashMap::new(),
+            reader_common_name: None,
+            reader_authentication: AuthenticationStatus::Unchecked,
+            reader_authentication_errors: None,
+        };
+        let matched = match_doc_type(
+            "org.iso.18013.5.1.mDL",
+            vec![
+                request("eu.europa.ec.eudi.pid.1"),
+                request("org.iso.18013.5.1.mDL"),
+                request("eu.europa.ec.eudi.pid.1"),
+                request("org.iso.23220.photoid.1"),
+            ],
+        );
+
+        assert_eq!(matched.matched.len(), 1);
+        assert_eq!(matched.matched[0].doc_type, "org.iso.18013.5.1.mDL");
+        assert_eq!(
+            matched.unmatched_doc_types,
+            ["eu.europa.ec.eudi.pid.1", "org.iso.23220.photoid.1"]
+        );
+    }
+
+    #[test]
+    fn test_non_mdl_session() {
+        use crate::mdl::mdoc::Mdoc;
+        use crate::mdl::reader::{AuthenticationStatus, DeviceRequestBuilder, handle_response};
+        use crate::mdl::util::{IacaCertificateParams, generate_iaca_certificate};
+
+        const DOC_TYPE: &str = "com.example.doc";
+        const NAMESPACE: &str = "com.example.custom";
+        const MDL_DOC_TYPE: &str = "org.iso.18013.5.1.mDL";
+
+        let iaca = generate_iaca_certificate(IacaCertificateParams {
+            common_name: "Test IACA".to_string(),
+            country: "US".to_string(),
+            state_or_province: None,
+            organization: None,
+            issuer_alt_name: "issuer@example.com".to_string(),
+            crl_distribution_point: "https://example.com/iaca.crl".to_string(),
+            validity_days: 30,
+            key_type: None,
+        })
+        .unwrap();
+        let key_pair = Arc::new(P256KeyPair::new());
+        let mut value = vec![];
+        ciborium::into_writer(&Value::Text("custom-value".to_string()), &mut value).unwrap();
+        let mdoc = Mdoc::create_and_sign(
+            DOC_TYPE.to_string(),
+            HashMap::from([(
+                NAMESPACE.to_string(),
+                HashMap::from([("custom-element".to_string(), value)]),
+            )]),
+            key_pair.public_jwk(),
+            iaca.certificate_pem,
+            iaca.key_pem,
+        )
+        .unwrap();
+        let session = MdlPresentationSession::new(mdoc, Uuid::new_v4().to_string()).unwrap();
+        let builder = DeviceRequestBuilder::new();
+        builder.add_doc_type(DOC_TYPE.into()).unwrap();
+        builder
+            .add_element(NAMESPACE.into(), "custom-element".into(), false)
+            .unwrap();
+        builder.add_doc_type(MDL_DOC_TYPE.into()).unwrap();
+        builder
+            .add_element(MDL_NAMESPACE.into(), "family_name".into(), false)
+            .unwrap();
+        let reader = builder
+            .establish_session(session.get_qr_code_uri(), None)
+            .unwrap();
+
+        // 1. The requests are matched against the docType of the mdoc, not the mDL's
+        let items_requests = session
+            .handle_request_matching_doc_type(reader.request, vec![])
+            .unwrap();
+        assert_eq!(items_requests.matched.len(), 1);
+        assert_eq!(items_requests.matched[0].doc_type, DOC_TYPE);
+        assert_eq!(items_requests.unmatched_doc_types, [MDL_DOC_TYPE]);
+
+        // 2. The response holds the document under its own docType
+        let permitted = HashMap::from([(
+            DOC_TYPE.to_string(),
+            HashMap::from([(NAMESPACE.to_string(), vec!["custom-element".to_string()])]),
+        )]);
+        let payload = session.generate_response(permitted).unwrap().payload;
+        let response = session.submit_response(key_pair.sign(&payload)).unwrap();
+        let data = handle_response(reader.state, response, false).unwrap();
+        assert_eq!(data.documents.len(), 1);
+        assert_eq!(data.documents[0].doc_type, DOC_TYPE);
+        assert_eq!(data.device_authentication, AuthenticationStatus::Valid);
+        assert!(data.documents[0].namespaces[NAMESPACE].contains_key("custom-element"));
+    }
+
+    #[test]
+    fn test_consent_summary() {
+        use isomdl::definitions::device_request;
+
+        let doc_type = "org.iso.18013.5.1.mDL";
+        let elements = BTreeMap::from([(
+            MDL_NAMESPACE.to_string(),
+            BTreeSet::from(["family_name".to_string(), "portrait".to_string()]),
+        )]);
+        let requested = NonEmptyMap::maybe_new(BTreeMap::from([
+            ("family_name".to_string(), false),
+            ("portrait".to_string(), false),
+            ("age_over_21".to_string(), false),
+        ]))
+        .unwrap();
+        let requests = vec![device_request::ItemsRequest {
+            doc_type: doc_type.to_string(),
+            namespaces: NonEmptyMap::new(MDL_NAMESPACE.to_string(), requested),
+            request_info: None,
+        }];
+        let permitted = HashMap::from([(
+            doc_type.to_string(),
+            HashMap::from([(
+                MDL_NAMESPACE.to_string(),
+                vec!["family_name".to_string(), "age_over_21".to_string()],
+            )]),
+        )]);
+
+        let item = |element_identifier: &str| ConsentItem {
+            doc_type: doc_type.to_string(),
+            namespace: MDL_NAMESPACE.to_string(),
+            element_identifier: element_identifier.to_string(),
+        };
+        assert_eq!(
+            consent_summary(doc_type, &elements, &requests, &permitted),
+            ConsentSummary {
+                missing: vec![item("age_over_21")],
+                denied: vec![item("portrait")],
+                shared: vec![item("family_name")],
+            }
+        );
+    }
+
+    #[test]
+    fn test_age_over_substitute() {
+        let attestations = BTreeMap::from([(18, true), (21, false), (65, false)]);
+
+        // 1. The nearest greater NN that is true
+        assert_eq!(age_over_substitute(&attestations, 16), Some(18));
+        // 2. Otherwise the nearest smaller NN that is false
+        assert_eq!(age_over_substitute(&attestations, 25), Some(21));
+        assert_eq!(age_over_substitute(&attestations, 70), Some(65));
+        // 3. Nothing can be said about ages between a true and a false attestation
+        assert_eq!(age_over_substitute(&attestations, 19), None);
+        assert_eq!(age_over_substitute(&BTreeMap::new(), 21), None);
+    }
+
+    #[test]
+    fn test_device_retrieval_methods() {
+        let uuid = Uuid::new_v4().to_string();
+        let methods = [
+            RetrievalMethod::Nfc {
+                max_len_command_data_field: 255,
+                max_len_response_data_field: 256,
+            },
+            RetrievalMethod::BleCentralClient { uuid: uuid.clone() },
+            RetrievalMethod::WifiAware {
+                pass_phrase: None,
+                channel_info_operating_class: None,
+                channel_info_channel_number: None,
+                band_info: None,
+            },
+            RetrievalMethod::BlePeripheralServer {
+                uuid,
+                ble_device_address: None,
+            },
+        ];
+
+        // 1. Both BLE modes are combined into one method, in the position of the first
+        let drms = device_retrieval_methods(&methods).unwrap();
+        assert_eq!(drms.len(), 3);
+        assert!(matches!(drms[0], DeviceRetrievalMethod::NFC(_)));
+        let DeviceRetrievalMethod::BLE(ble) = &drms[1] else {
+            panic!("expected BLE second");
+        };
+        assert!(ble.central_client_mode.is_some() && ble.peripheral_server_mode.is_some());
+        assert!(matches!(drms[2], DeviceRetrievalMethod::WIFI(_)));
+
+        // 2. At least one valid method is required
+        assert!(device_retrieval_methods(&[]).is_err());
+        assert!(
+            device_retrieval_methods(&[RetrievalMethod::BleCentralClient {
+                uuid: "not a uuid".to_string()
+            }])
+            .is_err()
+        );
+    }
 }
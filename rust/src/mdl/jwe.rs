@@ -0,0 +1,277 @@
+// Copyright (c) 2025 Indicio
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+//
+// This software may be modified and distributed under the terms
+// of either the Apache License, Version 2.0 or the MIT license.
+// See the LICENSE-APACHE and LICENSE-MIT files for details.
+
+//! JWE encryption and decryption of OpenID4VP authorization responses for
+//! `direct_post.jwt`, with ECDH-ES key agreement (RFC 7518 4.6) and A128GCM content
+//! encryption.
+
+use aes_gcm::{
+    Aes128Gcm, KeyInit, Nonce,
+    aead::{Aead, Payload},
+};
+use anyhow::{Context, Result, bail};
+use base64::prelude::*;
+use p256::{
+    EncodedPoint, PublicKey, SecretKey,
+    ecdh::{EphemeralSecret, diffie_hellman},
+    elliptic_curve::{rand_core::OsRng, sec1::ToEncodedPoint},
+};
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+
+const ENC: &str = "A128GCM";
+/// Length of the A128GCM content encryption key, in bytes.
+const KEY_LENGTH: usize = 16;
+/// Length of the AES-GCM authentication tag, in bytes.
+const TAG_LENGTH: usize = 16;
+
+/// Encrypts `plaintext` to the P-256 public key of the verifier, given as a JWK, and
+/// returns the JWE in compact serialization. `apu` and `apv` are the Agreement
+/// PartyUInfo and PartyVInfo, included in the header and the key derivation.
+pub(crate) fn encrypt(
+    plaintext: &[u8],
+    recipient_jwk: &str,
+    apu: Option<&[u8]>,
+    apv: Option<&[u8]>,
+) -> Result<String> {
+    let recipient_jwk: Value = serde_json::from_str(recipient_jwk).context("invalid JWK")?;
+    let recipient = p256_public_key(&recipient_jwk)?;
+
+    let ephemeral = EphemeralSecret::random(&mut OsRng);
+    let shared_secret = ephemeral.diffie_hellman(&recipient);
+    let key = concat_kdf(
+        shared_secret.raw_secret_bytes(),
+        apu.unwrap_or_default(),
+        apv.unwrap_or_default(),
+    );
+
+    let epk = ephemeral.public_key().to_encoded_point(false);
+    let mut header = json!({
+        "alg": "ECDH-ES",
+        "enc": ENC,
+        "epk": {
+            "kty": "EC",
+            "crv": "P-256",
+            "x": BASE64_URL_SAFE_NO_PAD.encode(epk.x().context("missing x coordinate")?),
+            "y": BASE64_URL_SAFE_NO_PAD.encode(epk.y().context("missing y coordinate")?),
+        },
+    });
+    if let Some(kid) = recipient_jwk.get("kid") {
+        header["kid"] = kid.clone();
+    }
+    if let Some(apu) = apu {
+        header["apu"] = BASE64_URL_SAFE_NO_PAD.encode(apu).into();
+    }
+    if let Some(apv) = apv {
+        header["apv"] = BASE64_URL_SAFE_NO_PAD.encode(apv).into();
+    }
+    let protected = BASE64_URL_SAFE_NO_PAD.encode(header.to_string());
+
+    let iv: [u8; 12] = rand::random();
+    let cipher = Aes128Gcm::new_from_slice(&key).context("invalid content encryption key")?;
+    let mut ciphertext = cipher
+        .encrypt(
+            Nonce::from_slice(&iv),
+            Payload {
+                msg: plaintext,
+                aad: protected.as_bytes(),
+            },
+        )
+        .ok()
+        .context("encryption failed")?;
+    let tag = ciphertext.split_off(ciphertext.len() - TAG_LENGTH);
+
+    // ECDH-ES uses the agreed key directly, so the encrypted key is empty
+    Ok(format!(
+        "{protected}..{}.{}.{}",
+        BASE64_URL_SAFE_NO_PAD.encode(iv),
+        BASE64_URL_SAFE_NO_PAD.encode(ciphertext),
+        BASE64_URL_SAFE_NO_PAD.encode(tag),
+    ))
+}
+
+/// Decrypts a JWE in compact serialization, encrypted with ECDH-ES and A128GCM to the
+/// P-256 key of the recipient, given as a private JWK. Returns the protected header and
+/// the plaintext.
+pub(crate) fn decrypt(jwe: &str, recipient_jwk: &str) -> Result<(Value, Vec<u8>)> {
+    let [protected, encrypted_key, iv, ciphertext, tag] =
+        jwe.trim().split('.').collect::<Vec<_>>()[..]
+    else {
+        bail!("expected a JWE in compact serialization");
+    };
+    let decode = |part: &str, name: &str| {
+        BASE64_URL_SAFE_NO_PAD
+            .decode(part)
+            .with_context(|| format!("invalid {name}"))
+    };
+    let header: Value =
+        serde_json::from_slice(&decode(protected, "header")?).context("invalid header")?;
+    if header["alg"] != "ECDH-ES" || header["enc"] != ENC {
+        bail!("only ECDH-ES with {ENC} is supported");
+    }
+    // ECDH-ES uses the agreed key directly, so the encrypted key is empty
+    if !encrypted_key.is_empty() {
+        bail!("unexpected encrypted key");
+    }
+    let header_bytes = |name: &str| match header.get(name).and_then(Value::as_str) {
+        Some(value) => decode(value, name),
+        None => Ok(Vec::new()),
+    };
+
+    let recipient = SecretKey::from_jwk_str(recipient_jwk).context("invalid JWK")?;
+    let epk = p256_public_key(&header["epk"])?;
+    let shared_secret = diffie_hellman(recipient.to_nonzero_scalar(), epk.as_affine());
+    let key = concat_kdf(
+        shared_secret.raw_secret_bytes(),
+        &header_bytes("apu")?,
+        &header_bytes("apv")?,
+    );
+
+    let iv = decode(iv, "iv")?;
+    if iv.len() != 12 {
+        bail!("invalid iv length");
+    }
+    let cipher = Aes128Gcm::new_from_slice(&key).context("invalid content encryption key")?;
+    let plaintext = cipher
+        .decrypt(
+            Nonce::from_slice(&iv),
+            Payload {
+                msg: &[decode(ciphertext, "ciphertext")?, decode(tag, "tag")?].concat(),
+                aad: protected.as_bytes(),
+            },
+        )
+        .ok()
+        .context("decryption failed")?;
+    Ok((header, plaintext))
+}
+
+fn p256_public_key(jwk: &Value) -> Result<PublicKey> {
+    let member = |name: &str| jwk.get(name).and_then(Value::as_str);
+    if member("kty") != Some("EC") || member("crv") != Some("P-256") {
+        bail!("only P-256 EC keys are supported");
+    }
+    let coordinate = |name: &str| -> Result<Vec<u8>> {
+        let coordinate = BASE64_URL_SAFE_NO_PAD
+            .decode(member(name).with_context(|| format!("missing {name} coordinate"))?)
+            .with_context(|| format!("invalid {name} coordinate"))?;
+        if coordinate.len() != 32 {
+            bail!("invalid {name} coordinate length");
+        }
+        Ok(coordinate)
+    };
+    let point = EncodedPoint::from_affine_coordinates(
+        coordinate("x")?.as_slice().into(),
+        coordinate("y")?.as_slice().into(),
+        false,
+    );
+    PublicKey::from_sec1_bytes(point.as_bytes()).context("invalid public key")
+}
+
+/// The Concat KDF of NIST SP 800-56A as profiled by RFC 7518 4.6.2, for a key of
+/// [KEY_LENGTH] bytes, which a single SHA-256 round covers.
+fn concat_kdf(shared_secret: &[u8], apu: &[u8], apv: &[u8]) -> Vec<u8> {
+    let with_length = |data: &[u8]| [&(data.len() as u32).to_be_bytes(), data].concat();
+
+    let mut hasher = Sha256::new();
+    hasher.update(1u32.to_be_bytes());
+    hasher.update(shared_secret);
+    hasher.update(with_length(ENC.as_bytes()));
+    hasher.update(with_length(apu));
+    hasher.update(with_length(apv));
+    hasher.update(((KEY_LENGTH * 8) as u32).to_be_bytes());
+    hasher.finalize()[..KEY_LENGTH].to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use p256::elliptic_curve::JwkEcKey;
+
+    #[test]
+    fn test_encrypt() {
+        let recipient = SecretKey::random(&mut OsRng);
+        let point = recipient.public_key().to_encoded_point(false);
+        let recipient_jwk = json!({
+            "kty": "EC",
+            "crv": "P-256",
+            "kid": "verifier-key",
+            "use": "enc",
+            "x": BASE64_URL_SAFE_NO_PAD.encode(point.x().unwrap()),
+            "y": BASE64_URL_SAFE_NO_PAD.encode(point.y().unwrap()),
+        })
+        .to_string();
+
+        let jwe = encrypt(
+            b"{\"vp_token\":\"x\"}",
+            &recipient_jwk,
+            None,
+            Some(b"nonce"),
+        )
+        .unwrap();
+        let parts: Vec<&str> = jwe.split('.').collect();
+        assert_eq!(parts.len(), 5);
+        assert!(parts[1].is_empty());
+
+        // 1. The header identifies the algorithms, the recipient key and apv
+        let header: Value =
+            serde_json::from_slice(&BASE64_URL_SAFE_NO_PAD.decode(parts[0]).unwrap()).unwrap();
+        assert_eq!(header["alg"], "ECDH-ES");
+        assert_eq!(header["enc"], "A128GCM");
+        assert_eq!(header["kid"], "verifier-key");
+        assert_eq!(header["apv"], BASE64_URL_SAFE_NO_PAD.encode("nonce"));
+        assert!(header.get("apu").is_none());
+
+        // 2. The recipient derives the same key and decrypts the response
+        let epk = p256_public_key(&header["epk"]).unwrap();
+        let shared_secret = diffie_hellman(recipient.to_nonzero_scalar(), epk.as_affine());
+        let key = concat_kdf(shared_secret.raw_secret_bytes(), b"", b"nonce");
+        let decode = |part: &str| BASE64_URL_SAFE_NO_PAD.decode(part).unwrap();
+        let ciphertext = [decode(parts[3]), decode(parts[4])].concat();
+        let plaintext = Aes128Gcm::new_from_slice(&key)
+            .unwrap()
+            .decrypt(
+                Nonce::from_slice(&decode(parts[2])),
+                Payload {
+                    msg: &ciphertext,
+                    aad: parts[0].as_bytes(),
+                },
+            )
+            .unwrap();
+        assert_eq!(plaintext, b"{\"vp_token\":\"x\"}");
+
+        // 3. Only P-256 keys are supported
+        let ed25519_jwk = r#"{"kty":"OKP","crv":"Ed25519","x":"AAAA"}"#;
+        assert!(encrypt(b"", ed25519_jwk, None, None).is_err());
+    }
+
+    #[test]
+    fn test_decrypt() {
+        let recipient = SecretKey::random(&mut OsRng);
+        let private_jwk = recipient.to_jwk_string();
+        let public_jwk = JwkEcKey::from(recipient.public_key()).to_string();
+
+        // 1. The recipient decrypts the response and reads apu and apv from the header
+        let jwe = encrypt(
+            b"{\"vp_token\":\"x\"}",
+            &public_jwk,
+            Some(b"mdoc nonce"),
+            Some(b"nonce"),
+        )
+        .unwrap();
+        let (header, plaintext) = decrypt(&jwe, &private_jwk).unwrap();
+        assert_eq!(plaintext, b"{\"vp_token\":\"x\"}");
+        assert_eq!(header["apu"], BASE64_URL_SAFE_NO_PAD.encode("mdoc nonce"));
+
+        // 2. Another key or a tampered ciphertext does not decrypt
+        let other = SecretKey::random(&mut OsRng).to_jwk_string();
+        assert!(decrypt(&jwe, &other).is_err());
+        let mut parts: Vec<String> = jwe.split('.').map(str::to_string).collect();
+        parts[3] = BASE64_URL_SAFE_NO_PAD.encode(b"tampered");
+        assert!(decrypt(&parts.join("."), &private_jwk).is_err());
+        assert!(decrypt("not a jwe", &private_jwk).is_err());
+    }
+}
@@ -11,41 +11,51 @@
 
 use std::{
     collections::{BTreeMap, HashMap},
-    io::Cursor,
     sync::Arc,
-    time::Duration,
+    time::{Duration, SystemTime},
 };
 
-use anyhow::{Context, Result};
+use anyhow::Result;
 use base64::prelude::*;
 use ciborium::{Value, from_reader};
 use coset::Label;
 use isomdl::{
     definitions::{
-        CoseKey, DeviceKeyInfo, DigestAlgorithm, EC2Curve, EC2Y, IssuerSigned, Mso, ValidityInfo,
-        helpers::{NonEmptyMap, Tag24},
+        CoseKey, DeviceKeyInfo, DigestAlgorithm, DigestId, EC2Curve, EC2Y, IssuerSigned,
+        IssuerSignedItem, Mso, ValidityInfo,
+        device_key::cose_key::OKPCurve,
+        helpers::{NonEmptyMap, NonEmptyVec, Tag24},
         namespaces::{
             org_iso_18013_5_1::OrgIso1801351, org_iso_18013_5_1_aamva::OrgIso1801351Aamva,
         },
         traits::{FromJson, ToNamespaceMap},
-        x509::{
-            X5Chain,
-            trust_anchor::{PemTrustAnchor, TrustAnchorRegistry, TrustPurpose},
-            x5chain::X5CHAIN_COSE_HEADER_LABEL,
-        },
+        x509::{X5Chain, trust_anchor::TrustAnchorRegistry, x5chain::X5CHAIN_COSE_HEADER_LABEL},
     },
     issuance::mdoc::Builder,
     presentation::{Stringify, authentication::mdoc::issuer_authentication, device::Document},
 };
 use p256::{PublicKey, elliptic_curve::sec1::ToEncodedPoint};
+use rand::{RngCore, SeedableRng, seq::SliceRandom};
+use rand_chacha::ChaCha20Rng;
 use serde::Deserialize;
 use serde::Serialize;
+use sha2::{Digest, Sha256, Sha384, Sha512};
 use time::OffsetDateTime;
 use uuid::Uuid;
 use x509_cert::Certificate;
-use x509_cert::der::DecodePem;
+use x509_cert::der::{Decode, DecodePem};
 
-use super::util::{build_intermediate_trust_chain, setup_certificate_chain};
+use super::holder::ItemsRequest;
+use super::namespaces::{
+    self, AAMVA, AAMVA_NAMESPACE, EU_PID, EU_PID_DOC_TYPE, ISO_23220, MDL, MDL_NAMESPACE,
+    NamespaceDefinition, PHOTO_ID, PHOTO_ID_DOC_TYPE,
+};
+use super::util::{
+    IssuerSigningKey, TrustAnchorPurpose, build_intermediate_trust_chain, common_name,
+    cose_key_thumbprint, parse_certificate_chain, pem_trust_anchor, run_blocking,
+    setup_issuer_certificate_chain, verify_device_auth_signature, verify_device_key_signature,
+    x5chain_certificates,
+};
 
 uniffi::custom_newtype!(Namespace, String);
 #[derive(Debug, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
@@ -79,12 +89,23 @@ impl Mdoc {
         base64url_encoded_issuer_signed: String,
         key_alias: KeyAlias,
     ) -> Result<Arc<Self>, MdocInitError> {
-        let issuer_signed = isomdl::cbor::from_slice(
-            &BASE64_URL_SAFE_NO_PAD
-                .decode(base64url_encoded_issuer_signed)
-                .map_err(|_| MdocInitError::IssuerSignedBase64UrlDecoding)?,
-        )
-        .map_err(|_| MdocInitError::IssuerSignedCborDecoding)?;
+        // Decode the base64url text straight into the CBOR deserializer, so the decoded
+        // IssuerSigned bytes (which may include a large portrait) are never buffered in full.
+        let decoder = base64::read::DecoderReader::new(
+            base64url_encoded_issuer_signed.as_bytes(),
+            &BASE64_URL_SAFE_NO_PAD,
+        );
+        // The decoder reports invalid base64 as an I/O error wrapping the DecodeError,
+        // while the CBOR of a truncated input ends in an I/O error of its own
+        let issuer_signed = from_reader(decoder).map_err(|e| match e {
+            ciborium::de::Error::Io(e)
+                if e.get_ref()
+                    .is_some_and(|inner| inner.is::<base64::DecodeError>()) =>
+            {
+                MdocInitError::IssuerSignedBase64UrlDecoding
+            }
+            _ => MdocInitError::IssuerSignedCborDecoding,
+        })?;
         Self::new_from_issuer_signed(key_alias, issuer_signed)
     }
 
@@ -122,6 +143,25 @@ impl Mdoc {
         Ok(Arc::new(Self { inner, key_alias }))
     }
 
+    #[uniffi::constructor]
+    /// Construct an MDoc from the credential data layout used by the Android Jetpack
+    /// Identity Credential library, as produced by [Mdoc::to_android_credential_data].
+    ///
+    /// `static_auth_data` is the CBOR-encoded `StaticAuthData` (digest ID mapping and
+    /// issuerAuth), and `element_values` holds the CBOR-encoded value of each element,
+    /// keyed by namespace and element identifier.
+    pub fn from_android_credential_data(
+        static_auth_data: Vec<u8>,
+        element_values: HashMap<String, HashMap<String, Vec<u8>>>,
+        key_alias: KeyAlias,
+    ) -> Result<Arc<Self>, MdocInitError> {
+        let issuer_signed =
+            android_static_auth_data_to_issuer_signed(&static_auth_data, &element_values)?;
+        let issuer_signed = isomdl::cbor::from_slice(&issuer_signed)
+            .map_err(|_| MdocInitError::IssuerSignedCborDecoding)?;
+        Self::new_from_issuer_signed(key_alias, issuer_signed)
+    }
+
     #[uniffi::constructor]
     pub fn create_and_sign(
         doc_type: String,
@@ -130,60 +170,55 @@ impl Mdoc {
         iaca_cert_perm: String,
         iaca_key_perm: String,
     ) -> Result<Arc<Self>, MdocInitError> {
-        let pub_key: PublicKey =
-            PublicKey::from_jwk_str(&holder_jwk).map_err(|_e| MdocInitError::InvalidJwk)?;
-
-        let namespaces = convert_namespaces(namespaces)?;
-        let builder = prepare_builder(pub_key, namespaces, doc_type)
-            .map_err(|_e| MdocInitError::GeneralConstructionError)?;
-
-        let (certificate, iaca_certs, signer) =
-            setup_certificate_chain(iaca_cert_perm, iaca_key_perm)
-                .map_err(|_e| MdocInitError::GeneralConstructionError)?;
-
-        let mut x5chain_builder = X5Chain::builder()
-            .with_certificate(certificate)
-            .map_err(|_e| MdocInitError::GeneralConstructionError)?;
-
-        for cert in iaca_certs {
-            x5chain_builder = x5chain_builder
-                .with_certificate(cert)
-                .map_err(|_e| MdocInitError::GeneralConstructionError)?;
-        }
+        Self::create_and_sign_with_options(
+            doc_type,
+            namespaces,
+            holder_jwk,
+            iaca_cert_perm,
+            iaca_key_perm,
+            IssuanceOptions::default(),
+        )
+    }
 
-        let x5chain = x5chain_builder
-            .build()
-            .map_err(|_e| MdocInitError::GeneralConstructionError)?;
+    #[uniffi::constructor]
+    /// Async variant of [Mdoc::create_and_sign]. Key parsing, encoding and signing
+    /// run on a separate thread.
+    pub async fn create_and_sign_async(
+        doc_type: String,
+        namespaces: HashMap<String, HashMap<String, Vec<u8>>>,
+        holder_jwk: String,
+        iaca_cert_perm: String,
+        iaca_key_perm: String,
+    ) -> Result<Arc<Self>, MdocInitError> {
+        run_blocking(move || {
+            Self::create_and_sign(
+                doc_type,
+                namespaces,
+                holder_jwk,
+                iaca_cert_perm,
+                iaca_key_perm,
+            )
+        })
+        .await
+        .ok_or(MdocInitError::GeneralConstructionError)?
+    }
 
-        let mdoc = builder
-            .issue::<p256::ecdsa::SigningKey, p256::ecdsa::Signature>(x5chain, signer)
-            .map_err(|_e| MdocInitError::GeneralConstructionError)?;
+    #[uniffi::constructor]
+    /// Same as [Mdoc::create_and_sign], with additional [IssuanceOptions].
+    pub fn create_and_sign_with_options(
+        doc_type: String,
+        namespaces: HashMap<String, HashMap<String, Vec<u8>>>,
+        holder_jwk: String,
+        iaca_cert_perm: String,
+        iaca_key_perm: String,
+        options: IssuanceOptions,
+    ) -> Result<Arc<Self>, MdocInitError> {
+        let device_key = device_key_from_jwk(&holder_jwk)?;
 
-        let namespaces = NonEmptyMap::maybe_new(
-            mdoc.namespaces
-                .into_inner()
-                .into_iter()
-                .map(|(namespace, elements)| {
-                    let inner_map = NonEmptyMap::maybe_new(
-                        elements
-                            .into_inner()
-                            .into_iter()
-                            .map(|element| (element.as_ref().element_identifier.clone(), element))
-                            .collect(),
-                    )
-                    .ok_or(MdocInitError::GeneralConstructionError)?;
-                    Ok((namespace, inner_map))
-                })
-                .collect::<Result<_, MdocInitError>>()?,
-        )
-        .ok_or(MdocInitError::GeneralConstructionError)?;
+        let namespaces = convert_namespaces(namespaces)?;
+        let builder = prepare_builder(device_key, namespaces, doc_type, &options)?;
 
-        let doc = Document {
-            id: Default::default(),
-            issuer_auth: mdoc.issuer_auth,
-            mso: mdoc.mso,
-            namespaces,
-        };
+        let doc = issue_document(builder, iaca_cert_perm, iaca_key_perm, &options)?;
 
         Ok(Arc::new(super::mdoc::Mdoc::new_from_parts(
             doc,
@@ -199,81 +234,166 @@ impl Mdoc {
         iaca_cert_pem: String,
         iaca_key_pem: String,
     ) -> Result<Arc<Self>, MdocInitError> {
-        let pub_key: PublicKey =
-            PublicKey::from_jwk_str(&holder_jwk).map_err(|_e| MdocInitError::InvalidJwk)?;
+        Self::create_and_sign_mdl_with_options(
+            mdl_items,
+            aamva_items,
+            holder_jwk,
+            iaca_cert_pem,
+            iaca_key_pem,
+            IssuanceOptions::default(),
+        )
+    }
+
+    #[uniffi::constructor]
+    /// Async variant of [Mdoc::create_and_sign_mdl]. Key parsing, encoding and signing
+    /// run on a separate thread.
+    pub async fn create_and_sign_mdl_async(
+        mdl_items: String,
+        aamva_items: Option<String>,
+        holder_jwk: String,
+        iaca_cert_pem: String,
+        iaca_key_pem: String,
+    ) -> Result<Arc<Self>, MdocInitError> {
+        run_blocking(move || {
+            Self::create_and_sign_mdl(
+                mdl_items,
+                aamva_items,
+                holder_jwk,
+                iaca_cert_pem,
+                iaca_key_pem,
+            )
+        })
+        .await
+        .ok_or(MdocInitError::GeneralConstructionError)?
+    }
 
-        let mut namespaces = BTreeMap::new();
+    #[uniffi::constructor]
+    /// Same as [Mdoc::create_and_sign_mdl], with additional [IssuanceOptions].
+    pub fn create_and_sign_mdl_with_options(
+        mdl_items: String,
+        aamva_items: Option<String>,
+        holder_jwk: String,
+        iaca_cert_pem: String,
+        iaca_key_pem: String,
+        options: IssuanceOptions,
+    ) -> Result<Arc<Self>, MdocInitError> {
+        let device_key = device_key_from_jwk(&holder_jwk)?;
+        let namespaces = mdl_namespaces(&mdl_items, aamva_items.as_deref())?;
+        let doc_type = MDL_DOC_TYPE.to_string();
 
-        // Parse mDL items
-        let json_value: serde_json::Value = serde_json::from_str(&mdl_items)
-            .map_err(|_e| MdocInitError::GeneralConstructionError)?;
-        let mdl_data = OrgIso1801351::from_json(&json_value)
-            .map_err(|_e| MdocInitError::GeneralConstructionError)?
-            .to_ns_map();
-        namespaces.insert("org.iso.18013.5.1".to_string(), mdl_data);
-
-        // Parse AAMVA items if present
-        if let Some(aamva_json) = aamva_items {
-            let json_value: serde_json::Value = serde_json::from_str(&aamva_json)
-                .map_err(|_e| MdocInitError::GeneralConstructionError)?;
-            let aamva_data = OrgIso1801351Aamva::from_json(&json_value)
-                .map_err(|_e| MdocInitError::GeneralConstructionError)?
-                .to_ns_map();
-            namespaces.insert("org.iso.18013.5.1.aamva".to_string(), aamva_data);
-        }
+        let builder = prepare_builder(device_key, namespaces, doc_type, &options)?;
 
-        let doc_type = "org.iso.18013.5.1.mDL".to_string();
+        let doc = issue_document(builder, iaca_cert_pem, iaca_key_pem, &options)?;
 
-        let builder = prepare_builder(pub_key, namespaces, doc_type)
-            .map_err(|_e| MdocInitError::GeneralConstructionError)?;
+        Ok(Arc::new(super::mdoc::Mdoc::new_from_parts(
+            doc,
+            KeyAlias(Uuid::new_v4().to_string()),
+        )))
+    }
 
-        let (certificate, iaca_certs, signer) =
-            setup_certificate_chain(iaca_cert_pem, iaca_key_pem)
-                .map_err(|_e| MdocInitError::GeneralConstructionError)?;
+    #[uniffi::constructor(default(options = None))]
+    /// Create an mdoc of any doc type from JSON data elements, given as one JSON object
+    /// per namespace. Values are converted to CBOR as is: objects become maps, arrays
+    /// become arrays, and byte strings are given as `{"$bytes": "<base64url>"}`.
+    pub fn create_and_sign_from_json(
+        doc_type: String,
+        namespaces_json: HashMap<String, String>,
+        holder_jwk: String,
+        iaca_cert_pem: String,
+        iaca_key_pem: String,
+        options: Option<IssuanceOptions>,
+    ) -> Result<Arc<Self>, MdocInitError> {
+        let options = options.unwrap_or_default();
+        let device_key = device_key_from_jwk(&holder_jwk)?;
+        let namespaces = generic_namespaces(namespaces_json)?;
+        let builder = prepare_builder(device_key, namespaces, doc_type, &options)?;
 
-        let mut x5chain_builder = X5Chain::builder()
-            .with_certificate(certificate)
-            .map_err(|_e| MdocInitError::GeneralConstructionError)?;
+        let doc = issue_document(builder, iaca_cert_pem, iaca_key_pem, &options)?;
 
-        for cert in iaca_certs {
-            x5chain_builder = x5chain_builder
-                .with_certificate(cert)
-                .map_err(|_e| MdocInitError::GeneralConstructionError)?;
-        }
+        Ok(Arc::new(super::mdoc::Mdoc::new_from_parts(
+            doc,
+            KeyAlias(Uuid::new_v4().to_string()),
+        )))
+    }
 
-        let x5chain = x5chain_builder
-            .build()
-            .map_err(|_e| MdocInitError::GeneralConstructionError)?;
+    #[uniffi::constructor(default(options = None))]
+    /// Create an mdoc of any doc type from JSON data elements, given as one JSON object
+    /// per namespace. Namespaces the crate has definitions for, such as the mDL, AAMVA,
+    /// EU PID and Photo ID namespaces, are validated and converted with their CBOR types,
+    /// other namespaces are converted as by [Mdoc::create_and_sign_from_json].
+    pub fn create_and_sign_multi(
+        doc_type: String,
+        namespaces: HashMap<String, String>,
+        holder_jwk: String,
+        iaca_cert_pem: String,
+        iaca_key_pem: String,
+        options: Option<IssuanceOptions>,
+    ) -> Result<Arc<Self>, MdocInitError> {
+        let options = options.unwrap_or_default();
+        let device_key = device_key_from_jwk(&holder_jwk)?;
+        let namespaces = json_namespaces(namespaces)?;
+        let builder = prepare_builder(device_key, namespaces, doc_type, &options)?;
 
-        let mdoc = builder
-            .issue::<p256::ecdsa::SigningKey, p256::ecdsa::Signature>(x5chain, signer)
-            .map_err(|_e| MdocInitError::GeneralConstructionError)?;
+        let doc = issue_document(builder, iaca_cert_pem, iaca_key_pem, &options)?;
 
-        let namespaces = NonEmptyMap::maybe_new(
-            mdoc.namespaces
-                .into_inner()
-                .into_iter()
-                .map(|(namespace, elements)| {
-                    let inner_map = NonEmptyMap::maybe_new(
-                        elements
-                            .into_inner()
-                            .into_iter()
-                            .map(|element| (element.as_ref().element_identifier.clone(), element))
-                            .collect(),
-                    )
-                    .ok_or(MdocInitError::GeneralConstructionError)?;
-                    Ok((namespace, inner_map))
-                })
-                .collect::<Result<_, MdocInitError>>()?,
-        )
-        .ok_or(MdocInitError::GeneralConstructionError)?;
+        Ok(Arc::new(super::mdoc::Mdoc::new_from_parts(
+            doc,
+            KeyAlias(Uuid::new_v4().to_string()),
+        )))
+    }
 
-        let doc = Document {
-            id: Default::default(),
-            issuer_auth: mdoc.issuer_auth,
-            mso: mdoc.mso,
+    #[uniffi::constructor(default(options = None))]
+    /// Create an EU PID (`eu.europa.ec.eudi.pid.1`) from JSON data elements. The
+    /// mandatory PID attributes are checked, and dates, country codes and the
+    /// portrait are encoded with their CBOR types.
+    pub fn create_and_sign_pid(
+        pid_items: String,
+        holder_jwk: String,
+        iaca_cert_pem: String,
+        iaca_key_pem: String,
+        options: Option<IssuanceOptions>,
+    ) -> Result<Arc<Self>, MdocInitError> {
+        let options = options.unwrap_or_default();
+        let device_key = device_key_from_jwk(&holder_jwk)?;
+        let namespaces = typed_namespaces(&[(&EU_PID, &pid_items)])?;
+        let builder = prepare_builder(
+            device_key,
             namespaces,
-        };
+            EU_PID_DOC_TYPE.to_string(),
+            &options,
+        )?;
+
+        let doc = issue_document(builder, iaca_cert_pem, iaca_key_pem, &options)?;
+
+        Ok(Arc::new(super::mdoc::Mdoc::new_from_parts(
+            doc,
+            KeyAlias(Uuid::new_v4().to_string()),
+        )))
+    }
+
+    #[uniffi::constructor(default(options = None))]
+    /// Create an ISO/IEC TS 23220-4 Photo ID (`org.iso.23220.photoid.1`) from JSON data
+    /// elements of the `org.iso.23220.1` namespace, and optionally of the
+    /// `org.iso.23220.photoid.1` namespace.
+    pub fn create_and_sign_photo_id(
+        iso23220_items: String,
+        photo_id_items: Option<String>,
+        holder_jwk: String,
+        iaca_cert_pem: String,
+        iaca_key_pem: String,
+        options: Option<IssuanceOptions>,
+    ) -> Result<Arc<Self>, MdocInitError> {
+        let options = options.unwrap_or_default();
+        let device_key = device_key_from_jwk(&holder_jwk)?;
+        let namespaces = photo_id_namespaces(&iso23220_items, photo_id_items.as_deref())?;
+        let builder = prepare_builder(
+            device_key,
+            namespaces,
+            PHOTO_ID_DOC_TYPE.to_string(),
+            &options,
+        )?;
+
+        let doc = issue_document(builder, iaca_cert_pem, iaca_key_pem, &options)?;
 
         Ok(Arc::new(super::mdoc::Mdoc::new_from_parts(
             doc,
@@ -293,21 +413,20 @@ impl Mdoc {
 
     /// Simple representation of mdoc namespace and data elements for display in the UI.
     pub fn details(&self) -> HashMap<Namespace, Vec<Element>> {
+        // Elements are rendered by reference to avoid cloning every IssuerSignedItem
+        // (including the raw portrait bytes) just to produce display strings.
         self.document()
             .namespaces
-            .clone()
-            .into_inner()
-            .into_iter()
+            .iter()
             .map(|(namespace, elements)| {
                 (
-                    Namespace(namespace),
+                    Namespace(namespace.clone()),
                     elements
-                        .into_inner()
-                        .into_values()
+                        .values()
                         .map(|tagged| {
-                            let element = tagged.into_inner();
+                            let element = tagged.as_ref();
                             Element {
-                                identifier: element.element_identifier,
+                                identifier: element.element_identifier.clone(),
                                 value: serde_json::to_string_pretty(&element.element_value).ok(),
                             }
                         })
@@ -321,6 +440,48 @@ impl Mdoc {
         self.key_alias.clone()
     }
 
+    /// The data elements of a namespace as a JSON object, or `None` if the mdoc does
+    /// not contain the namespace.
+    ///
+    /// Elements of namespaces the crate has typed definitions for (EU PID, ISO/IEC
+    /// 23220 Photo ID) use the same format as the typed issuance constructors: dates
+    /// as `YYYY-MM-DD` and binary data base64url encoded.
+    pub fn namespace_json(&self, namespace: String) -> Option<String> {
+        let elements = self.document().namespaces.get(&namespace)?;
+        let json = namespaces::elements_to_json(
+            &namespace,
+            elements
+                .iter()
+                .map(|(identifier, item)| (identifier, &item.as_ref().element_value)),
+        );
+        Some(serde_json::Value::Object(json).to_string())
+    }
+
+    /// When the issuer expects to update this credential, if it said so in the MSO.
+    pub fn expected_update(&self) -> Option<SystemTime> {
+        self.inner
+            .mso
+            .validity_info
+            .expected_update
+            .map(SystemTime::from)
+    }
+
+    /// The `keyInfo` entries of the DeviceKeyInfo, as CBOR-encoded values keyed by
+    /// integer label, if the issuer included any.
+    pub fn key_info(&self) -> Option<HashMap<i64, Vec<u8>>> {
+        let key_info = self.inner.mso.device_key_info.key_info.as_ref()?;
+        Some(
+            key_info
+                .iter()
+                .filter_map(|(label, value)| {
+                    let mut bytes = Vec::new();
+                    ciborium::into_writer(value, &mut bytes).ok()?;
+                    Some((i64::try_from(*label).ok()?, bytes))
+                })
+                .collect(),
+        )
+    }
+
     /// Serialize as JSON
     pub fn json(&self) -> Result<String, crate::mdl::mdoc::MdocEncodingError> {
         match serde_json::to_string(&self.inner) {
@@ -337,39 +498,156 @@ impl Mdoc {
         }
     }
 
+    /// Re-sign the data elements and device key of this mdoc with a fresh MSO, for
+    /// refreshing a credential before it expires. The validity window starts at the
+    /// signing time unless set otherwise in the options, and the key alias is kept since
+    /// the device key does not change.
+    #[uniffi::method(default(options = None))]
+    pub fn reissue(
+        &self,
+        iaca_cert_pem: String,
+        iaca_key_pem: String,
+        options: Option<IssuanceOptions>,
+    ) -> Result<Arc<Self>, MdocInitError> {
+        let options = options.unwrap_or_default();
+        let builder = self.reissue_builder(&options)?;
+
+        let doc = issue_document(builder, iaca_cert_pem, iaca_key_pem, &options)?;
+
+        Ok(Arc::new(Self::new_from_parts(doc, self.key_alias.clone())))
+    }
+
+    /// Serialize the IssuerSigned structure of this mdoc to base64url-encoded CBOR, as
+    /// used in OID4VCI credential responses. This is the inverse of
+    /// [Mdoc::new_from_base64url_encoded_issuer_signed].
+    pub fn to_base64url_issuer_signed(&self) -> Result<String, MdocEncodingError> {
+        let issuer_signed = self
+            .issuer_signed()
+            .ok_or(MdocEncodingError::DocumentCborEncoding)?;
+        let bytes = isomdl::cbor::to_vec(&issuer_signed)
+            .map_err(|_e| MdocEncodingError::DocumentCborEncoding)?;
+        Ok(BASE64_URL_SAFE_NO_PAD.encode(bytes))
+    }
+
+    /// Serialize the Document to CBOR. This is the inverse of
+    /// [Mdoc::from_cbor_encoded_document].
+    pub fn to_cbor_encoded_document(&self) -> Result<Vec<u8>, MdocEncodingError> {
+        isomdl::cbor::to_vec(&self.inner).map_err(|_e| MdocEncodingError::DocumentCborEncoding)
+    }
+
+    /// Serialize this mdoc in one of the text [OutputFormat]s. The CBOR-encoded Document
+    /// is binary, see [Mdoc::encode_bytes].
+    pub fn encode(&self, format: OutputFormat) -> Result<String, MdocEncodingError> {
+        match format {
+            OutputFormat::Stringified => self.stringify(),
+            OutputFormat::CborDocument => Err(MdocEncodingError::BinaryOutputFormat),
+            OutputFormat::Base64UrlIssuerSigned => self.to_base64url_issuer_signed(),
+            OutputFormat::HexCborDocument => Ok(self
+                .to_cbor_encoded_document()?
+                .iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect()),
+        }
+    }
+
+    /// Serialize this mdoc in the given [OutputFormat], text formats as their ASCII bytes.
+    pub fn encode_bytes(&self, format: OutputFormat) -> Result<Vec<u8>, MdocEncodingError> {
+        match format {
+            OutputFormat::CborDocument => self.to_cbor_encoded_document(),
+            _ => self.encode(format).map(String::into_bytes),
+        }
+    }
+
+    /// Export this mdoc in the credential data layout used by the Android Jetpack
+    /// Identity Credential library.
+    ///
+    /// The returned `static_auth_data` contains the IssuerSignedItems with their
+    /// element values replaced by null, alongside the issuerAuth, while the values
+    /// themselves are returned separately so they can be stored as credential data.
+    pub fn to_android_credential_data(&self) -> Result<AndroidCredentialData, MdocEncodingError> {
+        let mut digest_id_mapping = Vec::new();
+        let mut element_values = HashMap::new();
+
+        for (namespace, elements) in self.inner.namespaces.iter() {
+            let mut items = Vec::new();
+            let mut values = HashMap::new();
+            for tagged in elements.values() {
+                let element = tagged.as_ref();
+                let metadata = IssuerSignedItem {
+                    digest_id: element.digest_id,
+                    random: element.random.clone(),
+                    element_identifier: element.element_identifier.clone(),
+                    element_value: Value::Null,
+                };
+                let metadata_bytes = isomdl::cbor::to_vec(&metadata)
+                    .map_err(|_| MdocEncodingError::DocumentCborEncoding)?;
+                items.push(Value::Tag(24, Box::new(Value::Bytes(metadata_bytes))));
+
+                let value_bytes = isomdl::cbor::to_vec(&element.element_value)
+                    .map_err(|_| MdocEncodingError::DocumentCborEncoding)?;
+                values.insert(element.element_identifier.clone(), value_bytes);
+            }
+            digest_id_mapping.push((Value::Text(namespace.clone()), Value::Array(items)));
+            element_values.insert(namespace.clone(), values);
+        }
+
+        let issuer_auth_bytes = isomdl::cbor::to_vec(&self.inner.issuer_auth)
+            .map_err(|_| MdocEncodingError::DocumentCborEncoding)?;
+        let issuer_auth: Value = from_reader(issuer_auth_bytes.as_slice())
+            .map_err(|_| MdocEncodingError::DocumentCborEncoding)?;
+
+        let static_auth_data = Value::Map(vec![
+            (
+                Value::Text(ANDROID_DIGEST_ID_MAPPING.to_string()),
+                Value::Map(digest_id_mapping),
+            ),
+            (Value::Text(ANDROID_ISSUER_AUTH.to_string()), issuer_auth),
+        ]);
+        let mut static_auth_data_bytes = Vec::new();
+        ciborium::into_writer(&static_auth_data, &mut static_auth_data_bytes)
+            .map_err(|_| MdocEncodingError::SerializationError)?;
+
+        Ok(AndroidCredentialData {
+            static_auth_data: static_auth_data_bytes,
+            element_values,
+        })
+    }
+
     /// Verify the issuer signature of this mdoc credential.
     ///
     /// This method extracts the X5Chain from the issuer_auth header, validates it
-    /// against the provided trust anchors, and verifies the COSE_Sign1 signature.
+    /// against the provided trust anchors, and verifies the COSE_Sign1 signature. The
+    /// digest of each element is then checked against the signed MSO, as by
+    /// [Mdoc::check_digests], so that a tampered element value fails verification.
     ///
     /// # Arguments
-    /// * `trust_anchors` - Optional list of PEM-encoded trust anchor certificates.
+    /// * `trust_anchors` - Optional list of PEM-encoded trust anchor certificates, or
+    ///   of JSON serialized `PemTrustAnchor`s as taken by
+    ///   [crate::mdl::reader::verify_oid4vp_response].
     ///   If not provided, X5Chain validation is skipped but signature verification
     ///   is still performed using the certificate in the X5Chain.
     /// * `use_intermediate_chaining` - If true, the verifier will attempt to build a trust path
     ///   using intermediate certificates found in the X5Chain header. If false, only the
     ///   certificates explicitly provided in `trust_anchors` are trusted.
+    /// * `trust_anchor_purpose` - The purpose the PEM trust anchors are trusted for,
+    ///   IACA by default.
     ///
     /// # Returns
     /// * `Ok(IssuerVerificationResult)` - The verification result with verified status
-    ///   and optional common name from the issuer certificate.
+    ///   and optional common name from the issuer certificate. `verified` is false when
+    ///   an element does not match its digest, listed in `digest_mismatches`.
     /// * `Err(MdocVerificationError)` - If verification fails due to missing/invalid
     ///   X5Chain or signature verification failure.
+    #[uniffi::method(default(trust_anchor_purpose = None))]
     pub fn verify_issuer_signature(
         &self,
         trust_anchors: Option<Vec<String>>,
         use_intermediate_chaining: bool,
+        trust_anchor_purpose: Option<TrustAnchorPurpose>,
     ) -> Result<IssuerVerificationResult, MdocVerificationError> {
         // 1. Extract X5Chain from issuer_auth unprotected header
         let x5chain_cbor = self
-            .inner
-            .issuer_auth
-            .inner
-            .unprotected
-            .rest
-            .iter()
-            .find(|(label, _)| label == &Label::Int(X5CHAIN_COSE_HEADER_LABEL))
-            .map(|(_, value)| value.to_owned())
+            .x5chain_cbor()
             .ok_or(MdocVerificationError::X5ChainMissing)?;
 
         let x5chain = X5Chain::from_cbor(x5chain_cbor.clone())
@@ -380,19 +658,18 @@ impl Mdoc {
 
         // 3. If trust anchors are provided, validate the X5Chain against them
         if let Some(anchors) = trust_anchors.filter(|a| !a.is_empty()) {
-            let mut pem_anchors: Vec<PemTrustAnchor> = anchors
+            let purpose = trust_anchor_purpose.unwrap_or_default();
+            let mut pem_anchors = anchors
                 .iter()
-                .map(|cert_pem| PemTrustAnchor {
-                    certificate_pem: cert_pem.clone(),
-                    purpose: TrustPurpose::Iaca,
-                })
-                .collect();
+                .map(|anchor| pem_trust_anchor(anchor, purpose))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(MdocVerificationError::TrustAnchorRegistryError)?;
 
             if use_intermediate_chaining {
                 // Parse roots from provided anchors
-                let trusted_certs: Vec<Certificate> = anchors
+                let trusted_certs: Vec<Certificate> = pem_anchors
                     .iter()
-                    .filter_map(|pem| Certificate::from_pem(pem).ok())
+                    .filter_map(|anchor| Certificate::from_pem(&anchor.certificate_pem).ok())
                     .collect();
 
                 // Build trust chain by discovering intermediate CAs
@@ -421,46 +698,195 @@ impl Mdoc {
         // 4. Build IssuerSigned from the Document for verification
         // The issuer_authentication function expects IssuerSigned which contains
         // the issuer_auth (COSE_Sign1) and namespaces
-        let namespaces_map = self
-            .inner
+        let issuer_signed = self.issuer_signed().ok_or_else(|| {
+            MdocVerificationError::IssuerAuthFailed(
+                "Internal error: Empty namespace elements".to_string(),
+            )
+        })?;
+
+        // 5. Verify issuer signature
+        let eddsa = issuer_signed.issuer_auth.inner.protected.header.alg
+            == Some(coset::RegisteredLabelWithPrivate::Assigned(
+                coset::iana::Algorithm::EdDSA,
+            ));
+        if eddsa {
+            verify_eddsa_issuer_auth(&x5chain_cbor, &issuer_signed.issuer_auth.inner)
+                .map_err(MdocVerificationError::IssuerAuthFailed)?;
+        } else {
+            issuer_authentication(x5chain, &issuer_signed)
+                .map_err(|e| MdocVerificationError::IssuerAuthFailed(format!("{:?}", e)))?;
+        }
+
+        // 6. Check the elements against the value digests of the MSO the signature was
+        // verified over, rather than the decoded copy kept alongside it
+        let signed_mso: Tag24<Mso> = issuer_signed
+            .issuer_auth
+            .payload
+            .as_deref()
+            .ok_or_else(|| {
+                MdocVerificationError::IssuerAuthFailed("The issuerAuth has no payload".to_string())
+            })
+            .and_then(|payload| {
+                isomdl::cbor::from_slice(payload).map_err(|e| {
+                    MdocVerificationError::IssuerAuthFailed(format!(
+                        "The issuerAuth payload is not an MSO: {e:?}"
+                    ))
+                })
+            })?;
+        let digest_mismatches = check_value_digests(&self.inner, signed_mso.as_ref());
+        let error = (!digest_mismatches.is_empty()).then(|| {
+            let elements = digest_mismatches
+                .iter()
+                .map(|mismatch| format!("{}/{}", mismatch.namespace, mismatch.identifier))
+                .collect::<Vec<_>>();
+            format!(
+                "Elements do not match their digest: {}",
+                elements.join(", ")
+            )
+        });
+        Ok(IssuerVerificationResult {
+            verified: digest_mismatches.is_empty(),
+            common_name,
+            error,
+            digest_mismatches,
+        })
+    }
+
+    /// Verify a signature made by the device key of this mdoc outside of a presentation,
+    /// as proof of possession of the key, for backends receiving an mdoc together with a
+    /// fresh device signature.
+    ///
+    /// `signature` is that of `payload` as returned by a
+    /// [DeviceSigner](crate::mdl::holder::DeviceSigner): ECDSA, raw `r || s` or DER
+    /// encoded, or Ed25519.
+    pub fn verify_device_signature(
+        &self,
+        payload: Vec<u8>,
+        signature: Vec<u8>,
+    ) -> Result<(), MdocVerificationError> {
+        verify_device_key_signature(
+            &self.inner.mso.device_key_info.device_key,
+            &payload,
+            &signature,
+        )
+        .map_err(MdocVerificationError::DeviceSignatureInvalid)
+    }
+
+    /// Verify the deviceSignature of a presentation of this mdoc made without device-signed
+    /// elements: a COSE_Sign1 over the DeviceAuthentication of the CBOR encoded
+    /// `session_transcript`, with its payload detached or included. Its `alg` must be
+    /// that of the device key.
+    pub fn verify_device_authentication(
+        &self,
+        session_transcript: Vec<u8>,
+        device_signature: Vec<u8>,
+    ) -> Result<(), MdocVerificationError> {
+        use coset::{CborSerializable, TaggedCborSerializable};
+
+        let invalid = |value: String| MdocVerificationError::DeviceSignatureInvalid(value);
+        let session_transcript: Value = from_reader(session_transcript.as_slice())
+            .map_err(|e| invalid(format!("Invalid session transcript: {e}")))?;
+        let device_signature = coset::CoseSign1::from_tagged_slice(&device_signature)
+            .or_else(|_| coset::CoseSign1::from_slice(&device_signature))
+            .map_err(|e| invalid(format!("Invalid COSE_Sign1: {e:?}")))?;
+
+        let mut device_name_spaces = Vec::new();
+        ciborium::into_writer(&Value::Map(vec![]), &mut device_name_spaces)
+            .map_err(|e| invalid(format!("Could not encode DeviceNameSpaces: {e}")))?;
+        verify_device_auth_signature(
+            &self.inner.mso.device_key_info.device_key,
+            session_transcript,
+            &self.inner.mso.doc_type,
+            Value::Tag(24, Box::new(Value::Bytes(device_name_spaces))),
+            &device_signature,
+        )
+        .map_err(invalid)
+    }
+
+    /// Recompute the value digest of every issuer-signed element and compare it
+    /// against the digests recorded in the MSO.
+    ///
+    /// Returns the elements whose digest is missing from, or does not match, the MSO.
+    /// An empty list means every element is covered by the issuer signature.
+    ///
+    /// With the `parallel` feature enabled, digests are computed on the rayon thread
+    /// pool, which noticeably reduces latency for large mDL + AAMVA credentials.
+    pub fn check_digests(&self) -> Vec<DigestMismatch> {
+        check_value_digests(&self.inner, &self.inner.mso)
+    }
+
+    /// Report which of the elements requested by a reader this mdoc holds, so a wallet
+    /// holding several credentials can pick the one to present.
+    pub fn matches_request(&self, items_request: ItemsRequest) -> MatchResult {
+        let doc_type_matches = items_request.doc_type == self.inner.mso.doc_type;
+        let mut elements: Vec<ElementAvailability> = items_request
             .namespaces
-            .clone()
-            .into_inner()
             .into_iter()
-            .map(|(ns, elements)| {
-                let inner_elements = elements
-                    .into_inner()
-                    .into_values()
-                    .collect::<Vec<_>>()
-                    .try_into()
-                    .map_err(|_| {
-                        MdocVerificationError::IssuerAuthFailed(
-                            "Internal error: Empty inner namespace elements".to_string(),
-                        )
-                    })?;
-                Ok((ns, inner_elements))
+            .flat_map(|(namespace, requested)| {
+                let held = self
+                    .inner
+                    .namespaces
+                    .get(&namespace)
+                    .filter(|_| doc_type_matches);
+                requested
+                    .into_keys()
+                    .map(move |element_identifier| ElementAvailability {
+                        available: held.is_some_and(|held| held.contains_key(&element_identifier)),
+                        namespace: namespace.clone(),
+                        element_identifier,
+                    })
             })
-            .collect::<Result<std::collections::BTreeMap<_, _>, MdocVerificationError>>()?;
+            .collect();
+        elements.sort_by(|a, b| {
+            (&a.namespace, &a.element_identifier).cmp(&(&b.namespace, &b.element_identifier))
+        });
+
+        MatchResult {
+            doc_type_matches,
+            satisfied: doc_type_matches && elements.iter().all(|element| element.available),
+            elements,
+        }
+    }
 
-        let namespaces = namespaces_map.try_into().map_err(|_| {
-            MdocVerificationError::IssuerAuthFailed(
-                "Internal error: Empty namespaces map".to_string(),
-            )
-        })?;
+    /// A summary of what the issuer signed into this mdoc, for issuer backends to log
+    /// and audit issuance without re-parsing the credential.
+    pub fn issuance_report(&self) -> IssuanceReport {
+        let mso = &self.inner.mso;
+        let validity = &mso.validity_info;
 
-        let issuer_signed = isomdl::definitions::IssuerSigned {
-            namespaces: Some(namespaces),
-            issuer_auth: self.inner.issuer_auth.clone(),
-        };
+        let x5chain_common_names = self
+            .x5chain_cbor()
+            .map(|x5chain| {
+                x5chain_certificates(&x5chain)
+                    .iter()
+                    .map(|cert| {
+                        let subject = &cert.tbs_certificate.subject;
+                        common_name(subject).unwrap_or_else(|| subject.to_string())
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
 
-        // 5. Verify issuer signature
-        match issuer_authentication(x5chain, &issuer_signed) {
-            Ok(_) => Ok(IssuerVerificationResult {
-                verified: true,
-                common_name,
-                error: None,
-            }),
-            Err(e) => Err(MdocVerificationError::IssuerAuthFailed(format!("{:?}", e))),
+        IssuanceReport {
+            doc_type: mso.doc_type.clone(),
+            value_digest_counts: mso
+                .value_digests
+                .iter()
+                .map(|(namespace, digests)| (namespace.clone(), digests.len() as u32))
+                .collect(),
+            digest_algorithm: match mso.digest_algorithm {
+                DigestAlgorithm::SHA256 => "SHA-256",
+                DigestAlgorithm::SHA384 => "SHA-384",
+                DigestAlgorithm::SHA512 => "SHA-512",
+            }
+            .to_string(),
+            signed: validity.signed.into(),
+            valid_from: validity.valid_from.into(),
+            valid_until: validity.valid_until.into(),
+            expected_update: validity.expected_update.map(SystemTime::from),
+            x5chain_common_names,
+            device_key_thumbprint: cose_key_thumbprint(&mso.device_key_info.device_key),
+            encoded_size: encoded_size(&self.inner),
         }
     }
 }
@@ -470,6 +896,60 @@ impl Mdoc {
         &self.inner
     }
 
+    /// Whether `time` lies within the validity period of the MSO.
+    fn is_valid_at(&self, time: SystemTime) -> bool {
+        let validity = &self.inner.mso.validity_info;
+        SystemTime::from(validity.valid_from) <= time
+            && time <= SystemTime::from(validity.valid_until)
+    }
+
+    /// A builder for a new MSO over the data elements and device key of this mdoc.
+    fn reissue_builder(&self, options: &IssuanceOptions) -> Result<Builder, MdocInitError> {
+        let namespaces = self
+            .inner
+            .namespaces
+            .iter()
+            .map(|(namespace, elements)| {
+                let elements = elements
+                    .iter()
+                    .map(|(identifier, item)| {
+                        (identifier.clone(), item.as_ref().element_value.clone())
+                    })
+                    .collect();
+                (namespace.clone(), elements)
+            })
+            .collect();
+
+        let builder = prepare_builder(
+            self.inner.mso.device_key_info.device_key.clone(),
+            namespaces,
+            self.inner.mso.doc_type.clone(),
+            options,
+        )?;
+
+        Ok(match options.key_info {
+            Some(_) => builder,
+            None => builder.device_key_info(self.inner.mso.device_key_info.clone()),
+        })
+    }
+
+    /// The x5chain header of the issuerAuth, if present.
+    fn x5chain_cbor(&self) -> Option<Value> {
+        self.inner
+            .issuer_auth
+            .inner
+            .unprotected
+            .rest
+            .iter()
+            .find(|(label, _)| label == &Label::Int(X5CHAIN_COSE_HEADER_LABEL))
+            .map(|(_, value)| value.to_owned())
+    }
+
+    /// The IssuerSigned structure of this mdoc, `None` if a namespace has no elements.
+    pub(crate) fn issuer_signed(&self) -> Option<IssuerSigned> {
+        issuer_signed(&self.inner)
+    }
+
     pub(crate) fn new_from_parts(inner: Document, key_alias: KeyAlias) -> Self {
         Self { inner, key_alias }
     }
@@ -519,7 +999,56 @@ impl Mdoc {
     }
 }
 
-#[derive(Debug, uniffi::Error, thiserror::Error)]
+/// Pick the credential to present for each docRequest of a reader's request, in the
+/// order of the request: of the mdocs with the requested docType that are currently
+/// within the validity period of their MSO, one holding every requested element, or
+/// else the one holding the most, the first given on a tie.
+#[uniffi::export]
+pub fn select_credentials(
+    items_requests: Vec<ItemsRequest>,
+    mdocs: Vec<Arc<Mdoc>>,
+) -> Vec<CredentialSelection> {
+    let now = SystemTime::now();
+    let mdocs: Vec<_> = mdocs
+        .into_iter()
+        .filter(|mdoc| mdoc.is_valid_at(now))
+        .collect();
+    items_requests
+        .into_iter()
+        .map(|items_request| {
+            let doc_type = items_request.doc_type.clone();
+            let best = mdocs
+                .iter()
+                .map(|mdoc| (mdoc, mdoc.matches_request(items_request.clone())))
+                .filter(|(_, result)| result.doc_type_matches)
+                .map(|(mdoc, result)| {
+                    let available = result.elements.iter().filter(|e| e.available).count();
+                    (mdoc, result, available)
+                })
+                .reduce(|best, candidate| {
+                    if (candidate.1.satisfied, candidate.2) > (best.1.satisfied, best.2) {
+                        candidate
+                    } else {
+                        best
+                    }
+                });
+            match best {
+                Some((mdoc, result, _)) => CredentialSelection {
+                    doc_type,
+                    mdoc: Some(mdoc.clone()),
+                    match_result: Some(result),
+                },
+                None => CredentialSelection {
+                    doc_type,
+                    mdoc: None,
+                    match_result: None,
+                },
+            }
+        })
+        .collect()
+}
+
+#[derive(Debug, uniffi::Error, thiserror::Error)]
 pub enum MdocInitError {
     #[error("failed to decode Document from CBOR: {0}")]
     DocumentCborDecoding(String),
@@ -541,6 +1070,49 @@ pub enum MdocInitError {
     InvalidJwk,
     #[error("failed to construct mdoc")]
     GeneralConstructionError,
+    #[error("failed to decode Android credential data: {0}")]
+    AndroidCredentialDataDecoding(String),
+    #[error("invalid document signer: {0}")]
+    InvalidDocumentSigner(String),
+    #[error("invalid issuance options: {0}")]
+    InvalidOptions(String),
+    #[error("invalid validity: {0}")]
+    InvalidValidity(String),
+    #[error("failed to encode issued mdoc: {0}")]
+    OutputEncoding(String),
+    #[error("invalid data elements: {}", join_field_errors(.0))]
+    InvalidElements(Vec<FieldError>),
+    #[error(
+        "encoded credential is {size} bytes, over the limit of {max_encoded_size}, largest elements: {}",
+        largest_elements.join(", ")
+    )]
+    EncodedSizeExceeded {
+        size: u64,
+        max_encoded_size: u64,
+        largest_elements: Vec<String>,
+    },
+    #[error("data element {namespace}/{identifier} is given more than once")]
+    DuplicateElement {
+        namespace: String,
+        identifier: String,
+    },
+}
+
+/// A data element that is missing or malformed in the JSON items given for issuance.
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Record)]
+pub struct FieldError {
+    pub namespace: String,
+    /// Identifier of the data element, empty if the items are not a JSON object.
+    pub identifier: String,
+    pub reason: String,
+}
+
+fn join_field_errors(errors: &[FieldError]) -> String {
+    errors
+        .iter()
+        .map(|e| format!("{}/{}: {}", e.namespace, e.identifier, e.reason))
+        .collect::<Vec<_>>()
+        .join("; ")
 }
 
 #[derive(Debug, uniffi::Error, thiserror::Error)]
@@ -549,6 +1121,8 @@ pub enum MdocEncodingError {
     DocumentCborEncoding,
     #[error("failed to serialize mdoc")]
     SerializationError,
+    #[error("the output format is binary, encode the mdoc to bytes instead")]
+    BinaryOutputFormat,
 }
 
 /// Error type for issuer signature verification.
@@ -564,344 +1138,2865 @@ pub enum MdocVerificationError {
     X5ChainValidationFailed(String),
     #[error("Issuer signature verification failed: {0}")]
     IssuerAuthFailed(String),
+    #[error("Device signature verification failed: {0}")]
+    DeviceSignatureInvalid(String),
 }
 
 /// Result of issuer signature verification.
 #[derive(Debug, Clone, uniffi::Record)]
 pub struct IssuerVerificationResult {
-    /// Whether the issuer signature was successfully verified.
+    /// Whether the issuer signature was successfully verified. The x5chain is only
+    /// validated against trust anchors when some were given to
+    /// [Mdoc::verify_issuer_signature], so `true` without them means the document is
+    /// signed by its own certificate, not that the issuer is trusted.
     pub verified: bool,
     /// Common name from the issuer certificate, if available.
     pub common_name: Option<String>,
     /// Error message if verification failed.
     pub error: Option<String>,
+    /// The elements whose value does not match its digest in the MSO.
+    pub digest_mismatches: Vec<DigestMismatch>,
 }
 
-fn prepare_builder(
-    holder_key: PublicKey,
-    namespaces: BTreeMap<String, BTreeMap<String, ciborium::Value>>,
-    doc_type: String,
-) -> Result<Builder> {
-    let validity_info = ValidityInfo {
-        signed: OffsetDateTime::now_utc(),
-        valid_from: OffsetDateTime::now_utc(),
-        // mDL valid for thirty days.
-        valid_until: OffsetDateTime::now_utc() + Duration::from_secs(60 * 60 * 24 * 30),
-        expected_update: None,
-    };
+const ANDROID_DIGEST_ID_MAPPING: &str = "digestIdMapping";
+const ANDROID_ISSUER_AUTH: &str = "issuerAuth";
 
-    let digest_alg = DigestAlgorithm::SHA256;
+/// Credential data in the layout used by the Android Jetpack Identity Credential library.
+#[derive(Debug, Clone, uniffi::Record)]
+pub struct AndroidCredentialData {
+    /// CBOR-encoded `StaticAuthData`: the digest ID mapping (IssuerSignedItemBytes with
+    /// null element values) and the issuerAuth COSE_Sign1.
+    pub static_auth_data: Vec<u8>,
+    /// CBOR-encoded element values, keyed by namespace and element identifier.
+    pub element_values: HashMap<String, HashMap<String, Vec<u8>>>,
+}
 
-    let ec = holder_key.to_encoded_point(false);
-    let x = ec.x().context("EC missing X coordinate")?.to_vec();
-    let y = EC2Y::Value(ec.y().context("EC missing X coordinate")?.to_vec());
-    let device_key = CoseKey::EC2 {
-        crv: EC2Curve::P256,
-        x,
-        y,
+/// Rebuild a CBOR-encoded IssuerSigned from Android `StaticAuthData` by restoring each
+/// element value into its IssuerSignedItem.
+fn android_static_auth_data_to_issuer_signed(
+    static_auth_data: &[u8],
+    element_values: &HashMap<String, HashMap<String, Vec<u8>>>,
+) -> Result<Vec<u8>, MdocInitError> {
+    let decoding_error = |reason: &str| MdocInitError::AndroidCredentialDataDecoding(reason.into());
+    let text_key = |key: &Value, expected: &str| matches!(key, Value::Text(k) if k == expected);
+
+    let Value::Map(entries) = from_reader(static_auth_data)
+        .map_err(|_| decoding_error("StaticAuthData is not valid CBOR"))?
+    else {
+        return Err(decoding_error("StaticAuthData is not a map"));
     };
-    let device_key_info = DeviceKeyInfo {
-        device_key,
-        key_authorizations: None,
-        key_info: None,
+
+    let mut digest_id_mapping = None;
+    let mut issuer_auth = None;
+    for (key, value) in entries {
+        if text_key(&key, ANDROID_DIGEST_ID_MAPPING) {
+            digest_id_mapping = Some(value);
+        } else if text_key(&key, ANDROID_ISSUER_AUTH) {
+            issuer_auth = Some(value);
+        }
+    }
+    let Some(Value::Map(digest_id_mapping)) = digest_id_mapping else {
+        return Err(decoding_error("digestIdMapping missing or not a map"));
     };
+    let issuer_auth = issuer_auth.ok_or_else(|| decoding_error("issuerAuth missing"))?;
 
-    Ok(isomdl::issuance::Mdoc::builder()
-        .doc_type(doc_type)
-        .namespaces(namespaces)
-        .validity_info(validity_info)
-        .digest_algorithm(digest_alg)
-        .device_key_info(device_key_info))
-}
+    let mut namespaces = Vec::new();
+    for (namespace, items) in digest_id_mapping {
+        let (Value::Text(namespace), Value::Array(items)) = (namespace, items) else {
+            return Err(decoding_error("malformed digestIdMapping entry"));
+        };
+        let values = element_values.get(&namespace);
+
+        let mut restored = Vec::new();
+        for item in items {
+            let Value::Tag(24, tagged) = item else {
+                return Err(decoding_error("IssuerSignedItemBytes is not tagged"));
+            };
+            let Value::Bytes(item_bytes) = *tagged else {
+                return Err(decoding_error("IssuerSignedItemBytes is not a byte string"));
+            };
+            let Value::Map(mut fields) = from_reader(item_bytes.as_slice())
+                .map_err(|_| decoding_error("IssuerSignedItem is not valid CBOR"))?
+            else {
+                return Err(decoding_error("IssuerSignedItem is not a map"));
+            };
+
+            let identifier = fields
+                .iter()
+                .find_map(|(key, value)| match value {
+                    Value::Text(id) if text_key(key, "elementIdentifier") => Some(id.clone()),
+                    _ => None,
+                })
+                .ok_or_else(|| decoding_error("IssuerSignedItem has no elementIdentifier"))?;
+            let value_bytes = values.and_then(|v| v.get(&identifier)).ok_or_else(|| {
+                MdocInitError::AndroidCredentialDataDecoding(format!(
+                    "no value supplied for {namespace}/{identifier}"
+                ))
+            })?;
+            let value: Value = from_reader(value_bytes.as_slice()).map_err(|_| {
+                MdocInitError::AndroidCredentialDataDecoding(format!(
+                    "value for {namespace}/{identifier} is not valid CBOR"
+                ))
+            })?;
 
-fn convert_namespaces(
-    input: HashMap<String, HashMap<String, Vec<u8>>>,
-) -> Result<BTreeMap<String, BTreeMap<String, Value>>, MdocInitError> {
-    let mut outer = BTreeMap::new();
+            match fields
+                .iter_mut()
+                .find(|(key, _)| text_key(key, "elementValue"))
+            {
+                Some((_, element_value)) => *element_value = value,
+                None => fields.push((Value::Text("elementValue".to_string()), value)),
+            }
 
-    for (namespace, inner_map) in input {
-        let mut inner_btree = BTreeMap::new();
-        for (key, vec_bytes) in inner_map {
-            let mut cursor = Cursor::new(vec_bytes);
-            let value: Value = from_reader(&mut cursor).map_err(|_e| {
-                MdocInitError::DocumentCborDecoding("Error decoding CBOR value".to_owned())
-            })?;
-            inner_btree.insert(key, value);
+            let mut item_bytes = Vec::new();
+            ciborium::into_writer(&Value::Map(fields), &mut item_bytes)
+                .map_err(|_| decoding_error("failed to encode IssuerSignedItem"))?;
+            restored.push(Value::Tag(24, Box::new(Value::Bytes(item_bytes))));
         }
-        outer.insert(namespace, inner_btree);
+        namespaces.push((Value::Text(namespace), Value::Array(restored)));
     }
 
-    Ok(outer)
+    let issuer_signed = Value::Map(vec![
+        (
+            Value::Text("nameSpaces".to_string()),
+            Value::Map(namespaces),
+        ),
+        (Value::Text("issuerAuth".to_string()), issuer_auth),
+    ]);
+    let mut issuer_signed_bytes = Vec::new();
+    ciborium::into_writer(&issuer_signed, &mut issuer_signed_bytes)
+        .map_err(|_| decoding_error("failed to encode IssuerSigned"))?;
+    Ok(issuer_signed_bytes)
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use base64::Engine;
-    use p256::elliptic_curve::rand_core::OsRng;
-    use p256::{
-        ecdsa::SigningKey,
-        pkcs8::{EncodePrivateKey, LineEnding},
-    };
-    use std::time::Duration;
-    use x509_cert::{
-        builder::{Builder, CertificateBuilder, Profile},
-        der::EncodePem,
-        name::Name,
-        serial_number::SerialNumber,
-        spki::SubjectPublicKeyInfoOwned,
-        time::Validity,
-    };
+/// An issuer-signed element whose value digest could not be matched against the MSO.
+#[derive(Debug, Clone, PartialEq, uniffi::Record)]
+pub struct DigestMismatch {
+    pub namespace: String,
+    pub identifier: String,
+    pub reason: String,
+}
 
-    #[test]
-    fn test_create_and_sign_mdl() {
-        // 1. Generate Issuer Key
-        let issuer_key = SigningKey::random(&mut OsRng);
-        let issuer_key_pem = issuer_key.to_pkcs8_pem(LineEnding::LF).unwrap().to_string();
+/// Checks the issuer-signed elements of a document against the value digests of `mso`.
+pub(crate) fn check_value_digests(document: &Document, mso: &Mso) -> Vec<DigestMismatch> {
+    let items = document
+        .namespaces
+        .iter()
+        .flat_map(|(namespace, elements)| elements.values().map(move |item| (namespace, item)))
+        .collect();
+    check_item_digests(items, mso)
+}
 
-        // 2. Generate Issuer Certificate (Self-signed for simplicity)
-        let subject_name: Name = "CN=Test Issuer".parse().unwrap();
-        let serial_number = SerialNumber::from(1u64);
-        let validity = Validity::from_now(Duration::from_secs(3600)).unwrap();
+/// Checks issuer-signed elements, each with its namespace, against the value digests of
+/// `mso`, on the rayon thread pool with the `parallel` feature.
+pub(crate) fn check_item_digests(
+    items: Vec<(&String, &Tag24<IssuerSignedItem>)>,
+    mso: &Mso,
+) -> Vec<DigestMismatch> {
+    #[cfg(feature = "parallel")]
+    use rayon::prelude::*;
+
+    #[cfg(feature = "parallel")]
+    let items = items.par_iter();
+    #[cfg(not(feature = "parallel"))]
+    let items = items.iter();
+
+    items
+        .filter_map(|(namespace, item)| check_item_digest(mso, namespace, item).err())
+        .collect()
+}
 
-        // Use clone() to ensure we have the value, not a reference, as expected by from_key
-        let spki = SubjectPublicKeyInfoOwned::from_key(issuer_key.verifying_key().clone()).unwrap();
+/// The digest of an element as recorded in the MSO, computed over the tagged
+/// IssuerSignedItemBytes, #6.24(bstr .cbor IssuerSignedItem).
+fn value_digest(algorithm: &DigestAlgorithm, item: &Tag24<IssuerSignedItem>) -> Option<Vec<u8>> {
+    let encoded = isomdl::cbor::to_vec(item).ok()?;
+    Some(match algorithm {
+        DigestAlgorithm::SHA256 => Sha256::digest(&encoded).to_vec(),
+        DigestAlgorithm::SHA384 => Sha384::digest(&encoded).to_vec(),
+        DigestAlgorithm::SHA512 => Sha512::digest(&encoded).to_vec(),
+    })
+}
 
-        let builder = CertificateBuilder::new(
-            Profile::Root,
-            serial_number,
-            validity,
-            subject_name,
-            spki,
-            &issuer_key,
-        )
-        .unwrap();
+/// Verifies an EdDSA issuerAuth against the Ed25519 key of the end-entity certificate of
+/// its x5chain, as isomdl only verifies ECDSA issuer signatures.
+fn verify_eddsa_issuer_auth(x5chain: &Value, issuer_auth: &coset::CoseSign1) -> Result<(), String> {
+    use ed25519_dalek::{Verifier, pkcs8::DecodePublicKey};
+    use x509_cert::der::Encode;
+
+    let der = match x5chain {
+        Value::Bytes(der) => der,
+        Value::Array(certificates) => match certificates.first() {
+            Some(Value::Bytes(der)) => der,
+            _ => return Err("The x5chain has no end-entity certificate".to_string()),
+        },
+        _ => return Err("Invalid x5chain".to_string()),
+    };
+    let certificate =
+        Certificate::from_der(der).map_err(|e| format!("Invalid end-entity certificate: {e}"))?;
+    let spki = certificate
+        .tbs_certificate
+        .subject_public_key_info
+        .to_der()
+        .map_err(|e| format!("Invalid end-entity public key: {e}"))?;
+    let key = ed25519_dalek::VerifyingKey::from_public_key_der(&spki)
+        .map_err(|e| format!("The end-entity certificate has no Ed25519 key: {e}"))?;
+    issuer_auth.verify_signature(b"", |signature, tbs| {
+        let signature = ed25519_dalek::Signature::from_slice(signature)
+            .map_err(|e| format!("Invalid signature: {e}"))?;
+        key.verify(tbs, &signature)
+            .map_err(|e| format!("Signature verification failed: {e}"))
+    })
+}
 
-        let cert = builder.build::<p256::ecdsa::DerSignature>().unwrap();
-        let cert_pem = cert.to_pem(LineEnding::LF).unwrap();
+/// The IssuerSigned structure of a document, `None` if a namespace has no elements.
+fn issuer_signed(document: &Document) -> Option<IssuerSigned> {
+    let namespaces = document
+        .namespaces
+        .clone()
+        .into_inner()
+        .into_iter()
+        .map(|(ns, elements)| {
+            let elements = elements.into_inner().into_values().collect::<Vec<_>>();
+            Some((ns, elements.try_into().ok()?))
+        })
+        .collect::<Option<BTreeMap<_, _>>>()?
+        .try_into()
+        .ok()?;
+
+    Some(IssuerSigned {
+        namespaces: Some(namespaces),
+        issuer_auth: document.issuer_auth.clone(),
+    })
+}
 
-        // 3. Generate Holder Key (JWK)
-        let holder_key = SigningKey::random(&mut OsRng);
-        let point = holder_key.verifying_key().to_encoded_point(false);
-        let x = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(point.x().unwrap());
-        let y = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(point.y().unwrap());
+/// The size of the CBOR-encoded IssuerSigned structure of a document, as transferred to
+/// the wallet and, for each presentation, to the reader.
+fn encoded_size(document: &Document) -> Option<u64> {
+    let bytes = isomdl::cbor::to_vec(&issuer_signed(document)?).ok()?;
+    Some(bytes.len() as u64)
+}
 
-        let holder_jwk = serde_json::json!({
-            "kty": "EC",
-            "crv": "P-256",
-            "x": x,
-            "y": y
-        })
-        .to_string();
+/// Fail if the encoded document exceeds `max_encoded_size` bytes, naming its largest
+/// elements since an oversized portrait is the usual cause.
+fn check_encoded_size(document: &Document, max_encoded_size: u64) -> Result<(), MdocInitError> {
+    let size = encoded_size(document)
+        .ok_or_else(|| MdocInitError::OutputEncoding("failed to encode IssuerSigned".into()))?;
+    if size <= max_encoded_size {
+        return Ok(());
+    }
 
-        // 4. Sample Data
-        let mdl_items = serde_json::json!({
-            "family_name": "Doe",
-            "given_name": "John",
-            "birth_date": "1990-01-01",
-            "issue_date": "2023-01-01",
-            "expiry_date": "2028-01-01",
-            "issuing_country": "US",
-            "issuing_authority": "DMV",
-            "document_number": "123456789",
-            "portrait": "SGVsbG8gV29ybGQ=",
-            "driving_privileges": [
-                {
-                    "vehicle_category_code": "B",
-                    "issue_date": "2023-01-01",
-                    "expiry_date": "2028-01-01"
-                }
-            ],
-            "un_distinguishing_sign": "USA"
+    let mut elements: Vec<(usize, String)> = document
+        .namespaces
+        .iter()
+        .flat_map(|(namespace, elements)| {
+            elements.iter().map(move |(identifier, item)| {
+                let item_size = isomdl::cbor::to_vec(item).map_or(0, |bytes| bytes.len());
+                (item_size, format!("{namespace}/{identifier}"))
+            })
         })
-        .to_string();
+        .collect();
+    elements.sort_by_key(|(item_size, _)| std::cmp::Reverse(*item_size));
 
-        // 5. Call function
-        let result =
-            Mdoc::create_and_sign_mdl(mdl_items, None, holder_jwk, cert_pem, issuer_key_pem);
+    Err(MdocInitError::EncodedSizeExceeded {
+        size,
+        max_encoded_size,
+        largest_elements: elements
+            .into_iter()
+            .take(LARGEST_ELEMENTS_REPORTED)
+            .map(|(item_size, element)| format!("{element} ({item_size} bytes)"))
+            .collect(),
+    })
+}
 
-        let mdoc = result.unwrap();
+fn check_item_digest(
+    mso: &Mso,
+    namespace: &str,
+    item: &Tag24<IssuerSignedItem>,
+) -> Result<(), DigestMismatch> {
+    let element = item.as_ref();
+    let mismatch = |reason: &str| DigestMismatch {
+        namespace: namespace.to_string(),
+        identifier: element.element_identifier.clone(),
+        reason: reason.to_string(),
+    };
 
-        // 6. Verify Output
-        assert_eq!(mdoc.doctype(), "org.iso.18013.5.1.mDL");
+    let expected = mso
+        .value_digests
+        .get(namespace)
+        .and_then(|digests| digests.get(&element.digest_id))
+        .ok_or_else(|| mismatch("digest ID not present in the MSO"))?;
 
-        let details = mdoc.details();
-        let mdl_namespace = Namespace("org.iso.18013.5.1".to_string());
-        let elements = details
-            .get(&mdl_namespace)
-            .expect("mDL namespace not found");
+    let actual = value_digest(&mso.digest_algorithm, item)
+        .ok_or_else(|| mismatch("failed to encode IssuerSignedItemBytes"))?;
 
-        let family_name = elements
-            .iter()
-            .find(|e| e.identifier == "family_name")
-            .expect("family_name not found");
-        assert!(family_name.value.as_ref().unwrap().contains("Doe"));
+    if actual.as_slice() != expected.as_ref() {
+        return Err(mismatch("value digest does not match the MSO"));
+    }
+    Ok(())
+}
 
-        let given_name = elements
-            .iter()
-            .find(|e| e.identifier == "given_name")
-            .expect("given_name not found");
-        assert!(given_name.value.as_ref().unwrap().contains("John"));
+/// Whether an mdoc can satisfy a reader's request, as returned by [Mdoc::matches_request].
+#[derive(Debug, Clone, PartialEq, uniffi::Record)]
+pub struct MatchResult {
+    /// Whether the mdoc has the requested docType.
+    pub doc_type_matches: bool,
+    /// Whether every requested element is available.
+    pub satisfied: bool,
+    /// The requested elements, sorted by namespace and identifier.
+    pub elements: Vec<ElementAvailability>,
+}
 
-        let doc_num = elements
-            .iter()
-            .find(|e| e.identifier == "document_number")
-            .expect("document_number not found");
-        assert!(doc_num.value.as_ref().unwrap().contains("123456789"));
-    }
+/// Whether a requested element is held by the mdoc.
+#[derive(Debug, Clone, PartialEq, uniffi::Record)]
+pub struct ElementAvailability {
+    pub namespace: String,
+    pub element_identifier: String,
+    pub available: bool,
+}
 
-    #[test]
-    fn test_verify_issuer_signature_valid() {
-        // 1. Generate Issuer Key
-        let issuer_key = SigningKey::random(&mut OsRng);
-        let issuer_key_pem = issuer_key.to_pkcs8_pem(LineEnding::LF).unwrap().to_string();
+/// The credential picked for a docRequest by [select_credentials].
+#[derive(uniffi::Record)]
+pub struct CredentialSelection {
+    /// The requested docType.
+    pub doc_type: String,
+    /// The best matching mdoc, `None` if no mdoc has the requested docType.
+    pub mdoc: Option<Arc<Mdoc>>,
+    /// The elements the picked mdoc holds of those requested.
+    pub match_result: Option<MatchResult>,
+}
 
-        // 2. Generate Issuer Certificate (Self-signed for simplicity)
-        let subject_name: Name = "CN=Test Issuer".parse().unwrap();
-        let serial_number = SerialNumber::from(1u64);
-        let validity = Validity::from_now(Duration::from_secs(3600)).unwrap();
+/// What an issuer signed into an mdoc, as returned by [Mdoc::issuance_report].
+#[derive(Debug, Clone, uniffi::Record)]
+pub struct IssuanceReport {
+    pub doc_type: String,
+    /// The number of value digests in the MSO for each namespace.
+    pub value_digest_counts: HashMap<String, u32>,
+    /// The MSO digest algorithm, for example `SHA-256`.
+    pub digest_algorithm: String,
+    pub signed: SystemTime,
+    pub valid_from: SystemTime,
+    pub valid_until: SystemTime,
+    pub expected_update: Option<SystemTime>,
+    /// Subject common names of the x5chain certificates, document signer first. The
+    /// full subject is used for certificates without a common name.
+    pub x5chain_common_names: Vec<String>,
+    /// The RFC 7638 JWK thumbprint (SHA-256, base64url) of the device key, `None` if
+    /// the key uses an unsupported curve.
+    pub device_key_thumbprint: Option<String>,
+    /// Size in bytes of the CBOR-encoded IssuerSigned structure, see
+    /// [IssuanceOptions::max_encoded_size].
+    pub encoded_size: Option<u64>,
+}
 
-        let spki = SubjectPublicKeyInfoOwned::from_key(issuer_key.verifying_key().clone()).unwrap();
+const MDL_DOC_TYPE: &str = "org.iso.18013.5.1.mDL";
+
+/// Issues mdocs with a document signer certificate and key provided by the caller.
+///
+/// Unlike [Mdoc::create_and_sign], no ephemeral document signer is generated: the
+/// provided certificate and key are used as-is, followed by the IACA chain in the
+/// x5chain.
+#[derive(uniffi::Object)]
+pub struct MdocIssuer {
+    ds_certificate: Certificate,
+    iaca_chain: Vec<Certificate>,
+    ds_key: IssuerSigningKey,
+}
 
-        let builder = CertificateBuilder::new(
-            Profile::Root,
-            serial_number,
-            validity,
-            subject_name,
-            spki,
-            &issuer_key,
-        )
-        .unwrap();
+#[uniffi::export]
+impl MdocIssuer {
+    #[uniffi::constructor]
+    /// Construct an issuer from a PEM encoded document signer certificate, its PKCS#8
+    /// PEM encoded private key, and the PEM encoded IACA chain (may be empty).
+    pub fn new(
+        ds_cert_pem: String,
+        ds_key_pem: String,
+        iaca_chain_pem: String,
+    ) -> Result<Arc<Self>, MdocInitError> {
+        let ds_certificate = Certificate::from_pem(&ds_cert_pem)
+            .map_err(|e| MdocInitError::InvalidDocumentSigner(e.to_string()))?;
+        let iaca_chain = if iaca_chain_pem.trim().is_empty() {
+            Vec::new()
+        } else {
+            parse_certificate_chain(&iaca_chain_pem)
+                .map_err(|e| MdocInitError::InvalidDocumentSigner(e.to_string()))?
+        };
 
-        let cert = builder.build::<p256::ecdsa::DerSignature>().unwrap();
-        let cert_pem = cert.to_pem(LineEnding::LF).unwrap();
+        Self::from_parts(ds_certificate, iaca_chain, &ds_key_pem)
+    }
 
-        // 3. Generate Holder Key (JWK)
-        let holder_key = SigningKey::random(&mut OsRng);
-        let point = holder_key.verifying_key().to_encoded_point(false);
-        let x = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(point.x().unwrap());
-        let y = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(point.y().unwrap());
+    #[uniffi::constructor]
+    /// Construct an issuer from a prebuilt x5chain, as DER encoded certificates starting
+    /// with the document signer certificate, and the PKCS#8 PEM encoded document signer
+    /// key. The certificates are placed in the x5chain header in the given order.
+    pub fn new_with_x5chain(
+        x5chain: Vec<Vec<u8>>,
+        ds_key_pem: String,
+    ) -> Result<Arc<Self>, MdocInitError> {
+        let mut certificates = x5chain
+            .iter()
+            .map(|der| Certificate::from_der(der))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| MdocInitError::InvalidDocumentSigner(e.to_string()))?;
+        if certificates.is_empty() {
+            return Err(MdocInitError::InvalidDocumentSigner(
+                "x5chain is empty".to_string(),
+            ));
+        }
+        let ds_certificate = certificates.remove(0);
 
-        let holder_jwk = serde_json::json!({
-            "kty": "EC",
-            "crv": "P-256",
-            "x": x,
-            "y": y
-        })
-        .to_string();
+        Self::from_parts(ds_certificate, certificates, &ds_key_pem)
+    }
 
-        // 4. Sample Data
-        let mdl_items = serde_json::json!({
-            "family_name": "Doe",
-            "given_name": "John",
-            "birth_date": "1990-01-01",
-            "issue_date": "2023-01-01",
-            "expiry_date": "2028-01-01",
-            "issuing_country": "US",
-            "issuing_authority": "DMV",
-            "document_number": "123456789",
-            "portrait": "SGVsbG8gV29ybGQ=",
-            "driving_privileges": [
-                {
-                    "vehicle_category_code": "B",
-                    "issue_date": "2023-01-01",
-                    "expiry_date": "2028-01-01"
-                }
-            ],
-            "un_distinguishing_sign": "USA"
-        })
-        .to_string();
+    /// Issue an mdoc of the given doc type, see [Mdoc::create_and_sign].
+    #[uniffi::method(default(options = None))]
+    pub fn issue(
+        &self,
+        doc_type: String,
+        namespaces: HashMap<String, HashMap<String, Vec<u8>>>,
+        holder_jwk: String,
+        options: Option<IssuanceOptions>,
+    ) -> Result<Arc<Mdoc>, MdocInitError> {
+        let options = options.unwrap_or_default();
+        let device_key = device_key_from_jwk(&holder_jwk)?;
+        let namespaces = convert_namespaces(namespaces)?;
+        let builder = prepare_builder(device_key, namespaces, doc_type, &options)?;
 
-        // 5. Create mdoc
-        let mdoc = Mdoc::create_and_sign_mdl(
-            mdl_items,
-            None,
-            holder_jwk,
-            cert_pem.clone(),
-            issuer_key_pem,
-        )
-        .expect("Failed to create mdoc");
+        self.sign(builder, &options)
+    }
+
+    /// Issue an mdoc like [MdocIssuer::issue], returning it serialized in the given
+    /// text [OutputFormat], see [Mdoc::encode].
+    #[uniffi::method(default(options = None))]
+    pub fn issue_encoded(
+        &self,
+        doc_type: String,
+        namespaces: HashMap<String, HashMap<String, Vec<u8>>>,
+        holder_jwk: String,
+        format: OutputFormat,
+        options: Option<IssuanceOptions>,
+    ) -> Result<String, MdocInitError> {
+        self.issue(doc_type, namespaces, holder_jwk, options)?
+            .encode(format)
+            .map_err(|e| MdocInitError::OutputEncoding(e.to_string()))
+    }
+
+    /// Issue an mdoc like [MdocIssuer::issue], returning it serialized in the given
+    /// [OutputFormat] as bytes, see [Mdoc::encode_bytes].
+    #[uniffi::method(default(options = None))]
+    pub fn issue_encoded_bytes(
+        &self,
+        doc_type: String,
+        namespaces: HashMap<String, HashMap<String, Vec<u8>>>,
+        holder_jwk: String,
+        format: OutputFormat,
+        options: Option<IssuanceOptions>,
+    ) -> Result<Vec<u8>, MdocInitError> {
+        self.issue(doc_type, namespaces, holder_jwk, options)?
+            .encode_bytes(format)
+            .map_err(|e| MdocInitError::OutputEncoding(e.to_string()))
+    }
+
+    /// Re-sign an mdoc with this issuer, see [Mdoc::reissue].
+    #[uniffi::method(default(options = None))]
+    pub fn reissue(
+        &self,
+        mdoc: Arc<Mdoc>,
+        options: Option<IssuanceOptions>,
+    ) -> Result<Arc<Mdoc>, MdocInitError> {
+        let options = options.unwrap_or_default();
+        let builder = mdoc.reissue_builder(&options)?;
+        let doc = sign_document(
+            builder,
+            self.ds_certificate.clone(),
+            self.iaca_chain.clone(),
+            self.ds_key.clone(),
+            &options,
+        )?;
+
+        Ok(Arc::new(Mdoc::new_from_parts(doc, mdoc.key_alias.clone())))
+    }
+
+    /// Async variant of [MdocIssuer::issue]. Key parsing, encoding and signing run on
+    /// a separate thread.
+    #[uniffi::method(default(options = None))]
+    pub async fn issue_async(
+        self: Arc<Self>,
+        doc_type: String,
+        namespaces: HashMap<String, HashMap<String, Vec<u8>>>,
+        holder_jwk: String,
+        options: Option<IssuanceOptions>,
+    ) -> Result<Arc<Mdoc>, MdocInitError> {
+        run_blocking(move || self.issue(doc_type, namespaces, holder_jwk, options))
+            .await
+            .ok_or(MdocInitError::GeneralConstructionError)?
+    }
+
+    /// Issue an mDL from JSON items, see [Mdoc::create_and_sign_mdl].
+    #[uniffi::method(default(options = None))]
+    pub fn issue_mdl(
+        &self,
+        mdl_items: String,
+        aamva_items: Option<String>,
+        holder_jwk: String,
+        options: Option<IssuanceOptions>,
+    ) -> Result<Arc<Mdoc>, MdocInitError> {
+        let options = options.unwrap_or_default();
+        let device_key = device_key_from_jwk(&holder_jwk)?;
+        let namespaces = mdl_namespaces(&mdl_items, aamva_items.as_deref())?;
+        let builder = prepare_builder(device_key, namespaces, MDL_DOC_TYPE.to_string(), &options)?;
+
+        self.sign(builder, &options)
+    }
+
+    /// Issue an mdoc from JSON data elements, see [Mdoc::create_and_sign_from_json].
+    #[uniffi::method(default(options = None))]
+    pub fn issue_from_json(
+        &self,
+        doc_type: String,
+        namespaces_json: HashMap<String, String>,
+        holder_jwk: String,
+        options: Option<IssuanceOptions>,
+    ) -> Result<Arc<Mdoc>, MdocInitError> {
+        let options = options.unwrap_or_default();
+        let device_key = device_key_from_jwk(&holder_jwk)?;
+        let namespaces = generic_namespaces(namespaces_json)?;
+        let builder = prepare_builder(device_key, namespaces, doc_type, &options)?;
+
+        self.sign(builder, &options)
+    }
+
+    /// Issue an mdoc from JSON data elements of several namespaces, see
+    /// [Mdoc::create_and_sign_multi].
+    #[uniffi::method(default(options = None))]
+    pub fn issue_multi(
+        &self,
+        doc_type: String,
+        namespaces: HashMap<String, String>,
+        holder_jwk: String,
+        options: Option<IssuanceOptions>,
+    ) -> Result<Arc<Mdoc>, MdocInitError> {
+        let options = options.unwrap_or_default();
+        let device_key = device_key_from_jwk(&holder_jwk)?;
+        let namespaces = json_namespaces(namespaces)?;
+        let builder = prepare_builder(device_key, namespaces, doc_type, &options)?;
+
+        self.sign(builder, &options)
+    }
+
+    /// Issue an EU PID from JSON data elements, see [Mdoc::create_and_sign_pid].
+    #[uniffi::method(default(options = None))]
+    pub fn issue_pid(
+        &self,
+        pid_items: String,
+        holder_jwk: String,
+        options: Option<IssuanceOptions>,
+    ) -> Result<Arc<Mdoc>, MdocInitError> {
+        let options = options.unwrap_or_default();
+        let device_key = device_key_from_jwk(&holder_jwk)?;
+        let namespaces = typed_namespaces(&[(&EU_PID, &pid_items)])?;
+        let builder = prepare_builder(
+            device_key,
+            namespaces,
+            EU_PID_DOC_TYPE.to_string(),
+            &options,
+        )?;
+
+        self.sign(builder, &options)
+    }
+
+    /// Issue a Photo ID from JSON data elements, see [Mdoc::create_and_sign_photo_id].
+    #[uniffi::method(default(options = None))]
+    pub fn issue_photo_id(
+        &self,
+        iso23220_items: String,
+        photo_id_items: Option<String>,
+        holder_jwk: String,
+        options: Option<IssuanceOptions>,
+    ) -> Result<Arc<Mdoc>, MdocInitError> {
+        let options = options.unwrap_or_default();
+        let device_key = device_key_from_jwk(&holder_jwk)?;
+        let namespaces = photo_id_namespaces(&iso23220_items, photo_id_items.as_deref())?;
+        let builder = prepare_builder(
+            device_key,
+            namespaces,
+            PHOTO_ID_DOC_TYPE.to_string(),
+            &options,
+        )?;
+
+        self.sign(builder, &options)
+    }
+
+    /// Issue one mdoc of the given doc type per holder key from the same data elements,
+    /// see [MdocIssuer::issue_mdl_batch].
+    #[uniffi::method(default(options = None))]
+    pub fn issue_batch(
+        &self,
+        doc_type: String,
+        namespaces: HashMap<String, HashMap<String, Vec<u8>>>,
+        holder_jwks: Vec<String>,
+        validity_stagger: Duration,
+        options: Option<IssuanceOptions>,
+    ) -> Result<Vec<Arc<Mdoc>>, MdocInitError> {
+        let namespaces = convert_namespaces(namespaces)?;
+
+        self.sign_batch(
+            doc_type,
+            namespaces,
+            holder_jwks,
+            validity_stagger,
+            options.unwrap_or_default(),
+        )
+    }
+
+    /// Issue one mDL per holder key from the same JSON items, for wallets that present
+    /// single-use credentials so that their presentations cannot be linked.
+    ///
+    /// Every mdoc has its own device key and salts, and its validity period starts
+    /// `validity_stagger` after that of the previous one. Its signing time is drawn at
+    /// random from the hour before that of the batch, so that it does not link the mdocs
+    /// either. The holder keys must be distinct. A `random_seed` in the options seeds the
+    /// whole batch.
+    #[uniffi::method(default(options = None))]
+    pub fn issue_mdl_batch(
+        &self,
+        mdl_items: String,
+        aamva_items: Option<String>,
+        holder_jwks: Vec<String>,
+        validity_stagger: Duration,
+        options: Option<IssuanceOptions>,
+    ) -> Result<Vec<Arc<Mdoc>>, MdocInitError> {
+        let namespaces = mdl_namespaces(&mdl_items, aamva_items.as_deref())?;
+
+        self.sign_batch(
+            MDL_DOC_TYPE.to_string(),
+            namespaces,
+            holder_jwks,
+            validity_stagger,
+            options.unwrap_or_default(),
+        )
+    }
+}
+
+impl MdocIssuer {
+    fn from_parts(
+        ds_certificate: Certificate,
+        iaca_chain: Vec<Certificate>,
+        ds_key_pem: &str,
+    ) -> Result<Arc<Self>, MdocInitError> {
+        let ds_key = IssuerSigningKey::from_pkcs8_pem(ds_key_pem)
+            .map_err(|e| MdocInitError::InvalidDocumentSigner(e.to_string()))?;
+
+        let spki = ds_key
+            .subject_public_key_info()
+            .map_err(|e| MdocInitError::InvalidDocumentSigner(e.to_string()))?;
+        if ds_certificate.tbs_certificate.subject_public_key_info != spki {
+            return Err(MdocInitError::InvalidDocumentSigner(
+                "certificate does not match the provided key".to_string(),
+            ));
+        }
+
+        Ok(Arc::new(Self {
+            ds_certificate,
+            iaca_chain,
+            ds_key,
+        }))
+    }
+
+    fn sign(
+        &self,
+        builder: Builder,
+        options: &IssuanceOptions,
+    ) -> Result<Arc<Mdoc>, MdocInitError> {
+        let doc = sign_document(
+            builder,
+            self.ds_certificate.clone(),
+            self.iaca_chain.clone(),
+            self.ds_key.clone(),
+            options,
+        )?;
+
+        Ok(Arc::new(Mdoc::new_from_parts(
+            doc,
+            KeyAlias(Uuid::new_v4().to_string()),
+        )))
+    }
+
+    fn sign_batch(
+        &self,
+        doc_type: String,
+        namespaces: BTreeMap<String, BTreeMap<String, Value>>,
+        holder_jwks: Vec<String>,
+        validity_stagger: Duration,
+        options: IssuanceOptions,
+    ) -> Result<Vec<Arc<Mdoc>>, MdocInitError> {
+        let mut device_keys: Vec<CoseKey> = Vec::with_capacity(holder_jwks.len());
+        for holder_jwk in &holder_jwks {
+            let device_key = device_key_from_jwk(holder_jwk)?;
+            if device_keys.contains(&device_key) {
+                return Err(MdocInitError::InvalidOptions(
+                    "holder keys must be distinct".to_string(),
+                ));
+            }
+            device_keys.push(device_key);
+        }
+
+        // Each mdoc draws its own seed, so that seeded batches still get distinct salts.
+        let mut seeds = options
+            .random_seed
+            .as_deref()
+            .map(|seed| {
+                seed.try_into().map(ChaCha20Rng::from_seed).map_err(|_| {
+                    MdocInitError::InvalidOptions("random_seed must be 32 bytes".to_string())
+                })
+            })
+            .transpose()?;
+        let signed = options.signed.unwrap_or_else(SystemTime::now);
+        let valid_from = options.valid_from.unwrap_or(signed);
+        let overflow = || {
+            MdocInitError::InvalidValidity(
+                "validity_stagger puts the validity period out of range".to_string(),
+            )
+        };
+
+        device_keys
+            .into_iter()
+            .zip(0u32..)
+            .map(|(device_key, index)| {
+                let valid_from = validity_stagger
+                    .checked_mul(index)
+                    .and_then(|offset| valid_from.checked_add(offset))
+                    .ok_or_else(overflow)?;
+                let (random_seed, jitter) = match seeds.as_mut() {
+                    Some(seeds) => {
+                        let mut seed = vec![0; 32];
+                        seeds.fill_bytes(&mut seed);
+                        (Some(seed), seeds.next_u64())
+                    }
+                    None => (None, rand::rng().next_u64()),
+                };
+                let signed = signed
+                    .checked_sub(Duration::from_secs(jitter % BATCH_SIGNED_JITTER.as_secs()))
+                    .ok_or_else(overflow)?;
+                let options = IssuanceOptions {
+                    signed: Some(signed),
+                    valid_from: Some(valid_from),
+                    random_seed,
+                    ..options.clone()
+                };
+                let builder =
+                    prepare_builder(device_key, namespaces.clone(), doc_type.clone(), &options)?;
+                self.sign(builder, &options)
+            })
+            .collect()
+    }
+}
+
+/// The range of the random offset by which the signing times of a batch precede the
+/// signing time of the batch.
+const BATCH_SIGNED_JITTER: Duration = Duration::from_secs(60 * 60);
+
+/// Serialization of an issued mdoc.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, uniffi::Enum)]
+pub enum OutputFormat {
+    /// The Document, as returned by [Mdoc::stringify].
+    Stringified,
+    /// The CBOR-encoded Document, as returned by [Mdoc::to_cbor_encoded_document]. Binary,
+    /// only available from [Mdoc::encode_bytes].
+    CborDocument,
+    /// The base64url encoded IssuerSigned, as returned by [Mdoc::to_base64url_issuer_signed].
+    Base64UrlIssuerSigned,
+    /// The CBOR-encoded Document as lowercase hex, for logs and test vectors.
+    HexCborDocument,
+}
+
+/// Encoding of the x5chain COSE header in the issuer_auth of an issued mdoc.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, uniffi::Enum)]
+pub enum X5ChainEncoding {
+    /// Always encode the chain as an array, even when it holds a single certificate.
+    Array,
+    /// Encode a single-certificate chain as a bare bstr. Chains with more than one
+    /// certificate are always encoded as an array.
+    SingleCertificate,
+}
+
+/// Optional settings controlling how an mdoc is issued.
+#[derive(Debug, Clone, Default, uniffi::Record)]
+pub struct IssuanceOptions {
+    /// Encoding of the x5chain header. When unset, isomdl's default encoding is kept.
+    #[uniffi(default = None)]
+    pub x5chain_encoding: Option<X5ChainEncoding>,
+    /// Signing time recorded in the MSO, for example when re-signing a previously
+    /// issued document. Defaults to now.
+    #[uniffi(default = None)]
+    pub signed: Option<SystemTime>,
+    /// Start of the validity period, at or after the signing time. Defaults to the
+    /// signing time. The credential is valid for thirty days from then.
+    #[uniffi(default = None)]
+    pub valid_from: Option<SystemTime>,
+    /// When the issuer expects to re-sign the MSO, for example with updated data or
+    /// to renew the device key, so that wallets can schedule a refresh.
+    #[uniffi(default = None)]
+    pub expected_update: Option<SystemTime>,
+    /// 32 byte seed for the digest IDs and salts of the issued elements. Together with a
+    /// fixed `signed` time, issuing the same data twice yields byte-identical mdocs, for
+    /// golden file tests. This requires an [MdocIssuer], the `create_and_sign`
+    /// constructors generate a new document signer for every mdoc. Never set this in
+    /// production, the salts are what keep undisclosed elements from being guessed.
+    #[uniffi(default = None)]
+    pub random_seed: Option<Vec<u8>>,
+    /// Length in bytes of the random salt of every element, at least 16. Defaults to 32.
+    #[uniffi(default = None)]
+    pub salt_length: Option<u32>,
+    /// How digest IDs are assigned. When unset, isomdl's assignment is kept unless a
+    /// `random_seed` or `salt_length` is given, in which case they are shuffled.
+    #[uniffi(default = None)]
+    pub digest_ids: Option<DigestIdAssignment>,
+    /// Entries of the `keyInfo` map of the DeviceKeyInfo, for example references to a
+    /// key attestation or the origin of the device key, as CBOR-encoded values keyed by
+    /// integer label. ISO 18013-5 reserves positive labels, use negative labels for
+    /// proprietary entries. When reissuing, the existing entries are kept if unset.
+    #[uniffi(default = None)]
+    pub key_info: Option<HashMap<i64, Vec<u8>>>,
+    /// Fail issuance if the CBOR-encoded IssuerSigned structure is larger than this
+    /// many bytes, for example to keep credentials practical to present over BLE. The
+    /// error names the largest elements.
+    #[uniffi(default = None)]
+    pub max_encoded_size: Option<u64>,
+}
+
+/// How the digest IDs of the elements of each namespace are assigned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, uniffi::Enum)]
+pub enum DigestIdAssignment {
+    /// 0, 1, 2, ... in element order.
+    Sequential,
+    /// A random permutation of 0 to n - 1, hiding the element order.
+    Shuffled,
+    /// Distinct random integers, hiding both the element order and the number of
+    /// elements.
+    Random,
+}
+
+/// Number of elements named when an issued credential exceeds `max_encoded_size`.
+const LARGEST_ELEMENTS_REPORTED: usize = 3;
+
+/// Minimum salt length, ISO 18013-5 requires at least 16 bytes of randomness.
+const MIN_SALT_LENGTH: u32 = 16;
+const DEFAULT_SALT_LENGTH: u32 = 32;
+
+/// Sign the prepared builder with a document signer derived from the IACA material,
+/// and assemble the issued mdoc into a [Document].
+fn issue_document(
+    builder: Builder,
+    iaca_cert_pem: String,
+    iaca_key_pem: String,
+    options: &IssuanceOptions,
+) -> Result<Document, MdocInitError> {
+    let (certificate, iaca_certs, signer) =
+        setup_issuer_certificate_chain(iaca_cert_pem, iaca_key_pem)
+            .map_err(|_e| MdocInitError::GeneralConstructionError)?;
+
+    sign_document(builder, certificate, iaca_certs, signer, options)
+}
+
+/// Sign the prepared builder with the given document signer, and assemble the issued
+/// mdoc into a [Document].
+fn sign_document(
+    builder: Builder,
+    certificate: Certificate,
+    iaca_certs: Vec<Certificate>,
+    signer: IssuerSigningKey,
+    options: &IssuanceOptions,
+) -> Result<Document, MdocInitError> {
+    let mut reseal_rng: Option<Box<dyn RngCore>> = match options.random_seed.as_deref() {
+        Some(seed) => {
+            let seed = seed.try_into().map_err(|_| {
+                MdocInitError::InvalidOptions("random_seed must be 32 bytes".to_string())
+            })?;
+            Some(Box::new(ChaCha20Rng::from_seed(seed)))
+        }
+        None if options.salt_length.is_some() || options.digest_ids.is_some() => {
+            Some(Box::new(rand::rng()))
+        }
+        None => None,
+    };
+    if options
+        .salt_length
+        .is_some_and(|length| length < MIN_SALT_LENGTH)
+    {
+        return Err(MdocInitError::InvalidOptions(format!(
+            "salt_length must be at least {MIN_SALT_LENGTH} bytes"
+        )));
+    }
+
+    let mut x5chain_builder = X5Chain::builder()
+        .with_certificate(certificate)
+        .map_err(|_e| MdocInitError::GeneralConstructionError)?;
+
+    for cert in iaca_certs {
+        x5chain_builder = x5chain_builder
+            .with_certificate(cert)
+            .map_err(|_e| MdocInitError::GeneralConstructionError)?;
+    }
+
+    let x5chain = x5chain_builder
+        .build()
+        .map_err(|_e| MdocInitError::GeneralConstructionError)?;
+
+    let mut mdoc = match signer.clone() {
+        IssuerSigningKey::P256(signer) => {
+            builder.issue::<p256::ecdsa::SigningKey, p256::ecdsa::Signature>(x5chain, signer)
+        }
+        IssuerSigningKey::P384(signer) => {
+            builder.issue::<p384::ecdsa::SigningKey, p384::ecdsa::Signature>(x5chain, signer)
+        }
+        IssuerSigningKey::Ed25519(_) => {
+            builder
+                .prepare(coset::iana::Algorithm::EdDSA)
+                .map(|prepared| {
+                    let signature = signer.sign(prepared.signature_payload());
+                    prepared.complete(x5chain, signature)
+                })
+        }
+    }
+    .map_err(|_e| MdocInitError::GeneralConstructionError)?;
+
+    if let Some(rng) = reseal_rng.as_deref_mut() {
+        reseal(&mut mdoc, &signer, options, rng)?;
+    }
+
+    if let Some(encoding) = options.x5chain_encoding {
+        apply_x5chain_encoding(&mut mdoc.issuer_auth.inner, encoding);
+    }
+
+    let namespaces = NonEmptyMap::maybe_new(
+        mdoc.namespaces
+            .into_inner()
+            .into_iter()
+            .map(|(namespace, elements)| {
+                let inner_map = NonEmptyMap::maybe_new(
+                    elements
+                        .into_inner()
+                        .into_iter()
+                        .map(|element| (element.as_ref().element_identifier.clone(), element))
+                        .collect(),
+                )
+                .ok_or(MdocInitError::GeneralConstructionError)?;
+                Ok((namespace, inner_map))
+            })
+            .collect::<Result<_, MdocInitError>>()?,
+    )
+    .ok_or(MdocInitError::GeneralConstructionError)?;
+
+    let document = Document {
+        id: Default::default(),
+        issuer_auth: mdoc.issuer_auth,
+        mso: mdoc.mso,
+        namespaces,
+    };
+
+    if let Some(max_encoded_size) = options.max_encoded_size {
+        check_encoded_size(&document, max_encoded_size)?;
+    }
+    Ok(document)
+}
+
+/// Assigns new digest IDs and salts, drawn from `rng`, to the elements of an issued mdoc
+/// and signs the MSO with the updated value digests again.
+fn reseal(
+    mdoc: &mut isomdl::issuance::Mdoc,
+    signer: &IssuerSigningKey,
+    options: &IssuanceOptions,
+    rng: &mut dyn RngCore,
+) -> Result<(), MdocInitError> {
+    let salt_length = options.salt_length.unwrap_or(DEFAULT_SALT_LENGTH) as usize;
+    let mut value_digests = BTreeMap::new();
+    let mut namespaces = BTreeMap::new();
+
+    for (namespace, items) in mdoc.namespaces.clone().into_inner() {
+        let digest_ids = assign_digest_ids(
+            options.digest_ids.unwrap_or(DigestIdAssignment::Shuffled),
+            items.len(),
+            rng,
+        );
+
+        let mut digests = BTreeMap::new();
+        let mut resealed = Vec::new();
+        for (item, digest_id) in items.into_iter().zip(digest_ids) {
+            let mut item = item.into_inner();
+            let mut random = vec![0; salt_length];
+            rng.fill_bytes(&mut random);
+            item.digest_id = DigestId::new(digest_id);
+            item.random = random.into();
+
+            let item = Tag24::new(item).map_err(|_e| MdocInitError::GeneralConstructionError)?;
+            let digest = value_digest(&mdoc.mso.digest_algorithm, &item)
+                .ok_or(MdocInitError::GeneralConstructionError)?;
+            digests.insert(DigestId::new(digest_id), digest.into());
+            resealed.push(item);
+        }
+
+        value_digests.insert(namespace.clone(), digests);
+        namespaces.insert(
+            namespace,
+            NonEmptyVec::maybe_new(resealed).ok_or(MdocInitError::GeneralConstructionError)?,
+        );
+    }
+
+    mdoc.namespaces =
+        NonEmptyMap::maybe_new(namespaces).ok_or(MdocInitError::GeneralConstructionError)?;
+    mdoc.mso.value_digests = value_digests;
+
+    let mso = Tag24::new(mdoc.mso.clone()).map_err(|_e| MdocInitError::GeneralConstructionError)?;
+    let issuer_auth = &mut mdoc.issuer_auth.inner;
+    issuer_auth.payload =
+        Some(isomdl::cbor::to_vec(&mso).map_err(|_e| MdocInitError::GeneralConstructionError)?);
+    issuer_auth.signature = signer.sign(&issuer_auth.tbs_data(&[]));
+    Ok(())
+}
+
+fn assign_digest_ids(
+    assignment: DigestIdAssignment,
+    count: usize,
+    rng: &mut dyn RngCore,
+) -> Vec<i32> {
+    let mut digest_ids: Vec<i32> = (0..count as i32).collect();
+    match assignment {
+        DigestIdAssignment::Sequential => {}
+        DigestIdAssignment::Shuffled => digest_ids.shuffle(rng),
+        DigestIdAssignment::Random => {
+            let mut assigned = std::collections::BTreeSet::new();
+            digest_ids.clear();
+            while digest_ids.len() < count {
+                // Digest IDs are unsigned integers, keep them within the positive i32 range.
+                let digest_id = (rng.next_u32() >> 1) as i32;
+                if assigned.insert(digest_id) {
+                    digest_ids.push(digest_id);
+                }
+            }
+        }
+    }
+    digest_ids
+}
+
+/// Re-encode the x5chain header of an issuer_auth. The unprotected header is not
+/// covered by the issuer signature, so this does not invalidate the credential.
+fn apply_x5chain_encoding(issuer_auth: &mut coset::CoseSign1, encoding: X5ChainEncoding) {
+    let Some((_, x5chain)) = issuer_auth
+        .unprotected
+        .rest
+        .iter_mut()
+        .find(|(label, _)| label == &Label::Int(X5CHAIN_COSE_HEADER_LABEL))
+    else {
+        return;
+    };
+
+    *x5chain = match (encoding, std::mem::replace(x5chain, Value::Null)) {
+        (X5ChainEncoding::Array, Value::Bytes(certificate)) => {
+            Value::Array(vec![Value::Bytes(certificate)])
+        }
+        (X5ChainEncoding::SingleCertificate, Value::Array(mut certificates))
+            if certificates.len() == 1 =>
+        {
+            certificates.remove(0)
+        }
+        (_, unchanged) => unchanged,
+    };
+}
+
+/// Convert JSON data elements into the namespaces of their definitions.
+fn typed_namespaces(
+    items: &[(&NamespaceDefinition, &str)],
+) -> Result<BTreeMap<String, BTreeMap<String, Value>>, MdocInitError> {
+    reject_duplicate_elements(
+        items
+            .iter()
+            .map(|(definition, items)| (definition.namespace, *items)),
+    )?;
+
+    let mut namespaces = BTreeMap::new();
+    let mut errors = Vec::new();
+
+    for (definition, items) in items {
+        match definition.elements_from_json(items) {
+            Ok(elements) => {
+                namespaces.insert(definition.namespace.to_string(), elements);
+            }
+            Err(element_errors) => errors.extend(field_errors(definition, element_errors)),
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(MdocInitError::InvalidElements(errors));
+    }
+    Ok(namespaces)
+}
+
+/// Reject JSON items that repeat an identifier, rather than issuing only the last value.
+fn reject_duplicate_elements<'a>(
+    items: impl IntoIterator<Item = (&'a str, &'a str)>,
+) -> Result<(), MdocInitError> {
+    for (namespace, json) in items {
+        if let Some(identifier) = namespaces::duplicate_identifier(json) {
+            return Err(MdocInitError::DuplicateElement {
+                namespace: namespace.to_string(),
+                identifier,
+            });
+        }
+    }
+    Ok(())
+}
+
+fn field_errors(
+    definition: &NamespaceDefinition,
+    errors: Vec<namespaces::ElementError>,
+) -> impl Iterator<Item = FieldError> {
+    errors.into_iter().map(|e| FieldError {
+        namespace: definition.namespace.to_string(),
+        identifier: e.identifier,
+        reason: e.reason,
+    })
+}
+
+/// Checks the mDL JSON items against the ISO 18013-5 data element definitions, and the
+/// AAMVA items against the AAMVA definitions: mandatory elements must be present and all
+/// elements must have the expected type and domain.
+pub(crate) fn validate_mdl_items(mdl_items: &str, aamva_items: Option<&str>) -> Vec<FieldError> {
+    let mut items = vec![(&MDL, mdl_items)];
+    if let Some(aamva_items) = aamva_items {
+        items.push((&AAMVA, aamva_items));
+    }
+
+    items
+        .into_iter()
+        .filter_map(|(definition, items)| {
+            let errors = definition.elements_from_json(items).err()?;
+            Some(field_errors(definition, errors))
+        })
+        .flatten()
+        .collect()
+}
+
+/// Convert JSON data elements without namespace definitions, see
+/// [Mdoc::create_and_sign_from_json].
+fn generic_namespaces(
+    namespaces_json: HashMap<String, String>,
+) -> Result<BTreeMap<String, BTreeMap<String, Value>>, MdocInitError> {
+    reject_duplicate_elements(
+        namespaces_json
+            .iter()
+            .map(|(namespace, json)| (namespace.as_str(), json.as_str())),
+    )?;
+
+    let mut namespaces = BTreeMap::new();
+    let mut errors = Vec::new();
+
+    for (namespace, json) in namespaces_json {
+        match namespaces::generic_from_json(&json) {
+            Ok(elements) => {
+                namespaces.insert(namespace, elements);
+            }
+            Err(element_errors) => errors.extend(element_errors.into_iter().map(|e| FieldError {
+                namespace: namespace.clone(),
+                identifier: e.identifier,
+                reason: e.reason,
+            })),
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(MdocInitError::InvalidElements(errors));
+    }
+    Ok(namespaces)
+}
+
+fn photo_id_namespaces(
+    iso23220_items: &str,
+    photo_id_items: Option<&str>,
+) -> Result<BTreeMap<String, BTreeMap<String, Value>>, MdocInitError> {
+    let mut items = vec![(&ISO_23220, iso23220_items)];
+    if let Some(photo_id_items) = photo_id_items {
+        items.push((&PHOTO_ID, photo_id_items));
+    }
+    typed_namespaces(&items)
+}
+
+/// Parse the mDL and optional AAMVA JSON items into their namespaces.
+fn mdl_namespaces(
+    mdl_items: &str,
+    aamva_items: Option<&str>,
+) -> Result<BTreeMap<String, BTreeMap<String, Value>>, MdocInitError> {
+    let mut items = HashMap::from([(MDL_NAMESPACE.to_string(), mdl_items.to_string())]);
+    if let Some(aamva_items) = aamva_items {
+        items.insert(AAMVA_NAMESPACE.to_string(), aamva_items.to_string());
+    }
+    json_namespaces(items)
+}
+
+/// Convert JSON data elements of any namespaces, see [Mdoc::create_and_sign_multi].
+fn json_namespaces(
+    namespaces_json: HashMap<String, String>,
+) -> Result<BTreeMap<String, BTreeMap<String, Value>>, MdocInitError> {
+    reject_duplicate_elements(
+        namespaces_json
+            .iter()
+            .map(|(namespace, json)| (namespace.as_str(), json.as_str())),
+    )?;
+
+    let mut namespaces = BTreeMap::new();
+    let mut errors = Vec::new();
+
+    for (namespace, json) in namespaces_json {
+        match json_namespace(&namespace, &json) {
+            Ok(elements) => {
+                namespaces.insert(namespace, elements);
+            }
+            Err(namespace_errors) => errors.extend(namespace_errors),
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(MdocInitError::InvalidElements(errors));
+    }
+    Ok(namespaces)
+}
+
+/// Convert the JSON data elements of a namespace, typed by its definition when the crate
+/// has one. The mDL and AAMVA namespaces are validated against their definitions and
+/// converted by isomdl, other namespaces without a definition are converted as by
+/// [Mdoc::create_and_sign_from_json].
+fn json_namespace(namespace: &str, json: &str) -> Result<BTreeMap<String, Value>, Vec<FieldError>> {
+    let field_error = |identifier: &str, reason: String| FieldError {
+        namespace: namespace.to_string(),
+        identifier: identifier.to_string(),
+        reason,
+    };
+    let Some(definition) = namespaces::definition(namespace) else {
+        return namespaces::generic_from_json(json).map_err(|errors| {
+            errors
+                .into_iter()
+                .map(|e| field_error(&e.identifier, e.reason))
+                .collect()
+        });
+    };
+
+    let elements = definition
+        .elements_from_json(json)
+        .map_err(|errors| field_errors(definition, errors).collect::<Vec<_>>())?;
+    if namespace != MDL_NAMESPACE && namespace != AAMVA_NAMESPACE {
+        return Ok(elements);
+    }
+
+    // Validated as a JSON object above.
+    let json_value: serde_json::Value =
+        serde_json::from_str(json).map_err(|e| vec![field_error("", e.to_string())])?;
+    let mut elements = if namespace == MDL_NAMESPACE {
+        OrgIso1801351::from_json(&json_value).map(|elements| elements.to_ns_map())
+    } else {
+        OrgIso1801351Aamva::from_json(&json_value).map(|elements| elements.to_ns_map())
+    }
+    .map_err(|e| vec![field_error("", e.to_string())])?;
+    definition.tag_dates(&mut elements);
+    Ok(elements)
+}
+
+/// Parse the holder public JWK into the mdoc device key. P-256, P-384 and P-521 keys are
+/// encoded as EC2 keys, Ed25519 keys as OKP keys.
+fn device_key_from_jwk(holder_jwk: &str) -> Result<CoseKey, MdocInitError> {
+    let jwk: serde_json::Value =
+        serde_json::from_str(holder_jwk).map_err(|_e| MdocInitError::InvalidJwk)?;
+
+    if jwk["kty"] == "OKP" {
+        if jwk["crv"] != "Ed25519" {
+            return Err(MdocInitError::InvalidJwk);
+        }
+        let x: [u8; 32] = jwk["x"]
+            .as_str()
+            .and_then(|x| BASE64_URL_SAFE_NO_PAD.decode(x).ok())
+            .and_then(|x| x.try_into().ok())
+            .ok_or(MdocInitError::InvalidJwk)?;
+        ed25519_dalek::VerifyingKey::from_bytes(&x).map_err(|_e| MdocInitError::InvalidJwk)?;
+        return Ok(CoseKey::OKP {
+            crv: OKPCurve::Ed25519,
+            x: x.to_vec(),
+        });
+    }
+
+    let (crv, point) = match jwk["crv"].as_str() {
+        Some("P-384") => (
+            EC2Curve::P384,
+            p384::PublicKey::from_jwk_str(holder_jwk)
+                .map(|key| key.to_encoded_point(false).as_bytes().to_vec()),
+        ),
+        Some("P-521") => (
+            EC2Curve::P521,
+            p521::PublicKey::from_jwk_str(holder_jwk)
+                .map(|key| key.to_encoded_point(false).as_bytes().to_vec()),
+        ),
+        _ => (
+            EC2Curve::P256,
+            PublicKey::from_jwk_str(holder_jwk)
+                .map(|key| key.to_encoded_point(false).as_bytes().to_vec()),
+        ),
+    };
+    let point = point.map_err(|_e| MdocInitError::InvalidJwk)?;
+    ec2_device_key(crv, &point).ok_or(MdocInitError::InvalidJwk)
+}
+
+/// Encode an uncompressed SEC1 point as an EC2 device key.
+fn ec2_device_key(crv: EC2Curve, point: &[u8]) -> Option<CoseKey> {
+    let coordinates = point.strip_prefix(&[0x04])?;
+    if coordinates.is_empty() || coordinates.len() % 2 != 0 {
+        return None;
+    }
+    let (x, y) = coordinates.split_at(coordinates.len() / 2);
+    Some(CoseKey::EC2 {
+        crv,
+        x: x.to_vec(),
+        y: EC2Y::Value(y.to_vec()),
+    })
+}
+
+/// Converts a time given by the caller, which may lie past the years an
+/// [OffsetDateTime] can represent.
+fn offset_date_time(time: SystemTime) -> Option<OffsetDateTime> {
+    match time.duration_since(SystemTime::UNIX_EPOCH) {
+        Ok(since) => OffsetDateTime::UNIX_EPOCH.checked_add(since.try_into().ok()?),
+        Err(before) => OffsetDateTime::UNIX_EPOCH.checked_sub(before.duration().try_into().ok()?),
+    }
+}
+
+fn prepare_builder(
+    device_key: CoseKey,
+    namespaces: BTreeMap<String, BTreeMap<String, ciborium::Value>>,
+    doc_type: String,
+    options: &IssuanceOptions,
+) -> Result<Builder, MdocInitError> {
+    let out_of_range =
+        |name: &str| MdocInitError::InvalidValidity(format!("{name} is out of range"));
+    let signed = match options.signed {
+        Some(signed) => offset_date_time(signed).ok_or_else(|| out_of_range("signed"))?,
+        None => OffsetDateTime::now_utc(),
+    };
+    let valid_from = match options.valid_from {
+        Some(valid_from) => {
+            offset_date_time(valid_from).ok_or_else(|| out_of_range("valid_from"))?
+        }
+        None => signed,
+    };
+    let expected_update = options
+        .expected_update
+        .map(|expected_update| {
+            offset_date_time(expected_update).ok_or_else(|| out_of_range("expected_update"))
+        })
+        .transpose()?;
+    let validity_info = ValidityInfo {
+        signed,
+        valid_from,
+        // mDL valid for thirty days.
+        valid_until: valid_from
+            .checked_add(time::Duration::days(30))
+            .ok_or_else(|| out_of_range("valid_from"))?,
+        expected_update,
+    };
+
+    if validity_info.valid_from < validity_info.signed {
+        return Err(MdocInitError::InvalidValidity(
+            "valid_from is before the signing time".to_string(),
+        ));
+    }
+    if let Some(expected_update) = validity_info.expected_update
+        && expected_update < validity_info.signed
+    {
+        return Err(MdocInitError::InvalidValidity(
+            "expected_update is before the signing time".to_string(),
+        ));
+    }
+
+    let digest_alg = DigestAlgorithm::SHA256;
+
+    let key_info = options
+        .key_info
+        .as_ref()
+        .map(|entries| {
+            entries
+                .iter()
+                .map(|(label, value)| {
+                    let value = from_reader(value.as_slice()).map_err(|_| {
+                        MdocInitError::InvalidOptions(format!(
+                            "key_info entry {label} is not valid CBOR"
+                        ))
+                    })?;
+                    Ok((i128::from(*label), value))
+                })
+                .collect::<Result<BTreeMap<_, _>, MdocInitError>>()
+        })
+        .transpose()?;
+
+    let device_key_info = DeviceKeyInfo {
+        device_key,
+        key_authorizations: None,
+        key_info,
+    };
+
+    Ok(isomdl::issuance::Mdoc::builder()
+        .doc_type(doc_type)
+        .namespaces(namespaces)
+        .validity_info(validity_info)
+        .digest_algorithm(digest_alg)
+        .device_key_info(device_key_info))
+}
+
+fn convert_namespaces(
+    input: HashMap<String, HashMap<String, Vec<u8>>>,
+) -> Result<BTreeMap<String, BTreeMap<String, Value>>, MdocInitError> {
+    let mut outer = BTreeMap::new();
+
+    for (namespace, inner_map) in input {
+        let mut inner_btree = BTreeMap::new();
+        for (key, vec_bytes) in inner_map {
+            let value: Value = from_reader(vec_bytes.as_slice()).map_err(|_e| {
+                MdocInitError::DocumentCborDecoding("Error decoding CBOR value".to_owned())
+            })?;
+            inner_btree.insert(key, value);
+        }
+        outer.insert(namespace, inner_btree);
+    }
+
+    Ok(outer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::Engine;
+    use p256::elliptic_curve::rand_core::OsRng;
+    use p256::{
+        ecdsa::SigningKey,
+        pkcs8::{EncodePrivateKey, LineEnding},
+    };
+    use std::io::Cursor;
+    use std::time::Duration;
+    use x509_cert::{
+        builder::{Builder, CertificateBuilder, Profile},
+        der::{Encode, EncodePem},
+        name::Name,
+        serial_number::SerialNumber,
+        spki::SubjectPublicKeyInfoOwned,
+        time::Validity,
+    };
+
+    #[test]
+    fn test_create_and_sign_mdl() {
+        // 1. Generate Issuer Key
+        let issuer_key = SigningKey::random(&mut OsRng);
+        let issuer_key_pem = issuer_key.to_pkcs8_pem(LineEnding::LF).unwrap().to_string();
+
+        // 2. Generate Issuer Certificate (Self-signed for simplicity)
+        let subject_name: Name = "CN=Test Issuer".parse().unwrap();
+        let serial_number = SerialNumber::from(1u64);
+        let validity = Validity::from_now(Duration::from_secs(3600)).unwrap();
+
+        // Use clone() to ensure we have the value, not a reference, as expected by from_key
+        let spki = SubjectPublicKeyInfoOwned::from_key(issuer_key.verifying_key().clone()).unwrap();
+
+        let builder = CertificateBuilder::new(
+            Profile::Root,
+            serial_number,
+            validity,
+            subject_name,
+            spki,
+            &issuer_key,
+        )
+        .unwrap();
+
+        let cert = builder.build::<p256::ecdsa::DerSignature>().unwrap();
+        let cert_pem = cert.to_pem(LineEnding::LF).unwrap();
+
+        // 3. Generate Holder Key (JWK)
+        let holder_key = SigningKey::random(&mut OsRng);
+        let point = holder_key.verifying_key().to_encoded_point(false);
+        let x = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(point.x().unwrap());
+        let y = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(point.y().unwrap());
+
+        let holder_jwk = serde_json::json!({
+            "kty": "EC",
+            "crv": "P-256",
+            "x": x,
+            "y": y
+        })
+        .to_string();
+
+        // 4. Sample Data
+        let mdl_items = serde_json::json!({
+            "family_name": "Doe",
+            "given_name": "John",
+            "birth_date": "1990-01-01",
+            "issue_date": "2023-01-01",
+            "expiry_date": "2028-01-01",
+            "issuing_country": "US",
+            "issuing_authority": "DMV",
+            "document_number": "123456789",
+            "portrait": "SGVsbG8gV29ybGQ=",
+            "driving_privileges": [
+                {
+                    "vehicle_category_code": "B",
+                    "issue_date": "2023-01-01",
+                    "expiry_date": "2028-01-01"
+                }
+            ],
+            "un_distinguishing_sign": "USA"
+        })
+        .to_string();
+
+        // 5. Call function
+        let result =
+            Mdoc::create_and_sign_mdl(mdl_items, None, holder_jwk, cert_pem, issuer_key_pem);
+
+        let mdoc = result.unwrap();
+
+        // 6. Verify Output
+        assert_eq!(mdoc.doctype(), "org.iso.18013.5.1.mDL");
+
+        let details = mdoc.details();
+        let mdl_namespace = Namespace("org.iso.18013.5.1".to_string());
+        let elements = details
+            .get(&mdl_namespace)
+            .expect("mDL namespace not found");
+
+        let family_name = elements
+            .iter()
+            .find(|e| e.identifier == "family_name")
+            .expect("family_name not found");
+        assert!(family_name.value.as_ref().unwrap().contains("Doe"));
+
+        let given_name = elements
+            .iter()
+            .find(|e| e.identifier == "given_name")
+            .expect("given_name not found");
+        assert!(given_name.value.as_ref().unwrap().contains("John"));
+
+        let doc_num = elements
+            .iter()
+            .find(|e| e.identifier == "document_number")
+            .expect("document_number not found");
+        assert!(doc_num.value.as_ref().unwrap().contains("123456789"));
+    }
+
+    #[test]
+    fn test_verify_issuer_signature_valid() {
+        // 1. Generate Issuer Key
+        let issuer_key = SigningKey::random(&mut OsRng);
+        let issuer_key_pem = issuer_key.to_pkcs8_pem(LineEnding::LF).unwrap().to_string();
+
+        // 2. Generate Issuer Certificate (Self-signed for simplicity)
+        let subject_name: Name = "CN=Test Issuer".parse().unwrap();
+        let serial_number = SerialNumber::from(1u64);
+        let validity = Validity::from_now(Duration::from_secs(3600)).unwrap();
+
+        let spki = SubjectPublicKeyInfoOwned::from_key(issuer_key.verifying_key().clone()).unwrap();
+
+        let builder = CertificateBuilder::new(
+            Profile::Root,
+            serial_number,
+            validity,
+            subject_name,
+            spki,
+            &issuer_key,
+        )
+        .unwrap();
+
+        let cert = builder.build::<p256::ecdsa::DerSignature>().unwrap();
+        let cert_pem = cert.to_pem(LineEnding::LF).unwrap();
+
+        // 3. Generate Holder Key (JWK)
+        let holder_key = SigningKey::random(&mut OsRng);
+        let point = holder_key.verifying_key().to_encoded_point(false);
+        let x = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(point.x().unwrap());
+        let y = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(point.y().unwrap());
+
+        let holder_jwk = serde_json::json!({
+            "kty": "EC",
+            "crv": "P-256",
+            "x": x,
+            "y": y
+        })
+        .to_string();
+
+        // 4. Sample Data
+        let mdl_items = serde_json::json!({
+            "family_name": "Doe",
+            "given_name": "John",
+            "birth_date": "1990-01-01",
+            "issue_date": "2023-01-01",
+            "expiry_date": "2028-01-01",
+            "issuing_country": "US",
+            "issuing_authority": "DMV",
+            "document_number": "123456789",
+            "portrait": "SGVsbG8gV29ybGQ=",
+            "driving_privileges": [
+                {
+                    "vehicle_category_code": "B",
+                    "issue_date": "2023-01-01",
+                    "expiry_date": "2028-01-01"
+                }
+            ],
+            "un_distinguishing_sign": "USA"
+        })
+        .to_string();
+
+        // 5. Create mdoc
+        let mdoc = Mdoc::create_and_sign_mdl(
+            mdl_items,
+            None,
+            holder_jwk,
+            cert_pem.clone(),
+            issuer_key_pem,
+        )
+        .expect("Failed to create mdoc");
 
         // 6. Verify issuer signature without trust anchors (just signature check)
-        let result = mdoc.verify_issuer_signature(None, false);
+        let result = mdoc.verify_issuer_signature(None, false, None);
         assert!(result.is_ok(), "Verification should succeed: {:?}", result);
 
-        let verification = result.unwrap();
-        assert!(verification.verified, "Signature should be valid");
-        // Note: setup_certificate_chain creates an intermediate "SpruceID Test DS" certificate
-        // signed by the provided IACA cert, so the common name is from the DS cert, not IACA
+        let verification = result.unwrap();
+        assert!(verification.verified, "Signature should be valid");
+        // Note: setup_certificate_chain creates an intermediate "SpruceID Test DS" certificate
+        // signed by the provided IACA cert, so the common name is from the DS cert, not IACA
+        assert_eq!(
+            verification.common_name,
+            Some("SpruceID Test DS".to_string()),
+            "Common name should match DS certificate"
+        );
+        assert!(verification.error.is_none(), "No error expected");
+
+        // Note: We skip the trust anchor test here because the test certificate doesn't meet
+        // all mDL validation requirements (country, state, CRL distribution points, etc.).
+        // The test_verify_issuer_signature_invalid_trust_anchor test covers the trust anchor
+        // validation path. For a real mDL issuance, proper IACA certificates would be used.
+    }
+
+    #[test]
+    fn test_verify_issuer_signature_invalid_trust_anchor() {
+        // 1. Generate Issuer Key and Certificate
+        let issuer_key = SigningKey::random(&mut OsRng);
+        let issuer_key_pem = issuer_key.to_pkcs8_pem(LineEnding::LF).unwrap().to_string();
+
+        let subject_name: Name = "CN=Test Issuer".parse().unwrap();
+        let serial_number = SerialNumber::from(1u64);
+        let validity = Validity::from_now(Duration::from_secs(3600)).unwrap();
+
+        let spki = SubjectPublicKeyInfoOwned::from_key(issuer_key.verifying_key().clone()).unwrap();
+
+        let builder = CertificateBuilder::new(
+            Profile::Root,
+            serial_number,
+            validity,
+            subject_name,
+            spki,
+            &issuer_key,
+        )
+        .unwrap();
+
+        let cert = builder.build::<p256::ecdsa::DerSignature>().unwrap();
+        let cert_pem = cert.to_pem(LineEnding::LF).unwrap();
+
+        // 2. Generate a DIFFERENT key for a different trust anchor
+        let other_key = SigningKey::random(&mut OsRng);
+        let other_name: Name = "CN=Other Issuer".parse().unwrap();
+        let other_spki =
+            SubjectPublicKeyInfoOwned::from_key(other_key.verifying_key().clone()).unwrap();
+
+        let other_builder = CertificateBuilder::new(
+            Profile::Root,
+            SerialNumber::from(2u64),
+            Validity::from_now(Duration::from_secs(3600)).unwrap(),
+            other_name,
+            other_spki,
+            &other_key,
+        )
+        .unwrap();
+
+        let other_cert = other_builder.build::<p256::ecdsa::DerSignature>().unwrap();
+        let other_cert_pem = other_cert.to_pem(LineEnding::LF).unwrap();
+
+        // 3. Generate Holder Key (JWK)
+        let holder_key = SigningKey::random(&mut OsRng);
+        let point = holder_key.verifying_key().to_encoded_point(false);
+        let x = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(point.x().unwrap());
+        let y = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(point.y().unwrap());
+
+        let holder_jwk = serde_json::json!({
+            "kty": "EC",
+            "crv": "P-256",
+            "x": x,
+            "y": y
+        })
+        .to_string();
+
+        // 4. Sample Data
+        let mdl_items = serde_json::json!({
+            "family_name": "Doe",
+            "given_name": "John",
+            "birth_date": "1990-01-01",
+            "issue_date": "2023-01-01",
+            "expiry_date": "2028-01-01",
+            "issuing_country": "US",
+            "issuing_authority": "DMV",
+            "document_number": "123456789",
+            "portrait": "SGVsbG8gV29ybGQ=",
+            "driving_privileges": [
+                {
+                    "vehicle_category_code": "B",
+                    "issue_date": "2023-01-01",
+                    "expiry_date": "2028-01-01"
+                }
+            ],
+            "un_distinguishing_sign": "USA"
+        })
+        .to_string();
+
+        // 5. Create mdoc with original issuer
+        let mdoc = Mdoc::create_and_sign_mdl(mdl_items, None, holder_jwk, cert_pem, issuer_key_pem)
+            .expect("Failed to create mdoc");
+
+        // 6. Try to verify with WRONG trust anchor - should fail validation
+        let result = mdoc.verify_issuer_signature(Some(vec![other_cert_pem]), false, None);
+
+        // The verification should fail because the mdoc's issuer cert is not trusted
+        assert!(
+            result.is_err(),
+            "Verification should fail with untrusted anchor"
+        );
+
+        match result {
+            Err(super::MdocVerificationError::X5ChainValidationFailed(_)) => {
+                // Expected - the x5chain validation should fail
+            }
+            Err(e) => {
+                panic!("Unexpected error type: {:?}", e);
+            }
+            Ok(_) => {
+                panic!("Should have failed verification");
+            }
+        }
+    }
+
+    #[test]
+    fn test_create_and_sign() {
+        // 1. Generate Issuer Key
+        let issuer_key = SigningKey::random(&mut OsRng);
+        let issuer_key_pem = issuer_key.to_pkcs8_pem(LineEnding::LF).unwrap().to_string();
+
+        // 2. Generate Issuer Certificate
+        let subject_name: Name = "CN=Test Issuer".parse().unwrap();
+        let serial_number = SerialNumber::from(1u64);
+        let validity = Validity::from_now(Duration::from_secs(3600)).unwrap();
+        let spki = SubjectPublicKeyInfoOwned::from_key(issuer_key.verifying_key().clone()).unwrap();
+
+        let builder = CertificateBuilder::new(
+            Profile::Root,
+            serial_number,
+            validity,
+            subject_name,
+            spki,
+            &issuer_key,
+        )
+        .unwrap();
+
+        let cert = builder.build::<p256::ecdsa::DerSignature>().unwrap();
+        let cert_pem = cert.to_pem(LineEnding::LF).unwrap();
+
+        // 3. Generate Holder Key (JWK)
+        let holder_key = SigningKey::random(&mut OsRng);
+        let point = holder_key.verifying_key().to_encoded_point(false);
+        let x = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(point.x().unwrap());
+        let y = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(point.y().unwrap());
+
+        let holder_jwk = serde_json::json!({
+            "kty": "EC",
+            "crv": "P-256",
+            "x": x,
+            "y": y
+        })
+        .to_string();
+
+        // 4. Sample Data (Generic Namespace)
+        let mut namespaces = HashMap::new();
+        let mut custom_ns = HashMap::new();
+        let mut cursor = Cursor::new(Vec::new());
+        ciborium::into_writer(&Value::Text("custom-value".to_string()), &mut cursor).unwrap();
+        custom_ns.insert("custom-element".to_string(), cursor.into_inner());
+        namespaces.insert("com.example.custom".to_string(), custom_ns);
+
+        // 5. Call function
+        let result = Mdoc::create_and_sign(
+            "com.example.doc".to_string(),
+            namespaces,
+            holder_jwk,
+            cert_pem,
+            issuer_key_pem,
+        );
+
+        assert!(result.is_ok());
+        let mdoc = result.unwrap();
+        assert_eq!(mdoc.doctype(), "com.example.doc");
+
+        let details = mdoc.details();
+        let ns = Namespace("com.example.custom".to_string());
+        let elements = details.get(&ns).expect("Namespace not found");
+        let element = elements
+            .iter()
+            .find(|e| e.identifier == "custom-element")
+            .expect("Element not found");
+        assert!(element.value.as_ref().unwrap().contains("custom-value"));
+    }
+
+    #[test]
+    fn test_create_and_sign_mdl_p384() {
+        // 1. Generate a P-384 IACA
+        let issuer_key = p384::ecdsa::SigningKey::random(&mut OsRng);
+        let issuer_key_pem = issuer_key.to_pkcs8_pem(LineEnding::LF).unwrap().to_string();
+        let spki = SubjectPublicKeyInfoOwned::from_key(*issuer_key.verifying_key()).unwrap();
+        let cert = CertificateBuilder::new(
+            Profile::Root,
+            SerialNumber::from(1u64),
+            Validity::from_now(Duration::from_secs(3600)).unwrap(),
+            "CN=Test P-384 Issuer".parse().unwrap(),
+            spki,
+            &issuer_key,
+        )
+        .unwrap()
+        .build::<p384::ecdsa::DerSignature>()
+        .unwrap();
+        let cert_pem = cert.to_pem(LineEnding::LF).unwrap();
+
+        // 2. The ephemeral document signer is minted on the same curve
+        let (_, _, signer) =
+            setup_issuer_certificate_chain(cert_pem.clone(), issuer_key_pem.clone()).unwrap();
+        assert!(matches!(signer, IssuerSigningKey::P384(_)));
+
+        // 3. Issue an mDL signed with ES384
+        let holder_jwk = crate::mdl::util::P256KeyPair::new().public_jwk();
+        let mdl_items = serde_json::json!({
+            "family_name": "Doe",
+            "given_name": "John",
+            "birth_date": "1990-01-01",
+            "issue_date": "2023-01-01",
+            "expiry_date": "2028-01-01",
+            "issuing_country": "US",
+            "issuing_authority": "DMV",
+            "document_number": "123456789",
+            "portrait": "SGVsbG8gV29ybGQ=",
+            "driving_privileges": [],
+            "un_distinguishing_sign": "USA"
+        })
+        .to_string();
+
+        let mdoc = Mdoc::create_and_sign_mdl(mdl_items, None, holder_jwk, cert_pem, issuer_key_pem)
+            .expect("Failed to create mdoc with a P-384 issuer");
+        assert_eq!(mdoc.doctype(), "org.iso.18013.5.1.mDL");
+        assert!(mdoc.check_digests().is_empty());
+    }
+
+    #[test]
+    fn test_ed25519_keys() {
+        use ed25519_dalek::pkcs8::EncodePrivateKey as _;
+
+        // 1. Ed25519 issuer keys are detected from their PKCS#8 encoding
+        let issuer_key = ed25519_dalek::SigningKey::generate(&mut OsRng);
+        let issuer_key_pem = issuer_key.to_pkcs8_pem(LineEnding::LF).unwrap();
+        assert!(matches!(
+            IssuerSigningKey::from_pkcs8_pem(&issuer_key_pem).unwrap(),
+            IssuerSigningKey::Ed25519(_)
+        ));
+
+        // 2. Ed25519 holder JWKs become OKP device keys
+        let holder_key = ed25519_dalek::SigningKey::generate(&mut OsRng);
+        let x = holder_key.verifying_key().to_bytes();
+        let holder_jwk = serde_json::json!({
+            "kty": "OKP",
+            "crv": "Ed25519",
+            "x": BASE64_URL_SAFE_NO_PAD.encode(x),
+        });
+        assert_eq!(
+            device_key_from_jwk(&holder_jwk.to_string()).unwrap(),
+            CoseKey::OKP {
+                crv: OKPCurve::Ed25519,
+                x: x.to_vec(),
+            }
+        );
+
+        // 3. Other OKP curves are rejected
+        let x25519_jwk = serde_json::json!({
+            "kty": "OKP",
+            "crv": "X25519",
+            "x": BASE64_URL_SAFE_NO_PAD.encode(x),
+        });
+        assert!(matches!(
+            device_key_from_jwk(&x25519_jwk.to_string()),
+            Err(MdocInitError::InvalidJwk)
+        ));
+    }
+
+    #[test]
+    fn test_device_key_curves() {
+        // 1. P-384 holder keys are encoded with their own curve
+        let holder_key = p384::SecretKey::random(&mut OsRng);
+        let holder_jwk = holder_key.public_key().to_jwk_string();
+        match device_key_from_jwk(&holder_jwk).unwrap() {
+            CoseKey::EC2 {
+                crv: EC2Curve::P384,
+                x,
+                y: EC2Y::Value(y),
+            } => {
+                assert_eq!(x.len(), 48);
+                assert_eq!(y.len(), 48);
+            }
+            other => panic!("unexpected device key {other:?}"),
+        }
+
+        // 2. Coordinates must match the curve
+        let mismatched = holder_jwk.replace("P-384", "P-256");
+        assert!(matches!(
+            device_key_from_jwk(&mismatched),
+            Err(MdocInitError::InvalidJwk)
+        ));
+    }
+
+    #[test]
+    fn test_create_and_sign_async_invalid_jwk() {
+        let result = futures_executor::block_on(Mdoc::create_and_sign_async(
+            "org.iso.18013.5.1.mDL".to_string(),
+            HashMap::new(),
+            "not a jwk".to_string(),
+            String::new(),
+            String::new(),
+        ));
+        assert!(matches!(result, Err(MdocInitError::InvalidJwk)));
+    }
+
+    #[test]
+    fn test_validate_mdl_items() {
+        let mdl_items = serde_json::json!({
+            "family_name": "Doe",
+            "given_name": "John",
+            "birth_date": "01/01/1990",
+            "issue_date": "2023-01-01T00:00:00Z",
+            "expiry_date": "2028-01-01",
+            "issuing_country": "US",
+            "issuing_authority": "DMV",
+            "document_number": "123456789",
+            "driving_privileges": [],
+            "un_distinguishing_sign": "USA",
+            "age_over_18": true
+        });
+        let errors = validate_mdl_items(&mdl_items.to_string(), None);
+
+        let field = |identifier: &str| {
+            errors
+                .iter()
+                .find(|e| e.identifier == identifier)
+                .unwrap_or_else(|| panic!("no error for {identifier}"))
+        };
+        assert_eq!(errors.len(), 2);
+        assert_eq!(field("portrait").namespace, "org.iso.18013.5.1");
+        assert_eq!(field("portrait").reason, "mandatory element is missing");
+        field("birth_date");
+
+        let error = MdocInitError::InvalidElements(errors);
+        assert!(error.to_string().contains("org.iso.18013.5.1/portrait"));
+    }
+
+    #[test]
+    fn test_validate_aamva_items() {
+        let aamva_items = serde_json::json!({
+            "family_name_truncation": "N",
+            "sex": 3,
+            "organ_donor": 1,
+            "race_ethnicity": "AI",
+            "DHS_compliance": "Y",
+            "hazmat_endorsement_expiration_date": "2024-01-30",
+        });
+        let errors = validate_mdl_items(&sample_mdl_items(), Some(&aamva_items.to_string()));
+
+        let mut identifiers: Vec<_> = errors.iter().map(|e| e.identifier.as_str()).collect();
+        identifiers.sort();
+        assert_eq!(
+            identifiers,
+            vec!["DHS_compliance", "given_name_truncation", "sex"]
+        );
+        assert!(
+            errors
+                .iter()
+                .all(|e| e.namespace == "org.iso.18013.5.1.aamva")
+        );
+        let dhs_compliance = errors
+            .iter()
+            .find(|e| e.identifier == "DHS_compliance")
+            .unwrap();
+        assert_eq!(dhs_compliance.reason, "expected one of F, N");
+    }
+
+    #[test]
+    fn test_json_namespaces() {
+        let namespaces = HashMap::from([
+            (
+                "eu.europa.ec.eudi.pid.1".to_string(),
+                serde_json::json!({"family_name": "Doe", "birth_date": "1990-01-01"}).to_string(),
+            ),
+            (
+                "org.example.1".to_string(),
+                serde_json::json!({"member_since": 2020, "card": {"$bytes": "AAE"}}).to_string(),
+            ),
+        ]);
+
+        // 1. Known namespaces are validated against their definitions
+        let Err(MdocInitError::InvalidElements(errors)) = json_namespaces(namespaces.clone())
+        else {
+            panic!("expected invalid PID elements");
+        };
+        assert!(!errors.is_empty());
+        assert!(
+            errors
+                .iter()
+                .all(|e| e.namespace == "eu.europa.ec.eudi.pid.1" && e.reason.contains("missing"))
+        );
+
+        // 2. Unknown namespaces are converted generically
+        let mut namespaces = namespaces;
+        namespaces.remove("eu.europa.ec.eudi.pid.1");
+        let converted = json_namespaces(namespaces).unwrap();
+        assert_eq!(converted["org.example.1"]["card"], Value::Bytes(vec![0, 1]));
+    }
+
+    #[test]
+    fn test_duplicate_elements() {
+        let duplicated = r#"{"family_name": "Doe", "given_name": "John", "family_name": "Roe"}"#;
+
+        // 1. Every conversion path rejects repeated identifiers
+        let namespaces = HashMap::from([("org.example.1".to_string(), duplicated.to_string())]);
+        for (result, expected_namespace) in [
+            (generic_namespaces(namespaces.clone()), "org.example.1"),
+            (json_namespaces(namespaces), "org.example.1"),
+            (mdl_namespaces(duplicated, None), MDL_NAMESPACE),
+            (typed_namespaces(&[(&EU_PID, duplicated)]), EU_PID.namespace),
+        ] {
+            let Err(MdocInitError::DuplicateElement {
+                namespace,
+                identifier,
+            }) = result
+            else {
+                panic!("expected a duplicate element error for {expected_namespace}");
+            };
+            assert_eq!(namespace, expected_namespace);
+            assert_eq!(identifier, "family_name");
+        }
+
+        // 2. The same identifier may appear in different namespaces
+        let element = serde_json::json!({"family_name": "Doe"}).to_string();
+        let namespaces = HashMap::from([
+            ("org.example.1".to_string(), element.clone()),
+            ("org.example.2".to_string(), element),
+        ]);
+        assert_eq!(json_namespaces(namespaces).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_mdoc_issuer_requires_matching_key() {
+        // 1. Generate a document signer certificate
+        let ds_key = SigningKey::random(&mut OsRng);
+        let ds_key_pem = ds_key.to_pkcs8_pem(LineEnding::LF).unwrap().to_string();
+        let spki = SubjectPublicKeyInfoOwned::from_key(*ds_key.verifying_key()).unwrap();
+        let ds_cert = CertificateBuilder::new(
+            Profile::Root,
+            SerialNumber::from(2u64),
+            Validity::from_now(Duration::from_secs(3600)).unwrap(),
+            "CN=Test DS".parse().unwrap(),
+            spki,
+            &ds_key,
+        )
+        .unwrap()
+        .build::<p256::ecdsa::DerSignature>()
+        .unwrap();
+        let ds_cert_pem = ds_cert.to_pem(LineEnding::LF).unwrap();
+
+        // 2. The certificate and its own key are accepted
+        assert!(MdocIssuer::new(ds_cert_pem.clone(), ds_key_pem, String::new()).is_ok());
+
+        // 3. A key that does not match the certificate is rejected
+        let other_key_pem = SigningKey::random(&mut OsRng)
+            .to_pkcs8_pem(LineEnding::LF)
+            .unwrap()
+            .to_string();
+        assert!(matches!(
+            MdocIssuer::new(ds_cert_pem, other_key_pem, String::new()),
+            Err(MdocInitError::InvalidDocumentSigner(_))
+        ));
+    }
+
+    #[test]
+    fn test_issue_and_verify_ed25519() {
+        use ed25519_dalek::{Signer as _, pkcs8::EncodePrivateKey as _};
+        use x509_cert::der::asn1::BitString;
+
+        // 1. A self-signed Ed25519 document signer certificate
+        let ds_key = ed25519_dalek::SigningKey::generate(&mut OsRng);
+        let ds_key_pem = ds_key.to_pkcs8_pem(LineEnding::LF).unwrap().to_string();
+        let spki = SubjectPublicKeyInfoOwned::from_key(ds_key.verifying_key()).unwrap();
+        let mut builder = CertificateBuilder::new(
+            Profile::Root,
+            SerialNumber::from(3u64),
+            Validity::from_now(Duration::from_secs(3600)).unwrap(),
+            "CN=Test EdDSA DS".parse().unwrap(),
+            spki,
+            &ds_key,
+        )
+        .unwrap();
+        let tbs = builder.finalize().unwrap();
+        let signature = BitString::from_bytes(&ds_key.sign(&tbs).to_bytes()).unwrap();
+        let ds_cert_pem = builder
+            .assemble(signature)
+            .unwrap()
+            .to_pem(LineEnding::LF)
+            .unwrap();
+
+        // 2. Issue an mDL signed with EdDSA
+        let issuer = MdocIssuer::new(ds_cert_pem, ds_key_pem, String::new()).unwrap();
+        let holder_jwk = crate::mdl::util::P256KeyPair::new().public_jwk();
+        let mdoc = issuer
+            .issue_mdl(sample_mdl_items(), None, holder_jwk, None)
+            .expect("Failed to issue mdoc with an Ed25519 issuer");
+        assert_eq!(
+            mdoc.document().issuer_auth.inner.protected.header.alg,
+            Some(coset::RegisteredLabelWithPrivate::Assigned(
+                coset::iana::Algorithm::EdDSA
+            ))
+        );
+
+        // 3. The issuer signature verifies against the document signer certificate
+        let result = mdoc
+            .verify_issuer_signature(None, false, None)
+            .expect("Failed to verify the EdDSA issuer signature");
+        assert!(result.verified);
+        assert!(result.digest_mismatches.is_empty());
+        assert_eq!(result.common_name.as_deref(), Some("Test EdDSA DS"));
+
+        // 4. A tampered signature is rejected
+        let mut document = mdoc.document().clone();
+        document.issuer_auth.inner.signature[0] ^= 0xff;
+        let tampered = Mdoc::new_from_parts(document, mdoc.key_alias.clone());
+        assert!(matches!(
+            tampered.verify_issuer_signature(None, false, None),
+            Err(MdocVerificationError::IssuerAuthFailed(_))
+        ));
+    }
+
+    /// An issuer with a self-signed P-256 document signer certificate.
+    fn test_issuer() -> Arc<MdocIssuer> {
+        let (ds_cert, ds_key_pem) = test_ds_certificate();
+        let ds_cert_pem = ds_cert.to_pem(LineEnding::LF).unwrap();
+
+        MdocIssuer::new(ds_cert_pem, ds_key_pem, String::new()).unwrap()
+    }
+
+    /// A self-signed P-256 document signer certificate and its PKCS#8 PEM key.
+    fn test_ds_certificate() -> (Certificate, String) {
+        let ds_key = SigningKey::random(&mut OsRng);
+        let ds_key_pem = ds_key.to_pkcs8_pem(LineEnding::LF).unwrap().to_string();
+        let spki = SubjectPublicKeyInfoOwned::from_key(*ds_key.verifying_key()).unwrap();
+        let ds_cert = CertificateBuilder::new(
+            Profile::Root,
+            SerialNumber::from(2u64),
+            Validity::from_now(Duration::from_secs(3600)).unwrap(),
+            "CN=Test DS".parse().unwrap(),
+            spki,
+            &ds_key,
+        )
+        .unwrap()
+        .build::<p256::ecdsa::DerSignature>()
+        .unwrap();
+
+        (ds_cert, ds_key_pem)
+    }
+
+    #[test]
+    fn test_issue_with_x5chain() {
+        let (ds_cert, ds_key_pem) = test_ds_certificate();
+        let (other_cert, _) = test_ds_certificate();
+        let x5chain = vec![ds_cert.to_der().unwrap(), other_cert.to_der().unwrap()];
+
+        // 1. An empty chain is rejected
+        assert!(matches!(
+            MdocIssuer::new_with_x5chain(Vec::new(), ds_key_pem.clone()),
+            Err(MdocInitError::InvalidDocumentSigner(_))
+        ));
+
+        // 2. The chain is placed in the x5chain header as given
+        let issuer = MdocIssuer::new_with_x5chain(x5chain.clone(), ds_key_pem).unwrap();
+        let holder_jwk = crate::mdl::util::P256KeyPair::new().public_jwk();
+        let mdoc = issuer
+            .issue_mdl(sample_mdl_items(), None, holder_jwk, None)
+            .expect("Failed to issue mdoc");
+        let header = mdoc
+            .document()
+            .issuer_auth
+            .unprotected
+            .rest
+            .iter()
+            .find(|(label, _)| *label == Label::Int(X5CHAIN_COSE_HEADER_LABEL))
+            .map(|(_, value)| value.clone())
+            .expect("x5chain header missing");
+        assert_eq!(
+            header,
+            Value::Array(x5chain.into_iter().map(Value::Bytes).collect())
+        );
+    }
+
+    #[test]
+    fn test_matches_request() {
+        let mdoc = test_issuer()
+            .issue_mdl(
+                sample_mdl_items(),
+                None,
+                crate::mdl::util::P256KeyPair::new().public_jwk(),
+                None,
+            )
+            .expect("Failed to issue mdoc");
+        let request = |doc_type: &str| ItemsRequest {
+            doc_type: doc_type.to_string(),
+            namespaces: HashMap::from([(
+                MDL_NAMESPACE.to_string(),
+                HashMap::from([
+                    ("family_name".to_string(), false),
+                    ("age_over_99".to_string(), false),
+                ]),
+            )]),
+            reader_common_name: None,
+            reader_authentication: crate::mdl::reader::AuthenticationStatus::Unchecked,
+            reader_authentication_errors: None,
+        };
+
+        // 1. Availability is reported per element
+        let result = mdoc.matches_request(request(MDL_DOC_TYPE));
+        assert!(result.doc_type_matches);
+        assert!(!result.satisfied);
+        let available: Vec<_> = result
+            .elements
+            .iter()
+            .map(|element| (element.element_identifier.as_str(), element.available))
+            .collect();
+        assert_eq!(available, [("age_over_99", false), ("family_name", true)]);
+
+        // 2. Nothing is available from an mdoc of another docType
+        let result = mdoc.matches_request(request("org.example.other"));
+        assert!(!result.doc_type_matches);
+        assert!(result.elements.iter().all(|element| !element.available));
+    }
+
+    #[test]
+    fn test_select_credentials() {
+        let issue = |items: serde_json::Value| {
+            test_issuer()
+                .issue_mdl(
+                    items.to_string(),
+                    None,
+                    crate::mdl::util::P256KeyPair::new().public_jwk(),
+                    None,
+                )
+                .expect("Failed to issue mdoc")
+        };
+        let partial = issue(serde_json::from_str(&sample_mdl_items()).unwrap());
+        let mut items: serde_json::Value = serde_json::from_str(&sample_mdl_items()).unwrap();
+        items["age_over_21"] = true.into();
+        let complete = issue(items);
+        let request = |doc_type: &str| ItemsRequest {
+            doc_type: doc_type.to_string(),
+            namespaces: HashMap::from([(
+                MDL_NAMESPACE.to_string(),
+                HashMap::from([
+                    ("family_name".to_string(), false),
+                    ("age_over_21".to_string(), false),
+                ]),
+            )]),
+            reader_common_name: None,
+            reader_authentication: crate::mdl::reader::AuthenticationStatus::Unchecked,
+            reader_authentication_errors: None,
+        };
+
+        let selections = select_credentials(
+            vec![request(MDL_DOC_TYPE), request("org.example.other")],
+            vec![partial.clone(), complete.clone()],
+        );
+
+        // 1. The mdoc holding every requested element is picked
+        assert_eq!(selections[0].doc_type, MDL_DOC_TYPE);
+        assert_eq!(selections[0].mdoc.as_ref().unwrap().id(), complete.id());
+        assert!(selections[0].match_result.as_ref().unwrap().satisfied);
+
+        // 2. No mdoc is picked for an unknown docType
+        assert_eq!(selections[1].doc_type, "org.example.other");
+        assert!(selections[1].mdoc.is_none());
+        assert!(selections[1].match_result.is_none());
+
+        // 3. Mdocs outside their validity period are never picked
+        let day = Duration::from_secs(24 * 60 * 60);
+        let issue_complete = |signed: SystemTime| {
+            let mut items: serde_json::Value = serde_json::from_str(&sample_mdl_items()).unwrap();
+            items["age_over_21"] = true.into();
+            test_issuer()
+                .issue_mdl(
+                    items.to_string(),
+                    None,
+                    crate::mdl::util::P256KeyPair::new().public_jwk(),
+                    Some(IssuanceOptions {
+                        signed: Some(signed),
+                        ..Default::default()
+                    }),
+                )
+                .unwrap()
+        };
+        let expired = issue_complete(SystemTime::now() - 60 * day);
+        let not_yet_valid = issue_complete(SystemTime::now() + day);
+        let selections = select_credentials(
+            vec![request(MDL_DOC_TYPE)],
+            vec![expired.clone(), not_yet_valid.clone(), partial.clone()],
+        );
+        assert_eq!(selections[0].mdoc.as_ref().unwrap().id(), partial.id());
+        let selections = select_credentials(vec![request(MDL_DOC_TYPE)], vec![expired]);
+        assert!(selections[0].mdoc.is_none());
+    }
+
+    #[test]
+    fn test_issuance_report() {
+        let issuer = test_issuer();
+        let holder_jwk = crate::mdl::util::P256KeyPair::new().public_jwk();
+        let mdoc = issuer
+            .issue_mdl(sample_mdl_items(), None, holder_jwk.clone(), None)
+            .expect("Failed to issue mdoc");
+
+        let report = mdoc.issuance_report();
+        assert_eq!(report.doc_type, MDL_DOC_TYPE);
+        assert_eq!(report.digest_algorithm, "SHA-256");
+        assert_eq!(report.x5chain_common_names, vec!["Test DS".to_string()]);
+        for (namespace, elements) in mdoc.details() {
+            assert_eq!(
+                report.value_digest_counts.get(&namespace.0).copied(),
+                Some(elements.len() as u32)
+            );
+        }
+        assert!(report.valid_from <= report.valid_until);
+
+        // The thumbprint identifies the holder key across issuances
+        let thumbprint = report.device_key_thumbprint.expect("thumbprint missing");
+        assert_eq!(thumbprint.len(), 43);
+        let same_holder = issuer
+            .issue_mdl(sample_mdl_items(), None, holder_jwk, None)
+            .unwrap();
+        assert_eq!(
+            same_holder.issuance_report().device_key_thumbprint,
+            Some(thumbprint.clone())
+        );
+        let other_holder = issuer
+            .issue_mdl(
+                sample_mdl_items(),
+                None,
+                crate::mdl::util::P256KeyPair::new().public_jwk(),
+                None,
+            )
+            .unwrap();
+        assert_ne!(
+            other_holder.issuance_report().device_key_thumbprint,
+            Some(thumbprint)
+        );
+    }
+
+    #[test]
+    fn test_issue_mdl_batch() {
+        let issuer = test_issuer();
+        let holder_jwks: Vec<String> = (0..3)
+            .map(|_| crate::mdl::util::P256KeyPair::new().public_jwk())
+            .collect();
+        let stagger = Duration::from_secs(60 * 60 * 24);
+
+        // 1. One mdoc per holder key, with distinct device keys and salts
+        let batch = issuer
+            .issue_mdl_batch(sample_mdl_items(), None, holder_jwks.clone(), stagger, None)
+            .expect("Failed to issue batch");
+        assert_eq!(batch.len(), 3);
+        let thumbprints: std::collections::BTreeSet<_> = batch
+            .iter()
+            .map(|mdoc| mdoc.issuance_report().device_key_thumbprint)
+            .collect();
+        assert_eq!(thumbprints.len(), 3);
+        let salts = |mdoc: &Arc<Mdoc>| {
+            mdoc.document().namespaces[MDL_NAMESPACE]["family_name"]
+                .as_ref()
+                .random
+                .clone()
+        };
+        assert_ne!(salts(&batch[0]), salts(&batch[1]));
+
+        // 2. Validity periods are staggered, signing times drawn from the hour before the
+        // batch was signed
+        let signed = |mdoc: &Arc<Mdoc>| mdoc.document().mso.validity_info.signed;
+        for pair in batch.windows(2) {
+            let (first, second) = (
+                &pair[0].document().mso.validity_info,
+                &pair[1].document().mso.validity_info,
+            );
+            assert_eq!(second.valid_from - first.valid_from, stagger);
+        }
+        let now = OffsetDateTime::now_utc();
+        for mdoc in &batch {
+            assert!(signed(mdoc) <= now);
+            assert!(signed(mdoc) >= now - BATCH_SIGNED_JITTER - Duration::from_secs(60));
+            assert!(signed(mdoc) <= mdoc.document().mso.validity_info.valid_from);
+        }
+        let signing_times: std::collections::BTreeSet<_> = batch.iter().map(signed).collect();
+        assert!(signing_times.len() > 1);
+
+        // 3. Holder keys must be distinct
+        let repeated = vec![holder_jwks[0].clone(), holder_jwks[0].clone()];
+        assert!(matches!(
+            issuer.issue_mdl_batch(sample_mdl_items(), None, repeated, stagger, None),
+            Err(MdocInitError::InvalidOptions(_))
+        ));
+
+        // 4. A stagger past the representable times is an error, not a panic
+        for stagger in [Duration::MAX, Duration::from_secs(1 << 40)] {
+            assert!(matches!(
+                issuer.issue_mdl_batch(
+                    sample_mdl_items(),
+                    None,
+                    holder_jwks.clone(),
+                    stagger,
+                    None
+                ),
+                Err(MdocInitError::InvalidValidity(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_max_encoded_size() {
+        let issuer = test_issuer();
+        let holder_jwk = crate::mdl::util::P256KeyPair::new().public_jwk();
+        let mut items: serde_json::Value = serde_json::from_str(&sample_mdl_items()).unwrap();
+        items["portrait"] = BASE64_STANDARD.encode(vec![0xff; 20_000]).into();
+        let items = items.to_string();
+        let with_limit = |max_encoded_size| {
+            Some(IssuanceOptions {
+                max_encoded_size: Some(max_encoded_size),
+                ..Default::default()
+            })
+        };
+
+        // 1. The encoded size is reported
+        let mdoc = issuer
+            .issue_mdl(items.clone(), None, holder_jwk.clone(), None)
+            .expect("Failed to issue mdoc");
+        let size = mdoc.issuance_report().encoded_size.unwrap();
+        assert!(size > 20_000);
+
+        // 2. Credentials within the limit are issued, digest IDs may vary in length
+        assert!(
+            issuer
+                .issue_mdl(
+                    items.clone(),
+                    None,
+                    holder_jwk.clone(),
+                    with_limit(size + 64)
+                )
+                .is_ok()
+        );
+
+        // 3. Oversized credentials fail, naming the portrait first
+        let Err(MdocInitError::EncodedSizeExceeded {
+            largest_elements, ..
+        }) = issuer.issue_mdl(items, None, holder_jwk, with_limit(16_000))
+        else {
+            panic!("expected the size limit to be exceeded");
+        };
+        assert_eq!(largest_elements.len(), LARGEST_ELEMENTS_REPORTED);
+        assert!(largest_elements[0].starts_with("org.iso.18013.5.1/portrait ("));
+    }
+
+    fn sample_mdl_items() -> String {
+        serde_json::json!({
+            "family_name": "Doe",
+            "given_name": "John",
+            "birth_date": "1990-01-01",
+            "issue_date": "2023-01-01",
+            "expiry_date": "2028-01-01",
+            "issuing_country": "US",
+            "issuing_authority": "DMV",
+            "document_number": "123456789",
+            "portrait": "SGVsbG8gV29ybGQ=",
+            "driving_privileges": [],
+            "un_distinguishing_sign": "USA"
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn test_verify_device_signature() {
+        let key_pair = crate::mdl::util::P256KeyPair::new();
+        let mdoc = test_issuer()
+            .issue_mdl(sample_mdl_items(), None, key_pair.public_jwk(), None)
+            .expect("Failed to issue mdoc");
+
+        // 1. A raw or DER encoded signature of the payload proves possession of the key
+        let signature = key_pair.sign(b"challenge");
+        assert!(
+            mdoc.verify_device_signature(b"challenge".to_vec(), signature.clone())
+                .is_ok()
+        );
+        let der = p256::ecdsa::Signature::from_slice(&signature)
+            .unwrap()
+            .to_der()
+            .to_bytes()
+            .to_vec();
+        assert!(
+            mdoc.verify_device_signature(b"challenge".to_vec(), der)
+                .is_ok()
+        );
+        assert!(matches!(
+            mdoc.verify_device_signature(b"other".to_vec(), signature),
+            Err(MdocVerificationError::DeviceSignatureInvalid(_))
+        ));
+
+        // 2. The deviceSignature of the holder's response is verified over the
+        // DeviceAuthentication of the reader's session transcript
+        use crate::mdl::holder::MdlPresentationSession;
+        use crate::mdl::reader::establish_session;
+        use coset::CborSerializable;
+
+        let session = MdlPresentationSession::new(mdoc.clone(), Uuid::new_v4().to_string())
+            .expect("Failed to start the presentation session");
+        let requested_items = HashMap::from([(
+            MDL_NAMESPACE.to_string(),
+            HashMap::from([("family_name".to_string(), false)]),
+        )]);
+        let reader =
+            establish_session(session.get_qr_code_uri(), requested_items, None, None, None)
+                .unwrap();
+        session.handle_request(reader.request).unwrap();
+        let permitted = HashMap::from([(
+            MDL_DOC_TYPE.to_string(),
+            HashMap::from([(MDL_NAMESPACE.to_string(), vec!["family_name".to_string()])]),
+        )]);
+        let payload = session.generate_response(permitted).unwrap().payload;
+        let sig_structure: Value = ciborium::from_reader(payload.as_slice()).unwrap();
+        let protected = sig_structure.as_array().unwrap()[1].clone();
+        let device_signature = |protected: coset::ProtectedHeader| {
+            coset::CoseSign1 {
+                protected,
+                unprotected: Default::default(),
+                payload: None,
+                signature: key_pair.sign(&payload),
+            }
+            .to_vec()
+            .unwrap()
+        };
+        let signed = device_signature(coset::ProtectedHeader::from_cbor_bstr(protected).unwrap());
+        let transcript = reader.state.session_transcript();
+        assert!(
+            mdoc.verify_device_authentication(transcript.clone(), signed.clone())
+                .is_ok()
+        );
+        let null_transcript = vec![0xf6];
+        assert!(
+            mdoc.verify_device_authentication(null_transcript, signed)
+                .is_err()
+        );
+
+        // 3. A signature claiming another algorithm than that of the device key is
+        // rejected before it is verified
+        let es384 = coset::ProtectedHeader {
+            original_data: None,
+            header: coset::HeaderBuilder::new()
+                .algorithm(coset::iana::Algorithm::ES384)
+                .build(),
+        };
+        assert!(matches!(
+            mdoc.verify_device_authentication(transcript, device_signature(es384)),
+            Err(MdocVerificationError::DeviceSignatureInvalid(error))
+                if error.contains("signature algorithm")
+        ));
+    }
+
+    #[test]
+    fn test_seeded_issuance_is_reproducible() {
+        let issuer = test_issuer();
+        let holder_jwk = crate::mdl::util::P256KeyPair::new().public_jwk();
+        let options = |seed: u8| IssuanceOptions {
+            signed: Some(SystemTime::now()),
+            random_seed: Some(vec![seed; 32]),
+            ..Default::default()
+        };
+        let issue = |options: IssuanceOptions| {
+            issuer
+                .issue_mdl(sample_mdl_items(), None, holder_jwk.clone(), Some(options))
+                .expect("Failed to issue mdoc")
+        };
+
+        // 1. The same seed and signing time give byte-identical mdocs
+        let first = options(1);
+        let mdoc = issue(first.clone());
+        assert!(mdoc.check_digests().is_empty());
         assert_eq!(
-            verification.common_name,
-            Some("SpruceID Test DS".to_string()),
-            "Common name should match DS certificate"
+            mdoc.to_base64url_issuer_signed().unwrap(),
+            issue(first).to_base64url_issuer_signed().unwrap()
         );
-        assert!(verification.error.is_none(), "No error expected");
 
-        // Note: We skip the trust anchor test here because the test certificate doesn't meet
-        // all mDL validation requirements (country, state, CRL distribution points, etc.).
-        // The test_verify_issuer_signature_invalid_trust_anchor test covers the trust anchor
-        // validation path. For a real mDL issuance, proper IACA certificates would be used.
+        // 2. Another seed gives other salts
+        assert_ne!(
+            mdoc.to_base64url_issuer_signed().unwrap(),
+            issue(options(2)).to_base64url_issuer_signed().unwrap()
+        );
+
+        // 3. The seed must be 32 bytes
+        let result = issuer.issue_mdl(
+            sample_mdl_items(),
+            None,
+            holder_jwk.clone(),
+            Some(IssuanceOptions {
+                random_seed: Some(vec![1; 16]),
+                ..Default::default()
+            }),
+        );
+        assert!(matches!(result, Err(MdocInitError::InvalidOptions(_))));
     }
 
     #[test]
-    fn test_verify_issuer_signature_invalid_trust_anchor() {
-        // 1. Generate Issuer Key and Certificate
-        let issuer_key = SigningKey::random(&mut OsRng);
-        let issuer_key_pem = issuer_key.to_pkcs8_pem(LineEnding::LF).unwrap().to_string();
+    fn test_reissue_keeps_device_key() {
+        let issuer = test_issuer();
+        let holder_jwk = crate::mdl::util::P256KeyPair::new().public_jwk();
+        let signed = SystemTime::now() - Duration::from_secs(60 * 60 * 24 * 20);
+        let options = IssuanceOptions {
+            signed: Some(signed),
+            ..Default::default()
+        };
+        let mdoc = issuer
+            .issue_mdl(sample_mdl_items(), None, holder_jwk, Some(options))
+            .expect("Failed to issue mdoc");
 
-        let subject_name: Name = "CN=Test Issuer".parse().unwrap();
-        let serial_number = SerialNumber::from(1u64);
-        let validity = Validity::from_now(Duration::from_secs(3600)).unwrap();
+        let refreshed = issuer
+            .reissue(mdoc.clone(), None)
+            .expect("Failed to reissue mdoc");
 
-        let spki = SubjectPublicKeyInfoOwned::from_key(issuer_key.verifying_key().clone()).unwrap();
+        let (old, new) = (&mdoc.document().mso, &refreshed.document().mso);
+        assert!(new.validity_info.signed > old.validity_info.signed);
+        assert!(new.validity_info.valid_until > old.validity_info.valid_until);
+        assert_eq!(
+            new.device_key_info.device_key,
+            old.device_key_info.device_key
+        );
+        assert_eq!(refreshed.key_alias(), mdoc.key_alias());
+        assert_eq!(refreshed.details().len(), mdoc.details().len());
+        assert!(refreshed.check_digests().is_empty());
+    }
 
-        let builder = CertificateBuilder::new(
-            Profile::Root,
-            serial_number,
-            validity,
-            subject_name,
-            spki,
-            &issuer_key,
-        )
-        .unwrap();
+    #[test]
+    fn test_key_info() {
+        let issuer = test_issuer();
+        let holder_jwk = crate::mdl::util::P256KeyPair::new().public_jwk();
+        let mut attestation = Vec::new();
+        ciborium::into_writer(&Value::Text("attestation-1".into()), &mut attestation).unwrap();
+        let key_info = HashMap::from([(-1, attestation)]);
+
+        // 1. Entries are embedded in the DeviceKeyInfo and read back
+        let mdoc = issuer
+            .issue_mdl(
+                sample_mdl_items(),
+                None,
+                holder_jwk.clone(),
+                Some(IssuanceOptions {
+                    key_info: Some(key_info.clone()),
+                    ..Default::default()
+                }),
+            )
+            .expect("Failed to issue mdoc");
+        assert_eq!(mdoc.key_info(), Some(key_info.clone()));
+
+        // 2. Reissuing keeps them
+        let refreshed = issuer.reissue(mdoc, None).unwrap();
+        assert_eq!(refreshed.key_info(), Some(key_info));
+
+        // 3. Values must be CBOR
+        assert!(matches!(
+            issuer.issue_mdl(
+                sample_mdl_items(),
+                None,
+                holder_jwk,
+                Some(IssuanceOptions {
+                    key_info: Some(HashMap::from([(-1, vec![0xff])])),
+                    ..Default::default()
+                }),
+            ),
+            Err(MdocInitError::InvalidOptions(_))
+        ));
+    }
 
-        let cert = builder.build::<p256::ecdsa::DerSignature>().unwrap();
-        let cert_pem = cert.to_pem(LineEnding::LF).unwrap();
+    #[test]
+    fn test_assign_digest_ids() {
+        let mut rng = ChaCha20Rng::from_seed([0; 32]);
 
-        // 2. Generate a DIFFERENT key for a different trust anchor
-        let other_key = SigningKey::random(&mut OsRng);
-        let other_name: Name = "CN=Other Issuer".parse().unwrap();
-        let other_spki =
-            SubjectPublicKeyInfoOwned::from_key(other_key.verifying_key().clone()).unwrap();
+        let sequential = assign_digest_ids(DigestIdAssignment::Sequential, 5, &mut rng);
+        assert_eq!(sequential, vec![0, 1, 2, 3, 4]);
 
-        let other_builder = CertificateBuilder::new(
+        let mut shuffled = assign_digest_ids(DigestIdAssignment::Shuffled, 5, &mut rng);
+        shuffled.sort();
+        assert_eq!(shuffled, sequential);
+
+        let random = assign_digest_ids(DigestIdAssignment::Random, 100, &mut rng);
+        let distinct: std::collections::BTreeSet<_> = random.iter().collect();
+        assert_eq!(distinct.len(), 100);
+        assert!(random.iter().all(|id| *id >= 0));
+    }
+
+    #[test]
+    fn test_salt_length() {
+        let issuer = test_issuer();
+        let holder_jwk = crate::mdl::util::P256KeyPair::new().public_jwk();
+        let issue = |salt_length| {
+            issuer.issue_mdl(
+                sample_mdl_items(),
+                None,
+                holder_jwk.clone(),
+                Some(IssuanceOptions {
+                    salt_length: Some(salt_length),
+                    digest_ids: Some(DigestIdAssignment::Sequential),
+                    ..Default::default()
+                }),
+            )
+        };
+
+        // 1. Salts shorter than 16 bytes are rejected
+        assert!(matches!(issue(8), Err(MdocInitError::InvalidOptions(_))));
+
+        // 2. Longer salts are used for every element
+        let mdoc = issue(48).expect("Failed to issue mdoc");
+        assert!(mdoc.check_digests().is_empty());
+        let items = mdoc.document().namespaces["org.iso.18013.5.1"].values();
+        let mut digest_ids = Vec::new();
+        for item in items {
+            assert_eq!(item.as_ref().random.as_ref().len(), 48);
+            digest_ids.push(i32::from(item.as_ref().digest_id));
+        }
+        digest_ids.sort();
+        assert_eq!(digest_ids, (0..digest_ids.len() as i32).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_expected_update_before_signing_is_rejected() {
+        let holder_jwk = crate::mdl::util::P256KeyPair::new().public_jwk();
+        let options = IssuanceOptions {
+            expected_update: Some(SystemTime::UNIX_EPOCH),
+            ..Default::default()
+        };
+
+        let result = Mdoc::create_and_sign_with_options(
+            "org.iso.18013.5.1.mDL".to_string(),
+            HashMap::new(),
+            holder_jwk,
+            String::new(),
+            String::new(),
+            options,
+        );
+        assert!(matches!(result, Err(MdocInitError::InvalidValidity(_))));
+    }
+
+    #[test]
+    fn test_custom_validity_timestamps() {
+        let device_key =
+            device_key_from_jwk(&crate::mdl::util::P256KeyPair::new().public_jwk()).unwrap();
+        let signed = SystemTime::now() - Duration::from_secs(60 * 60);
+
+        // 1. valid_from may be later than the signing time
+        let options = IssuanceOptions {
+            signed: Some(signed),
+            valid_from: Some(signed + Duration::from_secs(60)),
+            ..Default::default()
+        };
+        assert!(
+            prepare_builder(
+                device_key.clone(),
+                BTreeMap::new(),
+                "org.iso.18013.5.1.mDL".to_string(),
+                &options
+            )
+            .is_ok()
+        );
+
+        // 2. But not earlier
+        let options = IssuanceOptions {
+            signed: Some(signed),
+            valid_from: Some(signed - Duration::from_secs(60)),
+            ..Default::default()
+        };
+        assert!(matches!(
+            prepare_builder(
+                device_key,
+                BTreeMap::new(),
+                "org.iso.18013.5.1.mDL".to_string(),
+                &options
+            ),
+            Err(MdocInitError::InvalidValidity(_))
+        ));
+    }
+
+    #[test]
+    fn test_new_from_base64url_encoded_issuer_signed_errors() {
+        let result = Mdoc::new_from_base64url_encoded_issuer_signed(
+            "not*valid*base64".to_string(),
+            KeyAlias("test".to_string()),
+        );
+        assert!(matches!(
+            result,
+            Err(MdocInitError::IssuerSignedBase64UrlDecoding)
+        ));
+
+        // Valid base64url, but the decoded bytes are not an IssuerSigned structure.
+        let result = Mdoc::new_from_base64url_encoded_issuer_signed(
+            BASE64_URL_SAFE_NO_PAD.encode([0x01, 0x02, 0x03]),
+            KeyAlias("test".to_string()),
+        );
+        assert!(matches!(
+            result,
+            Err(MdocInitError::IssuerSignedCborDecoding)
+        ));
+
+        // Valid base64url of truncated CBOR, a map missing its entries
+        let result = Mdoc::new_from_base64url_encoded_issuer_signed(
+            BASE64_URL_SAFE_NO_PAD.encode([0xa2, 0x6a]),
+            KeyAlias("test".to_string()),
+        );
+        assert!(matches!(
+            result,
+            Err(MdocInitError::IssuerSignedCborDecoding)
+        ));
+    }
+
+    #[test]
+    fn test_check_digests_on_issued_mdoc() {
+        let issuer_key = SigningKey::random(&mut OsRng);
+        let issuer_key_pem = issuer_key.to_pkcs8_pem(LineEnding::LF).unwrap().to_string();
+        let spki = SubjectPublicKeyInfoOwned::from_key(*issuer_key.verifying_key()).unwrap();
+        let cert = CertificateBuilder::new(
             Profile::Root,
-            SerialNumber::from(2u64),
+            SerialNumber::from(1u64),
             Validity::from_now(Duration::from_secs(3600)).unwrap(),
-            other_name,
-            other_spki,
-            &other_key,
+            "CN=Test Issuer".parse().unwrap(),
+            spki,
+            &issuer_key,
         )
+        .unwrap()
+        .build::<p256::ecdsa::DerSignature>()
         .unwrap();
+        let cert_pem = cert.to_pem(LineEnding::LF).unwrap();
 
-        let other_cert = other_builder.build::<p256::ecdsa::DerSignature>().unwrap();
-        let other_cert_pem = other_cert.to_pem(LineEnding::LF).unwrap();
-
-        // 3. Generate Holder Key (JWK)
-        let holder_key = SigningKey::random(&mut OsRng);
-        let point = holder_key.verifying_key().to_encoded_point(false);
-        let x = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(point.x().unwrap());
-        let y = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(point.y().unwrap());
-
-        let holder_jwk = serde_json::json!({
-            "kty": "EC",
-            "crv": "P-256",
-            "x": x,
-            "y": y
-        })
-        .to_string();
+        let holder_jwk = crate::mdl::util::P256KeyPair::new().public_jwk();
 
-        // 4. Sample Data
         let mdl_items = serde_json::json!({
             "family_name": "Doe",
             "given_name": "John",
@@ -912,111 +4007,169 @@ mod tests {
             "issuing_authority": "DMV",
             "document_number": "123456789",
             "portrait": "SGVsbG8gV29ybGQ=",
-            "driving_privileges": [
-                {
-                    "vehicle_category_code": "B",
-                    "issue_date": "2023-01-01",
-                    "expiry_date": "2028-01-01"
-                }
-            ],
+            "driving_privileges": [],
             "un_distinguishing_sign": "USA"
         })
         .to_string();
 
-        // 5. Create mdoc with original issuer
         let mdoc = Mdoc::create_and_sign_mdl(mdl_items, None, holder_jwk, cert_pem, issuer_key_pem)
             .expect("Failed to create mdoc");
 
-        // 6. Try to verify with WRONG trust anchor - should fail validation
-        let result = mdoc.verify_issuer_signature(Some(vec![other_cert_pem]), false);
-
-        // The verification should fail because the mdoc's issuer cert is not trusted
         assert!(
-            result.is_err(),
-            "Verification should fail with untrusted anchor"
+            mdoc.check_digests().is_empty(),
+            "Freshly issued mdoc should have matching digests"
         );
-
-        match result {
-            Err(super::MdocVerificationError::X5ChainValidationFailed(_)) => {
-                // Expected - the x5chain validation should fail
-            }
-            Err(e) => {
-                panic!("Unexpected error type: {:?}", e);
-            }
-            Ok(_) => {
-                panic!("Should have failed verification");
-            }
-        }
+        let verification = mdoc.verify_issuer_signature(None, false, None).unwrap();
+        assert!(verification.verified);
+        assert!(verification.digest_mismatches.is_empty());
+
+        // A tampered element value still carries a valid MSO signature, but fails its digest
+        let mut tampered = (*mdoc).clone();
+        tampered.inner.namespaces = NonEmptyMap::maybe_new(
+            tampered
+                .inner
+                .namespaces
+                .into_inner()
+                .into_iter()
+                .map(|(namespace, elements)| {
+                    let elements = elements
+                        .into_inner()
+                        .into_iter()
+                        .map(|(identifier, item)| {
+                            let mut item = item.into_inner();
+                            if identifier == "family_name" {
+                                item.element_value = Value::Text("Roe".to_string());
+                            }
+                            (identifier, Tag24::new(item).unwrap())
+                        })
+                        .collect();
+                    (namespace, NonEmptyMap::maybe_new(elements).unwrap())
+                })
+                .collect(),
+        )
+        .unwrap();
+        let verification = tampered.verify_issuer_signature(None, false, None).unwrap();
+        assert!(!verification.verified);
+        assert_eq!(verification.digest_mismatches.len(), 1);
+        assert_eq!(verification.digest_mismatches[0].identifier, "family_name");
+        assert!(verification.error.unwrap().contains("family_name"));
+
+        // Recording the tampered digest in the decoded MSO does not help, the digests
+        // being those of the MSO under the signature
+        let item = tampered.inner.namespaces[MDL_NAMESPACE]["family_name"].clone();
+        let digest = value_digest(&tampered.inner.mso.digest_algorithm, &item).unwrap();
+        let digest_id = item.as_ref().digest_id;
+        tampered
+            .inner
+            .mso
+            .value_digests
+            .get_mut(MDL_NAMESPACE)
+            .unwrap()
+            .insert(digest_id, digest.into());
+        assert!(tampered.check_digests().is_empty());
+        let verification = tampered.verify_issuer_signature(None, false, None).unwrap();
+        assert!(!verification.verified);
+        assert_eq!(verification.digest_mismatches[0].identifier, "family_name");
     }
 
     #[test]
-    fn test_create_and_sign() {
-        // 1. Generate Issuer Key
-        let issuer_key = SigningKey::random(&mut OsRng);
-        let issuer_key_pem = issuer_key.to_pkcs8_pem(LineEnding::LF).unwrap().to_string();
+    fn test_x5chain_encoding() {
+        let certificate = Value::Bytes(vec![0x30, 0x00]);
+        let mut issuer_auth = coset::CoseSign1Builder::new()
+            .unprotected(
+                coset::HeaderBuilder::new()
+                    .value(X5CHAIN_COSE_HEADER_LABEL, certificate.clone())
+                    .build(),
+            )
+            .build();
+        let x5chain = |issuer_auth: &coset::CoseSign1| issuer_auth.unprotected.rest[0].1.clone();
 
-        // 2. Generate Issuer Certificate
-        let subject_name: Name = "CN=Test Issuer".parse().unwrap();
-        let serial_number = SerialNumber::from(1u64);
-        let validity = Validity::from_now(Duration::from_secs(3600)).unwrap();
-        let spki = SubjectPublicKeyInfoOwned::from_key(issuer_key.verifying_key().clone()).unwrap();
+        // 1. A bare bstr is wrapped into an array
+        apply_x5chain_encoding(&mut issuer_auth, X5ChainEncoding::Array);
+        assert_eq!(
+            x5chain(&issuer_auth),
+            Value::Array(vec![certificate.clone()])
+        );
 
-        let builder = CertificateBuilder::new(
-            Profile::Root,
-            serial_number,
-            validity,
-            subject_name,
-            spki,
-            &issuer_key,
-        )
-        .unwrap();
+        // 2. A single-certificate array is unwrapped again
+        apply_x5chain_encoding(&mut issuer_auth, X5ChainEncoding::SingleCertificate);
+        assert_eq!(x5chain(&issuer_auth), certificate);
 
-        let cert = builder.build::<p256::ecdsa::DerSignature>().unwrap();
-        let cert_pem = cert.to_pem(LineEnding::LF).unwrap();
+        // 3. Longer chains are always kept as an array
+        let chain = Value::Array(vec![certificate.clone(), certificate]);
+        issuer_auth.unprotected.rest[0].1 = chain.clone();
+        apply_x5chain_encoding(&mut issuer_auth, X5ChainEncoding::SingleCertificate);
+        assert_eq!(x5chain(&issuer_auth), chain);
+    }
 
-        // 3. Generate Holder Key (JWK)
-        let holder_key = SigningKey::random(&mut OsRng);
-        let point = holder_key.verifying_key().to_encoded_point(false);
-        let x = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(point.x().unwrap());
-        let y = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(point.y().unwrap());
+    #[test]
+    fn test_base64url_issuer_signed_round_trip() {
+        let key_pair = Arc::new(crate::mdl::util::P256KeyPair::new());
+        let mdoc = crate::mdl::util::generate_test_mdl(key_pair).expect("Failed to create mdoc");
+
+        let encoded = mdoc
+            .encode(OutputFormat::Base64UrlIssuerSigned)
+            .expect("Failed to encode IssuerSigned");
+        let decoded =
+            Mdoc::new_from_base64url_encoded_issuer_signed(encoded, KeyAlias("test".to_string()))
+                .expect("Failed to decode IssuerSigned");
+
+        assert_eq!(decoded.doctype(), mdoc.doctype());
+        assert_eq!(decoded.details().len(), mdoc.details().len());
+        assert!(decoded.check_digests().is_empty());
+    }
 
-        let holder_jwk = serde_json::json!({
-            "kty": "EC",
-            "crv": "P-256",
-            "x": x,
-            "y": y
-        })
-        .to_string();
+    #[test]
+    fn test_cbor_document_output_formats() {
+        let key_pair = Arc::new(crate::mdl::util::P256KeyPair::new());
+        let mdoc = crate::mdl::util::generate_test_mdl(key_pair).expect("Failed to create mdoc");
+
+        let cbor = mdoc.encode_bytes(OutputFormat::CborDocument).unwrap();
+        assert!(matches!(
+            mdoc.encode(OutputFormat::CborDocument),
+            Err(MdocEncodingError::BinaryOutputFormat)
+        ));
+        let decoded = Mdoc::from_cbor_encoded_document(cbor.clone(), mdoc.key_alias())
+            .expect("Failed to decode Document");
+        assert_eq!(decoded.id(), mdoc.id());
+        assert!(decoded.check_digests().is_empty());
+
+        let hex = mdoc.encode(OutputFormat::HexCborDocument).unwrap();
+        assert_eq!(
+            mdoc.encode_bytes(OutputFormat::HexCborDocument).unwrap(),
+            hex.as_bytes()
+        );
+        let from_hex: Vec<u8> = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect();
+        assert_eq!(from_hex, cbor);
+    }
 
-        // 4. Sample Data (Generic Namespace)
-        let mut namespaces = HashMap::new();
-        let mut custom_ns = HashMap::new();
-        let mut cursor = Cursor::new(Vec::new());
-        ciborium::into_writer(&Value::Text("custom-value".to_string()), &mut cursor).unwrap();
-        custom_ns.insert("custom-element".to_string(), cursor.into_inner());
-        namespaces.insert("com.example.custom".to_string(), custom_ns);
+    #[test]
+    fn test_android_credential_data_round_trip() {
+        let key_pair = Arc::new(crate::mdl::util::P256KeyPair::new());
+        let mdoc = crate::mdl::util::generate_test_mdl(key_pair).expect("Failed to create mdoc");
 
-        // 5. Call function
-        let result = Mdoc::create_and_sign(
-            "com.example.doc".to_string(),
-            namespaces,
-            holder_jwk,
-            cert_pem,
-            issuer_key_pem,
+        let exported = mdoc
+            .to_android_credential_data()
+            .expect("Failed to export Android credential data");
+        assert!(
+            exported
+                .element_values
+                .get("org.iso.18013.5.1")
+                .is_some_and(|values| values.contains_key("family_name"))
         );
 
-        assert!(result.is_ok());
-        let mdoc = result.unwrap();
-        assert_eq!(mdoc.doctype(), "com.example.doc");
+        let imported = Mdoc::from_android_credential_data(
+            exported.static_auth_data,
+            exported.element_values,
+            KeyAlias("android".to_string()),
+        )
+        .expect("Failed to import Android credential data");
 
-        let details = mdoc.details();
-        let ns = Namespace("com.example.custom".to_string());
-        let elements = details.get(&ns).expect("Namespace not found");
-        let element = elements
-            .iter()
-            .find(|e| e.identifier == "custom-element")
-            .expect("Element not found");
-        assert!(element.value.as_ref().unwrap().contains("custom-value"));
+        assert_eq!(imported.doctype(), mdoc.doctype());
+        assert!(imported.check_digests().is_empty());
     }
 
     #[test]
@@ -1141,7 +4294,7 @@ mod tests {
         // The mDL is signed by Ephemeral DS, which is signed by Intermediate.
         // We only trust Root. Intermediate is not in trust anchors.
         let result_no_chain =
-            mdoc.verify_issuer_signature(Some(vec![root_cert_pem.clone()]), false);
+            mdoc.verify_issuer_signature(Some(vec![root_cert_pem.clone()]), false, None);
         assert!(
             result_no_chain.is_err(),
             "Verification should fail when chaining is disabled and intermediate is missing from anchors"
@@ -1149,7 +4302,7 @@ mod tests {
 
         // Case B: Chaining Enabled - Should Succeed
         // The verifier should find Intermediate in the x5chain, verify it against Root, and then verify Ephemeral DS against Intermediate.
-        let result_chain = mdoc.verify_issuer_signature(Some(vec![root_cert_pem]), true);
+        let result_chain = mdoc.verify_issuer_signature(Some(vec![root_cert_pem]), true, None);
         assert!(
             result_chain.is_ok(),
             "Verification should succeed when chaining is enabled: {:?}",
@@ -9,7 +9,14 @@
 // This project contains code from Spruce Systems, Inc.
 // https://github.com/spruceid/sprucekit-mobile
 
+pub mod aamva;
+pub mod ble;
+pub mod dcql;
 pub mod holder;
+mod jwe;
 pub mod mdoc;
+pub mod namespaces;
+mod nfc;
 pub mod reader;
+mod session_encryption;
 pub mod util;
@@ -0,0 +1,1226 @@
+// Copyright (c) 2025 Indicio
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+//
+// This software may be modified and distributed under the terms
+// of either the Apache License, Version 2.0 or the MIT license.
+// See the LICENSE-APACHE and LICENSE-MIT files for details.
+
+//! Typed JSON to CBOR conversion and validation of data elements, for namespaces that
+//! isomdl has no definitions for and for validating the mDL namespace before issuance.
+
+use std::collections::BTreeMap;
+
+use base64::prelude::*;
+use ciborium::Value;
+
+/// CBOR tag of an RFC 8943 full-date string.
+pub(crate) const FULL_DATE_TAG: u64 = 1004;
+/// CBOR tag of an RFC 3339 date-time string.
+pub(crate) const TDATE_TAG: u64 = 0;
+
+pub const MDL_NAMESPACE: &str = "org.iso.18013.5.1";
+pub const AAMVA_NAMESPACE: &str = "org.iso.18013.5.1.aamva";
+
+pub const EU_PID_DOC_TYPE: &str = "eu.europa.ec.eudi.pid.1";
+pub const EU_PID_NAMESPACE: &str = "eu.europa.ec.eudi.pid.1";
+
+pub const PHOTO_ID_DOC_TYPE: &str = "org.iso.23220.photoid.1";
+pub const ISO_23220_NAMESPACE: &str = "org.iso.23220.1";
+pub const PHOTO_ID_NAMESPACE: &str = "org.iso.23220.photoid.1";
+
+pub const MICOV_DOC_TYPE: &str = "org.micov.1";
+pub const MICOV_VTR_NAMESPACE: &str = "org.micov.vtr.1";
+pub const MICOV_ATTESTATION_NAMESPACE: &str = "org.micov.attestation.1";
+
+/// Type of a data element, used to validate and convert its JSON value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ElementType {
+    Text,
+    Bool,
+    UnsignedInt,
+    /// `YYYY-MM-DD`, encoded as a tagged full-date.
+    FullDate,
+    /// Either a full-date or an RFC 3339 date-time, encoded as a tagged tdate.
+    Date,
+    /// Base64 or base64url encoded binary data, encoded as a bstr.
+    Bytes,
+    /// ISO 3166-1 alpha-2 country code.
+    CountryCode,
+    /// Array of ISO 3166-1 alpha-2 country codes.
+    CountryCodes,
+    /// JSON object, encoded as a map without further type information.
+    Map,
+    /// JSON array, encoded without further type information.
+    Array,
+    /// Text from a fixed set of codes.
+    TextCode(&'static [&'static str]),
+    /// Unsigned integer from a fixed set of codes.
+    UnsignedCode(&'static [u64]),
+    /// Array of ISO 18013-5 driving privileges, whose `issue_date` and `expiry_date` are
+    /// encoded as tagged full-dates.
+    DrivingPrivileges,
+    /// JSON object with typed fields, encoded as a map keyed by the field identifiers.
+    Structure(&'static [ElementDefinition]),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct ElementDefinition {
+    pub identifier: &'static str,
+    pub element_type: ElementType,
+    pub mandatory: bool,
+}
+
+const fn mandatory(identifier: &'static str, element_type: ElementType) -> ElementDefinition {
+    ElementDefinition {
+        identifier,
+        element_type,
+        mandatory: true,
+    }
+}
+
+const fn optional(identifier: &'static str, element_type: ElementType) -> ElementDefinition {
+    ElementDefinition {
+        identifier,
+        element_type,
+        mandatory: false,
+    }
+}
+
+/// The data elements of a namespace.
+pub(crate) struct NamespaceDefinition {
+    pub namespace: &'static str,
+    pub elements: &'static [ElementDefinition],
+    /// Types of elements whose identifiers follow a pattern, such as `age_over_NN`.
+    pub pattern: Option<fn(&str) -> Option<ElementType>>,
+}
+
+/// A data element that is missing or could not be converted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ElementError {
+    pub identifier: String,
+    pub reason: String,
+}
+
+impl NamespaceDefinition {
+    /// Converts a JSON object of data elements to CBOR values, checking that mandatory
+    /// elements are present and that no unknown elements are given.
+    pub(crate) fn elements_from_json(
+        &self,
+        json: &str,
+    ) -> Result<BTreeMap<String, Value>, Vec<ElementError>> {
+        let error = |identifier: &str, reason: &str| ElementError {
+            identifier: identifier.to_string(),
+            reason: reason.to_string(),
+        };
+
+        let object = match serde_json::from_str::<serde_json::Value>(json) {
+            Ok(serde_json::Value::Object(object)) => object,
+            _ => return Err(vec![error("", "expected a JSON object")]),
+        };
+
+        let mut errors: Vec<ElementError> = self
+            .elements
+            .iter()
+            .filter(|element| element.mandatory && !object.contains_key(element.identifier))
+            .map(|element| error(element.identifier, "mandatory element is missing"))
+            .collect();
+
+        let mut elements = BTreeMap::new();
+        for (identifier, value) in object {
+            let Some(element_type) = self.element_type(&identifier) else {
+                errors.push(error(&identifier, "unknown element"));
+                continue;
+            };
+            match element_type.convert(value) {
+                Ok(value) => {
+                    elements.insert(identifier, value);
+                }
+                Err(reason) => errors.push(error(&identifier, &reason)),
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(elements)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Adds the CBOR date tags to untagged date elements, for values converted without
+    /// type information.
+    pub(crate) fn tag_dates(&self, elements: &mut BTreeMap<String, Value>) {
+        for (identifier, value) in elements.iter_mut() {
+            if let Some(element_type) = self.element_type(identifier) {
+                element_type.tag_dates(value);
+            }
+        }
+    }
+
+    pub(crate) fn element_type(&self, identifier: &str) -> Option<ElementType> {
+        if let Some(element) = self.elements.iter().find(|e| e.identifier == identifier) {
+            return Some(element.element_type);
+        }
+        self.pattern.and_then(|pattern| pattern(identifier))
+    }
+}
+
+impl ElementType {
+    fn convert(self, value: serde_json::Value) -> Result<Value, String> {
+        use serde_json::Value as Json;
+
+        match (self, value) {
+            (Self::Text, Json::String(text)) => Ok(Value::Text(text)),
+            (Self::Bool, Json::Bool(b)) => Ok(Value::Bool(b)),
+            (Self::TextCode(codes), Json::String(code)) => {
+                if codes.contains(&code.as_str()) {
+                    Ok(Value::Text(code))
+                } else {
+                    Err(format!("expected one of {}", codes.join(", ")))
+                }
+            }
+            (Self::UnsignedCode(codes), Json::Number(n)) => match n.as_u64() {
+                Some(code) if codes.contains(&code) => Ok(Value::Integer(code.into())),
+                _ => Err(format!(
+                    "expected one of {}",
+                    codes
+                        .iter()
+                        .map(u64::to_string)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )),
+            },
+            (Self::UnsignedInt, Json::Number(n)) => n
+                .as_u64()
+                .map(|n| Value::Integer(n.into()))
+                .ok_or_else(|| "expected an unsigned integer".to_string()),
+            (Self::FullDate, Json::String(date)) => {
+                parse_full_date(&date).ok_or_else(|| "expected a YYYY-MM-DD date".to_string())?;
+                Ok(Value::Tag(FULL_DATE_TAG, Box::new(Value::Text(date))))
+            }
+            (Self::Date, Json::String(date)) => {
+                if parse_full_date(&date).is_some() {
+                    Ok(Value::Tag(FULL_DATE_TAG, Box::new(Value::Text(date))))
+                } else if chrono::DateTime::parse_from_rfc3339(&date).is_ok() {
+                    Ok(Value::Tag(TDATE_TAG, Box::new(Value::Text(date))))
+                } else {
+                    Err("expected a YYYY-MM-DD date or an RFC 3339 date-time".to_string())
+                }
+            }
+            (Self::Bytes, Json::String(encoded)) => BASE64_URL_SAFE_NO_PAD
+                .decode(encoded.trim_end_matches('='))
+                .or_else(|_| BASE64_STANDARD.decode(&encoded))
+                .map(Value::Bytes)
+                .map_err(|_| "expected base64 or base64url encoded bytes".to_string()),
+            (Self::CountryCode, Json::String(code)) => country_code(code),
+            (Self::CountryCodes, Json::Array(codes)) => codes
+                .into_iter()
+                .map(|code| match code {
+                    Json::String(code) => country_code(code),
+                    _ => Err("expected an array of country codes".to_string()),
+                })
+                .collect::<Result<_, _>>()
+                .map(Value::Array),
+            (Self::Map, object @ Json::Object(_)) => Ok(untyped_cbor(object)),
+            (Self::Array, array @ Json::Array(_)) => Ok(untyped_cbor(array)),
+            (Self::DrivingPrivileges, privileges @ Json::Array(_)) => {
+                let mut privileges = untyped_cbor(privileges);
+                self.tag_dates(&mut privileges);
+                Ok(privileges)
+            }
+            (Self::Structure(fields), Json::Object(object)) => {
+                let mut errors: Vec<String> = fields
+                    .iter()
+                    .filter(|field| field.mandatory && !object.contains_key(field.identifier))
+                    .map(|field| format!("{}: mandatory field is missing", field.identifier))
+                    .collect();
+                let mut map = BTreeMap::new();
+                for (identifier, value) in object {
+                    let Some(field) = fields.iter().find(|field| field.identifier == identifier)
+                    else {
+                        errors.push(format!("{identifier}: unknown field"));
+                        continue;
+                    };
+                    match field.element_type.convert(value) {
+                        Ok(value) => {
+                            map.insert(identifier, value);
+                        }
+                        Err(reason) => errors.push(format!("{identifier}: {reason}")),
+                    }
+                }
+                if !errors.is_empty() {
+                    return Err(errors.join("; "));
+                }
+                Ok(Value::Map(
+                    map.into_iter()
+                        .map(|(identifier, value)| (Value::Text(identifier), value))
+                        .collect(),
+                ))
+            }
+            (element_type, _) => Err(format!("expected a value of type {element_type:?}")),
+        }
+    }
+}
+
+impl ElementType {
+    /// Tags a text date as a full-date, or as a tdate if it is an RFC 3339 date-time.
+    fn tag_dates(self, value: &mut Value) {
+        match (self, &mut *value) {
+            (Self::FullDate | Self::Date, Value::Text(date)) => {
+                let tag = if parse_full_date(date).is_some() {
+                    FULL_DATE_TAG
+                } else if self == Self::Date && chrono::DateTime::parse_from_rfc3339(date).is_ok() {
+                    TDATE_TAG
+                } else {
+                    return;
+                };
+                let date = std::mem::take(date);
+                *value = Value::Tag(tag, Box::new(Value::Text(date)));
+            }
+            (Self::DrivingPrivileges, Value::Array(privileges)) => {
+                for (key, value) in privileges
+                    .iter_mut()
+                    .filter_map(Value::as_map_mut)
+                    .flatten()
+                {
+                    if matches!(key.as_text(), Some("issue_date" | "expiry_date")) {
+                        Self::FullDate.tag_dates(value);
+                    }
+                }
+            }
+            (Self::Structure(fields), Value::Map(map)) => {
+                for (key, value) in map.iter_mut() {
+                    if let Some(field) = fields
+                        .iter()
+                        .find(|field| key.as_text() == Some(field.identifier))
+                    {
+                        field.element_type.tag_dates(value);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn to_json(self, value: &Value) -> Option<serde_json::Value> {
+        use serde_json::Value as Json;
+
+        match (self, value) {
+            (Self::Text | Self::CountryCode | Self::TextCode(_), Value::Text(text)) => {
+                Some(Json::String(text.clone()))
+            }
+            (Self::Bool, Value::Bool(b)) => Some(Json::Bool(*b)),
+            (Self::UnsignedInt | Self::UnsignedCode(_), Value::Integer(n)) => {
+                u64::try_from(*n).ok().map(Json::from)
+            }
+            (Self::FullDate, Value::Tag(FULL_DATE_TAG, date)) => match date.as_ref() {
+                Value::Text(date) => Some(Json::String(date.clone())),
+                _ => None,
+            },
+            (Self::Date, Value::Tag(FULL_DATE_TAG | TDATE_TAG, date)) => match date.as_ref() {
+                Value::Text(date) => Some(Json::String(date.clone())),
+                _ => None,
+            },
+            (Self::FullDate | Self::Date, Value::Text(date)) => Some(Json::String(date.clone())),
+            (Self::Bytes, Value::Bytes(bytes)) => {
+                Some(Json::String(BASE64_URL_SAFE_NO_PAD.encode(bytes)))
+            }
+            (Self::CountryCodes, Value::Array(codes)) => codes
+                .iter()
+                .map(|code| Self::CountryCode.to_json(code))
+                .collect::<Option<_>>()
+                .map(Json::Array),
+            (Self::Map, map @ Value::Map(_)) => Some(untyped_json(map)),
+            (Self::Array | Self::DrivingPrivileges, array @ Value::Array(_)) => {
+                Some(untyped_json(array))
+            }
+            (Self::Structure(fields), Value::Map(map)) => map
+                .iter()
+                .map(|(key, value)| {
+                    let identifier = key.as_text()?;
+                    let json = fields
+                        .iter()
+                        .find(|field| field.identifier == identifier)
+                        .and_then(|field| field.element_type.to_json(value))
+                        .unwrap_or_else(|| untyped_json(value));
+                    Some((identifier.to_string(), json))
+                })
+                .collect::<Option<_>>()
+                .map(Json::Object),
+            _ => None,
+        }
+    }
+}
+
+/// Converts the CBOR data elements of a namespace to JSON, in the format accepted by
+/// [NamespaceDefinition::elements_from_json] when the namespace has a definition. Other
+/// elements, or elements that do not have the expected type, are converted without type
+/// information.
+pub(crate) fn elements_to_json<'a>(
+    namespace: &str,
+    elements: impl IntoIterator<Item = (&'a String, &'a Value)>,
+) -> serde_json::Map<String, serde_json::Value> {
+    let definition = definition(namespace);
+    elements
+        .into_iter()
+        .map(|(identifier, value)| {
+            let json = definition
+                .and_then(|definition| definition.element_type(identifier))
+                .and_then(|element_type| element_type.to_json(value))
+                .unwrap_or_else(|| untyped_json(value));
+            (identifier.clone(), json)
+        })
+        .collect()
+}
+
+/// Converts a JSON value to CBOR without type information: integers that fit are
+/// encoded as integers, other numbers as floats.
+pub(crate) fn untyped_cbor(value: serde_json::Value) -> Value {
+    use serde_json::Value as Json;
+
+    match value {
+        Json::Null => Value::Null,
+        Json::Bool(b) => Value::Bool(b),
+        Json::Number(n) => match (n.as_u64(), n.as_i64()) {
+            (Some(n), _) => Value::Integer(n.into()),
+            (_, Some(n)) => Value::Integer(n.into()),
+            _ => Value::Float(n.as_f64().unwrap_or(f64::NAN)),
+        },
+        Json::String(text) => Value::Text(text),
+        Json::Array(values) => Value::Array(values.into_iter().map(untyped_cbor).collect()),
+        Json::Object(entries) => Value::Map(
+            entries
+                .into_iter()
+                .map(|(key, value)| (Value::Text(key), untyped_cbor(value)))
+                .collect(),
+        ),
+    }
+}
+
+/// Member of a JSON object standing for a byte string in data elements converted without
+/// a namespace definition, `{"$bytes": "<base64url>"}`.
+pub const BYTES_MEMBER: &str = "$bytes";
+
+/// Converts a JSON object of data elements to CBOR values without a namespace
+/// definition, see [generic_cbor].
+pub(crate) fn generic_from_json(json: &str) -> Result<BTreeMap<String, Value>, Vec<ElementError>> {
+    let object = match serde_json::from_str::<serde_json::Value>(json) {
+        Ok(serde_json::Value::Object(object)) => object,
+        _ => {
+            return Err(vec![ElementError {
+                identifier: String::new(),
+                reason: "expected a JSON object".to_string(),
+            }]);
+        }
+    };
+
+    let mut elements = BTreeMap::new();
+    let mut errors = Vec::new();
+    for (identifier, value) in object {
+        match generic_cbor(value) {
+            Ok(value) => {
+                elements.insert(identifier, value);
+            }
+            Err(reason) => errors.push(ElementError { identifier, reason }),
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(elements)
+    } else {
+        Err(errors)
+    }
+}
+
+/// The first identifier that occurs more than once in a JSON object of data elements.
+/// serde_json keeps the last value of a repeated key, silently dropping the others.
+pub(crate) fn duplicate_identifier(json: &str) -> Option<String> {
+    struct FirstDuplicate(Option<String>);
+
+    impl<'de> serde::Deserialize<'de> for FirstDuplicate {
+        fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            struct Visitor;
+
+            impl<'de> serde::de::Visitor<'de> for Visitor {
+                type Value = FirstDuplicate;
+
+                fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                    f.write_str("a JSON object")
+                }
+
+                fn visit_map<A: serde::de::MapAccess<'de>>(
+                    self,
+                    mut map: A,
+                ) -> Result<Self::Value, A::Error> {
+                    let mut seen = std::collections::HashSet::new();
+                    let mut duplicate = None;
+                    while let Some(identifier) = map.next_key::<String>()? {
+                        map.next_value::<serde::de::IgnoredAny>()?;
+                        if duplicate.is_none() && !seen.insert(identifier.clone()) {
+                            duplicate = Some(identifier);
+                        }
+                    }
+                    Ok(FirstDuplicate(duplicate))
+                }
+            }
+
+            deserializer.deserialize_map(Visitor)
+        }
+    }
+
+    serde_json::from_str::<FirstDuplicate>(json).ok()?.0
+}
+
+/// Converts a JSON value to CBOR like [untyped_cbor], except that objects whose only
+/// member is `"$bytes"` are decoded from base64 or base64url to a byte string.
+pub(crate) fn generic_cbor(value: serde_json::Value) -> Result<Value, String> {
+    use serde_json::Value as Json;
+
+    match value {
+        Json::Object(entries) if entries.len() == 1 && entries.contains_key(BYTES_MEMBER) => {
+            let (_, bytes) = entries.into_iter().next().expect("one member");
+            ElementType::Bytes.convert(bytes)
+        }
+        Json::Object(entries) => entries
+            .into_iter()
+            .map(|(key, value)| Ok((Value::Text(key), generic_cbor(value)?)))
+            .collect::<Result<_, String>>()
+            .map(Value::Map),
+        Json::Array(values) => values
+            .into_iter()
+            .map(generic_cbor)
+            .collect::<Result<_, String>>()
+            .map(Value::Array),
+        scalar => Ok(untyped_cbor(scalar)),
+    }
+}
+
+/// Converts a CBOR value to JSON without type information: tags are dropped and
+/// byte strings are base64url encoded.
+fn untyped_json(value: &Value) -> serde_json::Value {
+    use serde_json::Value as Json;
+
+    match value {
+        Value::Text(text) => Json::String(text.clone()),
+        Value::Bool(b) => Json::Bool(*b),
+        Value::Integer(n) => i64::try_from(*n)
+            .map(Json::from)
+            .unwrap_or_else(|_| Json::String(i128::from(*n).to_string())),
+        Value::Float(f) => Json::from(*f),
+        Value::Bytes(bytes) => Json::String(BASE64_URL_SAFE_NO_PAD.encode(bytes)),
+        Value::Tag(_, value) => untyped_json(value),
+        Value::Array(values) => Json::Array(values.iter().map(untyped_json).collect()),
+        Value::Map(entries) => Json::Object(
+            entries
+                .iter()
+                .map(|(key, value)| {
+                    let key = match key {
+                        Value::Text(key) => key.clone(),
+                        key => untyped_json(key).to_string(),
+                    };
+                    (key, untyped_json(value))
+                })
+                .collect(),
+        ),
+        _ => Json::Null,
+    }
+}
+
+/// Parses an RFC 3339 full-date, `YYYY-MM-DD`.
+pub(crate) fn parse_full_date(date: &str) -> Option<time::Date> {
+    let mut parts = date.split('-');
+    let (year, month, day) = (parts.next()?, parts.next()?, parts.next()?);
+    if parts.next().is_some() || year.len() != 4 || month.len() != 2 || day.len() != 2 {
+        return None;
+    }
+    let month = time::Month::try_from(month.parse::<u8>().ok()?).ok()?;
+    time::Date::from_calendar_date(year.parse().ok()?, month, day.parse().ok()?).ok()
+}
+
+fn country_code(code: String) -> Result<Value, String> {
+    if code.len() == 2 && code.bytes().all(|b| b.is_ascii_uppercase()) {
+        Ok(Value::Text(code))
+    } else {
+        Err(format!(
+            "expected an ISO 3166-1 alpha-2 country code, got {code:?}"
+        ))
+    }
+}
+
+/// `age_over_NN` booleans, for any two digit `NN`.
+fn age_over(identifier: &str) -> Option<ElementType> {
+    age_over_nn(identifier).map(|_| ElementType::Bool)
+}
+
+/// The `NN` of an `age_over_NN` identifier, for any two digit `NN`.
+pub(crate) fn age_over_nn(identifier: &str) -> Option<u8> {
+    let age = identifier.strip_prefix("age_over_")?;
+    if age.len() != 2 || !age.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    age.parse().ok()
+}
+
+/// The ISO 18013-5 mDL data elements. Conversion is done by isomdl, this definition is
+/// used to validate the JSON before issuance.
+pub(crate) const MDL: NamespaceDefinition = {
+    use ElementType::*;
+    NamespaceDefinition {
+        namespace: MDL_NAMESPACE,
+        elements: &[
+            mandatory("family_name", Text),
+            mandatory("given_name", Text),
+            mandatory("birth_date", FullDate),
+            mandatory("issue_date", Date),
+            mandatory("expiry_date", Date),
+            mandatory("issuing_country", CountryCode),
+            mandatory("issuing_authority", Text),
+            mandatory("document_number", Text),
+            mandatory("portrait", Bytes),
+            mandatory("driving_privileges", DrivingPrivileges),
+            mandatory("un_distinguishing_sign", Text),
+            optional("administrative_number", Text),
+            optional("sex", UnsignedInt),
+            optional("height", UnsignedInt),
+            optional("weight", UnsignedInt),
+            optional("eye_colour", Text),
+            optional("hair_colour", Text),
+            optional("birth_place", Text),
+            optional("resident_address", Text),
+            optional("portrait_capture_date", Date),
+            optional("age_in_years", UnsignedInt),
+            optional("age_birth_year", UnsignedInt),
+            optional("issuing_jurisdiction", Text),
+            optional("nationality", CountryCode),
+            optional("resident_city", Text),
+            optional("resident_state", Text),
+            optional("resident_postal_code", Text),
+            optional("resident_country", CountryCode),
+            optional("family_name_national_character", Text),
+            optional("given_name_national_character", Text),
+            optional("signature_usual_mark", Bytes),
+        ],
+        pattern: Some(mdl_element),
+    }
+};
+
+/// `age_over_NN` booleans and `biometric_template_xx` bytes.
+fn mdl_element(identifier: &str) -> Option<ElementType> {
+    if identifier.starts_with("biometric_template_") {
+        return Some(ElementType::Bytes);
+    }
+    age_over(identifier)
+}
+
+/// The AAMVA mDL data elements. As for the mDL namespace, conversion is done by isomdl
+/// and this definition validates the JSON and the domains of coded elements.
+pub(crate) const AAMVA: NamespaceDefinition = {
+    use ElementType::*;
+
+    const TRUNCATION: ElementType = TextCode(&["T", "N", "U"]);
+    const INDICATOR: ElementType = UnsignedCode(&[1]);
+
+    NamespaceDefinition {
+        namespace: AAMVA_NAMESPACE,
+        elements: &[
+            mandatory("family_name_truncation", TRUNCATION),
+            mandatory("given_name_truncation", TRUNCATION),
+            // ISO/IEC 5218
+            mandatory("sex", UnsignedCode(&[0, 1, 2, 9])),
+            optional("domestic_driving_privileges", Array),
+            optional("name_suffix", Text),
+            optional("organ_donor", INDICATOR),
+            optional("veteran", INDICATOR),
+            optional("aka_family_name.v2", Text),
+            optional("aka_given_name.v2", Text),
+            optional("aka_suffix", Text),
+            optional(
+                "weight_range",
+                UnsignedCode(&[0, 1, 2, 3, 4, 5, 6, 7, 8, 9]),
+            ),
+            optional(
+                "race_ethnicity",
+                TextCode(&["AI", "AP", "BK", "H", "O", "U", "W"]),
+            ),
+            optional("DHS_compliance", TextCode(&["F", "N"])),
+            optional("DHS_temporary_lawful_status", INDICATOR),
+            optional("EDL_credential", UnsignedCode(&[1, 2])),
+            optional("resident_county", Text),
+            optional("hazmat_endorsement_expiration_date", FullDate),
+            optional("CDL_indicator", INDICATOR),
+            optional("DHS_compliance_text", Text),
+            optional("aamva_version", UnsignedInt),
+        ],
+        pattern: None,
+    }
+};
+
+/// EU Person Identification Data, as defined by the PID Rulebook of the EUDI Wallet
+/// Architecture and Reference Framework.
+pub(crate) const EU_PID: NamespaceDefinition = {
+    use ElementType::*;
+
+    NamespaceDefinition {
+        namespace: EU_PID_NAMESPACE,
+        elements: &[
+            mandatory("family_name", Text),
+            mandatory("given_name", Text),
+            mandatory("birth_date", FullDate),
+            mandatory("birth_place", Text),
+            mandatory("nationality", CountryCodes),
+            mandatory("expiry_date", FullDate),
+            mandatory("issuing_authority", Text),
+            mandatory("issuing_country", CountryCode),
+            optional("resident_address", Text),
+            optional("resident_country", CountryCode),
+            optional("resident_state", Text),
+            optional("resident_city", Text),
+            optional("resident_postal_code", Text),
+            optional("resident_street", Text),
+            optional("resident_house_number", Text),
+            optional("personal_administrative_number", Text),
+            optional("portrait", Bytes),
+            optional("family_name_birth", Text),
+            optional("given_name_birth", Text),
+            optional("sex", UnsignedInt),
+            optional("email_address", Text),
+            optional("mobile_phone_number", Text),
+            optional("issuance_date", FullDate),
+            optional("document_number", Text),
+            optional("issuing_jurisdiction", Text),
+            optional("age_in_years", UnsignedInt),
+            optional("age_birth_year", UnsignedInt),
+        ],
+        pattern: Some(age_over),
+    }
+};
+
+/// Core person and document elements of ISO/IEC 23220-2, used by the Photo ID.
+pub(crate) const ISO_23220: NamespaceDefinition = {
+    use ElementType::*;
+
+    NamespaceDefinition {
+        namespace: ISO_23220_NAMESPACE,
+        elements: &[
+            mandatory("family_name_unicode", Text),
+            mandatory("given_name_unicode", Text),
+            mandatory("birth_date", FullDate),
+            mandatory("portrait", Bytes),
+            mandatory("issue_date", FullDate),
+            mandatory("expiry_date", FullDate),
+            mandatory("issuing_authority_unicode", Text),
+            mandatory("issuing_country", CountryCode),
+            optional("family_name_latin1", Text),
+            optional("given_name_latin1", Text),
+            optional("age_in_years", UnsignedInt),
+            optional("age_birth_year", UnsignedInt),
+            optional("portrait_capture_date", FullDate),
+            optional("birthplace", Text),
+            optional("name_at_birth", Text),
+            optional("resident_address_unicode", Text),
+            optional("resident_city_unicode", Text),
+            optional("resident_postal_code", Text),
+            optional("resident_country", CountryCode),
+            optional("sex", UnsignedInt),
+            optional("nationality", CountryCode),
+            optional("document_number", Text),
+            optional("issuing_subdivision", Text),
+        ],
+        pattern: Some(age_over),
+    }
+};
+
+/// Photo ID specific elements of ISO/IEC TS 23220-4.
+pub(crate) const PHOTO_ID: NamespaceDefinition = {
+    use ElementType::*;
+
+    NamespaceDefinition {
+        namespace: PHOTO_ID_NAMESPACE,
+        elements: &[
+            optional("person_id", Text),
+            optional("birth_country", CountryCode),
+            optional("birth_state", Text),
+            optional("birth_city", Text),
+            optional("administrative_number", Text),
+            optional("resident_street", Text),
+            optional("resident_house_number", Text),
+            optional("resident_state", Text),
+            optional("travel_document_number", Text),
+        ],
+        pattern: None,
+    }
+};
+
+/// Vaccination and test records of the micov (mobile international certificate of
+/// vaccination) document.
+pub(crate) const MICOV_VTR: NamespaceDefinition = {
+    use ElementType::*;
+
+    NamespaceDefinition {
+        namespace: MICOV_VTR_NAMESPACE,
+        elements: &[
+            mandatory("fn", Text),
+            mandatory("gn", Text),
+            mandatory("dob", FullDate),
+            optional("sex", UnsignedInt),
+        ],
+        pattern: Some(micov_vtr_element),
+    }
+};
+
+/// A vaccination event of the micov document.
+const MICOV_VACCINATION_EVENT: ElementType = {
+    use ElementType::*;
+
+    Structure(&[
+        // Disease or agent targeted
+        mandatory("tg", Text),
+        // Vaccine or prophylaxis
+        optional("vp", Text),
+        // Vaccine medicinal product
+        mandatory("mp", Text),
+        optional("br", Text),
+        // Marketing authorization holder or manufacturer
+        optional("ma", Text),
+        // Batch or lot number
+        mandatory("bn", Text),
+        // Dose number and total series of doses
+        mandatory("dn", UnsignedInt),
+        optional("sd", UnsignedInt),
+        // Date of vaccination and of the next dose
+        mandatory("dt", FullDate),
+        optional("nx", FullDate),
+        optional("co", CountryCode),
+        // Administering organization and professional
+        optional("ao", Text),
+        optional("ap", Text),
+        mandatory("is", Text),
+        optional("ci", Text),
+        optional("vf", FullDate),
+        optional("vu", FullDate),
+    ])
+};
+
+/// A test event of the micov document.
+const MICOV_TEST_EVENT: ElementType = {
+    use ElementType::*;
+
+    Structure(&[
+        // Disease or agent targeted
+        mandatory("tg", Text),
+        // Type of test, test name and manufacturer
+        mandatory("tt", Text),
+        optional("nm", Text),
+        optional("ma", Text),
+        // Date and time of the sample collection
+        mandatory("sc", Date),
+        // Test result
+        mandatory("tr", Text),
+        // Testing centre
+        optional("tc", Text),
+        mandatory("co", CountryCode),
+        mandatory("is", Text),
+        optional("ci", Text),
+    ])
+};
+
+/// `v_<disease>_<n>` vaccination events, `t_<disease>_<n>` test events and `pid_<type>`
+/// person identifiers.
+fn micov_vtr_element(identifier: &str) -> Option<ElementType> {
+    if identifier.starts_with("v_") {
+        Some(MICOV_VACCINATION_EVENT)
+    } else if identifier.starts_with("t_") {
+        Some(MICOV_TEST_EVENT)
+    } else if identifier.starts_with("pid_") {
+        Some(ElementType::Text)
+    } else {
+        None
+    }
+}
+
+/// Privacy preserving attestations of the micov document, for verification without
+/// disclosing the full vaccination records.
+pub(crate) const MICOV_ATTESTATION: NamespaceDefinition = {
+    use ElementType::*;
+
+    NamespaceDefinition {
+        namespace: MICOV_ATTESTATION_NAMESPACE,
+        elements: &[
+            optional("fac", Bytes),
+            optional("fni", Text),
+            optional("gni", Text),
+            optional("by", UnsignedInt),
+            optional("bm", UnsignedInt),
+            optional("bd", UnsignedInt),
+        ],
+        pattern: Some(micov_attestation_element),
+    }
+};
+
+/// `<disease>_vaccinated` booleans, `<disease>_test` results and `safeEntry_<use>`
+/// entry attestations.
+fn micov_attestation_element(identifier: &str) -> Option<ElementType> {
+    if identifier.ends_with("_vaccinated") {
+        Some(ElementType::Bool)
+    } else if identifier.ends_with("_test") || identifier.starts_with("safeEntry_") {
+        Some(ElementType::Map)
+    } else {
+        None
+    }
+}
+
+/// The namespace definitions known to the crate.
+const DEFINITIONS: &[&NamespaceDefinition] = &[
+    &MDL,
+    &AAMVA,
+    &EU_PID,
+    &ISO_23220,
+    &PHOTO_ID,
+    &MICOV_VTR,
+    &MICOV_ATTESTATION,
+];
+
+/// Looks up the definition of a namespace.
+pub(crate) fn definition(namespace: &str) -> Option<&'static NamespaceDefinition> {
+    DEFINITIONS
+        .iter()
+        .copied()
+        .find(|definition| definition.namespace == namespace)
+}
+
+/// The label of an mDL or AAMVA data element, as named by ISO 18013-5 table 5 and the
+/// AAMVA mDL Implementation Guidelines.
+pub(crate) fn element_label(namespace: &str, identifier: &str) -> Option<String> {
+    let label = match (namespace, identifier) {
+        (MDL_NAMESPACE, "family_name") => "Family name",
+        (MDL_NAMESPACE, "given_name") => "Given names",
+        (MDL_NAMESPACE, "birth_date") => "Date of birth",
+        (MDL_NAMESPACE, "issue_date") => "Date of issue",
+        (MDL_NAMESPACE, "expiry_date") => "Date of expiry",
+        (MDL_NAMESPACE, "issuing_country") => "Issuing country",
+        (MDL_NAMESPACE, "issuing_authority") => "Issuing authority",
+        (MDL_NAMESPACE, "document_number") => "Licence number",
+        (MDL_NAMESPACE, "portrait") => "Portrait of holder",
+        (MDL_NAMESPACE, "driving_privileges") => "Categories of vehicles",
+        (MDL_NAMESPACE, "un_distinguishing_sign") => "UN distinguishing sign",
+        (MDL_NAMESPACE, "administrative_number") => "Administrative number",
+        (MDL_NAMESPACE | AAMVA_NAMESPACE, "sex") => "Sex",
+        (MDL_NAMESPACE, "height") => "Height (cm)",
+        (MDL_NAMESPACE, "weight") => "Weight (kg)",
+        (MDL_NAMESPACE, "eye_colour") => "Eye colour",
+        (MDL_NAMESPACE, "hair_colour") => "Hair colour",
+        (MDL_NAMESPACE, "birth_place") => "Place of birth",
+        (MDL_NAMESPACE, "resident_address") => "Permanent place of residence",
+        (MDL_NAMESPACE, "portrait_capture_date") => "Portrait image timestamp",
+        (MDL_NAMESPACE, "age_in_years") => "Age in years",
+        (MDL_NAMESPACE, "age_birth_year") => "Year of birth",
+        (MDL_NAMESPACE, "issuing_jurisdiction") => "Issuing jurisdiction",
+        (MDL_NAMESPACE, "nationality") => "Nationality",
+        (MDL_NAMESPACE, "resident_city") => "Resident city",
+        (MDL_NAMESPACE, "resident_state") => "Resident state/province/district",
+        (MDL_NAMESPACE, "resident_postal_code") => "Resident postal code",
+        (MDL_NAMESPACE, "resident_country") => "Resident country",
+        (MDL_NAMESPACE, "family_name_national_character") => "Family name in national characters",
+        (MDL_NAMESPACE, "given_name_national_character") => "Given name in national characters",
+        (MDL_NAMESPACE, "signature_usual_mark") => "Signature / usual mark",
+        (MDL_NAMESPACE, identifier) => {
+            if let Some(age) = age_over_nn(identifier) {
+                return Some(format!("Age over {age}"));
+            }
+            let modality = identifier.strip_prefix("biometric_template_")?;
+            return Some(format!("Biometric template {modality}"));
+        }
+        (AAMVA_NAMESPACE, "domestic_driving_privileges") => "Domestic driving privileges",
+        (AAMVA_NAMESPACE, "name_suffix") => "Name suffix",
+        (AAMVA_NAMESPACE, "organ_donor") => "Organ donor",
+        (AAMVA_NAMESPACE, "veteran") => "Veteran",
+        (AAMVA_NAMESPACE, "family_name_truncation") => "Family name truncation",
+        (AAMVA_NAMESPACE, "given_name_truncation") => "Given name truncation",
+        (AAMVA_NAMESPACE, "aka_family_name.v2") => "Alias / AKA family name",
+        (AAMVA_NAMESPACE, "aka_given_name.v2") => "Alias / AKA given name",
+        (AAMVA_NAMESPACE, "aka_suffix") => "Alias / AKA suffix name",
+        (AAMVA_NAMESPACE, "weight_range") => "Weight range",
+        (AAMVA_NAMESPACE, "race_ethnicity") => "Race / ethnicity",
+        (AAMVA_NAMESPACE, "DHS_compliance") => "Compliance type",
+        (AAMVA_NAMESPACE, "DHS_temporary_lawful_status") => "Limited duration document",
+        (AAMVA_NAMESPACE, "EDL_credential") => "Enhanced credential",
+        (AAMVA_NAMESPACE, "resident_county") => "Resident county",
+        (AAMVA_NAMESPACE, "hazmat_endorsement_expiration_date") => {
+            "HAZMAT endorsement expiration date"
+        }
+        (AAMVA_NAMESPACE, "CDL_indicator") => "Commercial driver's license",
+        (AAMVA_NAMESPACE, "DHS_compliance_text") => "Non-REAL ID credential text",
+        (AAMVA_NAMESPACE, "aamva_version") => "AAMVA version",
+        _ => return None,
+    };
+    Some(label.to_string())
+}
+
+/// The meaning of a coded value of an mDL or AAMVA data element, given as text.
+pub(crate) fn code_label(namespace: &str, identifier: &str, code: &str) -> Option<&'static str> {
+    let label = match (namespace, identifier, code) {
+        // ISO/IEC 5218
+        (MDL_NAMESPACE | AAMVA_NAMESPACE, "sex", "0") => "Not known",
+        (MDL_NAMESPACE | AAMVA_NAMESPACE, "sex", "1") => "Male",
+        (MDL_NAMESPACE | AAMVA_NAMESPACE, "sex", "2") => "Female",
+        (MDL_NAMESPACE | AAMVA_NAMESPACE, "sex", "9") => "Not applicable",
+        (AAMVA_NAMESPACE, "family_name_truncation" | "given_name_truncation", code) => match code {
+            "T" => "Truncated",
+            "N" => "Not truncated",
+            "U" => "Unknown",
+            _ => return None,
+        },
+        (
+            AAMVA_NAMESPACE,
+            "organ_donor" | "veteran" | "DHS_temporary_lawful_status" | "CDL_indicator",
+            "1",
+        ) => "Yes",
+        (AAMVA_NAMESPACE, "weight_range", code) => match code {
+            "0" => "Up to 31 kg",
+            "1" => "32 to 45 kg",
+            "2" => "46 to 59 kg",
+            "3" => "60 to 70 kg",
+            "4" => "71 to 86 kg",
+            "5" => "87 to 100 kg",
+            "6" => "101 to 113 kg",
+            "7" => "114 to 127 kg",
+            "8" => "128 to 145 kg",
+            "9" => "146 kg or more",
+            _ => return None,
+        },
+        (AAMVA_NAMESPACE, "race_ethnicity", code) => match code {
+            "AI" => "Alaskan or American Indian",
+            "AP" => "Asian or Pacific Islander",
+            "BK" => "Black",
+            "H" => "Hispanic origin",
+            "O" => "Non-Hispanic",
+            "U" => "Unknown",
+            "W" => "White",
+            _ => return None,
+        },
+        (AAMVA_NAMESPACE, "DHS_compliance", "F") => "Fully compliant",
+        (AAMVA_NAMESPACE, "DHS_compliance", "N") => "Non-compliant",
+        (AAMVA_NAMESPACE, "EDL_credential", "1") => "Enhanced driver's license",
+        (AAMVA_NAMESPACE, "EDL_credential", "2") => "Enhanced identification card",
+        _ => return None,
+    };
+    Some(label)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_eu_pid_from_json() {
+        // 1. Valid PID data converts to typed CBOR values
+        let json = serde_json::json!({
+            "family_name": "Mustermann",
+            "given_name": "Erika",
+            "birth_date": "1964-08-12",
+            "birth_place": "Berlin",
+            "nationality": ["DE"],
+            "expiry_date": "2030-01-01",
+            "issuing_authority": "Bundesdruckerei",
+            "issuing_country": "DE",
+            "age_over_18": true,
+        });
+        let elements = EU_PID.elements_from_json(&json.to_string()).unwrap();
+        assert_eq!(
+            elements["birth_date"],
+            Value::Tag(FULL_DATE_TAG, Box::new(Value::Text("1964-08-12".into())))
+        );
+        assert_eq!(elements["age_over_18"], Value::Bool(true));
+
+        // 2. Every problem is reported
+        let json = serde_json::json!({
+            "family_name": "Mustermann",
+            "birth_date": "12.08.1964",
+            "nationality": "DE",
+            "favorite_color": "blue",
+        });
+        let mut identifiers: Vec<String> = EU_PID
+            .elements_from_json(&json.to_string())
+            .unwrap_err()
+            .into_iter()
+            .map(|e| e.identifier)
+            .collect();
+        identifiers.sort();
+        assert_eq!(
+            identifiers,
+            [
+                "birth_date",
+                "birth_place",
+                "expiry_date",
+                "favorite_color",
+                "given_name",
+                "issuing_authority",
+                "issuing_country",
+                "nationality",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_photo_id_json_round_trip() {
+        let json = serde_json::json!({
+            "family_name_unicode": "Doe",
+            "given_name_unicode": "Jane",
+            "birth_date": "1990-01-01",
+            "portrait": "SGVsbG8gV29ybGQ",
+            "issue_date": "2024-01-01",
+            "expiry_date": "2034-01-01",
+            "issuing_authority_unicode": "Test Authority",
+            "issuing_country": "US",
+            "age_over_21": true,
+        });
+        let elements = ISO_23220.elements_from_json(&json.to_string()).unwrap();
+        assert_eq!(
+            serde_json::Value::Object(elements_to_json(ISO_23220_NAMESPACE, &elements)),
+            json
+        );
+    }
+
+    #[test]
+    fn test_micov_from_json() {
+        // 1. Vaccination and test events are encoded as maps of typed fields
+        let vaccination = serde_json::json!({
+            "tg": "840539006",
+            "mp": "EU/1/20/1528",
+            "bn": "ABC123",
+            "dn": 1,
+            "sd": 2,
+            "dt": "2021-04-01",
+            "is": "Ministry of Health",
+        });
+        let test = serde_json::json!({
+            "tg": "840539006",
+            "tt": "LP6464-4",
+            "sc": "2021-04-13T14:20:00Z",
+            "tr": "260415000",
+            "co": "NL",
+            "is": "Ministry of Health",
+        });
+        let json = serde_json::json!({
+            "fn": "Doe",
+            "gn": "John",
+            "dob": "1990-01-01",
+            "v_RA01_1": vaccination,
+            "t_RA01_1": test,
+            "pid_PPN": "123456789",
+        });
+        let elements = MICOV_VTR.elements_from_json(&json.to_string()).unwrap();
+        let field = |element: &str, identifier: &str| {
+            elements[element]
+                .as_map()
+                .unwrap()
+                .iter()
+                .find(|(key, _)| key.as_text() == Some(identifier))
+                .map(|(_, value)| value.clone())
+                .unwrap()
+        };
+        assert_eq!(field("v_RA01_1", "dn"), Value::Integer(1.into()));
+        assert_eq!(
+            field("v_RA01_1", "dt"),
+            Value::Tag(FULL_DATE_TAG, Box::new(Value::Text("2021-04-01".into())))
+        );
+        assert_eq!(
+            field("t_RA01_1", "sc"),
+            Value::Tag(
+                TDATE_TAG,
+                Box::new(Value::Text("2021-04-13T14:20:00Z".into()))
+            )
+        );
+        assert_eq!(
+            serde_json::Value::Object(elements_to_json(MICOV_VTR_NAMESPACE, &elements)),
+            json
+        );
+
+        // 2. Event fields are validated
+        let mut invalid = vaccination.clone();
+        invalid["dn"] = serde_json::json!("first");
+        invalid.as_object_mut().unwrap().remove("bn");
+        invalid["lot"] = serde_json::json!("ABC123");
+        let errors = MICOV_VTR
+            .elements_from_json(&serde_json::json!({"fn": "Doe", "gn": "John", "dob": "1990-01-01", "v_RA01_1": invalid}).to_string())
+            .unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].identifier, "v_RA01_1");
+        for field in ["bn: mandatory", "dn: expected", "lot: unknown field"] {
+            assert!(errors[0].reason.contains(field), "{}", errors[0].reason);
+        }
+
+        // 3. Attestations follow their identifier patterns
+        let json = serde_json::json!({"RA01_vaccinated": "yes"});
+        let errors = MICOV_ATTESTATION
+            .elements_from_json(&json.to_string())
+            .unwrap_err();
+        assert_eq!(errors[0].identifier, "RA01_vaccinated");
+    }
+
+    #[test]
+    fn test_mdl_tag_dates() {
+        let date = |tag, date: &str| Value::Tag(tag, Box::new(Value::Text(date.into())));
+        let privileges = |issue_date| {
+            Value::Array(vec![Value::Map(vec![
+                (
+                    Value::Text("vehicle_category_code".into()),
+                    Value::Text("B".into()),
+                ),
+                (Value::Text("issue_date".into()), issue_date),
+            ])])
+        };
+
+        let mut elements = BTreeMap::from([
+            ("birth_date".to_string(), Value::Text("1990-01-01".into())),
+            (
+                "issue_date".to_string(),
+                Value::Text("2023-01-01T00:00:00Z".into()),
+            ),
+            ("expiry_date".to_string(), date(FULL_DATE_TAG, "2028-01-01")),
+            ("family_name".to_string(), Value::Text("2028-01-01".into())),
+            (
+                "driving_privileges".to_string(),
+                privileges(Value::Text("2023-01-01".into())),
+            ),
+        ]);
+        MDL.tag_dates(&mut elements);
+
+        assert_eq!(elements["birth_date"], date(FULL_DATE_TAG, "1990-01-01"));
+        assert_eq!(
+            elements["issue_date"],
+            date(TDATE_TAG, "2023-01-01T00:00:00Z")
+        );
+        assert_eq!(elements["expiry_date"], date(FULL_DATE_TAG, "2028-01-01"));
+        assert_eq!(elements["family_name"], Value::Text("2028-01-01".into()));
+        assert_eq!(
+            elements["driving_privileges"],
+            privileges(date(FULL_DATE_TAG, "2023-01-01"))
+        );
+    }
+
+    #[test]
+    fn test_generic_from_json() {
+        let json = serde_json::json!({
+            "name": "Doe",
+            "level": 3,
+            "photo": {"$bytes": "SGVsbG8"},
+            "history": [{"year": 2020, "data": {"$bytes": "AAE="}}],
+        });
+        let elements = generic_from_json(&json.to_string()).unwrap();
+
+        assert_eq!(elements["name"], Value::Text("Doe".into()));
+        assert_eq!(elements["level"], Value::Integer(3.into()));
+        assert_eq!(elements["photo"], Value::Bytes(b"Hello".to_vec()));
+        assert_eq!(
+            elements["history"],
+            Value::Array(vec![Value::Map(vec![
+                (Value::Text("data".into()), Value::Bytes(vec![0, 1])),
+                (Value::Text("year".into()), Value::Integer(2020.into())),
+            ])])
+        );
+
+        let json = serde_json::json!({"photo": {"$bytes": "not base64!"}});
+        let errors = generic_from_json(&json.to_string()).unwrap_err();
+        assert_eq!(errors[0].identifier, "photo");
+    }
+}
@@ -0,0 +1,358 @@
+// Copyright (c) 2025 Indicio
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+//
+// This software may be modified and distributed under the terms
+// of either the Apache License, Version 2.0 or the MIT license.
+// See the LICENSE-APACHE and LICENSE-MIT files for details.
+
+//! NDEF messages of NFC engagement, per ISO 18013-5 8.2.2.1: the Handover Select
+//! message carrying the DeviceEngagement and the BLE alternative carriers, for static
+//! handover and in answer to a reader's Handover Request in negotiated handover.
+
+use uuid::Uuid;
+
+/// Version 1.5 of the NFC Forum Connection Handover specification.
+const HANDOVER_VERSION: u8 = 0x15;
+const TNF_WELL_KNOWN: u8 = 0x01;
+const TNF_MEDIA: u8 = 0x02;
+const TNF_EXTERNAL: u8 = 0x04;
+const BLE_OOB_TYPE: &[u8] = b"application/vnd.bluetooth.le.oob";
+const DEVICE_ENGAGEMENT_TYPE: &[u8] = b"iso.org:18013:deviceengagement";
+const DEVICE_ENGAGEMENT_ID: &[u8] = b"mdoc";
+/// Carrier Power State "active".
+const CPS_ACTIVE: u8 = 0x01;
+const AD_LE_ROLE: u8 = 0x1c;
+const LE_ROLE_PERIPHERAL_ONLY: u8 = 0x00;
+const LE_ROLE_CENTRAL_ONLY: u8 = 0x01;
+
+/// A BLE alternative carrier offered by the mdoc.
+pub(crate) struct BleCarrier {
+    pub(crate) uuid: Uuid,
+    /// Whether the mdoc acts as peripheral server, rather than central client.
+    pub(crate) peripheral_server: bool,
+    pub(crate) device_address: Option<Vec<u8>>,
+}
+
+/// The Handover Select message with the DeviceEngagement and the given carriers.
+pub(crate) fn handover_select(
+    device_engagement: &[u8],
+    carriers: &[BleCarrier],
+) -> Result<Vec<u8>, String> {
+    let ids: Vec<Vec<u8>> = (0..carriers.len())
+        .map(|index| index.to_string().into_bytes())
+        .collect();
+
+    let alternative_carriers: Vec<Record> = ids
+        .iter()
+        .map(|id| {
+            let mut payload = vec![CPS_ACTIVE, id.len() as u8];
+            payload.extend_from_slice(id);
+            payload.extend_from_slice(&[1, DEVICE_ENGAGEMENT_ID.len() as u8]);
+            payload.extend_from_slice(DEVICE_ENGAGEMENT_ID);
+            Record::new(TNF_WELL_KNOWN, b"ac", None, payload)
+        })
+        .collect();
+    let mut handover_select = vec![HANDOVER_VERSION];
+    handover_select.extend(encode_message(&alternative_carriers));
+
+    let mut records = vec![Record::new(TNF_WELL_KNOWN, b"Hs", None, handover_select)];
+    for (carrier, id) in carriers.iter().zip(&ids) {
+        records.push(Record::new(
+            TNF_MEDIA,
+            BLE_OOB_TYPE,
+            Some(id),
+            ble_oob(carrier)?,
+        ));
+    }
+    records.push(Record::new(
+        TNF_EXTERNAL,
+        DEVICE_ENGAGEMENT_TYPE,
+        Some(DEVICE_ENGAGEMENT_ID),
+        device_engagement.to_vec(),
+    ));
+    Ok(encode_message(&records))
+}
+
+impl BleCarrier {
+    /// Whether the mode of the carrier suits a reader with the given LE role, any role
+    /// if it is unknown.
+    pub(crate) fn accepts(&self, reader_role: Option<u8>) -> bool {
+        match reader_role {
+            Some(LE_ROLE_PERIPHERAL_ONLY) => !self.peripheral_server,
+            Some(LE_ROLE_CENTRAL_ONLY) => self.peripheral_server,
+            _ => true,
+        }
+    }
+}
+
+/// The LE roles of the BLE carriers offered by a Handover Request message, `None` for
+/// a carrier that does not tell its role.
+pub(crate) fn requested_ble_roles(handover_request: &[u8]) -> Result<Vec<Option<u8>>, String> {
+    let records = decode_message(handover_request)?;
+    match records.first() {
+        Some(record) if record.tnf == TNF_WELL_KNOWN && record.record_type == b"Hr" => {}
+        _ => return Err("expected a Handover Request message".to_string()),
+    }
+    Ok(records
+        .iter()
+        .filter(|record| record.tnf == TNF_MEDIA && record.record_type == BLE_OOB_TYPE)
+        .map(|record| le_role(&record.payload))
+        .collect())
+}
+
+/// The LE Role AD structure of LE OOB data.
+fn le_role(mut data: &[u8]) -> Option<u8> {
+    while let [length, rest @ ..] = data {
+        let length = *length as usize;
+        if length == 0 || rest.len() < length {
+            return None;
+        }
+        if let [AD_LE_ROLE, role, ..] = &rest[..length] {
+            return Some(*role);
+        }
+        data = &rest[length..];
+    }
+    None
+}
+
+/// The LE OOB data of a carrier configuration record: the LE role, the service UUID
+/// and the device address of a peripheral server.
+///
+/// The address is the 6 byte device address, optionally followed by the address type
+/// byte.
+fn ble_oob(carrier: &BleCarrier) -> Result<Vec<u8>, String> {
+    // LE Role, only peripheral or only central role supported
+    let role = if carrier.peripheral_server {
+        LE_ROLE_PERIPHERAL_ONLY
+    } else {
+        LE_ROLE_CENTRAL_ONLY
+    };
+    let mut data = vec![2, AD_LE_ROLE, role];
+
+    // Complete list of 128-bit service UUIDs, in little-endian order
+    let mut uuid = carrier.uuid.as_bytes().to_vec();
+    uuid.reverse();
+    data.extend_from_slice(&[17, 0x07]);
+    data.extend(uuid);
+
+    // LE Bluetooth device address, public unless the type is given
+    if let Some(address) = carrier
+        .device_address
+        .as_ref()
+        .filter(|_| carrier.peripheral_server)
+    {
+        let mut address = address.clone();
+        match address.len() {
+            6 => address.push(0x00),
+            7 => {}
+            length => {
+                return Err(format!(
+                    "A BLE device address has 6 bytes, or 7 with its type, not {length}"
+                ));
+            }
+        }
+        data.extend_from_slice(&[address.len() as u8 + 1, 0x1b]);
+        data.extend(address);
+    }
+    Ok(data)
+}
+
+struct Record {
+    tnf: u8,
+    record_type: Vec<u8>,
+    id: Option<Vec<u8>>,
+    payload: Vec<u8>,
+}
+
+impl Record {
+    fn new(tnf: u8, record_type: &[u8], id: Option<&[u8]>, payload: Vec<u8>) -> Self {
+        Self {
+            tnf,
+            record_type: record_type.to_vec(),
+            id: id.map(<[u8]>::to_vec),
+            payload,
+        }
+    }
+}
+
+fn encode_message(records: &[Record]) -> Vec<u8> {
+    let mut message = Vec::new();
+    for (index, record) in records.iter().enumerate() {
+        let short = record.payload.len() < 256;
+        let mut header = record.tnf;
+        if index == 0 {
+            header |= 0x80; // MB
+        }
+        if index == records.len() - 1 {
+            header |= 0x40; // ME
+        }
+        if short {
+            header |= 0x10; // SR
+        }
+        if record.id.is_some() {
+            header |= 0x08; // IL
+        }
+        message.push(header);
+        message.push(record.record_type.len() as u8);
+        if short {
+            message.push(record.payload.len() as u8);
+        } else {
+            message.extend_from_slice(&(record.payload.len() as u32).to_be_bytes());
+        }
+        if let Some(id) = &record.id {
+            message.push(id.len() as u8);
+        }
+        message.extend_from_slice(&record.record_type);
+        if let Some(id) = &record.id {
+            message.extend_from_slice(id);
+        }
+        message.extend_from_slice(&record.payload);
+    }
+    message
+}
+
+fn decode_message(mut message: &[u8]) -> Result<Vec<Record>, String> {
+    let mut take = |length: usize| -> Result<&[u8], String> {
+        if message.len() < length {
+            return Err("truncated NDEF message".to_string());
+        }
+        let (taken, rest) = message.split_at(length);
+        message = rest;
+        Ok(taken)
+    };
+
+    let mut records = Vec::new();
+    loop {
+        let header = take(1)?[0];
+        if header & 0x20 != 0 {
+            return Err("chunked NDEF records are not supported".to_string());
+        }
+        let type_length = take(1)?[0] as usize;
+        let payload_length = if header & 0x10 != 0 {
+            take(1)?[0] as usize
+        } else {
+            u32::from_be_bytes(take(4)?.try_into().unwrap_or_default()) as usize
+        };
+        let id_length = if header & 0x08 != 0 {
+            Some(take(1)?[0] as usize)
+        } else {
+            None
+        };
+        let record_type = take(type_length)?.to_vec();
+        let id = id_length.map(&mut take).transpose()?.map(<[u8]>::to_vec);
+        let payload = take(payload_length)?.to_vec();
+        records.push(Record {
+            tnf: header & 0x07,
+            record_type,
+            id,
+            payload,
+        });
+        if header & 0x40 != 0 {
+            return Ok(records);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_handover_select() {
+        let uuid = Uuid::new_v4();
+        let carriers = [
+            BleCarrier {
+                uuid,
+                peripheral_server: false,
+                device_address: None,
+            },
+            BleCarrier {
+                uuid,
+                peripheral_server: true,
+                device_address: Some(vec![1, 2, 3, 4, 5, 6]),
+            },
+        ];
+        let message = handover_select(&[0xa1, 0x00, 0x63], &carriers).unwrap();
+        let records = decode_message(&message).unwrap();
+
+        // 1. Hs record, one carrier configuration per carrier, then the DeviceEngagement
+        let types: Vec<&[u8]> = records.iter().map(|r| r.record_type.as_slice()).collect();
+        assert_eq!(
+            types,
+            [
+                b"Hs".as_slice(),
+                BLE_OOB_TYPE,
+                BLE_OOB_TYPE,
+                DEVICE_ENGAGEMENT_TYPE
+            ]
+        );
+        assert_eq!(records[3].payload, [0xa1, 0x00, 0x63]);
+        assert_eq!(records[3].id.as_deref(), Some(DEVICE_ENGAGEMENT_ID));
+
+        // 2. The alternative carriers reference the configurations and the engagement
+        let hs = &records[0].payload;
+        assert_eq!(hs[0], HANDOVER_VERSION);
+        let alternative_carriers = decode_message(&hs[1..]).unwrap();
+        assert_eq!(alternative_carriers.len(), 2);
+        assert_eq!(
+            alternative_carriers[1].payload,
+            [CPS_ACTIVE, 1, b'1', 1, 4, b'm', b'd', b'o', b'c']
+        );
+        assert_eq!(records[2].id.as_deref(), Some(b"1".as_slice()));
+
+        // 3. The LE OOB data carries the role, the UUID and the peripheral's address
+        let mut uuid_le = uuid.as_bytes().to_vec();
+        uuid_le.reverse();
+        assert_eq!(records[1].payload[..3], [2, 0x1c, 0x01]);
+        assert_eq!(records[1].payload[5..21], uuid_le[..]);
+        assert_eq!(records[1].payload.len(), 21);
+        assert_eq!(records[2].payload[..3], [2, 0x1c, 0x00]);
+        assert_eq!(records[2].payload[21..], [8, 0x1b, 1, 2, 3, 4, 5, 6, 0]);
+
+        // 4. An address with its type is kept, other lengths are rejected
+        let with_address = |device_address: Vec<u8>| {
+            ble_oob(&BleCarrier {
+                uuid,
+                peripheral_server: true,
+                device_address: Some(device_address),
+            })
+        };
+        let random = with_address(vec![1, 2, 3, 4, 5, 6, 1]).unwrap();
+        assert_eq!(random[21..], [8, 0x1b, 1, 2, 3, 4, 5, 6, 1]);
+        assert!(with_address(vec![1, 2, 3, 4, 5]).is_err());
+        assert!(with_address(vec![0; 8]).is_err());
+    }
+
+    #[test]
+    fn test_requested_ble_roles() {
+        let request = |carrier_type: &[u8], oob: Vec<u8>| {
+            encode_message(&[
+                Record::new(TNF_WELL_KNOWN, b"Hr", None, vec![HANDOVER_VERSION]),
+                Record::new(TNF_MEDIA, carrier_type, Some(b"0"), oob),
+            ])
+        };
+        let central = request(BLE_OOB_TYPE, vec![3, 0x07, 0xaa, 0xbb, 2, 0x1c, 0x01]);
+
+        // 1. The LE role is read from the LE OOB data of BLE carriers only
+        assert_eq!(requested_ble_roles(&central), Ok(vec![Some(0x01)]));
+        assert_eq!(
+            requested_ble_roles(&request(BLE_OOB_TYPE, vec![])),
+            Ok(vec![None])
+        );
+        let wifi_aware = request(b"application/vnd.wfa.nan", vec![2, 0x1c, 0x01]);
+        assert_eq!(requested_ble_roles(&wifi_aware), Ok(vec![]));
+        assert!(requested_ble_roles(&handover_select(&[], &[]).unwrap()).is_err());
+        assert!(requested_ble_roles(&central[..5]).is_err());
+
+        // 2. A central reader needs the mdoc in peripheral server mode
+        let carrier = |peripheral_server| BleCarrier {
+            uuid: Uuid::nil(),
+            peripheral_server,
+            device_address: None,
+        };
+        assert!(carrier(true).accepts(Some(LE_ROLE_CENTRAL_ONLY)));
+        assert!(!carrier(false).accepts(Some(LE_ROLE_CENTRAL_ONLY)));
+        assert!(carrier(false).accepts(Some(LE_ROLE_PERIPHERAL_ONLY)));
+        assert!(carrier(false).accepts(None));
+    }
+}
@@ -9,31 +9,43 @@
 // This project contains code from Spruce Systems, Inc.
 // https://github.com/spruceid/sprucekit-mobile
 
+use base64::prelude::*;
 use ciborium;
 use coset::Label;
 use isomdl::definitions::x509::x5chain::X5CHAIN_COSE_HEADER_LABEL;
 use serde::{Deserialize, Serialize};
 use std::{
     collections::{BTreeMap, HashMap},
-    sync::Arc,
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, SystemTime},
 };
 use x509_cert::Certificate;
-use x509_cert::der::DecodePem;
+use x509_cert::der::{DecodePem, Encode};
 
 use isomdl::{
     definitions::{
-        device_request,
-        helpers::{NonEmptyMap, non_empty_map},
-        x509::{
-            self,
-            trust_anchor::{PemTrustAnchor, TrustAnchorRegistry},
-        },
+        DeviceEngagement, DeviceRetrievalMethod, IssuerSigned, Mso, device_request,
+        helpers::{NonEmptyMap, NonEmptyVec, Tag24, non_empty_map},
+        session,
+        x509::trust_anchor::{PemTrustAnchor, TrustAnchorRegistry},
     },
-    presentation::{authentication::AuthenticationStatus as IsoMdlAuthenticationStatus, reader},
+    presentation::authentication::AuthenticationStatus as IsoMdlAuthenticationStatus,
 };
 use uuid::Uuid;
 
-use super::util::build_intermediate_trust_chain;
+use super::aamva::AamvaElement;
+use super::dcql::{self, UnmetClaim};
+use super::holder::ServerRetrieval;
+use super::jwe;
+use super::mdoc::{DigestMismatch, check_item_digests};
+use super::namespaces;
+use super::session_encryption::{self, SessionKeys};
+use super::util::{
+    IssuerKeyType, IssuerSigningKey, TrustAnchorPurpose, build_intermediate_trust_chain,
+    common_name, country_name, jwk_thumbprint, parse_certificate_chain, pem_trust_anchor,
+    state_or_province_name, verify_device_auth_mac, verify_device_auth_signature,
+    x5chain_certificates,
+};
 
 /// OID4VP SessionTranscript per OpenID4VP over ISO 18013-5 spec (updated 2024):
 /// SessionTranscript = [null, null, OID4VPHandover]
@@ -58,22 +70,202 @@ pub struct OID4VPHandover(
 /// Used to compute the hash for OID4VPHandover
 #[derive(Serialize, Clone)]
 pub struct OID4VPHandoverInfo(
-    pub String,          // clientId
-    pub String,          // nonce
-    pub Option<Vec<u8>>, // jwkThumbprint (null if no encryption)
-    pub String,          // responseUri
+    pub String,                                         // clientId
+    pub String,                                         // nonce
+    #[serde(with = "serde_bytes")] pub Option<Vec<u8>>, // jwkThumbprint (null if no encryption)
+    pub String,                                         // responseUri
+);
+
+/// OpenID4VPDCAPIHandoverInfo = [origin, nonce, jwkThumbprint]
+/// Used to compute the hash of the handover of Digital Credentials API requests
+#[derive(Serialize, Clone)]
+pub struct OID4VPDCAPIHandoverInfo(
+    pub String,                                         // origin
+    pub String,                                         // nonce
+    #[serde(with = "serde_bytes")] pub Option<Vec<u8>>, // jwkThumbprint (null if no encryption)
 );
 
+impl OID4VPSessionTranscript {
+    /// SessionTranscript = [null, null, ["OpenID4VPHandover", sha256(cbor([clientId, nonce, jwkThumbprint, responseUri]))]]
+    /// jwkThumbprint is the thumbprint of the verifier's encryption key if the response
+    /// is encrypted, null otherwise.
+    pub(crate) fn new(
+        client_id: &str,
+        nonce: &str,
+        jwk_thumbprint: Option<Vec<u8>>,
+        response_uri: &str,
+    ) -> Result<Self, ciborium::ser::Error<std::io::Error>> {
+        let handover_info = OID4VPHandoverInfo(
+            client_id.to_string(),
+            nonce.to_string(),
+            jwk_thumbprint,
+            response_uri.to_string(),
+        );
+        Self::with_handover("OpenID4VPHandover", &handover_info)
+    }
+
+    /// SessionTranscript = [null, null, ["OpenID4VPDCAPIHandover", sha256(cbor([origin, nonce, jwkThumbprint]))]]
+    /// for requests made through the Digital Credentials API, per OpenID4VP Appendix
+    /// B.2.6.2. jwkThumbprint is the thumbprint of the verifier's encryption key if the
+    /// response is encrypted.
+    pub(crate) fn dc_api(
+        origin: &str,
+        nonce: &str,
+        jwk_thumbprint: Option<Vec<u8>>,
+    ) -> Result<Self, ciborium::ser::Error<std::io::Error>> {
+        let handover_info =
+            OID4VPDCAPIHandoverInfo(origin.to_string(), nonce.to_string(), jwk_thumbprint);
+        Self::with_handover("OpenID4VPDCAPIHandover", &handover_info)
+    }
+
+    fn with_handover(
+        identifier: &str,
+        handover_info: &impl Serialize,
+    ) -> Result<Self, ciborium::ser::Error<std::io::Error>> {
+        use sha2::{Digest, Sha256};
+
+        // CBOR-encode the handover info
+        let mut handover_info_bytes = Vec::new();
+        ciborium::into_writer(handover_info, &mut handover_info_bytes)?;
+
+        // Hash the CBOR-encoded handover info
+        let handover_info_hash = Sha256::digest(&handover_info_bytes).to_vec();
+
+        Ok(OID4VPSessionTranscript(
+            None, // DeviceEngagementBytes - null for OID4VP
+            None, // EReaderKeyBytes - null for OID4VP
+            OID4VPHandover(identifier.to_string(), handover_info_hash),
+        ))
+    }
+}
+
 impl isomdl::definitions::session::SessionTranscript for OID4VPSessionTranscript {}
 
+/// SessionTranscript of wallets implementing ISO 18013-7 over OpenID4VP draft 20:
+/// SessionTranscript = [null, null, [clientIdHash, responseUriHash, nonce]]
+#[derive(Serialize, Deserialize, Clone)]
+pub struct OID4VPDraft20SessionTranscript(
+    pub Option<()>, // DeviceEngagementBytes - null for OID4VP
+    pub Option<()>, // EReaderKeyBytes - null for OID4VP
+    pub OID4VPDraft20Handover,
+);
+
+/// OID4VPHandover = [clientIdHash, responseUriHash, nonce]
+/// Where clientIdHash = sha256(cbor([clientId, mdocGeneratedNonce]))
+/// And responseUriHash = sha256(cbor([responseUri, mdocGeneratedNonce]))
+#[derive(Serialize, Deserialize, Clone)]
+pub struct OID4VPDraft20Handover(
+    #[serde(with = "serde_bytes")] pub Vec<u8>, // clientIdHash
+    #[serde(with = "serde_bytes")] pub Vec<u8>, // responseUriHash
+    pub String,                                 // nonce
+);
+
+impl OID4VPDraft20SessionTranscript {
+    /// `mdoc_generated_nonce` is the nonce chosen by the wallet, sent in the `apu` header
+    /// of the encrypted response.
+    pub(crate) fn new(
+        client_id: &str,
+        nonce: &str,
+        mdoc_generated_nonce: &str,
+        response_uri: &str,
+    ) -> Result<Self, ciborium::ser::Error<std::io::Error>> {
+        use sha2::{Digest, Sha256};
+
+        let hash = |value: &str| {
+            let mut bytes = Vec::new();
+            ciborium::into_writer(&(value, mdoc_generated_nonce), &mut bytes)?;
+            Ok::<_, ciborium::ser::Error<std::io::Error>>(Sha256::digest(&bytes).to_vec())
+        };
+        Ok(OID4VPDraft20SessionTranscript(
+            None,
+            None,
+            OID4VPDraft20Handover(hash(client_id)?, hash(response_uri)?, nonce.to_string()),
+        ))
+    }
+}
+
+impl isomdl::definitions::session::SessionTranscript for OID4VPDraft20SessionTranscript {}
+
+/// The definitions of the OID4VPHandover implemented by wallets.
+#[derive(uniffi::Enum, Debug, Clone, PartialEq)]
+pub enum HandoverVariant {
+    /// OpenID4VP 1.0: a hash over [clientId, nonce, jwkThumbprint, responseUri].
+    OpenId4Vp,
+    /// ISO 18013-7 over OpenID4VP draft 20: [clientIdHash, responseUriHash, nonce], salted
+    /// with the wallet's `mdoc_generated_nonce` from the `apu` header of the response.
+    Draft20 { mdoc_generated_nonce: String },
+}
+
+/// The failures of reader sessions and of the verification of OpenID4VP responses, by
+/// kind, for apps to branch on. `value` describes the failure for logs.
 #[derive(thiserror::Error, uniffi::Error, Debug)]
 pub enum MDLReaderSessionError {
+    /// The mdoc URI or the DeviceEngagement of the holder could not be decoded.
+    #[error("{value}")]
+    EngagementParse { value: String },
+    /// The holder offered no device retrieval method supported by the reader.
+    #[error("{value}")]
+    UnsupportedRetrievalMethod { value: String },
+    /// No session could be established with the engaged holder, such as with an
+    /// EDeviceKey the reader does not support or a failed key agreement.
+    #[error("{value}")]
+    SessionEstablishment { value: String },
+    /// The requested elements do not make a valid request.
+    #[error("{value}")]
+    InvalidRequest { value: String },
+    /// A message or structure of the reader could not be encoded.
+    #[error("{value}")]
+    Encoding { value: String },
+    /// The encrypted response could not be decrypted with the verifier's key.
+    #[error("{value}")]
+    DecryptionFailed { value: String },
+    /// The response was not made for this request.
+    #[error("{value}")]
+    TranscriptMismatch { value: String },
+    /// A trust anchor is invalid, or no registry could be built from them.
+    #[error("{value}")]
+    TrustAnchorInvalid { value: String },
+    /// The reader certificate or key to sign ReaderAuth with is invalid.
+    #[error("{value}")]
+    ReaderAuthInvalid { value: String },
+    /// The response or its DeviceResponse could not be decoded.
+    #[error("{value}")]
+    ResponseParse { value: String },
+    /// The DCQL query to match the response against could not be decoded.
+    #[error("{value}")]
+    InvalidQuery { value: String },
     #[error("{value}")]
     Generic { value: String },
 }
 
-#[derive(uniffi::Object)]
-pub struct MDLSessionManager(reader::SessionManager);
+/// The reader session, with the elements requested in it.
+#[derive(uniffi::Object, Clone)]
+pub struct MDLSessionManager {
+    keys: SessionKeys,
+    trust_anchors: TrustAnchors,
+    /// The elements requested of each docType, by namespace.
+    requested_documents: BTreeMap<String, HashMap<String, Vec<RequestedElement>>>,
+    session_transcript: Vec<u8>,
+    ble_ident: [u8; 16],
+    /// The reader's key of the key agreement. The EMacKey of a document authenticated
+    /// with a deviceMac is derived from it and the SDeviceKey of the document, so it is
+    /// kept for the session.
+    reader_key: Arc<dyn session_encryption::ReaderKey>,
+}
+
+impl MDLSessionManager {
+    /// The elements requested of every docType, by namespace.
+    fn requested_elements(&self) -> HashMap<String, Vec<RequestedElement>> {
+        let mut requested_elements: HashMap<String, Vec<RequestedElement>> = HashMap::new();
+        for (namespace, elements) in self.requested_documents.values().flatten() {
+            requested_elements
+                .entry(namespace.clone())
+                .or_default()
+                .extend(elements.iter().cloned());
+        }
+        requested_elements
+    }
+}
 
 impl std::fmt::Debug for MDLSessionManager {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -81,20 +273,338 @@ impl std::fmt::Debug for MDLSessionManager {
     }
 }
 
+#[uniffi::export]
+impl MDLSessionManager {
+    /// Returns the CBOR encoded SessionTranscript of this session, which the
+    /// DeviceAuthentication of the response is signed over, to log it or to compare it
+    /// with the one of a holder whose device authentication fails.
+    pub fn session_transcript(&self) -> Vec<u8> {
+        self.session_transcript.clone()
+    }
+
+    /// Returns the BLE Ident the holder's GATT server is expected to expose, derived from
+    /// the holder's ephemeral key per ISO 18013-5 8.3.3.1.1.4.
+    pub fn ble_ident(&self) -> Vec<u8> {
+        self.ble_ident.to_vec()
+    }
+
+    /// Checks the value read from the Ident characteristic of a peripheral, to confirm
+    /// the reader connected to the holder it engaged with before sending the request.
+    /// The Ident is exactly the 16 bytes derived by both sides.
+    pub fn verify_ble_ident(&self, received: Vec<u8>) -> bool {
+        received == self.ble_ident
+    }
+
+    /// Returns the SessionData message terminating the session, with status 20, to be
+    /// transmitted to the holder.
+    pub fn termination_message(&self) -> Result<Vec<u8>, MDLReaderSessionError> {
+        session_status_message(SessionStatus::SessionTermination)
+    }
+
+    /// Returns a SessionData message with the given status, to end the session when
+    /// the response could not be decrypted or decoded.
+    pub fn termination_message_with_status(
+        &self,
+        status: SessionStatus,
+    ) -> Result<Vec<u8>, MDLReaderSessionError> {
+        session_status_message(status)
+    }
+}
+
+/// The status of a SessionData message ending a session, see
+/// [MDLSessionManager::termination_message_with_status].
+#[derive(uniffi::Enum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionStatus {
+    /// Status 10, the response could not be decrypted.
+    SessionEncryptionError,
+    /// Status 11, the response could not be decoded.
+    CborDecodingError,
+    /// Status 20, the session is terminated.
+    SessionTermination,
+}
+
+fn session_status_message(status: SessionStatus) -> Result<Vec<u8>, MDLReaderSessionError> {
+    let status = match status {
+        SessionStatus::SessionEncryptionError => session::Status::SessionEncryptionError,
+        SessionStatus::CborDecodingError => session::Status::CborDecodingError,
+        SessionStatus::SessionTermination => session::Status::SessionTermination,
+    };
+    let msg = session::SessionData {
+        data: None,
+        status: Some(status),
+    };
+    isomdl::cbor::to_vec(&msg).map_err(|e| MDLReaderSessionError::Encoding {
+        value: format!("Could not serialize message bytes: {e:?}"),
+    })
+}
+
 #[derive(uniffi::Record)]
 pub struct MDLReaderSessionData {
     pub state: Arc<MDLSessionManager>,
+    /// The BLE service UUID, of central client mode if the holder offers it, else of
+    /// peripheral server mode.
     uuid: Uuid,
     pub request: Vec<u8>,
-    ble_ident: Vec<u8>,
+    /// The BLE Ident expected of the holder, see [MDLSessionManager::verify_ble_ident].
+    pub ble_ident: Vec<u8>,
+    /// The service UUID of central client mode, in which the reader advertises the
+    /// service for the holder to connect to.
+    central_client_uuid: Option<Uuid>,
+    /// Peripheral server mode, in which the reader connects as a GATT client to the
+    /// service the holder advertises.
+    peripheral_server: Option<PeripheralServerOptions>,
 }
 
-#[uniffi::export]
+/// A data element requested from the holder.
+#[derive(uniffi::Record, Debug, Clone, PartialEq, Eq)]
+pub struct RequestedElement {
+    pub element_id: String,
+    /// Whether the reader intends to retain the element after the transaction, sent
+    /// as IntentToRetain in the DeviceRequest for the holder to show to the user.
+    pub intent_to_retain: bool,
+}
+
+/// The peripheral server mode offered by the holder in the DeviceEngagement.
+#[derive(uniffi::Record, Debug, Clone, PartialEq)]
+pub struct PeripheralServerOptions {
+    pub uuid: Uuid,
+    /// The BLE device address of the holder, if given, to connect without scanning.
+    pub ble_device_address: Option<Vec<u8>>,
+}
+
+/// Start a reader session with the holder engaged through the `mdoc:` URI, returning
+/// the SessionEstablishment message requesting the given elements by namespace, each
+/// flagged with the intent to retain.
+///
+/// The elements are requested of the mDL docType. Use the [DeviceRequestBuilder] to
+/// request further docTypes, such as the PID, in the same session. Every document the
+/// holder returns is validated, see [handle_response].
+///
+/// With `reader_auth`, each DocRequest carries a ReaderAuth signed over the
+/// SessionTranscript with the reader certificate's key, for the holder to authenticate
+/// the reader. Many wallets disclose nothing to an unauthenticated reader.
+///
+/// The reader's ephemeral session key is generated for each session, unless
+/// `key_agreement` performs the key agreement with a key the caller holds. It is kept
+/// with the session to verify the deviceMac of documents, see [handle_response].
+#[uniffi::export(default(key_agreement = None, reader_auth = None))]
 pub fn establish_session(
     uri: String,
     requested_items: HashMap<String, HashMap<String, bool>>,
     trust_anchor_registry: Option<Vec<String>>,
+    key_agreement: Option<Box<dyn ReaderKeyAgreement>>,
+    reader_auth: Option<ReaderAuthKey>,
+) -> Result<MDLReaderSessionData, MDLReaderSessionError> {
+    let namespaces = request_namespaces(requested_items)?;
+    establish_session_with_namespaces(
+        uri,
+        BTreeMap::from([(MDL_DOC_TYPE.to_string(), namespaces)]),
+        trust_anchor_registry,
+        key_agreement,
+        reader_auth,
+    )
+}
+
+/// The reader certificate and key the ReaderAuth of a request is signed with, see
+/// [establish_session].
+#[derive(uniffi::Record, Clone)]
+pub struct ReaderAuthKey {
+    /// The PEM encoded reader certificate, followed by the intermediate certificates
+    /// up to the reader CA if any.
+    pub certificate_chain_pem: String,
+    /// The PKCS#8 PEM encoded key of the reader certificate, on P-256, P-384 or
+    /// Ed25519.
+    pub key_pem: String,
+}
+
+impl std::fmt::Debug for ReaderAuthKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReaderAuthKey")
+            .field("certificate_chain_pem", &self.certificate_chain_pem)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A [ReaderAuthKey] parsed to sign requests with.
+struct ReaderAuth {
+    key: IssuerSigningKey,
+    x5chain: ciborium::Value,
+}
+
+impl ReaderAuth {
+    fn new(reader_auth: ReaderAuthKey) -> Result<Self, MDLReaderSessionError> {
+        let error = |value: String| MDLReaderSessionError::ReaderAuthInvalid { value };
+        let key = IssuerSigningKey::from_pkcs8_pem(&reader_auth.key_pem)
+            .map_err(|e| error(format!("Invalid reader key: {e:#}")))?;
+        let certificates = parse_certificate_chain(&reader_auth.certificate_chain_pem)
+            .map_err(|e| error(format!("Invalid reader certificate: {e:#}")))?
+            .iter()
+            .map(|certificate| certificate.to_der().map(ciborium::Value::Bytes))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| error(format!("Invalid reader certificate: {e}")))?;
+        let reader_key = key
+            .subject_public_key_info()
+            .map_err(|e| error(format!("Invalid reader key: {e:#}")))?;
+        let reader_certificate = Certificate::from_pem(&reader_auth.certificate_chain_pem)
+            .map_err(|e| error(format!("Invalid reader certificate: {e}")))?;
+        if reader_certificate.tbs_certificate.subject_public_key_info != reader_key {
+            return Err(error(
+                "The reader certificate does not match the reader key".to_string(),
+            ));
+        }
+        // A single certificate is a bstr, a chain an array of them
+        let x5chain = match <[_; 1]>::try_from(certificates) {
+            Ok([certificate]) => certificate,
+            Err(certificates) => ciborium::Value::Array(certificates),
+        };
+        Ok(Self { key, x5chain })
+    }
+
+    /// The ReaderAuth of a DocRequest per ISO 18013-5 9.1.4: a COSE_Sign1 with the
+    /// tagged ReaderAuthentication as its detached payload, and the reader certificate
+    /// chain in its x5chain.
+    fn sign(
+        &self,
+        session_transcript: &[u8],
+        items_request_bytes: ciborium::Value,
+    ) -> Result<ciborium::Value, String> {
+        use ciborium::Value;
+        use coset::AsCborValue;
+
+        let session_transcript: Value = ciborium::from_reader(session_transcript)
+            .map_err(|e| format!("Invalid session transcript: {e}"))?;
+        let reader_authentication = Value::Array(vec![
+            "ReaderAuthentication".into(),
+            session_transcript,
+            items_request_bytes,
+        ]);
+        let mut payload = Vec::new();
+        ciborium::into_writer(&reader_authentication, &mut payload)
+            .map_err(|e| format!("Could not encode the ReaderAuthentication: {e}"))?;
+        let mut reader_authentication_bytes = Vec::new();
+        ciborium::into_writer(
+            &Value::Tag(24, Box::new(Value::Bytes(payload))),
+            &mut reader_authentication_bytes,
+        )
+        .map_err(|e| format!("Could not encode the ReaderAuthentication: {e}"))?;
+        coset::CoseSign1Builder::new()
+            .protected(
+                coset::HeaderBuilder::new()
+                    .algorithm(cose_algorithm(&self.key))
+                    .build(),
+            )
+            .unprotected(
+                coset::HeaderBuilder::new()
+                    .value(X5CHAIN_COSE_HEADER_LABEL, self.x5chain.clone())
+                    .build(),
+            )
+            .create_detached_signature(&reader_authentication_bytes, &[], |tbs| self.key.sign(tbs))
+            .build()
+            .to_cbor_value()
+            .map_err(|e| format!("Could not encode the ReaderAuth: {e:?}"))
+    }
+}
+
+/// The COSE algorithm of the signatures of a key.
+fn cose_algorithm(key: &IssuerSigningKey) -> coset::iana::Algorithm {
+    match key.key_type() {
+        IssuerKeyType::P256 => coset::iana::Algorithm::ES256,
+        IssuerKeyType::P384 => coset::iana::Algorithm::ES384,
+        IssuerKeyType::Ed25519 => coset::iana::Algorithm::EdDSA,
+    }
+}
+
+/// Performs the key agreement of a reader session with a P-256 key held by the caller,
+/// such as a key in an HSM or one fixed for tests, instead of a key generated for the
+/// session, see [establish_session].
+#[uniffi::export(callback_interface)]
+pub trait ReaderKeyAgreement: Send + Sync {
+    /// The SEC1 encoded public key, sent to the holder as the EReaderKey.
+    fn public_key(&self) -> Vec<u8>;
+    /// The ECDH shared secret with the holder's SEC1 encoded EDeviceKey, the 32 byte
+    /// x-coordinate of the shared point.
+    fn agree(&self, device_key: Vec<u8>) -> Result<Vec<u8>, KeyAgreementError>;
+}
+
+#[derive(thiserror::Error, uniffi::Error, Debug)]
+pub enum KeyAgreementError {
+    #[error("{value}")]
+    Generic { value: String },
+}
+
+impl From<uniffi::UnexpectedUniFFICallbackError> for KeyAgreementError {
+    fn from(e: uniffi::UnexpectedUniFFICallbackError) -> Self {
+        Self::Generic { value: e.reason }
+    }
+}
+
+impl session_encryption::ReaderKey for Box<dyn ReaderKeyAgreement> {
+    fn public_key(&self) -> anyhow::Result<p256::PublicKey> {
+        p256::PublicKey::from_sec1_bytes(&ReaderKeyAgreement::public_key(self.as_ref()))
+            .map_err(|e| anyhow::anyhow!("invalid reader public key: {e}"))
+    }
+
+    fn shared_secret(&self, device_key: &p256::PublicKey) -> anyhow::Result<Vec<u8>> {
+        use p256::elliptic_curve::sec1::ToEncodedPoint;
+
+        let shared_secret = self.agree(device_key.to_encoded_point(false).as_bytes().to_vec())?;
+        if shared_secret.len() != 32 {
+            anyhow::bail!("the shared secret is not the 32 byte x-coordinate");
+        }
+        Ok(shared_secret)
+    }
+}
+
+/// Start a reader session as [establish_session] does, with the holder's
+/// DeviceEngagement CBOR received over a channel other than the QR code.
+///
+/// For a holder engaged through NFC, `handover_select` is its Handover Select message
+/// and `handover_request` the reader's Handover Request message in negotiated handover,
+/// which the SessionTranscript is bound to. Without them the SessionTranscript is built
+/// with the QR handover, null.
+#[uniffi::export(default(
+    handover_select = None,
+    handover_request = None,
+    key_agreement = None,
+    reader_auth = None
+))]
+pub fn establish_session_with_device_engagement(
+    device_engagement: Vec<u8>,
+    requested_items: HashMap<String, HashMap<String, bool>>,
+    trust_anchor_registry: Option<Vec<String>>,
+    handover_select: Option<Vec<u8>>,
+    handover_request: Option<Vec<u8>>,
+    key_agreement: Option<Box<dyn ReaderKeyAgreement>>,
+    reader_auth: Option<ReaderAuthKey>,
 ) -> Result<MDLReaderSessionData, MDLReaderSessionError> {
+    use ciborium::Value;
+
+    let handover = match (handover_select, handover_request) {
+        (None, None) => Value::Null,
+        (Some(select), request) => Value::Array(vec![
+            Value::Bytes(select),
+            request.map_or(Value::Null, Value::Bytes),
+        ]),
+        (None, Some(_)) => {
+            return Err(MDLReaderSessionError::InvalidRequest {
+                value: "A Handover Request needs the Handover Select answering it".to_string(),
+            });
+        }
+    };
+    let namespaces = request_namespaces(requested_items)?;
+    establish_engaged_session(
+        &device_engagement,
+        handover,
+        BTreeMap::from([(MDL_DOC_TYPE.to_string(), namespaces)]),
+        trust_anchor_registry,
+        key_agreement,
+        reader_auth,
+    )
+}
+
+fn request_namespaces(
+    requested_items: HashMap<String, HashMap<String, bool>>,
+) -> Result<device_request::Namespaces, MDLReaderSessionError> {
     let namespaces: Result<BTreeMap<_, NonEmptyMap<_, _>>, non_empty_map::Error> = requested_items
         .into_iter()
         .map(|(doc_type, namespaces)| {
@@ -105,54 +615,412 @@ pub fn establish_session(
             }
         })
         .collect();
-    let namespaces = namespaces.map_err(|e| MDLReaderSessionError::Generic {
+    let namespaces = namespaces.map_err(|e| MDLReaderSessionError::InvalidRequest {
         value: format!("Unable to build data elements: {e:?}"),
     })?;
     let namespaces: device_request::Namespaces =
         namespaces
             .try_into()
-            .map_err(|e| MDLReaderSessionError::Generic {
+            .map_err(|e| MDLReaderSessionError::InvalidRequest {
                 value: format!("Unable to build namespaces: {e:?}"),
             })?;
+    Ok(namespaces)
+}
 
-    let registry = TrustAnchorRegistry::from_pem_certificates(
-        trust_anchor_registry
-            .into_iter()
-            .flat_map(|v| v.into_iter())
-            .map(|certificate_pem| PemTrustAnchor {
-                certificate_pem,
-                purpose: x509::trust_anchor::TrustPurpose::Iaca,
-            })
-            .collect(),
+/// Start a reader session as [establish_session] does, with the requested elements of
+/// each namespace given as records spelling out the intent to retain.
+#[uniffi::export(default(key_agreement = None, reader_auth = None))]
+pub fn establish_session_with_elements(
+    uri: String,
+    requested_elements: HashMap<String, Vec<RequestedElement>>,
+    trust_anchor_registry: Option<Vec<String>>,
+    key_agreement: Option<Box<dyn ReaderKeyAgreement>>,
+    reader_auth: Option<ReaderAuthKey>,
+) -> Result<MDLReaderSessionData, MDLReaderSessionError> {
+    let requested_items = requested_elements
+        .into_iter()
+        .map(|(namespace, elements)| {
+            let elements = elements
+                .into_iter()
+                .map(|element| (element.element_id, element.intent_to_retain))
+                .collect();
+            (namespace, elements)
+        })
+        .collect();
+    establish_session(
+        uri,
+        requested_items,
+        trust_anchor_registry,
+        key_agreement,
+        reader_auth,
+    )
+}
+
+/// The namespaces requested of each docType.
+type DocRequests = BTreeMap<String, device_request::Namespaces>;
+
+fn establish_session_with_namespaces(
+    uri: String,
+    doc_requests: DocRequests,
+    trust_anchor_registry: Option<Vec<String>>,
+    key_agreement: Option<Box<dyn ReaderKeyAgreement>>,
+    reader_auth: Option<ReaderAuthKey>,
+) -> Result<MDLReaderSessionData, MDLReaderSessionError> {
+    let device_engagement = device_engagement_bytes(&uri)?;
+    establish_engaged_session(
+        &device_engagement,
+        ciborium::Value::Null,
+        doc_requests,
+        trust_anchor_registry,
+        key_agreement,
+        reader_auth,
     )
-    .map_err(|e| MDLReaderSessionError::Generic {
-        value: format!("unable to construct TrustAnchorRegistry: {e:?}"),
+}
+
+/// Establishes the session with the holder of the CBOR encoded DeviceEngagement, bound
+/// to `handover` in the SessionTranscript.
+fn establish_engaged_session(
+    device_engagement: &[u8],
+    handover: ciborium::Value,
+    doc_requests: DocRequests,
+    trust_anchor_registry: Option<Vec<String>>,
+    key_agreement: Option<Box<dyn ReaderKeyAgreement>>,
+    reader_auth: Option<ReaderAuthKey>,
+) -> Result<MDLReaderSessionData, MDLReaderSessionError> {
+    let requested_documents = doc_requests
+        .iter()
+        .map(|(doc_type, namespaces)| {
+            let namespaces = namespaces
+                .iter()
+                .map(|(namespace, elements)| {
+                    let elements = elements
+                        .iter()
+                        .map(|(element_id, intent_to_retain)| RequestedElement {
+                            element_id: element_id.clone(),
+                            intent_to_retain: *intent_to_retain,
+                        })
+                        .collect();
+                    (namespace.clone(), elements)
+                })
+                .collect();
+            (doc_type.clone(), namespaces)
+        })
+        .collect();
+    isomdl::cbor::from_slice::<DeviceEngagement>(device_engagement).map_err(|e| {
+        MDLReaderSessionError::EngagementParse {
+            value: format!("Could not decode the device engagement: {e:?}"),
+        }
     })?;
+    let trust_anchors = TrustAnchors::new(trust_anchor_registry, None)?;
+    let reader_auth = reader_auth.map(ReaderAuth::new).transpose()?;
+    let establish_error = |e: anyhow::Error| MDLReaderSessionError::SessionEstablishment {
+        value: format!("unable to establish session: {e:?}"),
+    };
+    let reader_key: Arc<dyn session_encryption::ReaderKey> = match key_agreement {
+        Some(key_agreement) => Arc::new(key_agreement),
+        None => Arc::new(session_encryption::ephemeral_key()),
+    };
+    let mut session =
+        session_encryption::establish(device_engagement, handover, reader_key.as_ref())
+            .map_err(establish_error)?;
+    let device_request = device_request(
+        &doc_requests,
+        &session.session_transcript,
+        reader_auth.as_ref(),
+    )?;
+    let request = session
+        .session_establishment(&device_request)
+        .map_err(|e| MDLReaderSessionError::Encoding {
+            value: format!("Could not encode the session establishment: {e:?}"),
+        })?;
 
-    let (manager, request, ble_ident) =
-        reader::SessionManager::establish_session(uri.to_string(), namespaces, registry).map_err(
-            |e| MDLReaderSessionError::Generic {
-                value: format!("unable to establish session: {e:?}"),
-            },
-        )?;
-    let manager2 = manager.clone();
-    // Use the new API instead of deprecated first_central_client_uuid()
-    let uuid = manager2
-        .ble_central_client_options()
+    let ble_options = session
+        .device_engagement
+        .device_retrieval_methods
+        .iter()
+        .flat_map(|methods| methods.iter())
+        .filter_map(|method| match method {
+            DeviceRetrievalMethod::BLE(options) => Some(options),
+            _ => None,
+        });
+    let central_client_uuid = ble_options
+        .clone()
+        .find_map(|options| options.central_client_mode.as_ref())
+        .map(|central_client_mode| central_client_mode.uuid);
+    let peripheral_server = ble_options
+        .filter_map(|options| options.peripheral_server_mode.as_ref())
         .next()
-        .map(|central_client_mode| central_client_mode.uuid)
-        .ok_or_else(|| MDLReaderSessionError::Generic {
-            value: "the device did not transmit a central client uuid".to_string(),
+        .map(|peripheral_server_mode| PeripheralServerOptions {
+            uuid: peripheral_server_mode.uuid,
+            ble_device_address: peripheral_server_mode
+                .ble_device_address
+                .clone()
+                .map(Into::into),
+        });
+    let uuid = central_client_uuid
+        .or(peripheral_server.as_ref().map(|options| options.uuid))
+        .ok_or_else(|| MDLReaderSessionError::UnsupportedRetrievalMethod {
+            value: "the device did not transmit a BLE service uuid".to_string(),
         })?;
 
     Ok(MDLReaderSessionData {
-        state: Arc::new(MDLSessionManager(manager)),
+        state: Arc::new(MDLSessionManager {
+            keys: session.keys,
+            trust_anchors,
+            requested_documents,
+            session_transcript: session.session_transcript,
+            ble_ident: session.ble_ident,
+            reader_key,
+        }),
         request,
-        ble_ident: ble_ident.to_vec(),
+        ble_ident: session.ble_ident.to_vec(),
         uuid,
+        central_client_uuid,
+        peripheral_server,
     })
 }
 
+/// The docType of the mDL, requested by [establish_session].
+const MDL_DOC_TYPE: &str = "org.iso.18013.5.1.mDL";
+
+/// Builds the request of a reader session element by element, as a typed alternative
+/// to the nested maps of [establish_session], which can request several docTypes in
+/// the same session.
+///
+/// Elements of the namespaces this crate defines, such as `org.iso.18013.5.1`, are
+/// checked against their definition, so a misspelled identifier is reported here
+/// rather than silently not returned by the holder. Elements of other namespaces are
+/// requested as given.
+#[derive(uniffi::Object, Debug, Default)]
+pub struct DeviceRequestBuilder(Mutex<DeviceRequestBuilderState>);
+
+#[derive(Debug, Default)]
+struct DeviceRequestBuilderState {
+    doc_type: Option<String>,
+    /// The elements added of each docType, by namespace.
+    doc_types: BTreeMap<String, BTreeMap<String, BTreeMap<String, bool>>>,
+    reader_auth: Option<ReaderAuthKey>,
+}
+
+#[uniffi::export]
+impl DeviceRequestBuilder {
+    #[uniffi::constructor]
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    /// Adds a docType to request, such as `org.iso.18013.5.1.mDL` or
+    /// `eu.europa.ec.eudi.pid.1`, which the following elements are requested from. Each
+    /// docType is requested in its own DocRequest.
+    pub fn add_doc_type(&self, doc_type: String) -> Result<(), MDLReaderSessionError> {
+        if doc_type.is_empty() {
+            return Err(MDLReaderSessionError::InvalidRequest {
+                value: "The docType must not be empty".to_string(),
+            });
+        }
+        self.state().doc_type = Some(doc_type);
+        Ok(())
+    }
+
+    /// Adds a data element to request of the last added docType, flagged with the
+    /// intent to retain it.
+    pub fn add_element(
+        &self,
+        namespace: String,
+        element_identifier: String,
+        intent_to_retain: bool,
+    ) -> Result<(), MDLReaderSessionError> {
+        let error = |value: String| MDLReaderSessionError::InvalidRequest { value };
+        let mut state = self.state();
+        let Some(doc_type) = state.doc_type.clone() else {
+            return Err(error(
+                "A docType must be added before its elements".to_string(),
+            ));
+        };
+        if namespace.is_empty() || element_identifier.is_empty() {
+            return Err(error(
+                "The namespace and element identifier must not be empty".to_string(),
+            ));
+        }
+        if let Some(definition) = namespaces::definition(&namespace)
+            && definition.element_type(&element_identifier).is_none()
+        {
+            return Err(error(format!(
+                "{element_identifier} is not an element of {namespace}"
+            )));
+        }
+        state
+            .doc_types
+            .entry(doc_type)
+            .or_default()
+            .entry(namespace)
+            .or_default()
+            .insert(element_identifier, intent_to_retain);
+        Ok(())
+    }
+
+    /// Adds an element of the AAMVA namespace, as [DeviceRequestBuilder::add_element]
+    /// does.
+    pub fn add_aamva_element(
+        &self,
+        element: AamvaElement,
+        intent_to_retain: bool,
+    ) -> Result<(), MDLReaderSessionError> {
+        self.add_element(
+            namespaces::AAMVA_NAMESPACE.to_string(),
+            element.identifier().to_string(),
+            intent_to_retain,
+        )
+    }
+
+    /// Signs the request with ReaderAuth, with the reader certificate and key, as
+    /// [establish_session] does. The certificate and key are checked here.
+    pub fn with_reader_auth(
+        &self,
+        reader_auth: ReaderAuthKey,
+    ) -> Result<(), MDLReaderSessionError> {
+        ReaderAuth::new(reader_auth.clone())?;
+        self.state().reader_auth = Some(reader_auth);
+        Ok(())
+    }
+
+    /// Start a reader session with the holder engaged through the `mdoc:` URI,
+    /// requesting the added elements, as [establish_session] does.
+    pub fn establish_session(
+        &self,
+        uri: String,
+        trust_anchor_registry: Option<Vec<String>>,
+    ) -> Result<MDLReaderSessionData, MDLReaderSessionError> {
+        let doc_requests = self.doc_requests()?;
+        let reader_auth = self.state().reader_auth.clone();
+        establish_session_with_namespaces(
+            uri,
+            doc_requests,
+            trust_anchor_registry,
+            None,
+            reader_auth,
+        )
+    }
+}
+
+impl DeviceRequestBuilder {
+    fn state(&self) -> std::sync::MutexGuard<'_, DeviceRequestBuilderState> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// The namespaces requested of each docType, leaving out the docTypes no element
+    /// was added to.
+    fn doc_requests(&self) -> Result<DocRequests, MDLReaderSessionError> {
+        let doc_requests: DocRequests = self
+            .state()
+            .doc_types
+            .clone()
+            .into_iter()
+            .filter_map(|(doc_type, namespaces)| {
+                let namespaces: BTreeMap<_, NonEmptyMap<_, _>> = namespaces
+                    .into_iter()
+                    .filter_map(|(namespace, elements)| {
+                        elements
+                            .try_into()
+                            .ok()
+                            .map(|elements| (namespace, elements))
+                    })
+                    .collect();
+                namespaces
+                    .try_into()
+                    .ok()
+                    .map(|namespaces| (doc_type, namespaces))
+            })
+            .collect();
+        if doc_requests.is_empty() {
+            return Err(MDLReaderSessionError::InvalidRequest {
+                value: "No data elements have been added to the request".to_string(),
+            });
+        }
+        Ok(doc_requests)
+    }
+}
+
+/// Returns the server retrieval options the holder advertised in the DeviceEngagement
+/// of an `mdoc:` URI, to retrieve the mdoc from the issuer if the proximity transfer
+/// fails or is not possible.
+#[uniffi::export]
+pub fn server_retrieval_from_uri(
+    uri: String,
+) -> Result<Option<ServerRetrieval>, MDLReaderSessionError> {
+    let device_engagement = device_engagement_bytes(&uri)?;
+    let device_engagement: DeviceEngagement = isomdl::cbor::from_slice(&device_engagement)
+        .map_err(|e| MDLReaderSessionError::EngagementParse {
+            value: format!("Could not decode the device engagement: {e:?}"),
+        })?;
+    Ok(device_engagement.server_retrieval_methods.map(Into::into))
+}
+
+fn device_engagement_bytes(uri: &str) -> Result<Vec<u8>, MDLReaderSessionError> {
+    uri.strip_prefix("mdoc:")
+        .and_then(|engagement| BASE64_URL_SAFE_NO_PAD.decode(engagement).ok())
+        .ok_or_else(|| MDLReaderSessionError::EngagementParse {
+            value: "Invalid mdoc URI, expected the mdoc: URI of the holder's QR code".to_string(),
+        })
+}
+
+/// The CBOR encoded DeviceRequest with a DocRequest for each docType of `doc_requests`,
+/// each reader authenticated over the SessionTranscript with `reader_auth`.
+fn device_request(
+    doc_requests: &DocRequests,
+    session_transcript: &[u8],
+    reader_auth: Option<&ReaderAuth>,
+) -> Result<Vec<u8>, MDLReaderSessionError> {
+    use ciborium::Value;
+
+    let error = |e: String| MDLReaderSessionError::Encoding {
+        value: format!("Could not encode the device request: {e}"),
+    };
+    let encode = |value: &Value| {
+        let mut bytes = Vec::new();
+        ciborium::into_writer(value, &mut bytes).map_err(|e| error(e.to_string()))?;
+        Ok(bytes)
+    };
+    let doc_requests = doc_requests
+        .iter()
+        .map(|(doc_type, namespaces)| {
+            let namespaces = namespaces
+                .iter()
+                .map(|(namespace, elements)| {
+                    let elements = elements
+                        .iter()
+                        .map(|(element, intent_to_retain)| {
+                            (
+                                Value::from(element.as_str()),
+                                Value::Bool(*intent_to_retain),
+                            )
+                        })
+                        .collect();
+                    (Value::from(namespace.as_str()), Value::Map(elements))
+                })
+                .collect();
+            let items_request = Value::Map(vec![
+                ("docType".into(), doc_type.as_str().into()),
+                ("nameSpaces".into(), Value::Map(namespaces)),
+            ]);
+            let items_request_bytes =
+                Value::Tag(24, Box::new(Value::Bytes(encode(&items_request)?)));
+            let mut doc_request = vec![("itemsRequest".into(), items_request_bytes.clone())];
+            if let Some(reader_auth) = reader_auth {
+                let reader_auth = reader_auth
+                    .sign(session_transcript, items_request_bytes)
+                    .map_err(error)?;
+                doc_request.push(("readerAuth".into(), reader_auth));
+            }
+            Ok(Value::Map(doc_request))
+        })
+        .collect::<Result<Vec<_>, MDLReaderSessionError>>()?;
+    encode(&Value::Map(vec![
+        ("version".into(), "1.0".into()),
+        ("docRequests".into(), Value::Array(doc_requests)),
+    ]))
+}
+
 #[derive(thiserror::Error, uniffi::Error, Debug, PartialEq)]
 pub enum MDLReaderResponseError {
     #[error("Invalid decryption")]
@@ -167,37 +1035,147 @@ pub enum MDLReaderResponseError {
     Generic { value: String },
 }
 
-// Currently, a lot of information is lost in `isomdl`. For example, bytes are
-// converted to strings, but we could also imagine detecting images and having
-// a specific enum variant for them.
-#[derive(uniffi::Enum, Debug)]
+// Currently, a lot of information is lost in `isomdl`, which hands the elements
+// over as JSON. Byte strings and dates are restored from the CBOR of the document
+// where it is available, and otherwise from the element types of [namespaces].
+#[derive(uniffi::Enum, Debug, Clone)]
 pub enum MDocItem {
     Text(String),
     Bool(bool),
     Integer(i64),
     ItemMap(HashMap<String, MDocItem>),
     Array(Vec<MDocItem>),
+    Bytes(Vec<u8>),
+    /// A full-date, `YYYY-MM-DD`, tagged 1004 in CBOR.
+    Date(String),
+    /// An RFC 3339 date-time, tagged 0 in CBOR.
+    DateTime(String),
+    /// A byte string recognised as an image, such as the portrait.
+    Image {
+        format: ImageFormat,
+        data: Vec<u8>,
+    },
 }
 
-impl From<serde_json::Value> for MDocItem {
-    fn from(value: serde_json::Value) -> Self {
+/// The format of an image element, recognised from its leading bytes.
+#[derive(uniffi::Enum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Jpeg,
+    Jpeg2000,
+}
+
+impl MDocItem {
+    fn from_bytes(data: Vec<u8>) -> Self {
+        const JPEG: &[u8] = &[0xff, 0xd8, 0xff];
+        const JPEG_2000_CODESTREAM: &[u8] = &[0xff, 0x4f, 0xff, 0x51];
+        const JP2: &[u8] = &[0x00, 0x00, 0x00, 0x0c, 0x6a, 0x50, 0x20, 0x20];
+
+        let format = if data.starts_with(JPEG) {
+            ImageFormat::Jpeg
+        } else if data.starts_with(JPEG_2000_CODESTREAM) || data.starts_with(JP2) {
+            ImageFormat::Jpeg2000
+        } else {
+            return Self::Bytes(data);
+        };
+        Self::Image { format, data }
+    }
+
+    /// Converts the CBOR value of an element whose type is lost in the JSON isomdl
+    /// returns: byte strings and tagged dates.
+    fn from_cbor(value: &ciborium::Value) -> Option<Self> {
         match value {
-            serde_json::Value::Null => unreachable!("No null allowed in namespaces"),
-            serde_json::Value::Bool(b) => Self::Bool(b),
-            serde_json::Value::Number(n) => {
-                if let Some(i) = n.as_i64() {
-                    Self::Integer(i)
-                } else {
-                    unreachable!("Only integers allowed in namespaces")
-                }
+            ciborium::Value::Bytes(bytes) => Some(Self::from_bytes(bytes.clone())),
+            ciborium::Value::Tag(namespaces::FULL_DATE_TAG, date) => {
+                date.as_text().map(|date| Self::Date(date.to_string()))
             }
-            serde_json::Value::String(s) => Self::Text(s),
-            serde_json::Value::Array(a) => {
-                Self::Array(a.iter().map(|o| Into::<Self>::into(o.clone())).collect())
+            ciborium::Value::Tag(namespaces::TDATE_TAG, date) => {
+                date.as_text().map(|date| Self::DateTime(date.to_string()))
             }
-            serde_json::Value::Object(m) => Self::ItemMap(
-                m.iter()
-                    .map(|(k, v)| (k.clone(), Into::<Self>::into(v.clone())))
+            _ => None,
+        }
+    }
+
+    /// The CBOR encoding of the element, tagging dates as in the document.
+    fn to_cbor(&self) -> ciborium::Value {
+        use ciborium::Value;
+
+        match self {
+            Self::Text(text) => Value::Text(text.clone()),
+            Self::Bool(bool) => Value::Bool(*bool),
+            Self::Integer(integer) => Value::Integer((*integer).into()),
+            Self::ItemMap(map) => Value::Map(
+                map.iter()
+                    .map(|(key, value)| (Value::Text(key.clone()), value.to_cbor()))
+                    .collect(),
+            ),
+            Self::Array(items) => Value::Array(items.iter().map(Self::to_cbor).collect()),
+            Self::Bytes(data) | Self::Image { data, .. } => Value::Bytes(data.clone()),
+            Self::Date(date) => Value::Tag(
+                namespaces::FULL_DATE_TAG,
+                Box::new(Value::Text(date.clone())),
+            ),
+            Self::DateTime(date) => {
+                Value::Tag(namespaces::TDATE_TAG, Box::new(Value::Text(date.clone())))
+            }
+        }
+    }
+
+    /// Converts the JSON value isomdl returns for an element, restoring the byte
+    /// strings and dates of elements the namespace defines as such. isomdl encodes
+    /// byte strings as base64 text or as an array of octets.
+    fn from_element(namespace: &str, identifier: &str, value: serde_json::Value) -> Self {
+        use namespaces::ElementType;
+
+        let element_type = namespaces::definition(namespace)
+            .and_then(|definition| definition.element_type(identifier));
+        let item = match (element_type, &value) {
+            (Some(ElementType::Bytes), serde_json::Value::String(encoded)) => {
+                BASE64_URL_SAFE_NO_PAD
+                    .decode(encoded.trim_end_matches('='))
+                    .or_else(|_| BASE64_STANDARD.decode(encoded))
+                    .ok()
+                    .map(Self::from_bytes)
+            }
+            (Some(ElementType::Bytes), serde_json::Value::Array(octets)) => octets
+                .iter()
+                .map(|octet| octet.as_u64().and_then(|octet| u8::try_from(octet).ok()))
+                .collect::<Option<_>>()
+                .map(Self::from_bytes),
+            (Some(ElementType::FullDate | ElementType::Date), serde_json::Value::String(date))
+                if namespaces::parse_full_date(date).is_some() =>
+            {
+                Some(Self::Date(date.clone()))
+            }
+            (Some(ElementType::Date), serde_json::Value::String(date))
+                if chrono::DateTime::parse_from_rfc3339(date).is_ok() =>
+            {
+                Some(Self::DateTime(date.clone()))
+            }
+            _ => None,
+        };
+        item.unwrap_or_else(|| value.into())
+    }
+}
+
+impl From<serde_json::Value> for MDocItem {
+    fn from(value: serde_json::Value) -> Self {
+        match value {
+            serde_json::Value::Null => unreachable!("No null allowed in namespaces"),
+            serde_json::Value::Bool(b) => Self::Bool(b),
+            serde_json::Value::Number(n) => {
+                if let Some(i) = n.as_i64() {
+                    Self::Integer(i)
+                } else {
+                    unreachable!("Only integers allowed in namespaces")
+                }
+            }
+            serde_json::Value::String(s) => Self::Text(s),
+            serde_json::Value::Array(a) => {
+                Self::Array(a.iter().map(|o| Into::<Self>::into(o.clone())).collect())
+            }
+            serde_json::Value::Object(m) => Self::ItemMap(
+                m.iter()
+                    .map(|(k, v)| (k.clone(), Into::<Self>::into(v.clone())))
                     .collect(),
             ),
         }
@@ -214,6 +1192,10 @@ impl From<&MDocItem> for serde_json::Value {
                 Self::Object(m.iter().map(|(k, v)| (k.clone(), v.into())).collect())
             }
             MDocItem::Array(a) => Self::Array(a.iter().map(|o| o.into()).collect()),
+            MDocItem::Bytes(data) | MDocItem::Image { data, .. } => {
+                Self::String(BASE64_URL_SAFE_NO_PAD.encode(data))
+            }
+            MDocItem::Date(date) | MDocItem::DateTime(date) => Self::String(date.clone()),
         }
     }
 }
@@ -239,12 +1221,256 @@ pub struct MDLReaderResponseData {
     state: Arc<MDLSessionManager>,
     /// Contains the namespaces for the mDL directly, without top-level doc types
     verified_response: HashMap<String, HashMap<String, MDocItem>>,
-    /// Outcome of issuer authentication.
+    /// Outcome of issuer authentication of the first document.
     pub issuer_authentication: AuthenticationStatus,
-    /// Outcome of device authentication.
+    /// Outcome of device authentication of the first document, by deviceSignature
+    /// only, see [handle_response].
     pub device_authentication: AuthenticationStatus,
-    /// Errors that occurred during response processing.
-    pub errors: Option<String>,
+    /// The validity signed in the MSO of the first document.
+    pub validity: Option<MsoValidity>,
+    /// The document signer certificate of the first document.
+    pub issuer_certificate: Option<IssuerCertificate>,
+    /// Issues that occurred during response processing, empty if every requested
+    /// element was returned and authenticated.
+    pub issues: Vec<ResponseIssue>,
+    /// The documents of the response, each validated on its own. The elements of the
+    /// first are also flattened into the verified response.
+    pub documents: Vec<MDLReaderDocument>,
+    /// The returned elements compared with those requested in the session.
+    pub element_diff: ElementDiff,
+    /// The elements requested in the session by namespace, with the intent to retain
+    /// each, for the reader to keep a record of its retention commitments.
+    pub requested_elements: HashMap<String, Vec<RequestedElement>>,
+}
+
+/// An issue with a response, located as precisely as it is known.
+#[derive(uniffi::Record, Debug, Clone, PartialEq, Eq)]
+pub struct ResponseIssue {
+    pub doc_type: Option<String>,
+    pub namespace: Option<String>,
+    pub element: Option<String>,
+    pub kind: ResponseIssueKind,
+    pub message: String,
+}
+
+impl ResponseIssue {
+    /// Whether the response could not be read at all, rather than one of its documents.
+    fn unreadable(&self) -> bool {
+        self.doc_type.is_none()
+            && matches!(
+                self.kind,
+                ResponseIssueKind::Decryption | ResponseIssueKind::Parsing
+            )
+    }
+}
+
+/// The kind of a [ResponseIssue].
+#[derive(uniffi::Enum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResponseIssueKind {
+    /// The response could not be decrypted.
+    Decryption,
+    /// The response or a document could not be decoded, or the response contained no
+    /// document.
+    Parsing,
+    /// The holder returned a documentErrors status for a docType instead of the
+    /// document, such as when the user declined to share it.
+    DocumentError,
+    /// A requested element was not returned.
+    MissingElement,
+    /// The document or its certificate chain failed issuer authentication.
+    IssuerAuthentication,
+    /// The document failed device authentication.
+    DeviceAuthentication,
+    /// Any other error reported by isomdl.
+    Other,
+}
+
+/// The elements of a response compared with the request, each by namespace.
+#[derive(uniffi::Record, Debug, Clone, Default, PartialEq, Eq)]
+pub struct ElementDiff {
+    /// Requested and returned.
+    pub granted: HashMap<String, Vec<String>>,
+    /// Requested but not returned, withheld by the holder or lost to an error.
+    pub refused: HashMap<String, Vec<String>>,
+    /// Returned without being requested.
+    pub extra: HashMap<String, Vec<String>>,
+}
+
+impl ElementDiff {
+    fn new(
+        requested_elements: &HashMap<String, Vec<RequestedElement>>,
+        verified_response: &HashMap<String, HashMap<String, MDocItem>>,
+    ) -> Self {
+        let mut diff = Self::default();
+        let add = |diff: &mut HashMap<String, Vec<String>>, namespace: &str, element: &str| {
+            diff.entry(namespace.to_string())
+                .or_default()
+                .push(element.to_string())
+        };
+        for (namespace, elements) in requested_elements {
+            let returned = verified_response.get(namespace);
+            for element in elements {
+                if returned.is_some_and(|items| items.contains_key(&element.element_id)) {
+                    add(&mut diff.granted, namespace, &element.element_id);
+                } else {
+                    add(&mut diff.refused, namespace, &element.element_id);
+                }
+            }
+        }
+        for (namespace, items) in verified_response {
+            let requested = requested_elements.get(namespace);
+            for element in items.keys() {
+                if !requested.is_some_and(|elements| {
+                    elements
+                        .iter()
+                        .any(|requested| &requested.element_id == element)
+                }) {
+                    add(&mut diff.extra, namespace, element);
+                }
+            }
+        }
+        for elements in [&mut diff.granted, &mut diff.refused, &mut diff.extra] {
+            elements.values_mut().for_each(|elements| elements.sort());
+        }
+        diff
+    }
+
+    /// Removes the extra elements from a response, and the namespaces left empty.
+    fn remove_extra(&self, verified_response: &mut HashMap<String, HashMap<String, MDocItem>>) {
+        for (namespace, elements) in &self.extra {
+            if let Some(items) = verified_response.get_mut(namespace) {
+                elements.iter().for_each(|element| {
+                    items.remove(element);
+                });
+                if items.is_empty() {
+                    verified_response.remove(namespace);
+                }
+            }
+        }
+    }
+}
+
+/// The elements of a document, by namespace.
+type DocumentElements = HashMap<String, HashMap<String, MDocItem>>;
+
+/// Converts the errors isomdl reports by category to issues of the document of
+/// `doc_type`, or of the whole response without one.
+fn error_issues(
+    errors: &BTreeMap<String, serde_json::Value>,
+    doc_type: Option<&str>,
+) -> Vec<ResponseIssue> {
+    let mut issues = Vec::new();
+    for (category, messages) in errors {
+        let kind = match category.as_str() {
+            "decryption_errors" => ResponseIssueKind::Decryption,
+            "parsing_errors" => ResponseIssueKind::Parsing,
+            "issuer_authentication_errors" | "certificate_errors" => {
+                ResponseIssueKind::IssuerAuthentication
+            }
+            "device_authentication_errors" => ResponseIssueKind::DeviceAuthentication,
+            _ => ResponseIssueKind::Other,
+        };
+        let messages = match messages {
+            serde_json::Value::Array(messages) => messages.iter().collect(),
+            message => vec![message],
+        };
+        issues.extend(messages.into_iter().map(|message| {
+            let message = match message {
+                serde_json::Value::String(message) => message.clone(),
+                message => message.to_string(),
+            };
+            ResponseIssue {
+                doc_type: doc_type.map(str::to_string),
+                namespace: None,
+                element: None,
+                kind,
+                message: match kind {
+                    ResponseIssueKind::Other => format!("{category}: {message}"),
+                    _ => message,
+                },
+            }
+        }));
+    }
+    issues
+}
+
+/// The issues of the documentErrors of a DeviceResponse, one for each docType the
+/// holder returned a status for instead of the document.
+fn document_error_issues(
+    device_response: &isomdl::definitions::DeviceResponse,
+) -> Vec<ResponseIssue> {
+    use isomdl::definitions::device_response::DocumentErrorCode;
+
+    device_response
+        .document_errors
+        .iter()
+        .flat_map(|document_errors| document_errors.iter())
+        .flat_map(|document_error| document_error.iter())
+        .map(|(doc_type, code)| ResponseIssue {
+            doc_type: Some(doc_type.clone()),
+            namespace: None,
+            element: None,
+            kind: ResponseIssueKind::DocumentError,
+            message: match code {
+                DocumentErrorCode::DataNotReturned => {
+                    "The holder did not return the document".to_string()
+                }
+                DocumentErrorCode::ApplicationSpecific(code) => {
+                    format!("The holder did not return the document, status {code}")
+                }
+            },
+        })
+        .collect()
+}
+
+/// The issues of each element requested of a docType that no returned document of that
+/// docType holds, given the other `issues` of the response. None are reported if the
+/// response could not be read at all, nor for a docType the holder returned a
+/// documentErrors status for.
+fn missing_element_issues(
+    issues: &[ResponseIssue],
+    requested_documents: &BTreeMap<String, HashMap<String, Vec<RequestedElement>>>,
+    returned: &[(&str, &DocumentElements)],
+) -> Vec<ResponseIssue> {
+    if issues.iter().any(ResponseIssue::unreadable) {
+        return Vec::new();
+    }
+    let declined = |doc_type: &String| {
+        issues.iter().any(|issue| {
+            issue.kind == ResponseIssueKind::DocumentError
+                && issue.doc_type.as_ref() == Some(doc_type)
+        })
+    };
+    let mut requested: Vec<_> = requested_documents
+        .iter()
+        .filter(|(doc_type, _)| !declined(doc_type))
+        .flat_map(|(doc_type, namespaces)| {
+            namespaces.iter().flat_map(move |(namespace, elements)| {
+                elements
+                    .iter()
+                    .map(move |element| (doc_type, namespace, element))
+            })
+        })
+        .collect();
+    requested.sort_by(|a, b| (a.0, a.1, &a.2.element_id).cmp(&(b.0, b.1, &b.2.element_id)));
+    requested
+        .into_iter()
+        .filter(|(doc_type, namespace, element)| {
+            !returned.iter().any(|(returned_doc_type, namespaces)| {
+                returned_doc_type == doc_type
+                    && namespaces
+                        .get(*namespace)
+                        .is_some_and(|items| items.contains_key(&element.element_id))
+            })
+        })
+        .map(|(doc_type, namespace, element)| ResponseIssue {
+            doc_type: Some(doc_type.clone()),
+            namespace: Some(namespace.clone()),
+            element: Some(element.element_id.clone()),
+            kind: ResponseIssueKind::MissingElement,
+            message: format!("{} was not returned", element.element_id),
+        })
+        .collect()
 }
 
 #[derive(thiserror::Error, uniffi::Error, Debug)]
@@ -285,288 +1511,2717 @@ pub fn verified_response_as_json_string(
     })
 }
 
+/// A verified element as shown to the user.
+#[derive(uniffi::Record, Debug, Clone, PartialEq)]
+pub struct ElementDisplay {
+    /// The label of the element in ISO 18013-5 or the AAMVA guidelines, else its
+    /// identifier.
+    pub label: String,
+    /// The value, with codes such as `sex` spelled out.
+    pub value: String,
+}
+
+/// Labels the elements verified by [handle_response] or [verify_oid4vp_response], by
+/// namespace, for display.
 #[uniffi::export]
-pub fn handle_response(
-    state: Arc<MDLSessionManager>,
-    response: Vec<u8>,
-) -> Result<MDLReaderResponseData, MDLReaderResponseError> {
-    let mut state = state.0.clone();
-    let validated_response = state.handle_response(&response);
-    let errors = if !validated_response.errors.is_empty() {
-        Some(
-            serde_json::to_string(&validated_response.errors).map_err(|e| {
-                MDLReaderResponseError::Generic {
-                    value: format!("Could not serialze errors: {e:?}"),
-                }
-            })?,
-        )
+pub fn display_elements(
+    namespaces: HashMap<String, HashMap<String, MDocItem>>,
+) -> HashMap<String, HashMap<String, ElementDisplay>> {
+    namespaces
+        .into_iter()
+        .map(|(namespace, elements)| {
+            let elements = elements
+                .iter()
+                .map(|(identifier, value)| {
+                    let display = ElementDisplay {
+                        label: namespaces::element_label(&namespace, identifier)
+                            .unwrap_or_else(|| identifier.clone()),
+                        value: display_value(&namespace, identifier, value),
+                    };
+                    (identifier.clone(), display)
+                })
+                .collect();
+            (namespace, elements)
+        })
+        .collect()
+}
+
+fn display_value(namespace: &str, identifier: &str, value: &MDocItem) -> String {
+    let code = |code: String| {
+        namespaces::code_label(namespace, identifier, &code)
+            .map(str::to_string)
+            .unwrap_or(code)
+    };
+    match value {
+        MDocItem::Text(text) => code(text.clone()),
+        MDocItem::Integer(integer) => code(integer.to_string()),
+        MDocItem::Bool(true) => "Yes".to_string(),
+        MDocItem::Bool(false) => "No".to_string(),
+        MDocItem::Date(date) | MDocItem::DateTime(date) => date.clone(),
+        MDocItem::Bytes(bytes) => format!("{} bytes", bytes.len()),
+        MDocItem::Image { format, .. } => match format {
+            ImageFormat::Jpeg => "JPEG image".to_string(),
+            ImageFormat::Jpeg2000 => "JPEG 2000 image".to_string(),
+        },
+        MDocItem::Array(items) => items
+            .iter()
+            .map(|item| display_value(namespace, identifier, item))
+            .collect::<Vec<_>>()
+            .join("; "),
+        // Sorted, as the members of driving privileges have no set order
+        MDocItem::ItemMap(map) => map
+            .iter()
+            .collect::<BTreeMap<_, _>>()
+            .into_iter()
+            .map(|(key, item)| format!("{key}: {}", display_value(namespace, key, item)))
+            .collect::<Vec<_>>()
+            .join(", "),
+    }
+}
+
+/// Whether the holder is at least `age_over` years old, as attested by a response.
+#[derive(uniffi::Record, Debug, Clone, PartialEq)]
+pub struct AgeAttestation {
+    pub age_over: u8,
+    pub result: bool,
+    /// The identifier of the element the result follows from, such as `age_over_21`.
+    pub basis: String,
+}
+
+/// Interprets the age elements of a response, by namespace, as an attestation of being
+/// at least `age_over` years old. `None` if the response is inconclusive.
+///
+/// Per ISO 18013-5 7.2.5 a holder without the requested `age_over_NN` answers with the
+/// nearest one: a true `age_over_NN` attests `age_over` when NN is at least `age_over`,
+/// and a false one refutes it when NN is at most `age_over`. Failing that, `age_in_years`
+/// is compared, then `age_birth_year` when the current year settles it.
+#[uniffi::export]
+pub fn age_attestation(
+    namespaces: HashMap<String, HashMap<String, MDocItem>>,
+    age_over: u8,
+) -> Option<AgeAttestation> {
+    let current_year = time::OffsetDateTime::now_utc().year();
+    age_attestation_in(&namespaces, age_over, current_year)
+}
+
+fn age_attestation_in(
+    namespaces: &HashMap<String, HashMap<String, MDocItem>>,
+    age_over: u8,
+    current_year: i32,
+) -> Option<AgeAttestation> {
+    let attestation = |result, basis: &str| AgeAttestation {
+        age_over,
+        result,
+        basis: basis.to_string(),
+    };
+    let elements = || namespaces.values().flat_map(HashMap::iter);
+
+    // The nearest age_over_NN true above, or false below, the requested age
+    let mut nearest_true: Option<(u8, &str)> = None;
+    let mut nearest_false: Option<(u8, &str)> = None;
+    for (identifier, value) in elements() {
+        let (Some(nn), MDocItem::Bool(over)) = (namespaces::age_over_nn(identifier), value) else {
+            continue;
+        };
+        if *over && nn >= age_over && nearest_true.is_none_or(|(nearest, _)| nn < nearest) {
+            nearest_true = Some((nn, identifier));
+        }
+        if !*over && nn <= age_over && nearest_false.is_none_or(|(nearest, _)| nn > nearest) {
+            nearest_false = Some((nn, identifier));
+        }
+    }
+    if let Some((_, basis)) = nearest_true {
+        return Some(attestation(true, basis));
+    }
+    if let Some((_, basis)) = nearest_false {
+        return Some(attestation(false, basis));
+    }
+
+    let integer = |identifier: &str| {
+        elements().find_map(|(id, value)| match value {
+            MDocItem::Integer(integer) if id == identifier => Some(*integer),
+            _ => None,
+        })
+    };
+    if let Some(age) = integer("age_in_years") {
+        return Some(attestation(age >= age_over.into(), "age_in_years"));
+    }
+    // Born in that year, the holder is this year's difference old, or a year younger
+    let age_over = i64::from(age_over);
+    let birth_year = integer("age_birth_year")?;
+    let youngest = i64::from(current_year) - birth_year - 1;
+    if youngest >= age_over {
+        Some(attestation(true, "age_birth_year"))
+    } else if youngest + 1 < age_over {
+        Some(attestation(false, "age_birth_year"))
     } else {
         None
+    }
+}
+
+/// Signs a receipt of verified documents, as an auditable record of what a verifier
+/// verified: a COSE_Sign1 over the deterministically encoded CBOR map
+///
+/// ```text
+/// {
+///   "version": "1.0",
+///   "verifiedAt": tdate,
+///   "sessionTranscriptHash": bstr, ; SHA-256 of the SessionTranscript
+///   "documents": [+ {
+///     "docType": tstr,
+///     "nameSpaces": {+ tstr => {+ tstr => any}},
+///     "issuerAuthentication": "valid" / "invalid" / "unchecked",
+///     "deviceAuthentication": "valid" / "invalid" / "unchecked",
+///     ? "validFrom": tdate,
+///     ? "validUntil": tdate,
+///     ? "issues": [+ {
+///       "kind": tstr, ; the ResponseIssueKind, such as "issuerAuthentication"
+///       ? "nameSpace": tstr,
+///       ? "element": tstr,
+///       "message": tstr,
+///     }],
+///   }]
+/// }
+/// ```
+///
+/// The session transcript is [MDLSessionManager::session_transcript] for sessions and
+/// [MDLReaderVerifiedData::session_transcript] for OpenID4VP. The receipt is signed with
+/// the verifier's PKCS#8 PEM key, on P-256, P-384 or Ed25519, and carries the verifier
+/// certificate in its x5chain if given. `verified_at` defaults to now.
+#[uniffi::export(default(verifier_certificate_pem = None, verified_at = None))]
+pub fn verification_receipt(
+    documents: Vec<MDLReaderDocument>,
+    session_transcript: Vec<u8>,
+    verifier_key_pem: String,
+    verifier_certificate_pem: Option<String>,
+    verified_at: Option<SystemTime>,
+) -> Result<Vec<u8>, MDLReaderSessionError> {
+    use ciborium::Value;
+    use coset::CborSerializable;
+    use sha2::{Digest, Sha256};
+
+    let error = |value: String| MDLReaderSessionError::Generic { value };
+    let text = |text: &str| Value::Text(text.to_string());
+    let tdate = |time: SystemTime| {
+        let time = chrono::DateTime::<chrono::Utc>::from(time)
+            .to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+        Value::Tag(namespaces::TDATE_TAG, Box::new(Value::Text(time)))
     };
-    let verified_response: Result<_, _> = validated_response
-        .response
-        .into_iter()
-        .map(|(namespace, items)| {
-            if let Some(items) = items.as_object() {
-                let items = items
+    let status = |status: &AuthenticationStatus| match status {
+        AuthenticationStatus::Valid => text("valid"),
+        AuthenticationStatus::Invalid => text("invalid"),
+        AuthenticationStatus::Unchecked => text("unchecked"),
+    };
+    let issue_kind = |kind: ResponseIssueKind| match kind {
+        ResponseIssueKind::Decryption => "decryption",
+        ResponseIssueKind::Parsing => "parsing",
+        ResponseIssueKind::DocumentError => "documentError",
+        ResponseIssueKind::MissingElement => "missingElement",
+        ResponseIssueKind::IssuerAuthentication => "issuerAuthentication",
+        ResponseIssueKind::DeviceAuthentication => "deviceAuthentication",
+        ResponseIssueKind::Other => "other",
+    };
+
+    let documents = documents
+        .iter()
+        .map(|document| {
+            let namespaces = document
+                .namespaces
+                .iter()
+                .map(|(namespace, elements)| {
+                    let elements = elements
+                        .iter()
+                        .map(|(identifier, value)| (text(identifier), value.to_cbor()))
+                        .collect();
+                    (text(namespace), Value::Map(elements))
+                })
+                .collect();
+            let mut fields = vec![
+                (text("docType"), text(&document.doc_type)),
+                (text("nameSpaces"), Value::Map(namespaces)),
+                (
+                    text("issuerAuthentication"),
+                    status(&document.issuer_authentication),
+                ),
+                (
+                    text("deviceAuthentication"),
+                    status(&document.device_authentication),
+                ),
+            ];
+            if let Some(validity) = &document.validity {
+                fields.push((text("validFrom"), tdate(validity.valid_from)));
+                fields.push((text("validUntil"), tdate(validity.valid_until)));
+            }
+            if !document.issues.is_empty() {
+                let issues = document
+                    .issues
                     .iter()
-                    .map(|(item, value)| (item.clone(), value.clone().into()))
+                    .map(|issue| {
+                        let mut fields = vec![(text("kind"), text(issue_kind(issue.kind)))];
+                        if let Some(namespace) = &issue.namespace {
+                            fields.push((text("nameSpace"), text(namespace)));
+                        }
+                        if let Some(element) = &issue.element {
+                            fields.push((text("element"), text(element)));
+                        }
+                        fields.push((text("message"), text(&issue.message)));
+                        Value::Map(fields)
+                    })
                     .collect();
-                Ok((namespace.to_string(), items))
-            } else {
-                Err(MDLReaderResponseError::Generic {
-                    value: format!("Items not object, instead: {items:#?}"),
-                })
+                fields.push((text("issues"), Value::Array(issues)));
             }
+            Value::Map(fields)
         })
         .collect();
-    let verified_response = verified_response.map_err(|e| MDLReaderResponseError::Generic {
-        value: format!("Unable to parse response: {e:?}"),
-    })?;
+    let receipt = deterministic_cbor(Value::Map(vec![
+        (text("version"), text("1.0")),
+        (
+            text("verifiedAt"),
+            tdate(verified_at.unwrap_or_else(SystemTime::now)),
+        ),
+        (
+            text("sessionTranscriptHash"),
+            Value::Bytes(Sha256::digest(&session_transcript).to_vec()),
+        ),
+        (text("documents"), Value::Array(documents)),
+    ]));
+    let mut payload = Vec::new();
+    ciborium::into_writer(&receipt, &mut payload)
+        .map_err(|e| error(format!("Could not encode the receipt: {e}")))?;
+
+    let key = IssuerSigningKey::from_pkcs8_pem(&verifier_key_pem)
+        .map_err(|e| error(format!("Invalid verifier key: {e:#}")))?;
+    let algorithm = cose_algorithm(&key);
+    let mut unprotected = coset::HeaderBuilder::new();
+    if let Some(certificate_pem) = verifier_certificate_pem {
+        let certificate = Certificate::from_pem(&certificate_pem)
+            .and_then(|certificate| certificate.to_der())
+            .map_err(|e| error(format!("Invalid verifier certificate: {e}")))?;
+        unprotected = unprotected.value(X5CHAIN_COSE_HEADER_LABEL, Value::Bytes(certificate));
+    }
+    coset::CoseSign1Builder::new()
+        .protected(coset::HeaderBuilder::new().algorithm(algorithm).build())
+        .unprotected(unprotected.build())
+        .payload(payload)
+        .create_signature(&[], |tbs| key.sign(tbs))
+        .build()
+        .to_vec()
+        .map_err(|e| error(format!("Could not encode the receipt: {e:?}")))
+}
+
+/// Sorts the keys of every map by their encoding, per RFC 8949 4.2.1.
+fn deterministic_cbor(value: ciborium::Value) -> ciborium::Value {
+    use ciborium::Value;
+
+    match value {
+        Value::Map(entries) => {
+            let mut entries: Vec<_> = entries
+                .into_iter()
+                .map(|(key, value)| {
+                    let mut encoded = Vec::new();
+                    let _ = ciborium::into_writer(&key, &mut encoded);
+                    (encoded, key, deterministic_cbor(value))
+                })
+                .collect();
+            entries.sort_by(|(a, ..), (b, ..)| a.cmp(b));
+            Value::Map(
+                entries
+                    .into_iter()
+                    .map(|(_, key, value)| (key, value))
+                    .collect(),
+            )
+        }
+        Value::Array(items) => Value::Array(items.into_iter().map(deterministic_cbor).collect()),
+        Value::Tag(tag, value) => Value::Tag(tag, Box::new(deterministic_cbor(*value))),
+        value => value,
+    }
+}
+
+/// Decrypt and validate the holder's response. Every document of the response is
+/// validated on its own, and the elements of the first are also flattened by
+/// namespace into the verified response, see [establish_session].
+///
+/// Device authentication verifies a deviceSignature with the device key of the MSO, or
+/// a deviceMac with the EMacKey derived from the reader key of the session and that
+/// device key.
+///
+/// With `only_requested`, for data minimization, elements the holder returned without
+/// being requested are left out of the verified response and documents. They are still
+/// listed in the `extra` elements of the [ElementDiff].
+#[uniffi::export(default(only_requested = false))]
+pub fn handle_response(
+    state: Arc<MDLSessionManager>,
+    response: Vec<u8>,
+    only_requested: bool,
+) -> Result<MDLReaderResponseData, MDLReaderResponseError> {
+    process_response(&state, &response, only_requested)
+}
+
+fn process_response(
+    state: &MDLSessionManager,
+    response: &[u8],
+    only_requested: bool,
+) -> Result<MDLReaderResponseData, MDLReaderResponseError> {
+    let mut state = state.clone();
+    let (mut documents, mut issues) = match session_documents(&mut state, response) {
+        Ok((documents, document_errors)) => (documents, document_errors),
+        Err(issues) => (Vec::new(), issues),
+    };
+    issues.extend(
+        documents
+            .iter()
+            .flat_map(|document| document.issues.iter().cloned()),
+    );
+    let first = documents.first().cloned();
+    let mut verified_response = first
+        .as_ref()
+        .map(|first| first.namespaces.clone())
+        .unwrap_or_default();
+    let (issuer_authentication, device_authentication) = first.as_ref().map_or(
+        (
+            AuthenticationStatus::Unchecked,
+            AuthenticationStatus::Unchecked,
+        ),
+        |first| {
+            (
+                first.issuer_authentication.clone(),
+                first.device_authentication.clone(),
+            )
+        },
+    );
+    let returned: Vec<_> = documents
+        .iter()
+        .map(|document| (document.doc_type.as_str(), &document.namespaces))
+        .collect();
+    let missing_elements = missing_element_issues(&issues, &state.requested_documents, &returned);
+    issues.extend(missing_elements);
+    let requested_elements = state.requested_elements();
+    let mut returned_elements: HashMap<String, HashMap<String, MDocItem>> = HashMap::new();
+    for namespaces in returned.into_iter().map(|(_, namespaces)| namespaces) {
+        for (namespace, items) in namespaces {
+            returned_elements
+                .entry(namespace.clone())
+                .or_default()
+                .extend(items.clone());
+        }
+    }
+    let element_diff = ElementDiff::new(&requested_elements, &returned_elements);
+    if only_requested {
+        // Each document keeps only the elements requested of its docType
+        let no_elements = HashMap::new();
+        for document in &mut documents {
+            let requested = state
+                .requested_documents
+                .get(&document.doc_type)
+                .unwrap_or(&no_elements);
+            ElementDiff::new(requested, &document.namespaces)
+                .remove_extra(&mut document.namespaces);
+        }
+        if let Some(first) = documents.first() {
+            verified_response = first.namespaces.clone();
+        }
+    }
+    let (validity, issuer_certificate) = first
+        .map(|first| (first.validity, first.issuer_certificate))
+        .unwrap_or_default();
     Ok(MDLReaderResponseData {
-        state: Arc::new(MDLSessionManager(state)),
+        requested_elements,
+        state: Arc::new(state),
         verified_response,
-        issuer_authentication: AuthenticationStatus::from(validated_response.issuer_authentication),
-        device_authentication: AuthenticationStatus::from(validated_response.device_authentication),
-        errors,
+        issuer_authentication,
+        device_authentication,
+        validity,
+        issuer_certificate,
+        issues,
+        documents,
+        element_diff,
     })
 }
 
-#[derive(uniffi::Record, Debug)]
-pub struct MDLReaderVerifiedData {
-    /// The document type (e.g., "org.iso.18013.5.1.mDL")
-    pub doc_type: String,
-    pub verified_response: HashMap<String, HashMap<String, MDocItem>>,
-    pub issuer_authentication: AuthenticationStatus,
-    pub device_authentication: AuthenticationStatus,
-    pub errors: Option<String>,
-}
-
-impl MDLReaderVerifiedData {
-    pub fn verified_response_as_json(
-        &self,
-    ) -> Result<serde_json::Value, MDLReaderResponseSerializeError> {
-        serde_json::to_value(
-            self.verified_response
-                .iter()
-                .map(|(k, v)| {
-                    (
-                        k.clone(),
-                        v.iter().map(|(k, v)| (k.clone(), v.into())).collect(),
-                    )
-                })
-                .collect::<HashMap<String, HashMap<String, serde_json::Value>>>(),
+/// Decrypts the holder's response and validates each of its documents against the
+/// session transcript, with the issues of its documentErrors, or returns the issue of a
+/// response that could not be decrypted or decoded.
+fn session_documents(
+    state: &mut MDLSessionManager,
+    response: &[u8],
+) -> Result<(Vec<MDLReaderDocument>, Vec<ResponseIssue>), Vec<ResponseIssue>> {
+    let error = |kind: ResponseIssueKind, message: String| {
+        vec![ResponseIssue {
+            doc_type: None,
+            namespace: None,
+            element: None,
+            kind,
+            message,
+        }]
+    };
+    let session_data: session::SessionData = isomdl::cbor::from_slice(response).map_err(|e| {
+        error(
+            ResponseIssueKind::Decryption,
+            format!("Invalid session data: {e:?}"),
         )
-        .map_err(|e| MDLReaderResponseSerializeError::Generic {
-            value: format!("Serialization error: {}", e),
-        })
+    })?;
+    let Some(data) = session_data.data else {
+        return Err(error(
+            ResponseIssueKind::Decryption,
+            format!(
+                "The session data has no response, status {:?}",
+                session_data.status
+            ),
+        ));
+    };
+    let data: Vec<u8> = data.into();
+    let device_response = state
+        .keys
+        .decrypt_device_data(&data)
+        .map_err(|e| error(ResponseIssueKind::Decryption, format!("{e:#}")))?;
+    let device_response: isomdl::definitions::DeviceResponse =
+        isomdl::cbor::from_slice(&device_response).map_err(|e| {
+            error(
+                ResponseIssueKind::Parsing,
+                format!("Invalid DeviceResponse: {e:?}"),
+            )
+        })?;
+    let document_errors = document_error_issues(&device_response);
+    if device_response.documents.is_none() {
+        // A holder declining every document answers with its documentErrors only
+        if !document_errors.is_empty() {
+            return Ok((Vec::new(), document_errors));
+        }
+        return Err(error(
+            ResponseIssueKind::Parsing,
+            "The response contains no documents".to_string(),
+        ));
     }
+    let transcript: session::SessionTranscript180135 =
+        isomdl::cbor::from_slice(&state.session_transcript).map_err(|e| {
+            error(
+                ResponseIssueKind::Parsing,
+                format!("Invalid session transcript: {e:?}"),
+            )
+        })?;
+    let mac_key = SessionMacKey {
+        reader_key: state.reader_key.as_ref(),
+        session_transcript: &state.session_transcript,
+    };
+    verify_documents(
+        device_response,
+        transcript,
+        &state.trust_anchors,
+        false,
+        SystemTime::now(),
+        Some(mac_key),
+    )
+    .map(|documents| (documents, document_errors))
+    .map_err(|e| error(ResponseIssueKind::Parsing, e.to_string()))
+}
+
+/// Receives the verified elements of a streamed response, once the response has been
+/// verified as a whole.
+#[uniffi::export(callback_interface)]
+pub trait MDLResponseListener: Send + Sync {
+    fn on_element(&self, namespace: String, identifier: String, value: MDocItem);
+}
+
+/// Reports the progress of a [MDLResponseStream], for reader apps to show while a large
+/// response is transferred. The transfer is reported chunk by chunk as it is pushed,
+/// and the verified elements once the whole response has been verified.
+#[uniffi::export(callback_interface)]
+pub trait MDLResponseProgress: Send + Sync {
+    /// A chunk was pushed, with the bytes received so far and the expected length the
+    /// stream was started with.
+    fn on_bytes_received(&self, received: u64, expected: Option<u64>);
+    /// The whole response was decrypted and verified, with the number of verified
+    /// elements reported next.
+    fn on_response_verified(&self, element_count: u64);
+    /// A verified element, `index` counting from 0 to the element count.
+    fn on_element_verified(&self, namespace: String, identifier: String, index: u64);
+}
+
+/// Accumulates a DeviceResponse received in chunks, so that large responses (high
+/// resolution portraits, multiple documents) do not need to cross the FFI boundary as a
+/// single allocation.
+///
+/// This is a buffered receive with progress events: the response is encrypted as a
+/// single SessionData message, so it is only decrypted and verified once every chunk
+/// has been pushed, and no document or element is delivered before
+/// [MDLResponseStream::finish].
+#[derive(uniffi::Object)]
+pub struct MDLResponseStream {
+    state: Arc<MDLSessionManager>,
+    buffer: Mutex<ResponseBuffer>,
+    progress: Option<Box<dyn MDLResponseProgress>>,
 }
 
 #[uniffi::export]
-pub fn verify_oid4vp_response(
-    response: Vec<u8>,
-    nonce: String,
-    client_id: String,
-    response_uri: String,
-    trust_anchor_registry: Option<Vec<String>>,
-    use_intermediate_chaining: bool,
-) -> Result<MDLReaderVerifiedData, MDLReaderSessionError> {
-    // 1. Parse DeviceResponse
-    let device_response: isomdl::definitions::DeviceResponse = isomdl::cbor::from_slice(&response)
-        .map_err(|e| {
-            let debug_info = match ciborium::from_reader::<ciborium::Value, _>(response.as_slice())
-            {
-                Ok(v) => format!("Generic CBOR structure: {:?}", v),
-                Err(e2) => format!("Failed to parse as generic CBOR: {}", e2),
-            };
-            MDLReaderSessionError::Generic {
-                value: format!("Unable to parse DeviceResponse: {}. {}", e, debug_info),
-            }
-        })?;
+impl MDLResponseStream {
+    /// Start a new response stream for the given reader session.
+    ///
+    /// `expected_length` may be supplied when the total size is known upfront (e.g.
+    /// from the BLE transport). The stream then rejects chunks beyond it and a response
+    /// cut short of it. As the length comes from the holder, at most 1 MiB of the buffer
+    /// is allocated upfront.
+    #[uniffi::constructor]
+    pub fn new(state: Arc<MDLSessionManager>, expected_length: Option<u64>) -> Arc<Self> {
+        Arc::new(Self::with(state, expected_length, None))
+    }
 
-    // 2. Construct OID4VP SessionTranscript per updated spec (Appendix B.2.6.1)
-    // SessionTranscript = [null, null, ["OpenID4VPHandover", sha256(cbor([clientId, nonce, jwkThumbprint, responseUri]))]]
-    use sha2::{Digest, Sha256};
+    /// Start a new response stream as [MDLResponseStream::new] does, reporting each
+    /// chunk pushed to `progress`.
+    ///
+    /// The response is decrypted and verified as a whole, so its elements are only
+    /// reported once [MDLResponseStream::finish] has verified it.
+    #[uniffi::constructor]
+    pub fn new_with_progress(
+        state: Arc<MDLSessionManager>,
+        expected_length: Option<u64>,
+        progress: Box<dyn MDLResponseProgress>,
+    ) -> Arc<Self> {
+        Arc::new(Self::with(state, expected_length, Some(progress)))
+    }
 
-    // Build OpenID4VPHandoverInfo = [clientId, nonce, jwkThumbprint, responseUri]
-    // jwkThumbprint is null for non-encrypted responses
-    let handover_info = OID4VPHandoverInfo(
-        client_id.clone(),
-        nonce.clone(),
-        None, // jwkThumbprint - null for non-encrypted responses
-        response_uri.clone(),
-    );
+    /// Append a chunk of the response. Returns the number of bytes received so far.
+    pub fn push_chunk(&self, chunk: Vec<u8>) -> Result<u64, MDLReaderResponseError> {
+        let mut buffer = self.buffer()?;
+        let received = buffer
+            .push(chunk)
+            .map_err(|value| MDLReaderResponseError::Generic { value })?;
+        let expected_length = buffer.expected_length;
+        drop(buffer);
+        if let Some(progress) = &self.progress {
+            progress.on_bytes_received(received, expected_length);
+        }
+        Ok(received)
+    }
 
-    // CBOR-encode the handover info
-    let mut handover_info_bytes = Vec::new();
-    ciborium::into_writer(&handover_info, &mut handover_info_bytes).map_err(|e| {
-        MDLReaderSessionError::Generic {
-            value: format!("Failed to CBOR-encode handover info: {}", e),
+    /// Process the accumulated response, leaving out the elements that were not
+    /// requested with `only_requested` as [handle_response] does. The buffer is released
+    /// once processing completes, so the stream cannot be finished twice.
+    #[uniffi::method(default(only_requested = false))]
+    pub fn finish(
+        &self,
+        only_requested: bool,
+    ) -> Result<MDLReaderResponseData, MDLReaderResponseError> {
+        let response = self
+            .buffer()?
+            .take()
+            .map_err(|value| MDLReaderResponseError::Generic { value })?;
+        let data = process_response(&self.state, &response, only_requested)?;
+        if let Some(progress) = &self.progress {
+            let elements = data
+                .verified_response
+                .iter()
+                .flat_map(|(namespace, items)| items.keys().map(move |id| (namespace, id)));
+            progress.on_response_verified(elements.clone().count() as u64);
+            for (index, (namespace, identifier)) in elements.enumerate() {
+                progress.on_element_verified(namespace.clone(), identifier.clone(), index as u64);
+            }
         }
-    })?;
+        Ok(data)
+    }
+
+    /// Process the accumulated response as [MDLResponseStream::finish] does, delivering
+    /// each verified element to the listener once the whole response is verified, before
+    /// returning the complete result.
+    #[uniffi::method(default(only_requested = false))]
+    pub fn finish_with_listener(
+        &self,
+        listener: Box<dyn MDLResponseListener>,
+        only_requested: bool,
+    ) -> Result<MDLReaderResponseData, MDLReaderResponseError> {
+        let data = self.finish(only_requested)?;
+        for (namespace, items) in data.verified_response.iter() {
+            for (identifier, value) in items.iter() {
+                listener.on_element(namespace.clone(), identifier.clone(), value.clone());
+            }
+        }
+        Ok(data)
+    }
+}
 
-    // Hash the CBOR-encoded handover info
-    let handover_info_hash = Sha256::digest(&handover_info_bytes).to_vec();
+impl MDLResponseStream {
+    fn with(
+        state: Arc<MDLSessionManager>,
+        expected_length: Option<u64>,
+        progress: Option<Box<dyn MDLResponseProgress>>,
+    ) -> Self {
+        Self {
+            state,
+            buffer: Mutex::new(ResponseBuffer::new(expected_length)),
+            progress,
+        }
+    }
+
+    fn buffer(&self) -> Result<std::sync::MutexGuard<'_, ResponseBuffer>, MDLReaderResponseError> {
+        self.buffer
+            .lock()
+            .map_err(|_| MDLReaderResponseError::Generic {
+                value: "Could not lock response buffer".to_string(),
+            })
+    }
+}
+
+/// The most bytes a [MDLResponseStream] allocates upfront for the expected length of a
+/// response. Larger responses grow the buffer as their chunks arrive.
+const MAX_PREALLOCATED_LENGTH: u64 = 1024 * 1024;
+
+/// The chunks of a streamed response received so far.
+struct ResponseBuffer {
+    bytes: Vec<u8>,
+    expected_length: Option<u64>,
+}
+
+impl ResponseBuffer {
+    fn new(expected_length: Option<u64>) -> Self {
+        let capacity = expected_length
+            .unwrap_or_default()
+            .min(MAX_PREALLOCATED_LENGTH);
+        Self {
+            bytes: Vec::with_capacity(capacity as usize),
+            expected_length,
+        }
+    }
+
+    /// Appends a chunk, returning the bytes received so far. A chunk beyond the expected
+    /// length is rejected and not appended.
+    fn push(&mut self, chunk: Vec<u8>) -> Result<u64, String> {
+        let received = self.bytes.len() as u64 + chunk.len() as u64;
+        if let Some(expected_length) = self.expected_length
+            && received > expected_length
+        {
+            return Err(format!(
+                "Received {received} bytes, more than the expected {expected_length}"
+            ));
+        }
+        if self.bytes.is_empty() && self.bytes.capacity() <= chunk.len() {
+            // The first chunk is kept as is rather than copied
+            self.bytes = chunk;
+        } else {
+            self.bytes.extend_from_slice(&chunk);
+        }
+        Ok(received)
+    }
+
+    /// Takes the complete response, which must have the expected length if one was
+    /// given.
+    fn take(&mut self) -> Result<Vec<u8>, String> {
+        let received = self.bytes.len() as u64;
+        if received == 0 {
+            return Err("No response data has been received".to_string());
+        }
+        if let Some(expected_length) = self.expected_length
+            && received < expected_length
+        {
+            return Err(format!(
+                "Received {received} of the expected {expected_length} bytes"
+            ));
+        }
+        Ok(std::mem::take(&mut self.bytes))
+    }
+}
+
+#[derive(uniffi::Record, Debug)]
+pub struct MDLReaderVerifiedData {
+    /// The document type (e.g., "org.iso.18013.5.1.mDL")
+    pub doc_type: String,
+    pub verified_response: HashMap<String, HashMap<String, MDocItem>>,
+    pub issuer_authentication: AuthenticationStatus,
+    pub device_authentication: AuthenticationStatus,
+    /// The issues of every document, and those of the documentErrors of the response.
+    pub issues: Vec<ResponseIssue>,
+    /// The validity signed in the MSO.
+    pub validity: Option<MsoValidity>,
+    /// The document signer certificate.
+    pub issuer_certificate: Option<IssuerCertificate>,
+    /// Every document of the response, each verified on its own. The fields above are
+    /// those of the first.
+    pub documents: Vec<MDLReaderDocument>,
+    /// The CBOR encoded SessionTranscript the device authentication was verified
+    /// against, see [verification_receipt].
+    pub session_transcript: Vec<u8>,
+    /// The claims of the DCQL query given to `verify_oid4vp_response` that the documents
+    /// do not satisfy. Empty without a query.
+    pub unmet_claims: Vec<UnmetClaim>,
+}
+
+/// A document of a response, with its verified elements by namespace.
+#[derive(uniffi::Record, Debug, Clone)]
+pub struct MDLReaderDocument {
+    pub doc_type: String,
+    pub namespaces: HashMap<String, HashMap<String, MDocItem>>,
+    pub issuer_authentication: AuthenticationStatus,
+    pub device_authentication: AuthenticationStatus,
+    /// The issues of verifying this document, empty if it was authenticated.
+    pub issues: Vec<ResponseIssue>,
+    /// The validity signed in the MSO.
+    pub validity: Option<MsoValidity>,
+    /// The document signer certificate.
+    pub issuer_certificate: Option<IssuerCertificate>,
+}
+
+/// The subject and validity of the document signer certificate, to show who issued a
+/// document, for example "Issued by: NY DMV".
+#[derive(uniffi::Record, Debug, Clone, PartialEq)]
+pub struct IssuerCertificate {
+    pub common_name: Option<String>,
+    /// ISO 3166-1 alpha-2 country code.
+    pub country: Option<String>,
+    pub state_or_province: Option<String>,
+    pub not_before: SystemTime,
+    pub not_after: SystemTime,
+}
+
+impl IssuerCertificate {
+    /// Reads the first certificate of the x5chain in the IssuerAuth of a document.
+    fn of(issuer_signed: &IssuerSigned) -> Option<Self> {
+        let certificate = x5chain_certificates(&x5chain_cbor(issuer_signed)?)
+            .into_iter()
+            .next()?;
+        let tbs_certificate = certificate.tbs_certificate;
+        Some(Self {
+            common_name: common_name(&tbs_certificate.subject),
+            country: country_name(&tbs_certificate.subject),
+            state_or_province: state_or_province_name(&tbs_certificate.subject),
+            not_before: tbs_certificate.validity.not_before.to_system_time(),
+            not_after: tbs_certificate.validity.not_after.to_system_time(),
+        })
+    }
+}
+
+/// The X5Chain header of the IssuerAuth of a document.
+fn x5chain_cbor(issuer_signed: &IssuerSigned) -> Option<ciborium::Value> {
+    issuer_signed
+        .issuer_auth
+        .inner
+        .unprotected
+        .rest
+        .iter()
+        .find(|(label, _)| label == &Label::Int(X5CHAIN_COSE_HEADER_LABEL))
+        .map(|(_, value)| value.to_owned())
+}
+
+/// The validity of a document, as signed by the issuer in its MSO.
+#[derive(uniffi::Record, Debug, Clone, PartialEq)]
+pub struct MsoValidity {
+    pub signed: SystemTime,
+    pub valid_from: SystemTime,
+    pub valid_until: SystemTime,
+    pub expected_update: Option<SystemTime>,
+    /// Whether `valid_until` had passed at the validation time.
+    pub expired: bool,
+    /// Whether `valid_from` had not been reached at the validation time.
+    pub not_yet_valid: bool,
+}
+
+impl MsoValidity {
+    /// Decodes the validity of the MSO signed in the IssuerAuth of a document, as of
+    /// `validation_time`.
+    fn of(issuer_signed: &IssuerSigned, validation_time: SystemTime) -> Option<Self> {
+        let mso: Tag24<Mso> =
+            isomdl::cbor::from_slice(issuer_signed.issuer_auth.payload.as_ref()?).ok()?;
+        let validity = &mso.as_ref().validity_info;
+        let valid_from = SystemTime::from(validity.valid_from);
+        let valid_until = SystemTime::from(validity.valid_until);
+        Some(Self {
+            signed: validity.signed.into(),
+            valid_from,
+            valid_until,
+            expected_update: validity.expected_update.map(SystemTime::from),
+            expired: validation_time > valid_until,
+            not_yet_valid: validation_time < valid_from,
+        })
+    }
+}
+
+impl MDLReaderVerifiedData {
+    pub fn verified_response_as_json(
+        &self,
+    ) -> Result<serde_json::Value, MDLReaderResponseSerializeError> {
+        serde_json::to_value(
+            self.verified_response
+                .iter()
+                .map(|(k, v)| {
+                    (
+                        k.clone(),
+                        v.iter().map(|(k, v)| (k.clone(), v.into())).collect(),
+                    )
+                })
+                .collect::<HashMap<String, HashMap<String, serde_json::Value>>>(),
+        )
+        .map_err(|e| MDLReaderResponseSerializeError::Generic {
+            value: format!("Serialization error: {}", e),
+        })
+    }
+}
+
+/// Storage of the nonces issued by a [NonceRegistry], for verifiers that persist them
+/// or share them between instances.
+#[uniffi::export(callback_interface)]
+pub trait NonceStore: Send + Sync {
+    /// Records an issued nonce, valid until `expires_at`.
+    fn insert(&self, nonce: String, expires_at: SystemTime);
+    /// Removes a nonce and returns its expiry, or `None` if it is not recorded. Removal
+    /// must be atomic, so that concurrent presentations cannot both consume a nonce.
+    fn remove(&self, nonce: String) -> Option<SystemTime>;
+}
+
+#[derive(Default)]
+struct MemoryNonceStore(Mutex<HashMap<String, SystemTime>>);
+
+impl NonceStore for MemoryNonceStore {
+    fn insert(&self, nonce: String, expires_at: SystemTime) {
+        let mut nonces = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        let now = SystemTime::now();
+        nonces.retain(|_, expires_at| *expires_at > now);
+        nonces.insert(nonce, expires_at);
+    }
+
+    fn remove(&self, nonce: String) -> Option<SystemTime> {
+        let mut nonces = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        nonces.remove(&nonce)
+    }
+}
+
+#[derive(thiserror::Error, uniffi::Error, Debug, PartialEq)]
+pub enum NonceError {
+    #[error("The nonce was not issued or was already used")]
+    Unknown,
+    #[error("The nonce expired")]
+    Expired,
+    #[error("The nonce validity is too long to compute its expiry")]
+    ValidityOutOfRange,
+}
+
+/// Issues the nonces of OpenID4VP requests and consumes them as their responses are
+/// verified. The SessionTranscript signed by the device binds the nonce, so consuming
+/// it before `verify_oid4vp_response` rejects a replayed vp_token.
+#[derive(uniffi::Object)]
+pub struct NonceRegistry {
+    validity: Duration,
+    store: Box<dyn NonceStore>,
+}
+
+#[uniffi::export]
+impl NonceRegistry {
+    /// A registry keeping its nonces in memory, each valid for `validity_seconds`.
+    #[uniffi::constructor]
+    pub fn new(validity_seconds: u64) -> Arc<Self> {
+        Self::with_store(validity_seconds, Box::new(MemoryNonceStore::default()))
+    }
+
+    /// A registry keeping its nonces in `store`, each valid for `validity_seconds`.
+    #[uniffi::constructor]
+    pub fn with_store(validity_seconds: u64, store: Box<dyn NonceStore>) -> Arc<Self> {
+        Arc::new(Self {
+            validity: Duration::from_secs(validity_seconds),
+            store,
+        })
+    }
+
+    /// Issues a random nonce for a request.
+    pub fn generate_nonce(&self) -> Result<String, NonceError> {
+        let expires_at = SystemTime::now()
+            .checked_add(self.validity)
+            .ok_or(NonceError::ValidityOutOfRange)?;
+        let nonce = BASE64_URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>());
+        self.store.insert(nonce.clone(), expires_at);
+        Ok(nonce)
+    }
+
+    /// Consumes the nonce of a response, which is accepted once and before it expires.
+    pub fn consume_nonce(&self, nonce: String) -> Result<(), NonceError> {
+        let expires_at = self.store.remove(nonce).ok_or(NonceError::Unknown)?;
+        if SystemTime::now() > expires_at {
+            return Err(NonceError::Expired);
+        }
+        Ok(())
+    }
+}
+
+/// Verifies the vp_token of an OpenID4VP presentation. The validity of each document
+/// is checked as of `validation_time`, which defaults to now.
+///
+/// Each trust anchor is a PEM certificate, trusted for `trust_anchor_purpose` which
+/// defaults to IACA, or a JSON serialized `PemTrustAnchor` with its own purpose.
+///
+/// `handover_variants` are the OID4VPHandover definitions to verify device
+/// authentication against, by default OpenID4VP 1.0 only. Given several, each is tried
+/// in order and the first to authenticate every document is returned, else the result of
+/// the first.
+///
+/// Given the `dcql_query` of the request, the documents are also matched against its
+/// `mso_mdoc` credential queries, and the claims they do not satisfy are returned as
+/// `unmet_claims`.
+#[uniffi::export(default(
+    validation_time = None,
+    trust_anchor_purpose = None,
+    handover_variants = None,
+    dcql_query = None
+))]
+#[allow(clippy::too_many_arguments)]
+pub fn verify_oid4vp_response(
+    response: Vec<u8>,
+    nonce: String,
+    client_id: String,
+    response_uri: String,
+    trust_anchor_registry: Option<Vec<String>>,
+    use_intermediate_chaining: bool,
+    validation_time: Option<SystemTime>,
+    trust_anchor_purpose: Option<TrustAnchorPurpose>,
+    handover_variants: Option<Vec<HandoverVariant>>,
+    dcql_query: Option<String>,
+) -> Result<MDLReaderVerifiedData, MDLReaderSessionError> {
+    let verified = verify_oid4vp_handovers(
+        response,
+        &nonce,
+        &client_id,
+        &response_uri,
+        None,
+        &handover_variants.unwrap_or_else(|| vec![HandoverVariant::OpenId4Vp]),
+        &TrustAnchors::new(trust_anchor_registry, trust_anchor_purpose)?,
+        use_intermediate_chaining,
+        validation_time,
+    )?;
+    with_unmet_claims(verified, dcql_query)
+}
+
+/// Sets the claims of `dcql_query` that the verified documents do not satisfy.
+fn with_unmet_claims(
+    mut verified: MDLReaderVerifiedData,
+    dcql_query: Option<String>,
+) -> Result<MDLReaderVerifiedData, MDLReaderSessionError> {
+    if let Some(query) = dcql_query {
+        verified.unmet_claims = dcql::unmet_claims(&query, &verified.documents)
+            .map_err(|value| MDLReaderSessionError::InvalidQuery { value })?;
+    }
+    Ok(verified)
+}
+
+/// Verifies the encrypted authorization response of `response_mode=direct_post.jwt`:
+/// the JWE posted as the `response` parameter, encrypted with ECDH-ES and A128GCM to the
+/// verifier's ephemeral P-256 key, given as a private JWK.
+///
+/// The vp_token of the decrypted response is verified as by `verify_oid4vp_response`,
+/// against the OpenID4VP 1.0 handover binding the thumbprint of the verifier key, else
+/// the draft 20 handover with the JWE `apu` as the mdoc generated nonce. If the request
+/// had a `state`, the response must carry the same.
+#[uniffi::export(default(validation_time = None, trust_anchor_purpose = None))]
+#[allow(clippy::too_many_arguments)]
+pub fn verify_encrypted_oid4vp_response(
+    response: String,
+    verifier_jwk: String,
+    nonce: String,
+    client_id: String,
+    response_uri: String,
+    state: Option<String>,
+    trust_anchor_registry: Option<Vec<String>>,
+    use_intermediate_chaining: bool,
+    validation_time: Option<SystemTime>,
+    trust_anchor_purpose: Option<TrustAnchorPurpose>,
+) -> Result<MDLReaderVerifiedData, MDLReaderSessionError> {
+    verify_jwe_response(
+        &response,
+        &verifier_jwk,
+        &nonce,
+        &client_id,
+        &response_uri,
+        state.as_deref(),
+        &TrustAnchors::new(trust_anchor_registry, trust_anchor_purpose)?,
+        use_intermediate_chaining,
+        validation_time,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+fn verify_jwe_response(
+    response: &str,
+    verifier_jwk: &str,
+    nonce: &str,
+    client_id: &str,
+    response_uri: &str,
+    state: Option<&str>,
+    trust_anchors: &TrustAnchors,
+    use_intermediate_chaining: bool,
+    validation_time: Option<SystemTime>,
+) -> Result<MDLReaderVerifiedData, MDLReaderSessionError> {
+    let (header, payload) = jwe::decrypt(response, verifier_jwk).map_err(|e| {
+        MDLReaderSessionError::DecryptionFailed {
+            value: format!("Could not decrypt the response: {e:#}"),
+        }
+    })?;
+    let payload: serde_json::Value =
+        serde_json::from_slice(&payload).map_err(|e| MDLReaderSessionError::ResponseParse {
+            value: format!("Invalid decrypted response: {e}"),
+        })?;
+    if state.is_some() && payload["state"].as_str() != state {
+        return Err(MDLReaderSessionError::TranscriptMismatch {
+            value: "The state of the response does not match the request".to_string(),
+        });
+    }
+    let device_response = vp_token(&payload["vp_token"])
+        .and_then(|token| BASE64_URL_SAFE_NO_PAD.decode(token).ok())
+        .ok_or(MDLReaderSessionError::ResponseParse {
+            value: "Expected a single base64url encoded vp_token".to_string(),
+        })?;
+
+    let jwk_thumbprint = serde_json::from_str(verifier_jwk)
+        .ok()
+        .and_then(|jwk| jwk_thumbprint(&jwk));
+    let mut handover_variants = vec![HandoverVariant::OpenId4Vp];
+    if let Some(mdoc_generated_nonce) = header["apu"]
+        .as_str()
+        .and_then(|apu| BASE64_URL_SAFE_NO_PAD.decode(apu).ok())
+        .and_then(|apu| String::from_utf8(apu).ok())
+    {
+        handover_variants.push(HandoverVariant::Draft20 {
+            mdoc_generated_nonce,
+        });
+    }
+    verify_oid4vp_handovers(
+        device_response,
+        nonce,
+        client_id,
+        response_uri,
+        jwk_thumbprint,
+        &handover_variants,
+        trust_anchors,
+        use_intermediate_chaining,
+        validation_time,
+    )
+}
+
+/// The vp_token of an authorization response: a string, or the single presentation of a
+/// DCQL response `{"<credential_query_id>": ["<vp_token>"]}`.
+fn vp_token(value: &serde_json::Value) -> Option<&str> {
+    use serde_json::Value;
+
+    match value {
+        Value::String(token) => Some(token),
+        Value::Array(tokens) if tokens.len() == 1 => vp_token(&tokens[0]),
+        Value::Object(queries) if queries.len() == 1 => {
+            queries.values().next().and_then(|tokens| match tokens {
+                Value::Object(_) => None,
+                tokens => vp_token(tokens),
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Verifies `response` against the OID4VPHandover of each variant in turn, returning the
+/// first result to authenticate every document, else the first result.
+#[allow(clippy::too_many_arguments)]
+fn verify_oid4vp_handovers(
+    response: Vec<u8>,
+    nonce: &str,
+    client_id: &str,
+    response_uri: &str,
+    jwk_thumbprint: Option<Vec<u8>>,
+    handover_variants: &[HandoverVariant],
+    trust_anchors: &TrustAnchors,
+    use_intermediate_chaining: bool,
+    validation_time: Option<SystemTime>,
+) -> Result<MDLReaderVerifiedData, MDLReaderSessionError> {
+    let encoding_error =
+        |e: ciborium::ser::Error<std::io::Error>| MDLReaderSessionError::Encoding {
+            value: format!("Failed to CBOR-encode handover info: {}", e),
+        };
+    let mut first = None;
+    for variant in handover_variants {
+        let verified = match variant {
+            // OID4VP SessionTranscript per updated spec (Appendix B.2.6.1)
+            HandoverVariant::OpenId4Vp => verify_device_response(
+                response.clone(),
+                OID4VPSessionTranscript::new(
+                    client_id,
+                    nonce,
+                    jwk_thumbprint.clone(),
+                    response_uri,
+                )
+                .map_err(encoding_error)?,
+                trust_anchors,
+                use_intermediate_chaining,
+                validation_time,
+            )?,
+            HandoverVariant::Draft20 {
+                mdoc_generated_nonce,
+            } => verify_device_response(
+                response.clone(),
+                OID4VPDraft20SessionTranscript::new(
+                    client_id,
+                    nonce,
+                    mdoc_generated_nonce,
+                    response_uri,
+                )
+                .map_err(encoding_error)?,
+                trust_anchors,
+                use_intermediate_chaining,
+                validation_time,
+            )?,
+        };
+        if verified
+            .documents
+            .iter()
+            .all(|document| document.device_authentication == AuthenticationStatus::Valid)
+        {
+            return Ok(verified);
+        }
+        first.get_or_insert(verified);
+    }
+    first.ok_or_else(|| MDLReaderSessionError::Generic {
+        value: "No handover variant to verify the response against".to_string(),
+    })
+}
+
+/// Verifies the DeviceResponse of an OpenID4VP presentation made through the Digital
+/// Credentials API, by browsers or the Android Credential Manager, against the
+/// OpenID4VPDCAPIHandover SessionTranscript of the request's origin and nonce.
+///
+/// `jwk_thumbprint` is the SHA-256 JWK thumbprint of the verifier's encryption key if the
+/// response was encrypted. As of OpenID4VP 1.0 the client_id is not part of this handover.
+/// Trust anchors and validation are as for `verify_oid4vp_response`.
+#[uniffi::export(default(validation_time = None, trust_anchor_purpose = None))]
+#[allow(clippy::too_many_arguments)]
+pub fn verify_dc_api_response(
+    response: Vec<u8>,
+    origin: String,
+    nonce: String,
+    jwk_thumbprint: Option<Vec<u8>>,
+    trust_anchor_registry: Option<Vec<String>>,
+    use_intermediate_chaining: bool,
+    validation_time: Option<SystemTime>,
+    trust_anchor_purpose: Option<TrustAnchorPurpose>,
+) -> Result<MDLReaderVerifiedData, MDLReaderSessionError> {
+    let transcript =
+        OID4VPSessionTranscript::dc_api(&origin, &nonce, jwk_thumbprint).map_err(|e| {
+            MDLReaderSessionError::Encoding {
+                value: format!("Failed to CBOR-encode handover info: {}", e),
+            }
+        })?;
+    verify_device_response(
+        response,
+        transcript,
+        &TrustAnchors::new(trust_anchor_registry, trust_anchor_purpose)?,
+        use_intermediate_chaining,
+        validation_time,
+    )
+}
+
+/// Verifies the OpenID4VP responses to the requests of a verifier. Its trust anchors are
+/// parsed once, rather than on each call to `verify_oid4vp_response`, for verification
+/// servers to share a verifier across their requests.
+#[derive(uniffi::Object)]
+pub struct Oid4vpVerifier {
+    client_id: String,
+    response_uri: String,
+    trust_anchors: TrustAnchors,
+    use_intermediate_chaining: bool,
+    handover_variants: Vec<HandoverVariant>,
+    verifier_jwk: Option<String>,
+}
+
+#[uniffi::export]
+impl Oid4vpVerifier {
+    /// The trust anchors, their purpose and the handover variants are as for
+    /// `verify_oid4vp_response`. `verifier_jwk` is the private JWK that encrypted responses
+    /// are decrypted with, see `verify_encrypted_oid4vp_response`.
+    #[uniffi::constructor(default(
+        trust_anchor_purpose = None,
+        handover_variants = None,
+        verifier_jwk = None
+    ))]
+    pub fn new(
+        client_id: String,
+        response_uri: String,
+        trust_anchor_registry: Option<Vec<String>>,
+        use_intermediate_chaining: bool,
+        trust_anchor_purpose: Option<TrustAnchorPurpose>,
+        handover_variants: Option<Vec<HandoverVariant>>,
+        verifier_jwk: Option<String>,
+    ) -> Result<Arc<Self>, MDLReaderSessionError> {
+        Ok(Arc::new(Self {
+            client_id,
+            response_uri,
+            trust_anchors: TrustAnchors::new(trust_anchor_registry, trust_anchor_purpose)?,
+            use_intermediate_chaining,
+            handover_variants: handover_variants
+                .unwrap_or_else(|| vec![HandoverVariant::OpenId4Vp]),
+            verifier_jwk,
+        }))
+    }
+
+    /// Verifies the vp_token of a response to the request with `nonce`, as
+    /// `verify_oid4vp_response` does.
+    #[uniffi::method(default(validation_time = None, dcql_query = None))]
+    pub fn verify(
+        &self,
+        response: Vec<u8>,
+        nonce: String,
+        validation_time: Option<SystemTime>,
+        dcql_query: Option<String>,
+    ) -> Result<MDLReaderVerifiedData, MDLReaderSessionError> {
+        let verified = verify_oid4vp_handovers(
+            response,
+            &nonce,
+            &self.client_id,
+            &self.response_uri,
+            None,
+            &self.handover_variants,
+            &self.trust_anchors,
+            self.use_intermediate_chaining,
+            validation_time,
+        )?;
+        with_unmet_claims(verified, dcql_query)
+    }
+
+    /// Verifies the JWE of a `direct_post.jwt` response to the request with `nonce` and
+    /// `state`, as `verify_encrypted_oid4vp_response` does with the verifier's JWK.
+    #[uniffi::method(default(validation_time = None, dcql_query = None))]
+    pub fn verify_encrypted(
+        &self,
+        response: String,
+        nonce: String,
+        state: Option<String>,
+        validation_time: Option<SystemTime>,
+        dcql_query: Option<String>,
+    ) -> Result<MDLReaderVerifiedData, MDLReaderSessionError> {
+        let verifier_jwk = self.verifier_jwk.as_deref().ok_or_else(|| {
+            MDLReaderSessionError::DecryptionFailed {
+                value: "The verifier has no key to decrypt responses with".to_string(),
+            }
+        })?;
+        let verified = verify_jwe_response(
+            &response,
+            verifier_jwk,
+            &nonce,
+            &self.client_id,
+            &self.response_uri,
+            state.as_deref(),
+            &self.trust_anchors,
+            self.use_intermediate_chaining,
+            validation_time,
+        )?;
+        with_unmet_claims(verified, dcql_query)
+    }
+}
+
+/// The trust anchors of a verification, parsed once for all the documents verified
+/// against them.
+#[derive(Clone)]
+struct TrustAnchors {
+    anchors: Vec<PemTrustAnchor>,
+    certificates: Vec<Certificate>,
+    registry: TrustAnchorRegistry,
+}
+
+impl TrustAnchors {
+    /// Parses each PEM certificate or JSON serialized `PemTrustAnchor`, see
+    /// [pem_trust_anchor].
+    fn new(
+        trust_anchor_registry: Option<Vec<String>>,
+        purpose: Option<TrustAnchorPurpose>,
+    ) -> Result<Self, MDLReaderSessionError> {
+        let purpose = purpose.unwrap_or_default();
+        let anchors = trust_anchor_registry
+            .unwrap_or_default()
+            .iter()
+            .map(|anchor| pem_trust_anchor(anchor, purpose))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|value| MDLReaderSessionError::TrustAnchorInvalid { value })?;
+        let certificates = anchors
+            .iter()
+            .filter_map(|pem| Certificate::from_pem(&pem.certificate_pem).ok())
+            .collect();
+        let registry =
+            TrustAnchorRegistry::from_pem_certificates(anchors.clone()).map_err(|e| {
+                MDLReaderSessionError::TrustAnchorInvalid {
+                    value: format!("Failed to create trust registry: {}", e),
+                }
+            })?;
+        Ok(Self {
+            anchors,
+            certificates,
+            registry,
+        })
+    }
+
+    /// The registry to validate a document against, extended with the intermediate CAs
+    /// of its x5chain that chain to the anchors if `use_intermediate_chaining`.
+    fn registry(
+        &self,
+        issuer_signed: &IssuerSigned,
+        use_intermediate_chaining: bool,
+    ) -> Result<TrustAnchorRegistry, MDLReaderSessionError> {
+        let x5chain_cbor = x5chain_cbor(issuer_signed)
+            .filter(|_| use_intermediate_chaining && !self.anchors.is_empty());
+        let Some(x5chain_cbor) = x5chain_cbor else {
+            return Ok(self.registry.clone());
+        };
+        // Build trust chain by discovering intermediate CAs
+        let (_all_trusted, additional_anchors) =
+            build_intermediate_trust_chain(self.certificates.clone(), &x5chain_cbor);
+        let mut anchors = self.anchors.clone();
+        anchors.extend(additional_anchors);
+        TrustAnchorRegistry::from_pem_certificates(anchors).map_err(|e| {
+            MDLReaderSessionError::TrustAnchorInvalid {
+                value: format!("Failed to create trust registry: {}", e),
+            }
+        })
+    }
+}
+
+fn verify_device_response<T: session::SessionTranscript + Clone>(
+    response: Vec<u8>,
+    transcript: T,
+    trust_anchors: &TrustAnchors,
+    use_intermediate_chaining: bool,
+    validation_time: Option<SystemTime>,
+) -> Result<MDLReaderVerifiedData, MDLReaderSessionError> {
+    let validation_time = validation_time.unwrap_or_else(SystemTime::now);
+    let mut session_transcript = Vec::new();
+    ciborium::into_writer(&transcript, &mut session_transcript).map_err(|e| {
+        MDLReaderSessionError::Encoding {
+            value: format!("Failed to CBOR-encode the session transcript: {}", e),
+        }
+    })?;
+    // 1. Parse DeviceResponse
+    let device_response: isomdl::definitions::DeviceResponse = isomdl::cbor::from_slice(&response)
+        .map_err(|e| {
+            let debug_info = match ciborium::from_reader::<ciborium::Value, _>(response.as_slice())
+            {
+                Ok(v) => format!("Generic CBOR structure: {:?}", v),
+                Err(e2) => format!("Failed to parse as generic CBOR: {}", e2),
+            };
+            MDLReaderSessionError::ResponseParse {
+                value: format!("Unable to parse DeviceResponse: {}. {}", e, debug_info),
+            }
+        })?;
+
+    // 2. Parse and validate each document
+    let mut issues = document_error_issues(&device_response);
+    let documents = verify_documents(
+        device_response,
+        transcript,
+        trust_anchors,
+        use_intermediate_chaining,
+        validation_time,
+        None,
+    )?;
+    let first = documents[0].clone();
+    issues.extend(
+        documents
+            .iter()
+            .flat_map(|document| document.issues.iter().cloned()),
+    );
+    Ok(MDLReaderVerifiedData {
+        doc_type: first.doc_type,
+        verified_response: first.namespaces,
+        issuer_authentication: first.issuer_authentication,
+        device_authentication: first.device_authentication,
+        issues,
+        validity: first.validity,
+        issuer_certificate: first.issuer_certificate,
+        documents,
+        session_transcript,
+        unmet_claims: vec![],
+    })
+}
+
+/// Validates each document of the DeviceResponse on its own, as isomdl parses only the
+/// first document of a response. A document that cannot be validated is returned with
+/// the error in its `issues` and no elements, so that it does not hide the others. The
+/// error is only returned when no document can be validated.
+fn verify_documents<T: session::SessionTranscript + Clone>(
+    mut device_response: isomdl::definitions::DeviceResponse,
+    transcript: T,
+    trust_anchors: &TrustAnchors,
+    use_intermediate_chaining: bool,
+    validation_time: SystemTime,
+    mac_key: Option<SessionMacKey>,
+) -> Result<Vec<MDLReaderDocument>, MDLReaderSessionError> {
+    let Some(documents) = device_response.documents.take() else {
+        return verify_document(
+            &device_response,
+            transcript,
+            trust_anchors,
+            use_intermediate_chaining,
+            validation_time,
+            mac_key,
+        )
+        .map(|document| vec![document]);
+    };
+    // Each document is moved in turn into the otherwise unchanged response, so none
+    // is copied
+    let results: Vec<_> = documents
+        .into_inner()
+        .into_iter()
+        .map(|document| {
+            let doc_type = document.doc_type.clone();
+            device_response.documents = Some(NonEmptyVec::new(document));
+            let result = verify_document(
+                &device_response,
+                transcript.clone(),
+                trust_anchors,
+                use_intermediate_chaining,
+                validation_time,
+                mac_key,
+            );
+            (doc_type, result)
+        })
+        .collect();
+    if results.iter().all(|(_, result)| result.is_err()) {
+        return results.into_iter().map(|(_, result)| result).collect();
+    }
+    Ok(results
+        .into_iter()
+        .map(|(doc_type, result)| result.unwrap_or_else(|e| failed_document(doc_type, e)))
+        .collect())
+}
+
+/// The record of a document that could not be validated, with the error in its `issues`.
+fn failed_document(doc_type: String, error: MDLReaderSessionError) -> MDLReaderDocument {
+    let issue = ResponseIssue {
+        doc_type: Some(doc_type.clone()),
+        namespace: None,
+        element: None,
+        kind: ResponseIssueKind::Parsing,
+        message: error.to_string(),
+    };
+    MDLReaderDocument {
+        doc_type,
+        namespaces: HashMap::new(),
+        issuer_authentication: AuthenticationStatus::Unchecked,
+        device_authentication: AuthenticationStatus::Unchecked,
+        issues: vec![issue],
+        validity: None,
+        issuer_certificate: None,
+    }
+}
+
+fn verify_document<T: session::SessionTranscript + Clone>(
+    device_response: &isomdl::definitions::DeviceResponse,
+    transcript: T,
+    trust_anchors: &TrustAnchors,
+    use_intermediate_chaining: bool,
+    validation_time: SystemTime,
+    mac_key: Option<SessionMacKey>,
+) -> Result<MDLReaderDocument, MDLReaderSessionError> {
+    match isomdl::presentation::reader::parse(device_response) {
+        Ok((doc, x5chain, namespaces)) => {
+            let registry = trust_anchors.registry(&doc.issuer_signed, use_intermediate_chaining)?;
+
+            let mut validation_result = isomdl::presentation::reader_utils::validate_response(
+                transcript.clone(),
+                registry,
+                x5chain,
+                doc.clone(),
+                namespaces,
+            );
+            let mut device_authentication: AuthenticationStatus =
+                validation_result.device_authentication.into();
+            let device_authenticated = authenticate_device_mac(doc, &transcript, mac_key)
+                .or_else(|| authenticate_other_device_key(doc, &transcript));
+            if let Some(result) = device_authenticated {
+                validation_result
+                    .errors
+                    .remove("device_authentication_errors");
+                device_authentication = match result {
+                    Ok(()) => AuthenticationStatus::Valid,
+                    Err(error) => {
+                        validation_result.errors.insert(
+                            "device_authentication_errors".to_string(),
+                            serde_json::json!([error]),
+                        );
+                        AuthenticationStatus::Invalid
+                    }
+                };
+            }
+
+            // Extract doc_type from the parsed document
+            let doc_type = doc.doc_type.clone();
+
+            // Convert namespaces to HashMap<String, HashMap<String, MDocItem>>
+            let mut verified_response = HashMap::new();
+            for (ns, val) in validation_result.response {
+                // val is serde_json::Value (likely Object or Map)
+                // We need to convert it to HashMap<String, MDocItem>
+                if let serde_json::Value::Object(map) = val {
+                    let mut ns_map = HashMap::new();
+                    for (k, v) in map {
+                        let item = MDocItem::from_element(&ns, &k, v);
+                        ns_map.insert(k, item);
+                    }
+                    verified_response.insert(ns, ns_map);
+                }
+            }
+
+            // Restore the byte strings and dates isomdl converted to JSON from the document
+            for (ns, items) in doc.issuer_signed.namespaces.iter().flat_map(|n| n.iter()) {
+                let Some(ns_map) = verified_response.get_mut(ns) else {
+                    continue;
+                };
+                for item in items.iter() {
+                    let item = item.as_ref();
+                    if let Some(typed) = MDocItem::from_cbor(&item.element_value)
+                        && let Some(value) = ns_map.get_mut(&item.element_identifier)
+                    {
+                        *value = typed;
+                    }
+                }
+            }
+
+            let mut issues = error_issues(&validation_result.errors, Some(&doc_type));
+            let mut issuer_authentication = validation_result.issuer_authentication.into();
+
+            // The returned elements are checked against the value digests of the signed
+            // MSO, and those that do not match are withheld
+            let digest_issue = |namespace, element, message| ResponseIssue {
+                doc_type: Some(doc_type.clone()),
+                namespace,
+                element,
+                kind: ResponseIssueKind::IssuerAuthentication,
+                message,
+            };
+            match digest_mismatches(doc) {
+                Ok(mismatches) => {
+                    for mismatch in mismatches {
+                        if let Some(ns_map) = verified_response.get_mut(&mismatch.namespace) {
+                            ns_map.remove(&mismatch.identifier);
+                        }
+                        issues.push(digest_issue(
+                            Some(mismatch.namespace),
+                            Some(mismatch.identifier),
+                            mismatch.reason,
+                        ));
+                        issuer_authentication = AuthenticationStatus::Invalid;
+                    }
+                }
+                Err(message) => {
+                    issues.push(digest_issue(None, None, message));
+                    issuer_authentication = AuthenticationStatus::Invalid;
+                }
+            }
+            verified_response.retain(|_, ns_map| !ns_map.is_empty());
+
+            Ok(MDLReaderDocument {
+                doc_type,
+                namespaces: verified_response,
+                issuer_authentication,
+                device_authentication,
+                issues,
+                validity: MsoValidity::of(&doc.issuer_signed, validation_time),
+                issuer_certificate: IssuerCertificate::of(&doc.issuer_signed),
+            })
+        }
+        Err(e) => Err(MDLReaderSessionError::ResponseParse {
+            value: format!("Failed to parse device response: {}", e),
+        }),
+    }
+}
+
+/// The returned elements of a document whose value digests are missing from or do not
+/// match the MSO of its issuerAuth.
+fn digest_mismatches(
+    document: &isomdl::definitions::device_response::Document,
+) -> Result<Vec<DigestMismatch>, String> {
+    let mso: Tag24<Mso> = isomdl::cbor::from_slice(
+        document
+            .issuer_signed
+            .issuer_auth
+            .payload
+            .as_ref()
+            .ok_or("The issuerAuth has no MSO")?,
+    )
+    .map_err(|e| format!("Invalid MSO: {e:?}"))?;
+    let items = document
+        .issuer_signed
+        .namespaces
+        .iter()
+        .flat_map(|namespaces| namespaces.iter())
+        .flat_map(|(namespace, items)| items.iter().map(move |item| (namespace, item)))
+        .collect();
+    Ok(check_item_digests(items, mso.as_ref()))
+}
+
+/// The reader key and the CBOR encoded SessionTranscript of a session, which the
+/// EMacKey of a document authenticated with a deviceMac is derived from.
+#[derive(Clone, Copy)]
+struct SessionMacKey<'a> {
+    reader_key: &'a dyn session_encryption::ReaderKey,
+    session_transcript: &'a [u8],
+}
+
+/// Authenticates the deviceMac of a document, as isomdl does not, `None` for a document
+/// authenticated with a deviceSignature. Without the reader key of a session, as in
+/// OpenID4VP, a deviceMac cannot be verified and is invalid.
+fn authenticate_device_mac<T: Serialize>(
+    document: &isomdl::definitions::device_response::Document,
+    transcript: &T,
+    mac_key: Option<SessionMacKey>,
+) -> Option<Result<(), String>> {
+    if !matches!(
+        document.device_signed.device_auth,
+        isomdl::definitions::DeviceAuth::DeviceMac(_)
+    ) {
+        return None;
+    }
+    Some(verify_device_mac(document, transcript, mac_key))
+}
+
+/// Verifies the deviceMac of the DeviceSigned structure of a document with its EMacKey.
+fn verify_device_mac<T: Serialize>(
+    document: &isomdl::definitions::device_response::Document,
+    transcript: &T,
+    mac_key: Option<SessionMacKey>,
+) -> Result<(), String> {
+    use coset::AsCborValue;
+
+    let mac_key = mac_key.ok_or("A deviceMac can only be verified in a reader session")?;
+    let mso: Tag24<Mso> = isomdl::cbor::from_slice(
+        document
+            .issuer_signed
+            .issuer_auth
+            .payload
+            .as_ref()
+            .ok_or("The issuerAuth has no MSO")?,
+    )
+    .map_err(|e| format!("Invalid MSO: {e:?}"))?;
+    let e_mac_key = session_encryption::e_mac_key(
+        mac_key.reader_key,
+        &mso.as_ref().device_key_info.device_key,
+        mac_key.session_transcript,
+    )
+    .map_err(|e| format!("Could not derive the EMacKey: {e:#}"))?;
+    let (session_transcript, device_name_spaces_bytes, device_mac) =
+        device_auth(document, transcript, "deviceMac")?;
+    let device_mac = coset::CoseMac0::from_cbor_value(device_mac)
+        .map_err(|e| format!("Invalid deviceMac: {e:?}"))?;
+    verify_device_auth_mac(
+        &e_mac_key,
+        session_transcript,
+        &mso.as_ref().doc_type,
+        device_name_spaces_bytes,
+        &device_mac,
+    )
+}
+
+/// Authenticates the deviceSignature of a document whose device key is not a P-256
+/// key, as isomdl only verifies those, `None` for a P-256 key.
+fn authenticate_other_device_key<T: Serialize>(
+    document: &isomdl::definitions::device_response::Document,
+    transcript: &T,
+) -> Option<Result<(), String>> {
+    let mso: Tag24<Mso> =
+        isomdl::cbor::from_slice(document.issuer_signed.issuer_auth.payload.as_ref()?).ok()?;
+    let device_key = &mso.as_ref().device_key_info.device_key;
+    if device_key.signature_algorithm() == Some(coset::iana::Algorithm::ES256) {
+        return None;
+    }
+    Some(verify_device_signed(
+        document,
+        device_key,
+        &mso.as_ref().doc_type,
+        transcript,
+    ))
+}
+
+/// Verifies the deviceSignature of the DeviceSigned structure of a document.
+fn verify_device_signed<T: Serialize>(
+    document: &isomdl::definitions::device_response::Document,
+    device_key: &isomdl::definitions::CoseKey,
+    doc_type: &str,
+    transcript: &T,
+) -> Result<(), String> {
+    use coset::AsCborValue;
+
+    let (session_transcript, device_name_spaces_bytes, device_signature) =
+        device_auth(document, transcript, "deviceSignature")?;
+    let device_signature = coset::CoseSign1::from_cbor_value(device_signature)
+        .map_err(|e| format!("Invalid deviceSignature: {e:?}"))?;
+    verify_device_auth_signature(
+        device_key,
+        session_transcript,
+        doc_type,
+        device_name_spaces_bytes,
+        &device_signature,
+    )
+}
+
+/// The encoded session transcript, the tagged DeviceNameSpacesBytes and the untagged
+/// `deviceSignature` or `deviceMac`, as `kind` says, of the DeviceSigned structure of a
+/// document.
+fn device_auth<T: Serialize>(
+    document: &isomdl::definitions::device_response::Document,
+    transcript: &T,
+    kind: &str,
+) -> Result<(ciborium::Value, ciborium::Value, ciborium::Value), String> {
+    let session_transcript = isomdl::cbor::into_value(transcript)
+        .map_err(|e| format!("Could not encode the session transcript: {e:?}"))?;
+    let device_signed = isomdl::cbor::into_value(&document.device_signed)
+        .map_err(|e| format!("Could not encode DeviceSigned: {e:?}"))?;
+    let entry = |map: &ciborium::Value, key: &str| {
+        map.as_map()?
+            .iter()
+            .find(|(label, _)| label.as_text() == Some(key))
+            .map(|(_, value)| value.clone())
+    };
+    let device_name_spaces_bytes = entry(&device_signed, "nameSpaces")
+        .ok_or("The document has no device-signed namespaces")?;
+    let device_auth = match entry(&device_signed, "deviceAuth")
+        .and_then(|device_auth| entry(&device_auth, kind))
+        .ok_or(format!("The document is not authenticated with a {kind}"))?
+    {
+        // The COSE_Sign1 and COSE_Mac0 tags
+        ciborium::Value::Tag(17 | 18, device_auth) => *device_auth,
+        device_auth => device_auth,
+    };
+    Ok((session_transcript, device_name_spaces_bytes, device_auth))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_establish_session_uuid_extraction() {
+        // This test verifies that the UUID extraction works correctly with the new API
+        // and produces the same functional behavior as the deprecated method
+
+        // Create minimal test data
+        let uri = "mdoc://example.com/session".to_string();
+        let mut requested_items = HashMap::new();
+        let mut namespace_items = HashMap::new();
+        namespace_items.insert("given_name".to_string(), true);
+        namespace_items.insert("family_name".to_string(), true);
+        requested_items.insert("org.iso.18013.5.1.mDL".to_string(), namespace_items);
+
+        // Create a minimal trust anchor registry (empty for this test)
+        let trust_anchor_registry = Some(vec![]);
+
+        // Try to establish a session
+        // Note: This will likely fail with a network/connection error since we're using a fake URI,
+        // but it should at least verify that our UUID extraction code path is reachable
+        let result = establish_session(uri, requested_items, trust_anchor_registry, None, None);
+
+        // We expect this to fail with a connection error, not a UUID extraction error
+        match result {
+            Ok(_) => {
+                // If it somehow succeeds, that's great - the UUID extraction worked
+            }
+            Err(e) => {
+                let error_msg = e.to_string();
+
+                // The error should NOT be about UUID extraction if our fix is correct
+                // It should be about session establishment, QR code construction, etc.
+                assert!(
+                    !error_msg.contains("central client uuid"),
+                    "❌ UUID extraction failed: {}",
+                    error_msg
+                );
+
+                // Verify it's a legitimate session establishment error
+                assert!(
+                    error_msg.contains("unable to establish session")
+                        || error_msg.contains("QR code")
+                        || error_msg.contains("network")
+                        || error_msg.contains("connection"),
+                    "Expected session establishment error, got: {}",
+                    error_msg
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_establish_session_peripheral_server_mode() {
+        use crate::mdl::holder::{MdlPresentationSession, RetrievalMethod};
+        use crate::mdl::util::{P256KeyPair, generate_test_mdl};
+
+        let mdoc = generate_test_mdl(Arc::new(P256KeyPair::new())).unwrap();
+        let uuid = Uuid::new_v4();
+        let session = MdlPresentationSession::new_with_retrieval_methods(
+            Arc::new(mdoc),
+            vec![RetrievalMethod::BlePeripheralServer {
+                uuid: uuid.to_string(),
+                ble_device_address: Some(vec![1, 2, 3, 4, 5, 6]),
+            }],
+        )
+        .unwrap();
+        let requested_items = HashMap::from([(
+            "org.iso.18013.5.1".to_string(),
+            HashMap::from([("family_name".to_string(), false)]),
+        )]);
+
+        let session_data =
+            establish_session(session.get_qr_code_uri(), requested_items, None, None, None)
+                .unwrap();
+        assert_eq!(session_data.uuid, uuid);
+        assert_eq!(session_data.central_client_uuid, None);
+        assert_eq!(
+            session_data.peripheral_server,
+            Some(PeripheralServerOptions {
+                uuid,
+                ble_device_address: Some(vec![1, 2, 3, 4, 5, 6]),
+            })
+        );
+    }
+
+    #[test]
+    fn test_establish_session_with_elements() {
+        use crate::mdl::holder::MdlPresentationSession;
+        use crate::mdl::util::{P256KeyPair, generate_test_mdl};
+
+        let mdoc = generate_test_mdl(Arc::new(P256KeyPair::new())).unwrap();
+        let session =
+            MdlPresentationSession::new(Arc::new(mdoc), Uuid::new_v4().to_string()).unwrap();
+        let requested_elements = HashMap::from([(
+            "org.iso.18013.5.1".to_string(),
+            vec![
+                RequestedElement {
+                    element_id: "family_name".to_string(),
+                    intent_to_retain: true,
+                },
+                RequestedElement {
+                    element_id: "portrait".to_string(),
+                    intent_to_retain: false,
+                },
+            ],
+        )]);
+
+        // The session keeps the intent to retain of each requested element, to surface
+        // it in the response data
+        let session_data = establish_session_with_elements(
+            session.get_qr_code_uri(),
+            requested_elements.clone(),
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+        assert_eq!(session_data.state.requested_elements(), requested_elements);
+    }
+
+    #[test]
+    fn test_establish_session_errors() {
+        let requested_items = HashMap::from([(
+            "org.iso.18013.5.1".to_string(),
+            HashMap::from([("family_name".to_string(), false)]),
+        )]);
+        let establish = |uri: &str| {
+            establish_session(uri.to_string(), requested_items.clone(), None, None, None)
+        };
+
+        // 1. A URI that is not an mdoc URI, or whose DeviceEngagement cannot be decoded,
+        // is reported as such
+        assert!(matches!(
+            establish("https://example.com"),
+            Err(MDLReaderSessionError::EngagementParse { .. })
+        ));
+        assert!(matches!(
+            establish("mdoc:not base64!"),
+            Err(MDLReaderSessionError::EngagementParse { .. })
+        ));
+        assert!(matches!(
+            establish("mdoc:AAAA"),
+            Err(MDLReaderSessionError::EngagementParse { .. })
+        ));
+
+        // 2. So is an invalid request
+        assert!(matches!(
+            establish_session_with_device_engagement(
+                vec![],
+                requested_items.clone(),
+                None,
+                None,
+                Some(vec![0]),
+                None,
+                None
+            ),
+            Err(MDLReaderSessionError::InvalidRequest { .. })
+        ));
+    }
+
+    #[test]
+    fn test_session_status_message() {
+        let status = |status| {
+            let message = session_status_message(status).unwrap();
+            let message: session::SessionData = isomdl::cbor::from_slice(&message).unwrap();
+            assert!(message.data.is_none());
+            message.status
+        };
+        assert!(matches!(
+            status(SessionStatus::SessionEncryptionError),
+            Some(session::Status::SessionEncryptionError)
+        ));
+        assert!(matches!(
+            status(SessionStatus::CborDecodingError),
+            Some(session::Status::CborDecodingError)
+        ));
+        assert!(matches!(
+            status(SessionStatus::SessionTermination),
+            Some(session::Status::SessionTermination)
+        ));
+    }
+
+    #[test]
+    fn test_session_transcript() {
+        use crate::mdl::holder::{MdlPresentationSession, RetrievalMethod};
+        use crate::mdl::util::{P256KeyPair, generate_test_mdl};
+        use ciborium::Value;
+
+        let encode = |value: &Value| {
+            let mut bytes = Vec::new();
+            ciborium::into_writer(value, &mut bytes).unwrap();
+            bytes
+        };
+        let device_engagement = encode(&Value::Array(vec!["1.0".into()]));
+
+        // 1. The mdoc URI carries the engagement bytes
+        let uri = format!("mdoc:{}", BASE64_URL_SAFE_NO_PAD.encode(&device_engagement));
+        assert_eq!(device_engagement_bytes(&uri).unwrap(), device_engagement);
+        assert!(device_engagement_bytes("https://example.com").is_err());
+
+        // 2. An engagement through NFC binds the transcript to the handover messages,
+        // with which the holder decrypts the request
+        let mdoc = Arc::new(generate_test_mdl(Arc::new(P256KeyPair::new())).unwrap());
+        let holder = || {
+            MdlPresentationSession::new_with_retrieval_methods(
+                mdoc.clone(),
+                vec![RetrievalMethod::BlePeripheralServer {
+                    uuid: Uuid::new_v4().to_string(),
+                    ble_device_address: Some(vec![1, 2, 3, 4, 5, 6]),
+                }],
+            )
+            .unwrap()
+        };
+        let requested_items = || {
+            HashMap::from([(
+                "org.iso.18013.5.1".to_string(),
+                HashMap::from([("family_name".to_string(), false)]),
+            )])
+        };
+        let handover = |reader: &MDLReaderSessionData| {
+            let transcript = reader.state.session_transcript();
+            let transcript: Value = ciborium::from_reader(transcript.as_slice()).unwrap();
+            transcript.as_array().unwrap()[2].clone()
+        };
+        let session = holder();
+        let select = session.nfc_static_handover_select().unwrap();
+        let reader = establish_session_with_device_engagement(
+            session.get_device_engagement().unwrap(),
+            requested_items(),
+            None,
+            Some(select.clone()),
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            handover(&reader),
+            Value::Array(vec![Value::Bytes(select.clone()), Value::Null])
+        );
+        session.handle_request(reader.request).unwrap();
+
+        // 3. Negotiated handover binds it to the Handover Request as well
+        let request = vec![0xd1, 2, 0, b'H', b'r'];
+        let reader = establish_session_with_device_engagement(
+            session.get_device_engagement().unwrap(),
+            requested_items(),
+            None,
+            Some(select.clone()),
+            Some(request.clone()),
+            None,
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            handover(&reader),
+            Value::Array(vec![Value::Bytes(select), Value::Bytes(request.clone())])
+        );
+
+        // 4. A reader bound to the QR handover cannot talk to a holder engaged through
+        // NFC, and a Handover Request is only sent in answer to a Handover Select
+        let session = holder();
+        session.nfc_static_handover_select().unwrap();
+        let reader = establish_session_with_device_engagement(
+            session.get_device_engagement().unwrap(),
+            requested_items(),
+            None,
+            None,
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+        assert_eq!(handover(&reader), Value::Null);
+        assert!(session.handle_request(reader.request).is_err());
+        assert!(
+            establish_session_with_device_engagement(
+                session.get_device_engagement().unwrap(),
+                requested_items(),
+                None,
+                None,
+                Some(request),
+                None,
+                None,
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_reader_key_agreement() {
+        use crate::mdl::holder::MdlPresentationSession;
+        use crate::mdl::util::{P256KeyPair, generate_test_mdl};
+        use ciborium::Value;
+        use p256::elliptic_curve::sec1::ToEncodedPoint;
+
+        struct FixedKey(p256::SecretKey);
+
+        impl ReaderKeyAgreement for FixedKey {
+            fn public_key(&self) -> Vec<u8> {
+                self.0.public_key().to_sec1_bytes().to_vec()
+            }
+
+            fn agree(&self, device_key: Vec<u8>) -> Result<Vec<u8>, KeyAgreementError> {
+                let device_key = p256::PublicKey::from_sec1_bytes(&device_key).map_err(|e| {
+                    KeyAgreementError::Generic {
+                        value: e.to_string(),
+                    }
+                })?;
+                let shared_secret =
+                    p256::ecdh::diffie_hellman(self.0.to_nonzero_scalar(), device_key.as_affine());
+                Ok(shared_secret.raw_secret_bytes().to_vec())
+            }
+        }
+
+        let mdoc = Arc::new(generate_test_mdl(Arc::new(P256KeyPair::new())).unwrap());
+        let session = MdlPresentationSession::new(mdoc, Uuid::new_v4().to_string()).unwrap();
+        let requested_items = HashMap::from([(
+            "org.iso.18013.5.1".to_string(),
+            HashMap::from([("family_name".to_string(), false)]),
+        )]);
+        let reader_key = p256::SecretKey::from_slice(&[7; 32]).unwrap();
+        let reader = establish_session(
+            session.get_qr_code_uri(),
+            requested_items,
+            None,
+            Some(Box::new(FixedKey(reader_key.clone()))),
+            None,
+        )
+        .unwrap();
+
+        // 1. The EReaderKey of the transcript is the caller's key
+        let transcript: Value =
+            ciborium::from_reader(reader.state.session_transcript().as_slice()).unwrap();
+        let Value::Tag(24, e_reader_key) = &transcript.as_array().unwrap()[1] else {
+            panic!("EReaderKeyBytes is not tagged");
+        };
+        let e_reader_key: Value =
+            ciborium::from_reader(e_reader_key.as_bytes().unwrap().as_slice()).unwrap();
+        let coordinate = |label: i64| {
+            e_reader_key
+                .as_map()
+                .unwrap()
+                .iter()
+                .find(|(key, _)| key.as_integer() == Some(label.into()))
+                .and_then(|(_, value)| value.as_bytes().cloned())
+                .unwrap()
+        };
+        let point = reader_key.public_key().to_encoded_point(false);
+        assert_eq!(coordinate(-2), point.x().unwrap().to_vec());
+        assert_eq!(coordinate(-3), point.y().unwrap().to_vec());
+
+        // 2. The holder decrypts the request with the keys agreed through it
+        session.handle_request(reader.request).unwrap();
+    }
+
+    #[test]
+    fn test_reader_auth() {
+        use crate::mdl::holder::MdlPresentationSession;
+        use crate::mdl::util::{
+            IacaCertificateParams, P256KeyPair, generate_iaca_certificate, generate_test_mdl,
+            generate_test_reader_certificate,
+        };
+
+        let reader_ca = generate_iaca_certificate(IacaCertificateParams {
+            common_name: "Test Reader CA".to_string(),
+            country: "US".to_string(),
+            state_or_province: Some("NY".to_string()),
+            organization: None,
+            issuer_alt_name: "reader@example.com".to_string(),
+            crl_distribution_point: "https://example.com/reader.crl".to_string(),
+            validity_days: 30,
+            key_type: None,
+        })
+        .unwrap();
+        let reader_certificate = generate_test_reader_certificate(&reader_ca).unwrap();
+        let reader_auth = ReaderAuthKey {
+            certificate_chain_pem: reader_certificate.certificate_pem.clone(),
+            key_pem: reader_certificate.key_pem,
+        };
+        let mdoc = Arc::new(generate_test_mdl(Arc::new(P256KeyPair::new())).unwrap());
+        let session = MdlPresentationSession::new(mdoc, Uuid::new_v4().to_string()).unwrap();
+        let requested_items = || {
+            HashMap::from([(
+                "org.iso.18013.5.1".to_string(),
+                HashMap::from([("family_name".to_string(), false)]),
+            )])
+        };
+
+        // 1. The holder authenticates the reader of a signed request against the reader CA
+        let reader = establish_session(
+            session.get_qr_code_uri(),
+            requested_items(),
+            None,
+            None,
+            Some(reader_auth),
+        )
+        .unwrap();
+        let items_requests = session
+            .handle_request_with_reader_trust_anchors(
+                reader.request,
+                vec![reader_ca.certificate_pem.clone()],
+            )
+            .unwrap();
+        assert_eq!(items_requests.len(), 1);
+        assert_eq!(
+            items_requests[0].reader_authentication,
+            AuthenticationStatus::Valid
+        );
+        assert_eq!(
+            items_requests[0].reader_common_name.as_deref(),
+            Some("Test Reader")
+        );
+
+        // 2. A key that is not that of the reader certificate signs nothing
+        let other_key = ReaderAuthKey {
+            certificate_chain_pem: reader_certificate.certificate_pem,
+            key_pem: IssuerSigningKey::generate(IssuerKeyType::P256)
+                .to_pkcs8_pem()
+                .unwrap(),
+        };
+        let session = MdlPresentationSession::new(
+            Arc::new(generate_test_mdl(Arc::new(P256KeyPair::new())).unwrap()),
+            Uuid::new_v4().to_string(),
+        )
+        .unwrap();
+        assert!(matches!(
+            establish_session(
+                session.get_qr_code_uri(),
+                requested_items(),
+                None,
+                None,
+                Some(other_key),
+            ),
+            Err(MDLReaderSessionError::ReaderAuthInvalid { .. })
+        ));
+    }
+
+    #[test]
+    fn test_session_transcript_device_authentication() {
+        use crate::mdl::holder::MdlPresentationSession;
+        use crate::mdl::util::{P256KeyPair, generate_test_mdl};
+        use coset::CborSerializable;
+
+        let key_pair = Arc::new(P256KeyPair::new());
+        let mdoc = Arc::new(generate_test_mdl(key_pair.clone()).unwrap());
+        let session =
+            MdlPresentationSession::new(mdoc.clone(), Uuid::new_v4().to_string()).unwrap();
+        let requested_items = HashMap::from([(
+            "org.iso.18013.5.1".to_string(),
+            HashMap::from([("family_name".to_string(), false)]),
+        )]);
+        let reader =
+            establish_session(session.get_qr_code_uri(), requested_items, None, None, None)
+                .unwrap();
+        session.handle_request(reader.request).unwrap();
+        let permitted = HashMap::from([(
+            MDL_DOC_TYPE.to_string(),
+            HashMap::from([(
+                "org.iso.18013.5.1".to_string(),
+                vec!["family_name".to_string()],
+            )]),
+        )]);
+        let payload = session.generate_response(permitted).unwrap().payload;
+
+        // The holder signs the ToBeSigned structure of a COSE_Sign1 with a detached
+        // DeviceAuthentication payload, which verifies against the reader's transcript
+        let sig_structure: ciborium::Value = ciborium::from_reader(payload.as_slice()).unwrap();
+        let protected = sig_structure.as_array().unwrap()[1].clone();
+        let device_signature = coset::CoseSign1 {
+            protected: coset::ProtectedHeader::from_cbor_bstr(protected).unwrap(),
+            unprotected: Default::default(),
+            payload: None,
+            signature: key_pair.sign(&payload),
+        }
+        .to_vec()
+        .unwrap();
+        let transcript = reader.state.session_transcript();
+        mdoc.verify_device_authentication(transcript.clone(), device_signature.clone())
+            .unwrap();
+
+        // Not against another transcript
+        let mut other: ciborium::Value = ciborium::from_reader(transcript.as_slice()).unwrap();
+        other.as_array_mut().unwrap()[2] = ciborium::Value::Text("other".to_string());
+        let mut other_transcript = Vec::new();
+        ciborium::into_writer(&other, &mut other_transcript).unwrap();
+        assert!(
+            mdoc.verify_device_authentication(other_transcript, device_signature)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_mdoc_item_types() {
+        use serde_json::json;
+
+        let jpeg = vec![0xff, 0xd8, 0xff, 0xe0, 0x00];
+        let mdl = "org.iso.18013.5.1";
+
+        // 1. Elements defined as bytes are restored from base64 text or octet arrays,
+        // and recognised as images from their leading bytes
+        let portrait = json!(BASE64_URL_SAFE_NO_PAD.encode(&jpeg));
+        assert!(matches!(
+            MDocItem::from_element(mdl, "portrait", portrait),
+            MDocItem::Image { format: ImageFormat::Jpeg, data } if data == jpeg
+        ));
+        let jp2 = json!([0, 0, 0, 12, 106, 80, 32, 32, 13, 10, 135, 10]);
+        assert!(matches!(
+            MDocItem::from_element(mdl, "portrait", jp2),
+            MDocItem::Image {
+                format: ImageFormat::Jpeg2000,
+                ..
+            }
+        ));
+        let signature = json!(BASE64_STANDARD.encode([1, 2, 3]));
+        assert!(matches!(
+            MDocItem::from_element(mdl, "signature_usual_mark", signature),
+            MDocItem::Bytes(data) if data == [1, 2, 3]
+        ));
+
+        // 2. Dates are typed by their element definition
+        assert!(matches!(
+            MDocItem::from_element(mdl, "birth_date", json!("1990-01-15")),
+            MDocItem::Date(date) if date == "1990-01-15"
+        ));
+        assert!(matches!(
+            MDocItem::from_element(mdl, "issue_date", json!("2024-01-01T12:00:00Z")),
+            MDocItem::DateTime(_)
+        ));
+        let tagged = ciborium::Value::Tag(1004, Box::new("1990-01-15".into()));
+        assert!(matches!(
+            MDocItem::from_cbor(&tagged),
+            Some(MDocItem::Date(date)) if date == "1990-01-15"
+        ));
+        let tagged = ciborium::Value::Tag(0, Box::new("2024-01-01T12:00:00Z".into()));
+        assert!(matches!(
+            MDocItem::from_cbor(&tagged),
+            Some(MDocItem::DateTime(_))
+        ));
+        assert!(MDocItem::from_cbor(&"1990-01-15".into()).is_none());
+
+        // 3. Other elements are left as isomdl returns them
+        let name = json!("SGVsbG8");
+        assert!(matches!(
+            MDocItem::from_element(mdl, "family_name", name.clone()),
+            MDocItem::Text(text) if text == "SGVsbG8"
+        ));
+        assert!(matches!(
+            MDocItem::from_element("org.example.1", "portrait", name),
+            MDocItem::Text(_)
+        ));
+
+        // 4. Bytes are base64url encoded in JSON
+        let item = MDocItem::from_bytes(jpeg.clone());
+        assert_eq!(
+            serde_json::Value::from(&item),
+            json!(BASE64_URL_SAFE_NO_PAD.encode(&jpeg))
+        );
+    }
+
+    #[test]
+    fn test_response_issues() {
+        use serde_json::json;
+
+        let requested_documents = BTreeMap::from([
+            (
+                MDL_DOC_TYPE.to_string(),
+                HashMap::from([(
+                    "org.iso.18013.5.1".to_string(),
+                    vec![
+                        RequestedElement {
+                            element_id: "given_name".to_string(),
+                            intent_to_retain: true,
+                        },
+                        RequestedElement {
+                            element_id: "family_name".to_string(),
+                            intent_to_retain: false,
+                        },
+                    ],
+                )]),
+            ),
+            (
+                "eu.europa.ec.eudi.pid.1".to_string(),
+                HashMap::from([(
+                    "eu.europa.ec.eudi.pid.1".to_string(),
+                    vec![RequestedElement {
+                        element_id: "given_name".to_string(),
+                        intent_to_retain: false,
+                    }],
+                )]),
+            ),
+        ]);
+        let mdl = HashMap::from([(
+            "org.iso.18013.5.1".to_string(),
+            HashMap::from([("given_name".to_string(), MDocItem::Text("Alice".into()))]),
+        )]);
+        let pid = HashMap::from([(
+            "eu.europa.ec.eudi.pid.1".to_string(),
+            HashMap::from([("given_name".to_string(), MDocItem::Text("Alice".into()))]),
+        )]);
+        let response_issues =
+            |errors: &BTreeMap<String, serde_json::Value>,
+             doc_type: Option<&str>,
+             returned: &[(&str, &DocumentElements)]| {
+                let mut issues = error_issues(errors, doc_type);
+                issues.extend(missing_element_issues(
+                    &issues,
+                    &requested_documents,
+                    returned,
+                ));
+                issues
+            };
+
+        // 1. Authentication errors and elements that were not returned are located
+        let errors = BTreeMap::from([(
+            "device_authentication_errors".to_string(),
+            json!(["signature mismatch"]),
+        )]);
+        let returned = [(MDL_DOC_TYPE, &mdl), ("eu.europa.ec.eudi.pid.1", &pid)];
+        let issues = response_issues(&errors, Some(MDL_DOC_TYPE), &returned);
+        assert_eq!(
+            issues,
+            vec![
+                ResponseIssue {
+                    doc_type: Some("org.iso.18013.5.1.mDL".into()),
+                    namespace: None,
+                    element: None,
+                    kind: ResponseIssueKind::DeviceAuthentication,
+                    message: "signature mismatch".into(),
+                },
+                ResponseIssue {
+                    doc_type: Some("org.iso.18013.5.1.mDL".into()),
+                    namespace: Some("org.iso.18013.5.1".into()),
+                    element: Some("family_name".into()),
+                    kind: ResponseIssueKind::MissingElement,
+                    message: "family_name was not returned".into(),
+                },
+            ]
+        );
+
+        // 2. Elements are only returned by a document of the docType they were
+        // requested of
+        let returned = [(MDL_DOC_TYPE, &mdl), (MDL_DOC_TYPE, &pid)];
+        let issues = response_issues(&BTreeMap::new(), Some(MDL_DOC_TYPE), &returned);
+        assert_eq!(issues.len(), 2);
+        assert_eq!(
+            issues[0].doc_type.as_deref(),
+            Some("eu.europa.ec.eudi.pid.1")
+        );
+        assert_eq!(issues[0].element.as_deref(), Some("given_name"));
+        assert_eq!(issues[1].element.as_deref(), Some("family_name"));
+
+        // 3. An unreadable response is not reported element by element, unlike a
+        // document that could not be read
+        let errors = BTreeMap::from([("parsing_errors".to_string(), json!(["no documents"]))]);
+        let issues = response_issues(&errors, None, &[]);
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].kind, ResponseIssueKind::Parsing);
+        assert_eq!(issues[0].doc_type, None);
+        let issues = response_issues(&errors, Some(MDL_DOC_TYPE), &[]);
+        assert_eq!(issues.len(), 4);
+
+        // 4. A docType the holder returned a documentErrors status for is reported
+        // with it rather than element by element
+        let device_response = isomdl::definitions::DeviceResponse {
+            version: "1.0".to_string(),
+            documents: None,
+            document_errors: Some(NonEmptyVec::new(BTreeMap::from([(
+                "eu.europa.ec.eudi.pid.1".to_string(),
+                isomdl::definitions::device_response::DocumentErrorCode::DataNotReturned,
+            )]))),
+            status: isomdl::definitions::device_response::Status::OK,
+        };
+        let mut issues = document_error_issues(&device_response);
+        issues.extend(missing_element_issues(
+            &issues,
+            &requested_documents,
+            &[(MDL_DOC_TYPE, &mdl)],
+        ));
+        assert_eq!(
+            issues[0],
+            ResponseIssue {
+                doc_type: Some("eu.europa.ec.eudi.pid.1".into()),
+                namespace: None,
+                element: None,
+                kind: ResponseIssueKind::DocumentError,
+                message: "The holder did not return the document".into(),
+            }
+        );
+        assert_eq!(issues.len(), 2);
+        assert_eq!(issues[1].element.as_deref(), Some("family_name"));
+
+        // 5. Unknown categories are kept with their name
+        let errors = BTreeMap::from([("other_errors".to_string(), json!("unexpected"))]);
+        let issues = error_issues(&errors, Some(MDL_DOC_TYPE));
+        assert_eq!(issues[0].kind, ResponseIssueKind::Other);
+        assert_eq!(issues[0].message, "other_errors: unexpected");
+    }
+
+    #[test]
+    fn test_element_diff() {
+        let requested_elements = HashMap::from([(
+            "org.iso.18013.5.1".to_string(),
+            vec![
+                RequestedElement {
+                    element_id: "given_name".to_string(),
+                    intent_to_retain: true,
+                },
+                RequestedElement {
+                    element_id: "family_name".to_string(),
+                    intent_to_retain: false,
+                },
+            ],
+        )]);
+        let returned = HashMap::from([
+            (
+                "org.iso.18013.5.1".to_string(),
+                HashMap::from([("given_name".to_string(), MDocItem::Text("Alice".into()))]),
+            ),
+            (
+                "org.iso.18013.5.1.aamva".to_string(),
+                HashMap::from([("DHS_compliance".to_string(), MDocItem::Text("F".into()))]),
+            ),
+        ]);
+        let elements = |namespace: &str, elements: &[&str]| {
+            HashMap::from([(
+                namespace.to_string(),
+                elements.iter().map(ToString::to_string).collect(),
+            )])
+        };
+
+        // 1. The diff sorts the elements into granted, refused and extra
+        let diff = ElementDiff::new(&requested_elements, &returned);
+        assert_eq!(
+            diff,
+            ElementDiff {
+                granted: elements("org.iso.18013.5.1", &["given_name"]),
+                refused: elements("org.iso.18013.5.1", &["family_name"]),
+                extra: elements("org.iso.18013.5.1.aamva", &["DHS_compliance"]),
+            }
+        );
 
-    // Build the handover structure: ["OpenID4VPHandover", hash]
-    let transcript = OID4VPSessionTranscript(
-        None, // DeviceEngagementBytes - null for OID4VP
-        None, // EReaderKeyBytes - null for OID4VP
-        OID4VPHandover("OpenID4VPHandover".to_string(), handover_info_hash),
-    );
+        // 2. Nothing returned refuses every requested element
+        let diff = ElementDiff::new(&requested_elements, &HashMap::new());
+        assert!(diff.granted.is_empty() && diff.extra.is_empty());
+        let mut refused = diff.refused["org.iso.18013.5.1"].clone();
+        refused.sort();
+        assert_eq!(refused, vec!["family_name", "given_name"]);
+    }
 
-    // 3. Parse and Validate
-    match isomdl::presentation::reader::parse(&device_response) {
-        Ok((doc, x5chain, namespaces)) => {
-            let registry = if let Some(anchors) = trust_anchor_registry {
-                let mut pem_anchors = Vec::new();
-                for anchor in anchors {
-                    let anchor: PemTrustAnchor = serde_json::from_str(&anchor).map_err(|e| {
-                        MDLReaderSessionError::Generic {
-                            value: format!("Invalid trust anchor JSON: {}", e),
-                        }
-                    })?;
-                    pem_anchors.push(anchor);
-                }
+    #[test]
+    fn test_remove_extra() {
+        let requested_elements = HashMap::from([(
+            "org.iso.18013.5.1".to_string(),
+            vec![RequestedElement {
+                element_id: "given_name".to_string(),
+                intent_to_retain: false,
+            }],
+        )]);
+        let mut returned = HashMap::from([
+            (
+                "org.iso.18013.5.1".to_string(),
+                HashMap::from([
+                    ("given_name".to_string(), MDocItem::Text("Alice".into())),
+                    ("family_name".to_string(), MDocItem::Text("Smith".into())),
+                ]),
+            ),
+            (
+                "org.iso.18013.5.1.aamva".to_string(),
+                HashMap::from([("DHS_compliance".to_string(), MDocItem::Text("F".into()))]),
+            ),
+        ]);
+        let diff = ElementDiff::new(&requested_elements, &returned);
 
-                if use_intermediate_chaining {
-                    // Extract X5Chain CBOR from doc
-                    if let Some(x5chain_cbor) = doc
-                        .issuer_signed
-                        .issuer_auth
-                        .inner
-                        .unprotected
-                        .rest
-                        .iter()
-                        .find(|(label, _)| label == &Label::Int(X5CHAIN_COSE_HEADER_LABEL))
-                        .map(|(_, value)| value.to_owned())
-                    {
-                        // Parse roots from provided anchors
-                        let trusted_certs: Vec<Certificate> = pem_anchors
-                            .iter()
-                            .filter_map(|pem| Certificate::from_pem(&pem.certificate_pem).ok())
-                            .collect();
-
-                        // Build trust chain by discovering intermediate CAs
-                        let (_all_trusted, additional_anchors) =
-                            build_intermediate_trust_chain(trusted_certs, &x5chain_cbor);
-                        pem_anchors.extend(additional_anchors);
-                    }
-                }
+        // Only the requested elements are left, without the namespaces emptied of extras,
+        // while the diff still lists the extras
+        diff.remove_extra(&mut returned);
+        assert_eq!(
+            returned.keys().collect::<Vec<_>>(),
+            vec!["org.iso.18013.5.1"]
+        );
+        assert_eq!(
+            returned["org.iso.18013.5.1"].keys().collect::<Vec<_>>(),
+            vec!["given_name"]
+        );
+        assert_eq!(diff.extra.len(), 2);
+    }
 
-                TrustAnchorRegistry::from_pem_certificates(pem_anchors).map_err(|e| {
-                    MDLReaderSessionError::Generic {
-                        value: format!("Failed to create trust registry: {}", e),
-                    }
-                })?
-            } else {
-                TrustAnchorRegistry::from_pem_certificates(vec![]).map_err(|e| {
-                    MDLReaderSessionError::Generic {
-                        value: format!("Failed to create empty trust registry: {}", e),
-                    }
-                })?
-            };
+    #[test]
+    fn test_device_request_builder() {
+        use crate::mdl::util::{
+            IacaCertificateParams, generate_iaca_certificate, generate_test_reader_certificate,
+        };
 
-            let validation_result = isomdl::presentation::reader_utils::validate_response(
-                transcript,
-                registry,
-                x5chain,
-                doc.clone(),
-                namespaces,
-            );
+        let builder = DeviceRequestBuilder::new();
 
-            // Extract doc_type from the parsed document
-            let doc_type = doc.doc_type.clone();
+        // 1. Elements need a docType first
+        assert!(
+            builder
+                .add_element("org.iso.18013.5.1".into(), "family_name".into(), false)
+                .is_err()
+        );
+        assert!(builder.add_doc_type("".into()).is_err());
+        builder
+            .add_doc_type("org.iso.18013.5.1.mDL".into())
+            .unwrap();
+        assert!(builder.doc_requests().is_err());
 
-            // Convert namespaces to HashMap<String, HashMap<String, MDocItem>>
-            let mut verified_response = HashMap::new();
-            for (ns, val) in validation_result.response {
-                // val is serde_json::Value (likely Object or Map)
-                // We need to convert it to HashMap<String, MDocItem>
-                if let serde_json::Value::Object(map) = val {
-                    let mut ns_map = HashMap::new();
-                    for (k, v) in map {
-                        ns_map.insert(k, MDocItem::from(v));
-                    }
-                    verified_response.insert(ns, ns_map);
-                }
-            }
+        // 2. Elements of known namespaces are checked against their definition
+        assert!(
+            builder
+                .add_element("org.iso.18013.5.1".into(), "family_nme".into(), false)
+                .is_err()
+        );
+        assert!(
+            builder
+                .add_element("".into(), "family_name".into(), false)
+                .is_err()
+        );
+        builder
+            .add_element("org.iso.18013.5.1".into(), "family_name".into(), false)
+            .unwrap();
+        builder
+            .add_element("org.iso.18013.5.1".into(), "age_over_21".into(), true)
+            .unwrap();
+        builder
+            .add_element("org.example.1".into(), "membership".into(), false)
+            .unwrap();
+        builder
+            .add_aamva_element(AamvaElement::DhsCompliance, false)
+            .unwrap();
 
-            // Convert errors
-            let errors = if validation_result.errors.is_empty() {
-                None
-            } else {
-                Some(serde_json::to_string(&validation_result.errors).unwrap_or_default())
-            };
+        // 3. The request carries every added element and its intent to retain
+        let doc_requests = builder.doc_requests().unwrap();
+        assert_eq!(doc_requests.len(), 1);
+        let namespaces = &doc_requests[MDL_DOC_TYPE];
+        assert_eq!(namespaces.len(), 3);
+        assert_eq!(
+            namespaces["org.iso.18013.5.1.aamva"].get("DHS_compliance"),
+            Some(&false)
+        );
+        assert_eq!(
+            namespaces["org.iso.18013.5.1"].get("age_over_21"),
+            Some(&true)
+        );
+        assert_eq!(
+            namespaces["org.iso.18013.5.1"].get("family_name"),
+            Some(&false)
+        );
+        assert_eq!(namespaces["org.example.1"].get("membership"), Some(&false));
 
-            Ok(MDLReaderVerifiedData {
-                doc_type,
-                verified_response,
-                issuer_authentication: validation_result.issuer_authentication.into(),
-                device_authentication: validation_result.device_authentication.into(),
-                errors,
+        // 4. The reader certificate and key to sign the request with are checked when
+        // given
+        let reader_ca = generate_iaca_certificate(IacaCertificateParams {
+            common_name: "Test Reader CA".to_string(),
+            country: "US".to_string(),
+            state_or_province: None,
+            organization: None,
+            issuer_alt_name: "reader@example.com".to_string(),
+            crl_distribution_point: "https://example.com/reader.crl".to_string(),
+            validity_days: 30,
+            key_type: None,
+        })
+        .unwrap();
+        let reader_certificate = generate_test_reader_certificate(&reader_ca).unwrap();
+        assert!(matches!(
+            builder.with_reader_auth(ReaderAuthKey {
+                certificate_chain_pem: reader_certificate.certificate_pem.clone(),
+                key_pem: reader_ca.key_pem,
+            }),
+            Err(MDLReaderSessionError::ReaderAuthInvalid { .. })
+        ));
+        assert!(builder.state().reader_auth.is_none());
+        builder
+            .with_reader_auth(ReaderAuthKey {
+                certificate_chain_pem: reader_certificate.certificate_pem,
+                key_pem: reader_certificate.key_pem,
             })
-        }
-        Err(e) => Err(MDLReaderSessionError::Generic {
-            value: format!("Failed to parse device response: {}", e),
-        }),
+            .unwrap();
+        assert!(builder.state().reader_auth.is_some());
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::collections::HashMap;
 
     #[test]
-    fn test_establish_session_uuid_extraction() {
-        // This test verifies that the UUID extraction works correctly with the new API
-        // and produces the same functional behavior as the deprecated method
-
-        // Create minimal test data
-        let uri = "mdoc://example.com/session".to_string();
-        let mut requested_items = HashMap::new();
-        let mut namespace_items = HashMap::new();
-        namespace_items.insert("given_name".to_string(), true);
-        namespace_items.insert("family_name".to_string(), true);
-        requested_items.insert("org.iso.18013.5.1.mDL".to_string(), namespace_items);
-
-        // Create a minimal trust anchor registry (empty for this test)
-        let trust_anchor_registry = Some(vec![]);
+    fn test_device_request_doc_types() {
+        use ciborium::Value;
 
-        // Try to establish a session
-        // Note: This will likely fail with a network/connection error since we're using a fake URI,
-        // but it should at least verify that our UUID extraction code path is reachable
-        let result = establish_session(uri, requested_items, trust_anchor_registry);
-
-        // We expect this to fail with a connection error, not a UUID extraction error
-        match result {
-            Ok(_) => {
-                // If it somehow succeeds, that's great - the UUID extraction worked
-            }
-            Err(e) => {
-                let error_msg = e.to_string();
+        const PID_DOC_TYPE: &str = "eu.europa.ec.eudi.pid.1";
 
-                // The error should NOT be about UUID extraction if our fix is correct
-                // It should be about session establishment, QR code construction, etc.
-                assert!(
-                    !error_msg.contains("central client uuid"),
-                    "❌ UUID extraction failed: {}",
-                    error_msg
-                );
+        let builder = DeviceRequestBuilder::new();
+        builder.add_doc_type(MDL_DOC_TYPE.into()).unwrap();
+        builder
+            .add_element("org.iso.18013.5.1".into(), "family_name".into(), true)
+            .unwrap();
+        builder.add_doc_type(PID_DOC_TYPE.into()).unwrap();
+        builder
+            .add_element(PID_DOC_TYPE.into(), "birth_date".into(), false)
+            .unwrap();
+        // A docType no element was added to is not requested
+        builder.add_doc_type("org.example.unused".into()).unwrap();
 
-                // Verify it's a legitimate session establishment error
-                assert!(
-                    error_msg.contains("unable to establish session")
-                        || error_msg.contains("QR code")
-                        || error_msg.contains("network")
-                        || error_msg.contains("connection"),
-                    "Expected session establishment error, got: {}",
-                    error_msg
-                );
-            }
-        }
+        // Each docType is requested in its own DocRequest, with its own namespaces
+        let device_request = device_request(&builder.doc_requests().unwrap(), &[], None).unwrap();
+        let device_request: Value = ciborium::from_reader(device_request.as_slice()).unwrap();
+        let field = |map: &Value, key: &str| {
+            map.as_map()
+                .unwrap()
+                .iter()
+                .find(|(k, _)| k.as_text() == Some(key))
+                .map(|(_, v)| v.clone())
+                .unwrap()
+        };
+        let items_requests: Vec<_> = field(&device_request, "docRequests")
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|doc_request| match field(doc_request, "itemsRequest") {
+                Value::Tag(24, bytes) => {
+                    ciborium::from_reader::<Value, _>(bytes.as_bytes().unwrap().as_slice()).unwrap()
+                }
+                value => panic!("unexpected itemsRequest {value:?}"),
+            })
+            .collect();
+        assert_eq!(items_requests.len(), 2);
+        let namespaces = |items_request: &Value| {
+            field(items_request, "nameSpaces")
+                .as_map()
+                .unwrap()
+                .iter()
+                .map(|(namespace, _)| namespace.as_text().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            field(&items_requests[0], "docType").as_text(),
+            Some(PID_DOC_TYPE)
+        );
+        assert_eq!(namespaces(&items_requests[0]), vec![PID_DOC_TYPE]);
+        assert_eq!(
+            field(&items_requests[1], "docType").as_text(),
+            Some(MDL_DOC_TYPE)
+        );
+        assert_eq!(namespaces(&items_requests[1]), vec!["org.iso.18013.5.1"]);
     }
 
     #[test]
@@ -602,17 +4257,55 @@ mod tests {
             response_uri,
             trust_anchors,
             false,
+            None,
+            None,
+            None,
+            None,
         );
 
         assert!(result.is_err());
         match result {
-            Err(MDLReaderSessionError::Generic { value }) => {
+            Err(MDLReaderSessionError::ResponseParse { value }) => {
                 assert!(value.contains("Unable to parse DeviceResponse"));
             }
-            _ => panic!("Expected Generic error"),
+            _ => panic!("Expected ResponseParse error"),
         }
     }
 
+    #[test]
+    fn test_oid4vp_verifier() {
+        let verifier = |trust_anchors: Vec<String>| {
+            Oid4vpVerifier::new(
+                "client_id".to_string(),
+                "response_uri".to_string(),
+                Some(trust_anchors),
+                false,
+                None,
+                None,
+                None,
+            )
+        };
+
+        // 1. Trust anchors are parsed as the verifier is created
+        assert!(matches!(
+            verifier(vec!["not a certificate".to_string()]),
+            Err(MDLReaderSessionError::TrustAnchorInvalid { .. })
+        ));
+
+        // 2. Responses are verified as by verify_oid4vp_response
+        let verifier = verifier(vec![]).unwrap();
+        assert!(matches!(
+            verifier.verify(vec![0, 1, 2, 3], "nonce".to_string(), None, None),
+            Err(MDLReaderSessionError::ResponseParse { .. })
+        ));
+
+        // 3. Encrypted responses need the verifier key
+        assert!(matches!(
+            verifier.verify_encrypted("jwe".to_string(), "nonce".to_string(), None, None, None),
+            Err(MDLReaderSessionError::DecryptionFailed { .. })
+        ));
+    }
+
     #[test]
     fn test_oid4vp_session_transcript_serialization() {
         // Test that the spec-compliant OID4VP SessionTranscript serializes correctly
@@ -663,6 +4356,462 @@ mod tests {
         assert_eq!(parsed.2.1, handover_info_hash, "Handover hash should match");
     }
 
+    #[test]
+    fn test_dc_api_session_transcript() {
+        use sha2::{Digest, Sha256};
+
+        let transcript =
+            OID4VPSessionTranscript::dc_api("https://example.com", "nonce456", None).unwrap();
+        assert!(transcript.0.is_none() && transcript.1.is_none());
+        assert_eq!(transcript.2.0, "OpenID4VPDCAPIHandover");
+
+        // The hash covers [origin, nonce, null]
+        let handover_info = ciborium::Value::Array(vec![
+            ciborium::Value::Text("https://example.com".to_string()),
+            ciborium::Value::Text("nonce456".to_string()),
+            ciborium::Value::Null,
+        ]);
+        let mut bytes = Vec::new();
+        ciborium::into_writer(&handover_info, &mut bytes).unwrap();
+        assert_eq!(transcript.2.1, Sha256::digest(&bytes).to_vec());
+
+        // A thumbprint is encoded as a byte string
+        let thumbprint = vec![7; 32];
+        let handover_info = ciborium::Value::Array(vec![
+            ciborium::Value::Text("https://example.com".to_string()),
+            ciborium::Value::Text("nonce456".to_string()),
+            ciborium::Value::Bytes(thumbprint.clone()),
+        ]);
+        let mut bytes = Vec::new();
+        ciborium::into_writer(&handover_info, &mut bytes).unwrap();
+        let transcript =
+            OID4VPSessionTranscript::dc_api("https://example.com", "nonce456", Some(thumbprint))
+                .unwrap();
+        assert_eq!(transcript.2.1, Sha256::digest(&bytes).to_vec());
+    }
+
+    #[test]
+    fn test_display_elements() {
+        let namespaces = HashMap::from([(
+            namespaces::MDL_NAMESPACE.to_string(),
+            HashMap::from([
+                (
+                    "family_name".to_string(),
+                    MDocItem::Text("Smith".to_string()),
+                ),
+                ("sex".to_string(), MDocItem::Integer(2)),
+                ("age_over_21".to_string(), MDocItem::Bool(true)),
+                ("unknown_element".to_string(), MDocItem::Integer(7)),
+                (
+                    "driving_privileges".to_string(),
+                    MDocItem::Array(vec![MDocItem::ItemMap(HashMap::from([
+                        (
+                            "vehicle_category_code".to_string(),
+                            MDocItem::Text("B".to_string()),
+                        ),
+                        (
+                            "issue_date".to_string(),
+                            MDocItem::Date("2020-01-01".to_string()),
+                        ),
+                    ]))]),
+                ),
+            ]),
+        )]);
+
+        let displayed = display_elements(namespaces);
+        let display = |identifier: &str| {
+            let display = &displayed[namespaces::MDL_NAMESPACE][identifier];
+            (display.label.as_str(), display.value.as_str())
+        };
+        assert_eq!(display("family_name"), ("Family name", "Smith"));
+        assert_eq!(display("sex"), ("Sex", "Female"));
+        assert_eq!(display("age_over_21"), ("Age over 21", "Yes"));
+        assert_eq!(display("unknown_element"), ("unknown_element", "7"));
+        assert_eq!(
+            display("driving_privileges"),
+            (
+                "Categories of vehicles",
+                "issue_date: 2020-01-01, vehicle_category_code: B"
+            )
+        );
+    }
+
+    #[test]
+    fn test_age_attestation() {
+        let response = |elements: &[(&str, MDocItem)]| {
+            HashMap::from([(
+                namespaces::MDL_NAMESPACE.to_string(),
+                elements
+                    .iter()
+                    .map(|(identifier, value)| (identifier.to_string(), value.clone()))
+                    .collect::<HashMap<_, _>>(),
+            )])
+        };
+        let attest = |elements: &[(&str, MDocItem)], age_over| {
+            age_attestation_in(&response(elements), age_over, 2025)
+                .map(|attestation| (attestation.result, attestation.basis))
+        };
+        let basis = |result, basis: &str| Some((result, basis.to_string()));
+
+        // 1. The requested age_over_NN, else the nearest true above or false below
+        let over = [
+            ("age_over_18", MDocItem::Bool(true)),
+            ("age_over_21", MDocItem::Bool(true)),
+            ("age_over_65", MDocItem::Bool(false)),
+        ];
+        assert_eq!(attest(&over, 21), basis(true, "age_over_21"));
+        assert_eq!(attest(&over, 16), basis(true, "age_over_18"));
+        assert_eq!(attest(&over, 70), basis(false, "age_over_65"));
+        assert_eq!(attest(&over, 30), None);
+
+        // 2. Then the age in years and the year of birth
+        let age = [("age_in_years", MDocItem::Integer(20))];
+        assert_eq!(attest(&age, 21), basis(false, "age_in_years"));
+        let birth_year = [("age_birth_year", MDocItem::Integer(2000))];
+        assert_eq!(attest(&birth_year, 24), basis(true, "age_birth_year"));
+        assert_eq!(attest(&birth_year, 25), None);
+        assert_eq!(attest(&birth_year, 26), basis(false, "age_birth_year"));
+    }
+
+    #[test]
+    fn test_verification_receipt() {
+        use coset::CborSerializable;
+        use p256::ecdsa::{Signature, VerifyingKey, signature::Verifier};
+        use sha2::{Digest, Sha256};
+        use std::time::Duration;
+
+        let key = IssuerSigningKey::generate(IssuerKeyType::P256);
+        let document = MDLReaderDocument {
+            doc_type: MDL_DOC_TYPE.to_string(),
+            namespaces: HashMap::from([(
+                namespaces::MDL_NAMESPACE.to_string(),
+                HashMap::from([
+                    (
+                        "family_name".to_string(),
+                        MDocItem::Text("Smith".to_string()),
+                    ),
+                    (
+                        "birth_date".to_string(),
+                        MDocItem::Date("1990-01-01".to_string()),
+                    ),
+                ]),
+            )]),
+            issuer_authentication: AuthenticationStatus::Valid,
+            device_authentication: AuthenticationStatus::Invalid,
+            issues: vec![ResponseIssue {
+                doc_type: Some(MDL_DOC_TYPE.to_string()),
+                namespace: None,
+                element: None,
+                kind: ResponseIssueKind::DeviceAuthentication,
+                message: "signature mismatch".to_string(),
+            }],
+            validity: None,
+            issuer_certificate: None,
+        };
+        let verified_at = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let receipt = verification_receipt(
+            vec![document],
+            vec![0x83, 0xf6, 0xf6, 0xf6],
+            key.to_pkcs8_pem().unwrap(),
+            None,
+            Some(verified_at),
+        )
+        .unwrap();
+
+        // 1. The receipt is signed with the verifier key
+        let sign1 = coset::CoseSign1::from_slice(&receipt).unwrap();
+        let IssuerSigningKey::P256(signing_key) = &key else {
+            unreachable!()
+        };
+        sign1
+            .verify_signature(&[], |signature, tbs| {
+                VerifyingKey::from(signing_key).verify(tbs, &Signature::from_slice(signature)?)
+            })
+            .unwrap();
+
+        // 2. The payload is deterministically encoded and records what was verified
+        let payload = sign1.payload.unwrap();
+        let receipt: ciborium::Value = ciborium::from_reader(payload.as_slice()).unwrap();
+        let mut encoded = Vec::new();
+        ciborium::into_writer(&deterministic_cbor(receipt.clone()), &mut encoded).unwrap();
+        assert_eq!(encoded, payload);
+        let field = |value: &ciborium::Value, name: &str| {
+            value
+                .as_map()
+                .unwrap()
+                .iter()
+                .find(|(key, _)| key.as_text() == Some(name))
+                .map(|(_, value)| value.clone())
+                .unwrap()
+        };
+        assert_eq!(
+            field(&receipt, "verifiedAt"),
+            ciborium::Value::Tag(
+                0,
+                Box::new(ciborium::Value::Text("2023-11-14T22:13:20Z".to_string()))
+            )
+        );
+        assert_eq!(
+            field(&receipt, "sessionTranscriptHash"),
+            ciborium::Value::Bytes(Sha256::digest([0x83, 0xf6, 0xf6, 0xf6]).to_vec())
+        );
+        let document = field(&receipt, "documents").as_array().unwrap()[0].clone();
+        assert_eq!(
+            field(&document, "deviceAuthentication"),
+            ciborium::Value::Text("invalid".to_string())
+        );
+        let issue = field(&document, "issues").as_array().unwrap()[0].clone();
+        assert_eq!(
+            field(&issue, "kind"),
+            ciborium::Value::Text("deviceAuthentication".to_string())
+        );
+        assert_eq!(
+            field(&issue, "message"),
+            ciborium::Value::Text("signature mismatch".to_string())
+        );
+        let elements = field(&field(&document, "nameSpaces"), namespaces::MDL_NAMESPACE);
+        assert_eq!(
+            field(&elements, "birth_date"),
+            ciborium::Value::Tag(
+                1004,
+                Box::new(ciborium::Value::Text("1990-01-01".to_string()))
+            )
+        );
+    }
+
+    #[test]
+    fn test_response_buffer() {
+        // 1. Chunks are assembled in order
+        let mut buffer = ResponseBuffer::new(Some(6));
+        assert_eq!(buffer.push(vec![1, 2]).unwrap(), 2);
+        assert_eq!(buffer.push(vec![]).unwrap(), 2);
+        assert_eq!(buffer.push(vec![3, 4, 5, 6]).unwrap(), 6);
+        assert_eq!(buffer.take().unwrap(), vec![1, 2, 3, 4, 5, 6]);
+        assert!(buffer.take().is_err());
+
+        // 2. A chunk beyond the expected length is rejected and not appended
+        let mut buffer = ResponseBuffer::new(Some(3));
+        buffer.push(vec![1, 2]).unwrap();
+        assert!(buffer.push(vec![3, 4]).is_err());
+        assert_eq!(buffer.push(vec![3]).unwrap(), 3);
+
+        // 3. A response cut short of the expected length is rejected
+        let mut buffer = ResponseBuffer::new(Some(4));
+        buffer.push(vec![1, 2, 3]).unwrap();
+        assert!(buffer.take().unwrap_err().contains("3 of the expected 4"));
+
+        // 4. Without an expected length any length is accepted, and the upfront
+        // allocation is capped whatever length the holder announces
+        let mut buffer = ResponseBuffer::new(None);
+        buffer.push(vec![1]).unwrap();
+        assert_eq!(buffer.take().unwrap(), vec![1]);
+        let buffer = ResponseBuffer::new(Some(u64::MAX));
+        assert_eq!(buffer.bytes.capacity() as u64, MAX_PREALLOCATED_LENGTH);
+    }
+
+    /// A reader session with arbitrary keys, for the checks that need no holder.
+    fn session_manager() -> MDLSessionManager {
+        MDLSessionManager {
+            keys: SessionKeys::derive(&[1; 32], &[]).unwrap(),
+            trust_anchors: TrustAnchors::new(None, None).unwrap(),
+            requested_documents: BTreeMap::from([(
+                MDL_DOC_TYPE.to_string(),
+                HashMap::from([(
+                    "org.iso.18013.5.1".to_string(),
+                    vec![RequestedElement {
+                        element_id: "family_name".to_string(),
+                        intent_to_retain: false,
+                    }],
+                )]),
+            )]),
+            session_transcript: vec![],
+            ble_ident: [0; 16],
+            reader_key: Arc::new(session_encryption::ephemeral_key()),
+        }
+    }
+
+    #[test]
+    fn test_response_stream_progress() {
+        struct Events(Arc<Mutex<Vec<String>>>);
+
+        impl MDLResponseProgress for Events {
+            fn on_bytes_received(&self, received: u64, expected: Option<u64>) {
+                let event = format!("received {received} of {expected:?}");
+                self.0.lock().unwrap().push(event);
+            }
+            fn on_response_verified(&self, element_count: u64) {
+                let event = format!("verified {element_count}");
+                self.0.lock().unwrap().push(event);
+            }
+            fn on_element_verified(&self, _: String, identifier: String, _: u64) {
+                self.0.lock().unwrap().push(identifier);
+            }
+        }
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let stream = MDLResponseStream::new_with_progress(
+            Arc::new(session_manager()),
+            Some(5),
+            Box::new(Events(events.clone())),
+        );
+
+        // 1. The transfer is reported as each chunk is pushed, before the response is
+        // complete
+        stream.push_chunk(vec![0xa0, 0xa0]).unwrap();
+        assert_eq!(*events.lock().unwrap(), vec!["received 2 of Some(5)"]);
+        stream.push_chunk(vec![0xa0, 0xa0, 0xa0]).unwrap();
+        assert_eq!(events.lock().unwrap().len(), 2);
+        assert_eq!(events.lock().unwrap()[1], "received 5 of Some(5)");
+
+        // 2. A chunk past the expected length is rejected without being reported
+        assert!(stream.push_chunk(vec![0]).is_err());
+        assert_eq!(events.lock().unwrap().len(), 2);
+
+        // 3. Verification reports the verified elements, none for a response that
+        // cannot be decrypted
+        let data = stream.finish(false).unwrap();
+        assert!(data.verified_response.is_empty());
+        assert_eq!(events.lock().unwrap()[2..], ["verified 0"]);
+    }
+
+    #[test]
+    fn test_verify_ble_ident() {
+        use ciborium::Value;
+
+        // The EDeviceKeyBytes of an engagement whose key is the P-256 base point
+        let coordinate = |hex: &str| {
+            (0..hex.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+                .collect::<Vec<_>>()
+        };
+        let e_device_key = Value::Map(vec![
+            (1.into(), 2.into()),
+            ((-1).into(), 1.into()),
+            (
+                (-2).into(),
+                Value::Bytes(coordinate(
+                    "6b17d1f2e12c4247f8bce6e563a440f277037d812deb33a0f4a13945d898c296",
+                )),
+            ),
+            (
+                (-3).into(),
+                Value::Bytes(coordinate(
+                    "4fe342e2fe1a7f9b8ee7eb4a7c0f9e162bce33576b315ececbb6406837bf51f5",
+                )),
+            ),
+        ]);
+        let encode = |value: &Value| {
+            let mut bytes = Vec::new();
+            ciborium::into_writer(value, &mut bytes).unwrap();
+            bytes
+        };
+        let e_device_key_bytes = encode(&Value::Tag(
+            24,
+            Box::new(Value::Bytes(encode(&e_device_key))),
+        ));
+        let state = MDLSessionManager {
+            ble_ident: session_encryption::ble_ident(&e_device_key_bytes).unwrap(),
+            ..session_manager()
+        };
+
+        // 1. The Ident derived by the holder from the same key matches
+        let expected = [
+            0x2d, 0xdf, 0x58, 0x8d, 0xfd, 0x3c, 0x47, 0x49, 0xa1, 0xbe, 0xc5, 0xdd, 0x61, 0xae,
+            0x03, 0x6a,
+        ];
+        assert_eq!(state.ble_ident(), expected);
+        assert!(state.verify_ble_ident(expected.to_vec()));
+
+        // 2. Another peripheral's Ident, or a truncated one, does not
+        let mut other = expected;
+        other[15] ^= 1;
+        assert!(!state.verify_ble_ident(other.to_vec()));
+        assert!(!state.verify_ble_ident(expected[..15].to_vec()));
+        assert!(!state.verify_ble_ident(vec![]));
+    }
+
+    #[test]
+    fn test_nonce_registry() {
+        let registry = NonceRegistry::new(60);
+
+        // 1. A nonce is consumed once
+        let nonce = registry.generate_nonce().unwrap();
+        assert_ne!(nonce, registry.generate_nonce().unwrap());
+        assert_eq!(registry.consume_nonce(nonce.clone()), Ok(()));
+        assert_eq!(registry.consume_nonce(nonce), Err(NonceError::Unknown));
+        assert_eq!(
+            registry.consume_nonce("never issued".to_string()),
+            Err(NonceError::Unknown)
+        );
+
+        // 2. An expired nonce is rejected
+        registry
+            .store
+            .insert("expired".to_string(), SystemTime::UNIX_EPOCH);
+        assert_eq!(
+            registry.consume_nonce("expired".to_string()),
+            Err(NonceError::Expired)
+        );
+
+        // 3. A validity past the range of the clock issues no nonce
+        let registry = NonceRegistry::new(u64::MAX);
+        assert_eq!(
+            registry.generate_nonce(),
+            Err(NonceError::ValidityOutOfRange)
+        );
+    }
+
+    #[test]
+    fn test_vp_token() {
+        use serde_json::json;
+
+        assert_eq!(vp_token(&json!("token")), Some("token"));
+        assert_eq!(vp_token(&json!({"mdl": ["token"]})), Some("token"));
+        assert_eq!(vp_token(&json!({"mdl": ["token", "other"]})), None);
+        assert_eq!(vp_token(&json!({"mdl": ["token"], "pid": ["other"]})), None);
+        assert_eq!(vp_token(&json!({"mdl": {"nested": "token"}})), None);
+    }
+
+    #[test]
+    fn test_draft_20_session_transcript() {
+        use sha2::{Digest, Sha256};
+
+        let transcript = OID4VPDraft20SessionTranscript::new(
+            "client123",
+            "nonce456",
+            "mdoc789",
+            "https://response.uri",
+        )
+        .unwrap();
+        let hash = |value: &str| {
+            let to_hash = ciborium::Value::Array(vec![
+                ciborium::Value::Text(value.to_string()),
+                ciborium::Value::Text("mdoc789".to_string()),
+            ]);
+            let mut bytes = Vec::new();
+            ciborium::into_writer(&to_hash, &mut bytes).unwrap();
+            Sha256::digest(&bytes).to_vec()
+        };
+
+        // SessionTranscript = [null, null, [clientIdHash, responseUriHash, nonce]]
+        let mut bytes = Vec::new();
+        ciborium::into_writer(&transcript, &mut bytes).unwrap();
+        let value: ciborium::Value = ciborium::from_reader(bytes.as_slice()).unwrap();
+        assert_eq!(
+            value,
+            ciborium::Value::Array(vec![
+                ciborium::Value::Null,
+                ciborium::Value::Null,
+                ciborium::Value::Array(vec![
+                    ciborium::Value::Bytes(hash("client123")),
+                    ciborium::Value::Bytes(hash("https://response.uri")),
+                    ciborium::Value::Text("nonce456".to_string()),
+                ]),
+            ])
+        );
+    }
+
     #[test]
     fn test_handover_info_structure() {
         // Test that OID4VPHandoverInfo serializes as expected [clientId, nonce, jwkThumbprint, responseUri]
@@ -723,7 +4872,12 @@ mod tests {
             verified_response: HashMap::new(),
             issuer_authentication: AuthenticationStatus::Unchecked,
             device_authentication: AuthenticationStatus::Unchecked,
-            errors: None,
+            issues: vec![],
+            validity: None,
+            issuer_certificate: None,
+            documents: vec![],
+            session_transcript: vec![],
+            unmet_claims: vec![],
         };
 
         assert_eq!(verified_data.doc_type, "org.iso.18013.5.1.mDL");
@@ -752,7 +4906,12 @@ mod tests {
             verified_response,
             issuer_authentication: AuthenticationStatus::Valid,
             device_authentication: AuthenticationStatus::Valid,
-            errors: None,
+            issues: vec![],
+            validity: None,
+            issuer_certificate: None,
+            documents: vec![],
+            session_transcript: vec![],
+            unmet_claims: vec![],
         };
 
         // Verify doc_type
@@ -0,0 +1,643 @@
+// Copyright (c) 2025 Indicio
+// SPDX-License-Identifier: Apache-2.0 OR MIT
+//
+// This software may be modified and distributed under the terms
+// of either the Apache License, Version 2.0 or the MIT license.
+// See the LICENSE-APACHE and LICENSE-MIT files for details.
+
+//! Session encryption of reader and holder sessions per ISO 18013-5 9.1.1: the ECDH key
+//! agreement of the EReaderKey with the EDeviceKey of the holder, the session keys
+//! derived over the SessionTranscript, and the AES-256-GCM encryption of the messages of
+//! either party. Also the EMacKey of 9.1.3.5 a document authenticated with a deviceMac
+//! is MACed with.
+
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce, aead::Aead};
+use anyhow::{Context, Result, bail};
+use ciborium::Value;
+use hkdf::Hkdf;
+use isomdl::definitions::{
+    CoseKey, DeviceEngagement, EC2Curve, EC2Y, helpers::Tag24, session::SessionEstablishment,
+};
+use p256::{
+    EncodedPoint, PublicKey, SecretKey,
+    ecdh::EphemeralSecret,
+    elliptic_curve::{rand_core::OsRng, sec1::ToEncodedPoint},
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// The identifier of the messages of the reader, the first 8 bytes of their IV.
+const READER_IDENTIFIER: [u8; 8] = [0; 8];
+/// The identifier of the messages of the mdoc.
+const DEVICE_IDENTIFIER: [u8; 8] = [0, 0, 0, 0, 0, 0, 0, 1];
+
+/// A reader session established with an engaged holder.
+pub(crate) struct ReaderSession {
+    /// The decoded DeviceEngagement of the holder.
+    pub(crate) device_engagement: DeviceEngagement,
+    e_reader_key: Tag24<CoseKey>,
+    /// The CBOR encoded SessionTranscript the session keys are derived over.
+    pub(crate) session_transcript: Vec<u8>,
+    pub(crate) keys: SessionKeys,
+    /// The BLE Ident characteristic value the holder's peripheral server must present.
+    pub(crate) ble_ident: [u8; 16],
+}
+
+/// The reader's side of the key agreement, with a key generated for the session or one
+/// held by the caller.
+pub(crate) trait ReaderKey: Send + Sync {
+    /// The public key sent to the holder as the EReaderKey.
+    fn public_key(&self) -> Result<PublicKey>;
+    /// The ECDH shared secret with the EDeviceKey, the x-coordinate of the shared point.
+    fn shared_secret(&self, device_key: &PublicKey) -> Result<Vec<u8>>;
+}
+
+impl ReaderKey for EphemeralSecret {
+    fn public_key(&self) -> Result<PublicKey> {
+        Ok(EphemeralSecret::public_key(self))
+    }
+
+    fn shared_secret(&self, device_key: &PublicKey) -> Result<Vec<u8>> {
+        Ok(self.diffie_hellman(device_key).raw_secret_bytes().to_vec())
+    }
+}
+
+/// A key generated for a single session, dropped with the session.
+pub(crate) fn ephemeral_key() -> EphemeralSecret {
+    EphemeralSecret::random(&mut OsRng)
+}
+
+/// Establishes a session with the holder of the CBOR encoded DeviceEngagement.
+/// `handover` is the Handover of the SessionTranscript, null for a QR code engagement.
+pub(crate) fn establish(
+    device_engagement: &[u8],
+    handover: Value,
+    reader_key: &dyn ReaderKey,
+) -> Result<ReaderSession> {
+    let engagement: DeviceEngagement =
+        isomdl::cbor::from_slice(device_engagement).context("invalid device engagement")?;
+    let device_key =
+        p256_public_key(engagement.security.1.as_ref()).context("invalid EDeviceKey")?;
+
+    let e_reader_key = Tag24::new(cose_key(&reader_key.public_key()?)?)
+        .context("could not encode the EReaderKey")?;
+    let session_transcript = cbor(&Value::Array(vec![
+        tag24(device_engagement.to_vec()),
+        tag24(e_reader_key.inner_bytes.clone()),
+        handover,
+    ]))?;
+
+    let shared_secret = reader_key.shared_secret(&device_key)?;
+    let keys = SessionKeys::derive(&shared_secret, &session_transcript)?;
+    let e_device_key_bytes =
+        isomdl::cbor::to_vec(&engagement.security.1).context("could not encode the EDeviceKey")?;
+    let ble_ident = ble_ident(&e_device_key_bytes)?;
+    Ok(ReaderSession {
+        device_engagement: engagement,
+        e_reader_key,
+        session_transcript,
+        keys,
+        ble_ident,
+    })
+}
+
+impl ReaderSession {
+    /// The SessionEstablishment message carrying the CBOR encoded DeviceRequest, the
+    /// first message of the reader. The DeviceRequest may be bound to the
+    /// SessionTranscript with ReaderAuth, so it is built once the session is.
+    pub(crate) fn session_establishment(&mut self, device_request: &[u8]) -> Result<Vec<u8>> {
+        let data = self.keys.encrypt_reader_data(device_request)?;
+        isomdl::cbor::to_vec(&SessionEstablishment {
+            e_reader_key: self.e_reader_key.clone(),
+            data: data.into(),
+        })
+        .context("could not encode the session establishment")
+    }
+}
+
+/// A holder session established by the SessionEstablishment message of a reader.
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct DeviceSession {
+    /// The SEC1 encoded EReaderKey, which the EMacKey of a deviceMac is agreed with.
+    #[serde(with = "serde_bytes")]
+    pub(crate) e_reader_key: Vec<u8>,
+    /// The CBOR encoded SessionTranscript the session keys are derived over.
+    #[serde(with = "serde_bytes")]
+    pub(crate) session_transcript: Vec<u8>,
+    pub(crate) keys: SessionKeys,
+}
+
+/// A P-256 key generated for the engagement of a holder session, its EDeviceKey. It is
+/// kept, as its scalar, until the reader establishes the session.
+pub(crate) fn e_device_key() -> SecretKey {
+    SecretKey::random(&mut OsRng)
+}
+
+/// Accepts the CBOR encoded SessionEstablishment of a reader engaged with the CBOR
+/// encoded DeviceEngagement holding the public key of `e_device_key`. `handover` is the
+/// Handover of the SessionTranscript, null for a QR code engagement.
+///
+/// Returns the session with the decrypted DeviceRequest the message carries.
+pub(crate) fn accept(
+    e_device_key: &SecretKey,
+    device_engagement: &[u8],
+    handover: Value,
+    session_establishment: &[u8],
+) -> Result<(DeviceSession, Vec<u8>)> {
+    let session_establishment: SessionEstablishment =
+        isomdl::cbor::from_slice(session_establishment).context("invalid session establishment")?;
+    let e_reader_key = session_establishment.e_reader_key;
+    let reader_key = p256_public_key(e_reader_key.as_ref()).context("invalid EReaderKey")?;
+    let session_transcript = cbor(&Value::Array(vec![
+        tag24(device_engagement.to_vec()),
+        tag24(e_reader_key.inner_bytes),
+        handover,
+    ]))?;
+
+    let shared_secret =
+        p256::ecdh::diffie_hellman(e_device_key.to_nonzero_scalar(), reader_key.as_affine());
+    let mut keys = SessionKeys::derive(shared_secret.raw_secret_bytes(), &session_transcript)?;
+    let device_request = keys.decrypt_reader_data(session_establishment.data.as_ref())?;
+    let session = DeviceSession {
+        e_reader_key: reader_key.to_encoded_point(false).as_bytes().to_vec(),
+        session_transcript,
+        keys,
+    };
+    Ok((session, device_request))
+}
+
+/// The COSE_Key of a P-256 public key, as sent in the engagement or the
+/// SessionEstablishment.
+pub(crate) fn cose_key(key: &PublicKey) -> Result<CoseKey> {
+    let point = key.to_encoded_point(false);
+    Ok(CoseKey::EC2 {
+        crv: EC2Curve::P256,
+        x: point.x().context("missing x coordinate")?.to_vec(),
+        y: EC2Y::Value(point.y().context("missing y coordinate")?.to_vec()),
+    })
+}
+
+/// The EMacKey of a document authenticated with a deviceMac, derived as the session keys
+/// are but from the key agreement with the SDeviceKey of the document's MSO, so it can
+/// only be derived once the document is received.
+pub(crate) fn e_mac_key(
+    reader_key: &dyn ReaderKey,
+    device_key: &CoseKey,
+    session_transcript: &[u8],
+) -> Result<[u8; 32]> {
+    let device_key = p256_public_key(device_key).context("invalid SDeviceKey")?;
+    device_e_mac_key(&reader_key.shared_secret(&device_key)?, session_transcript)
+}
+
+/// The EMacKey the mdoc MACs a document with, from the shared secret of the key agreement
+/// of the SDeviceKey of the document with the EReaderKey.
+pub(crate) fn device_e_mac_key(
+    shared_secret: &[u8],
+    session_transcript: &[u8],
+) -> Result<[u8; 32]> {
+    let mut e_mac_key = [0; 32];
+    hkdf(shared_secret, session_transcript)?
+        .expand(b"EMacKey", &mut e_mac_key)
+        .ok()
+        .context("could not derive the EMacKey")?;
+    Ok(e_mac_key)
+}
+
+/// The HKDF of the keys of a session, salted with the hash of the tagged CBOR encoded
+/// SessionTranscript.
+fn hkdf(shared_secret: &[u8], session_transcript: &[u8]) -> Result<Hkdf<Sha256>> {
+    let salt = Sha256::digest(cbor(&tag24(session_transcript.to_vec()))?);
+    Ok(Hkdf::<Sha256>::new(Some(&salt), shared_secret))
+}
+
+/// The BLE Ident of ISO 18013-5 8.3.3.1.1.3, derived from the tagged CBOR encoded
+/// EDeviceKey of the engagement.
+pub(crate) fn ble_ident(e_device_key_bytes: &[u8]) -> Result<[u8; 16]> {
+    let mut ble_ident = [0; 16];
+    Hkdf::<Sha256>::new(None, e_device_key_bytes)
+        .expand(b"BLEIdent", &mut ble_ident)
+        .ok()
+        .context("could not derive the BLE Ident")?;
+    Ok(ble_ident)
+}
+
+/// The session keys of a session, with the counters of the messages encrypted with
+/// each.
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct SessionKeys {
+    sk_reader: [u8; 32],
+    sk_device: [u8; 32],
+    reader_message_counter: u32,
+    device_message_counter: u32,
+}
+
+impl SessionKeys {
+    /// Derives SKReader and SKDevice from the shared secret of the key agreement, salted
+    /// with the hash of the tagged CBOR encoded SessionTranscript.
+    pub(crate) fn derive(shared_secret: &[u8], session_transcript: &[u8]) -> Result<Self> {
+        let hkdf = hkdf(shared_secret, session_transcript)?;
+        let key = |info: &[u8]| -> Result<[u8; 32]> {
+            let mut key = [0; 32];
+            hkdf.expand(info, &mut key)
+                .ok()
+                .context("could not derive the session key")?;
+            Ok(key)
+        };
+        Ok(Self {
+            sk_reader: key(b"SKReader")?,
+            sk_device: key(b"SKDevice")?,
+            reader_message_counter: 0,
+            device_message_counter: 0,
+        })
+    }
+
+    /// Encrypts the next message of the reader.
+    pub(crate) fn encrypt_reader_data(&mut self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        self.reader_message_counter = next(self.reader_message_counter)?;
+        let nonce = nonce(READER_IDENTIFIER, self.reader_message_counter);
+        Aes256Gcm::new_from_slice(&self.sk_reader)
+            .context("invalid session key")?
+            .encrypt(Nonce::from_slice(&nonce), plaintext)
+            .ok()
+            .context("encryption failed")
+    }
+
+    /// Decrypts the next message of the mdoc. The counter only advances once a message
+    /// decrypts, so a corrupted message does not desynchronize the session.
+    pub(crate) fn decrypt_device_data(&mut self, ciphertext: &[u8]) -> Result<Vec<u8>> {
+        let counter = next(self.device_message_counter)?;
+        let nonce = nonce(DEVICE_IDENTIFIER, counter);
+        let plaintext = Aes256Gcm::new_from_slice(&self.sk_device)
+            .context("invalid session key")?
+            .decrypt(Nonce::from_slice(&nonce), ciphertext)
+            .ok()
+            .context("decryption failed")?;
+        self.device_message_counter = counter;
+        Ok(plaintext)
+    }
+
+    /// Encrypts the next message of the mdoc.
+    pub(crate) fn encrypt_device_data(&mut self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        self.device_message_counter = next(self.device_message_counter)?;
+        let nonce = nonce(DEVICE_IDENTIFIER, self.device_message_counter);
+        Aes256Gcm::new_from_slice(&self.sk_device)
+            .context("invalid session key")?
+            .encrypt(Nonce::from_slice(&nonce), plaintext)
+            .ok()
+            .context("encryption failed")
+    }
+
+    /// Decrypts the next message of the reader, the counter advancing as for
+    /// [SessionKeys::decrypt_device_data].
+    pub(crate) fn decrypt_reader_data(&mut self, ciphertext: &[u8]) -> Result<Vec<u8>> {
+        let counter = next(self.reader_message_counter)?;
+        let nonce = nonce(READER_IDENTIFIER, counter);
+        let plaintext = Aes256Gcm::new_from_slice(&self.sk_reader)
+            .context("invalid session key")?
+            .decrypt(Nonce::from_slice(&nonce), ciphertext)
+            .ok()
+            .context("decryption failed")?;
+        self.reader_message_counter = counter;
+        Ok(plaintext)
+    }
+}
+
+fn next(counter: u32) -> Result<u32> {
+    counter
+        .checked_add(1)
+        .context("the message counter is exhausted")
+}
+
+/// The IV of a message, its party's identifier followed by its counter.
+fn nonce(identifier: [u8; 8], counter: u32) -> [u8; 12] {
+    let mut nonce = [0; 12];
+    nonce[..8].copy_from_slice(&identifier);
+    nonce[8..].copy_from_slice(&counter.to_be_bytes());
+    nonce
+}
+
+fn p256_public_key(key: &CoseKey) -> Result<PublicKey> {
+    let CoseKey::EC2 {
+        crv: EC2Curve::P256,
+        x,
+        y,
+    } = key
+    else {
+        bail!("only P-256 keys are supported");
+    };
+    if x.len() != 32 {
+        bail!("invalid x coordinate length");
+    }
+    let point = match y {
+        EC2Y::Value(y) if y.len() == 32 => {
+            EncodedPoint::from_affine_coordinates(x.as_slice().into(), y.as_slice().into(), false)
+        }
+        EC2Y::Value(_) => bail!("invalid y coordinate length"),
+        EC2Y::SignBit(sign) => {
+            EncodedPoint::from_bytes([&[0x02 | *sign as u8], x.as_slice()].concat())
+                .context("invalid compressed point")?
+        }
+    };
+    PublicKey::from_sec1_bytes(point.as_bytes()).context("invalid P-256 key")
+}
+
+fn tag24(bytes: Vec<u8>) -> Value {
+    Value::Tag(24, Box::new(Value::Bytes(bytes)))
+}
+
+fn cbor(value: &Value) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    ciborium::into_writer(value, &mut bytes).context("could not encode CBOR")?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A reader key fixed by the test.
+    struct FixedKey(SecretKey);
+
+    impl ReaderKey for FixedKey {
+        fn public_key(&self) -> Result<PublicKey> {
+            Ok(self.0.public_key())
+        }
+
+        fn shared_secret(&self, device_key: &PublicKey) -> Result<Vec<u8>> {
+            let shared_secret =
+                p256::ecdh::diffie_hellman(self.0.to_nonzero_scalar(), device_key.as_affine());
+            Ok(shared_secret.raw_secret_bytes().to_vec())
+        }
+    }
+
+    fn hex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_session_keys() {
+        // The SessionTranscript [#6.24(h'01'), #6.24(h'02'), null], with the vectors
+        // computed independently of this crate
+        let session_transcript = hex("83d8184101d8184102f6");
+        let shared_secret: Vec<u8> = (1..=32).collect();
+        let mut keys = SessionKeys::derive(&shared_secret, &session_transcript).unwrap();
+        assert_eq!(
+            keys.sk_reader.to_vec(),
+            hex("485e2a87c7ff15371d01b386baf087b57e75e24a336e75e6d0cb7b4f7bb186da")
+        );
+        assert_eq!(
+            keys.sk_device.to_vec(),
+            hex("d1adcd1b5714f05a4159a8c9ced8c4666d8d03fa4d4502518498e49554f43be3")
+        );
+
+        // 1. Messages are encrypted with the IV of their party and counter
+        assert_eq!(
+            keys.encrypt_reader_data(b"request").unwrap(),
+            hex("1273bda663cbdb5363da10fac36c72834d1e6925b8d3d5")
+        );
+        let first = hex("42ad2cee184de9201e9bd92898c120d86998cc0dcc65d367");
+        let second = hex("c37b4ddfced3ede5bb6c097cb5e1933ebf72a4356062");
+
+        // 2. Messages of the mdoc decrypt in order, a failed one leaving the counter
+        assert!(keys.decrypt_device_data(&second).is_err());
+        assert_eq!(keys.decrypt_device_data(&first).unwrap(), b"response");
+        assert!(keys.decrypt_device_data(&first).is_err());
+        assert_eq!(keys.decrypt_device_data(&second).unwrap(), b"second");
+    }
+
+    #[test]
+    fn test_e_mac_key() {
+        /// A key agreement with a shared secret fixed by the test.
+        struct SharedSecret;
+
+        impl ReaderKey for SharedSecret {
+            fn public_key(&self) -> Result<PublicKey> {
+                bail!("not needed")
+            }
+
+            fn shared_secret(&self, _device_key: &PublicKey) -> Result<Vec<u8>> {
+                Ok((1..=32).collect())
+            }
+        }
+
+        let cose_key = |key: &SecretKey| {
+            let point = key.public_key().to_encoded_point(false);
+            CoseKey::EC2 {
+                crv: EC2Curve::P256,
+                x: point.x().unwrap().to_vec(),
+                y: EC2Y::Value(point.y().unwrap().to_vec()),
+            }
+        };
+        let session_transcript = hex("83d8184101d8184102f6");
+
+        // 1. The EMacKey is derived as the session keys of test_session_keys are
+        let device_key = SecretKey::random(&mut OsRng);
+        assert_eq!(
+            e_mac_key(&SharedSecret, &cose_key(&device_key), &session_transcript)
+                .unwrap()
+                .to_vec(),
+            hex("1ee3a8f9bad2c7410fd285196af2260cd7f32cd0c38b4c51aa1f57cabc5f067e")
+        );
+
+        // 2. The mdoc derives the same key with its SDeviceKey and the EReaderKey
+        let reader_key = SecretKey::random(&mut OsRng);
+        assert_eq!(
+            e_mac_key(
+                &FixedKey(reader_key.clone()),
+                &cose_key(&device_key),
+                &session_transcript
+            )
+            .unwrap(),
+            e_mac_key(
+                &FixedKey(device_key),
+                &cose_key(&reader_key),
+                &session_transcript
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_establish() {
+        let device_key = SecretKey::random(&mut OsRng);
+        let point = device_key.public_key().to_encoded_point(false);
+        let e_device_key = Value::Map(vec![
+            (1.into(), 2.into()),
+            ((-1).into(), 1.into()),
+            ((-2).into(), Value::Bytes(point.x().unwrap().to_vec())),
+            ((-3).into(), Value::Bytes(point.y().unwrap().to_vec())),
+        ]);
+        let device_engagement = cbor(&Value::Map(vec![
+            (0.into(), "1.0".into()),
+            (
+                1.into(),
+                Value::Array(vec![1.into(), tag24(cbor(&e_device_key).unwrap())]),
+            ),
+        ]))
+        .unwrap();
+
+        let mut session = establish(&device_engagement, Value::Null, &ephemeral_key()).unwrap();
+        let session_establishment = session.session_establishment(b"device request").unwrap();
+
+        // 1. The transcript binds the engagement and the EReaderKey to the handover
+        let field = |value: &Value, name: &str| {
+            value
+                .as_map()
+                .unwrap()
+                .iter()
+                .find(|(key, _)| key.as_text() == Some(name))
+                .map(|(_, value)| value.clone())
+                .unwrap()
+        };
+        let message: Value = ciborium::from_reader(session_establishment.as_slice()).unwrap();
+        let e_reader_key = field(&message, "eReaderKey");
+        assert_eq!(
+            session.session_transcript,
+            cbor(&Value::Array(vec![
+                tag24(device_engagement.clone()),
+                e_reader_key.clone(),
+                Value::Null
+            ]))
+            .unwrap()
+        );
+
+        // 2. The holder derives the same keys from its EDeviceKey and decrypts the request
+        let Value::Tag(24, e_reader_key) = e_reader_key else {
+            panic!("EReaderKeyBytes is not tagged");
+        };
+        let e_reader_key: Value =
+            ciborium::from_reader(e_reader_key.as_bytes().unwrap().as_slice()).unwrap();
+        let coordinate = |label: i64| {
+            e_reader_key
+                .as_map()
+                .unwrap()
+                .iter()
+                .find(|(key, _)| key.as_integer() == Some(label.into()))
+                .and_then(|(_, value)| value.as_bytes().cloned())
+                .unwrap()
+        };
+        let reader_key = PublicKey::from_sec1_bytes(
+            EncodedPoint::from_affine_coordinates(
+                coordinate(-2).as_slice().into(),
+                coordinate(-3).as_slice().into(),
+                false,
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+        let shared_secret =
+            p256::ecdh::diffie_hellman(device_key.to_nonzero_scalar(), reader_key.as_affine());
+        let holder_keys = SessionKeys::derive(
+            shared_secret.raw_secret_bytes(),
+            &session.session_transcript,
+        )
+        .unwrap();
+        let data = field(&message, "data");
+        let request = Aes256Gcm::new_from_slice(&holder_keys.sk_reader)
+            .unwrap()
+            .decrypt(
+                Nonce::from_slice(&nonce(READER_IDENTIFIER, 1)),
+                data.as_bytes().unwrap().as_slice(),
+            )
+            .unwrap();
+        assert_eq!(request, b"device request");
+
+        // 3. A key held by the caller is sent as the EReaderKey, and agrees on the same
+        // session keys each time
+        let reader_key = FixedKey(SecretKey::from_slice(&[7; 32]).unwrap());
+        let fixed = || {
+            let mut session = establish(&device_engagement, Value::Null, &reader_key).unwrap();
+            let session_establishment = session.session_establishment(b"device request").unwrap();
+            (session, session_establishment)
+        };
+        let ((first, first_message), (_, second_message)) = (fixed(), fixed());
+        assert_eq!(first_message, second_message);
+        let point = reader_key.0.public_key().to_encoded_point(false);
+        let e_reader_key = Tag24::new(CoseKey::EC2 {
+            crv: EC2Curve::P256,
+            x: point.x().unwrap().to_vec(),
+            y: EC2Y::Value(point.y().unwrap().to_vec()),
+        })
+        .unwrap();
+        assert_eq!(
+            first.session_transcript,
+            cbor(&Value::Array(vec![
+                tag24(device_engagement.clone()),
+                tag24(e_reader_key.inner_bytes),
+                Value::Null
+            ]))
+            .unwrap()
+        );
+
+        // 4. Only P-256 EDeviceKeys are supported
+        let x25519 = CoseKey::OKP {
+            crv: isomdl::definitions::OKPCurve::X25519,
+            x: vec![0; 32],
+        };
+        assert!(p256_public_key(&x25519).is_err());
+    }
+
+    #[test]
+    fn test_accept() {
+        let e_device_key = e_device_key();
+        let device_engagement = cbor(&Value::Map(vec![
+            (0.into(), "1.0".into()),
+            (
+                1.into(),
+                Value::Array(vec![
+                    1.into(),
+                    tag24(
+                        isomdl::cbor::to_vec(&cose_key(&e_device_key.public_key()).unwrap())
+                            .unwrap(),
+                    ),
+                ]),
+            ),
+        ]))
+        .unwrap();
+        let handover = Value::Array(vec![Value::Bytes(vec![1, 2, 3]), Value::Null]);
+        let reader_key = FixedKey(SecretKey::random(&mut OsRng));
+        let mut reader = establish(&device_engagement, handover.clone(), &reader_key).unwrap();
+        let session_establishment = reader.session_establishment(b"device request").unwrap();
+
+        // 1. The holder derives the reader's transcript and keys, and decrypts the request
+        let (mut session, device_request) = accept(
+            &e_device_key,
+            &device_engagement,
+            handover,
+            &session_establishment,
+        )
+        .unwrap();
+        assert_eq!(device_request, b"device request");
+        assert_eq!(session.session_transcript, reader.session_transcript);
+        assert_eq!(
+            session.e_reader_key,
+            reader_key.0.public_key().to_encoded_point(false).as_bytes()
+        );
+
+        // 2. The messages of either party decrypt in order
+        let response = session.keys.encrypt_device_data(b"response").unwrap();
+        assert_eq!(
+            reader.keys.decrypt_device_data(&response).unwrap(),
+            b"response"
+        );
+        let request = reader.keys.encrypt_reader_data(b"second request").unwrap();
+        assert_eq!(
+            session.keys.decrypt_reader_data(&request).unwrap(),
+            b"second request"
+        );
+
+        // 3. A transcript bound to another handover derives other keys
+        assert!(
+            accept(
+                &e_device_key,
+                &device_engagement,
+                Value::Null,
+                &session_establishment
+            )
+            .is_err()
+        );
+    }
+}
@@ -15,11 +15,13 @@ use std::{
     time::Duration,
 };
 
-use super::mdoc::{KeyAlias, Mdoc};
+use super::mdoc::{FieldError, KeyAlias, Mdoc};
+use super::namespaces::{self, NamespaceDefinition};
 use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD};
 use isomdl::{
     definitions::{
         CoseKey, DeviceKeyInfo, DigestAlgorithm, EC2Curve, EC2Y, ValidityInfo,
+        device_key::cose_key::OKPCurve,
         helpers::NonEmptyMap,
         namespaces::{
             org_iso_18013_5_1::OrgIso1801351, org_iso_18013_5_1_aamva::OrgIso1801351Aamva,
@@ -48,18 +50,22 @@ use p256::pkcs8::AssociatedOid;
 use isomdl::definitions::x509::trust_anchor::{PemTrustAnchor, TrustPurpose};
 use x509_cert::{
     Certificate,
-    builder::{Builder, CertificateBuilder},
-    der::{Decode, DecodePem as _, Encode, EncodePem as _, asn1::OctetString},
+    attr::AttributeTypeAndValue,
+    builder::{Builder, CertificateBuilder, RequestBuilder},
+    der::{
+        Any, Decode, DecodePem as _, Encode, EncodePem as _,
+        asn1::{BitString, OctetString, PrintableStringRef, SetOfVec, Utf8StringRef},
+        oid::db::rfc4519,
+    },
     ext::pkix::{
         AuthorityKeyIdentifier, BasicConstraints, CrlDistributionPoints, ExtendedKeyUsage,
         ID_CE_SUBJECT_KEY_IDENTIFIER, IssuerAltName, KeyUsage, KeyUsages, SubjectKeyIdentifier,
         crl::dp::DistributionPoint,
         name::{DistributionPointName, GeneralName},
     },
-    name::Name,
-    spki::{
-        DynSignatureAlgorithmIdentifier, SignatureBitStringEncoding, SubjectPublicKeyInfoOwned,
-    },
+    name::{Name, RdnSequence, RelativeDistinguishedName},
+    request::CertReq,
+    spki::{DynSignatureAlgorithmIdentifier, SubjectPublicKeyInfoOwned},
     time::Validity,
 };
 
@@ -69,8 +75,9 @@ use x509_cert::{
 
 /// Verifies that the `subject` certificate's signature was created by the `issuer`'s private key.
 ///
-/// This function checks that the subject certificate was properly signed by the issuer
-/// using ECDSA P-256 signature verification.
+/// This function checks that the subject certificate was properly signed by the issuer,
+/// dispatching on the issuer's SubjectPublicKeyInfo algorithm: ECDSA with a P-256, P-384
+/// or P-521 key, using the digest named by the subject's signature algorithm, or Ed25519.
 ///
 /// # Arguments
 /// * `subject` - The certificate whose signature should be verified
@@ -83,30 +90,81 @@ pub fn verify_certificate_signature(
     subject: &Certificate,
     issuer: &Certificate,
 ) -> Result<(), String> {
+    use signature::{Verifier, hazmat::PrehashVerifier};
+    use x509_cert::der::oid::db::{rfc5912, rfc8410};
+
     let spki = &issuer.tbs_certificate.subject_public_key_info;
     let key_bytes = spki
         .subject_public_key
         .as_bytes()
         .ok_or("Invalid public key bytes")?;
 
-    let verifying_key = p256::ecdsa::VerifyingKey::from_sec1_bytes(key_bytes)
-        .map_err(|e| format!("Failed to parse public key from SEC1 bytes: {:?}", e))?;
-
     let signature_bytes = subject.signature.as_bytes().ok_or("Missing signature")?;
-    let signature = p256::ecdsa::Signature::from_der(signature_bytes)
-        .map_err(|e| format!("Failed to parse signature: {:?}", e))?;
 
     let tbs_der = subject
         .tbs_certificate
         .to_der()
         .map_err(|e| format!("Failed to encode TBS: {:?}", e))?;
 
-    use signature::Verifier;
-    verifying_key
-        .verify(&tbs_der, &signature)
-        .map_err(|e| format!("Signature verification failed: {:?}", e))?;
+    let key_error = |e| format!("Failed to parse public key from SEC1 bytes: {:?}", e);
+    let signature_error = |e| format!("Failed to parse signature: {:?}", e);
+    let verification_error = |e| format!("Signature verification failed: {:?}", e);
+
+    if spki.algorithm.oid == rfc8410::ID_ED_25519 {
+        let key_bytes: &[u8; 32] = key_bytes
+            .try_into()
+            .map_err(|_| "Invalid Ed25519 public key length")?;
+        let verifying_key =
+            ed25519_dalek::VerifyingKey::from_bytes(key_bytes).map_err(signature_error)?;
+        let signature =
+            ed25519_dalek::Signature::from_slice(signature_bytes).map_err(signature_error)?;
+        return verifying_key
+            .verify(&tbs_der, &signature)
+            .map_err(verification_error);
+    }
+
+    if spki.algorithm.oid != rfc5912::ID_EC_PUBLIC_KEY {
+        return Err(format!(
+            "Unsupported public key algorithm: {}",
+            spki.algorithm.oid
+        ));
+    }
+    let curve = spki
+        .algorithm
+        .parameters
+        .as_ref()
+        .and_then(|parameters| parameters.decode_as::<ObjectIdentifier>().ok())
+        .ok_or("Missing EC curve parameters")?;
+
+    let prehash = match subject.signature_algorithm.oid {
+        rfc5912::ECDSA_WITH_SHA_256 => sha2::Sha256::digest(&tbs_der).to_vec(),
+        rfc5912::ECDSA_WITH_SHA_384 => sha2::Sha384::digest(&tbs_der).to_vec(),
+        rfc5912::ECDSA_WITH_SHA_512 => sha2::Sha512::digest(&tbs_der).to_vec(),
+        oid => return Err(format!("Unsupported signature algorithm: {oid}")),
+    };
 
-    Ok(())
+    match curve {
+        rfc5912::SECP_256_R_1 => p256::ecdsa::VerifyingKey::from_sec1_bytes(key_bytes)
+            .map_err(key_error)?
+            .verify_prehash(
+                &prehash,
+                &p256::ecdsa::Signature::from_der(signature_bytes).map_err(signature_error)?,
+            ),
+        rfc5912::SECP_384_R_1 => p384::ecdsa::VerifyingKey::from_sec1_bytes(key_bytes)
+            .map_err(key_error)?
+            .verify_prehash(
+                &prehash,
+                &p384::ecdsa::Signature::from_der(signature_bytes).map_err(signature_error)?,
+            ),
+        rfc5912::SECP_521_R_1 => p521::ecdsa::VerifyingKey::from_sec1_bytes(key_bytes)
+            .map_err(key_error)?
+            .verify_prehash(
+                &prehash,
+                &p521::ecdsa::Signature::from_der(signature_bytes).map_err(signature_error)?,
+            ),
+        curve => return Err(format!("Unsupported EC curve: {curve}")),
+    }
+    .map_err(verification_error)
 }
 
 /// Checks if a certificate is a Certificate Authority (CA) based on the BasicConstraints extension.
@@ -127,6 +185,58 @@ pub fn is_ca_certificate(cert: &Certificate) -> bool {
         .unwrap_or(false)
 }
 
+/// Parses the certificates of an X5Chain COSE header.
+///
+/// The header is either a single bstr (one certificate) or an array of bstrs, per
+/// RFC 9360. Entries that are not valid DER certificates are skipped.
+pub fn x5chain_certificates(x5chain_cbor: &ciborium::Value) -> Vec<Certificate> {
+    let parse = |value: &ciborium::Value| match value {
+        ciborium::Value::Bytes(cert_bytes) => Certificate::from_der(cert_bytes).ok(),
+        _ => None,
+    };
+
+    match x5chain_cbor {
+        ciborium::Value::Array(certs_vals) => certs_vals.iter().filter_map(parse).collect(),
+        single => parse(single).into_iter().collect(),
+    }
+}
+
+/// The purpose of a trust anchor certificate.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, uniffi::Enum)]
+pub enum TrustAnchorPurpose {
+    /// An IACA, trusted to issue document signer certificates.
+    #[default]
+    Iaca,
+    /// A reader CA, trusted to issue reader authentication certificates.
+    ReaderCa,
+}
+
+impl From<TrustAnchorPurpose> for TrustPurpose {
+    fn from(purpose: TrustAnchorPurpose) -> Self {
+        match purpose {
+            TrustAnchorPurpose::Iaca => Self::Iaca,
+            TrustAnchorPurpose::ReaderCa => Self::ReaderCa,
+        }
+    }
+}
+
+/// Parses a trust anchor given either as a PEM certificate, which is trusted for
+/// `purpose`, or as a JSON serialized `PemTrustAnchor` carrying its own purpose.
+pub(crate) fn pem_trust_anchor(
+    anchor: &str,
+    purpose: TrustAnchorPurpose,
+) -> Result<PemTrustAnchor, String> {
+    if anchor.trim_start().starts_with("-----BEGIN") {
+        return Ok(PemTrustAnchor {
+            certificate_pem: anchor.to_string(),
+            purpose: purpose.into(),
+        });
+    }
+    serde_json::from_str(anchor).map_err(|e| {
+        format!("Invalid trust anchor, expected a PEM certificate or PemTrustAnchor JSON: {e}")
+    })
+}
+
 /// Builds an extended trust chain by discovering intermediate CA certificates from the X5Chain
 /// that are signed by already-trusted certificates.
 ///
@@ -139,7 +249,7 @@ pub fn is_ca_certificate(cert: &Certificate) -> bool {
 ///
 /// # Arguments
 /// * `initial_trusted_certs` - Certificates already trusted (typically root CAs)
-/// * `x5chain_cbor` - The X5Chain as a CBOR Value (expected to be an Array of Bytes)
+/// * `x5chain_cbor` - The X5Chain as a CBOR Value (an Array of Bytes, or Bytes for a single certificate)
 ///
 /// # Returns
 /// A tuple containing:
@@ -153,18 +263,10 @@ pub fn build_intermediate_trust_chain(
     let mut additional_anchors: Vec<PemTrustAnchor> = Vec::new();
 
     // Extract candidate certificates from the X5Chain CBOR
-    let ciborium::Value::Array(certs_vals) = x5chain_cbor else {
-        return (trusted_certs, additional_anchors);
-    };
-
-    let mut candidates: Vec<(usize, Certificate)> = Vec::new();
-    for (idx, cert_val) in certs_vals.iter().enumerate() {
-        if let ciborium::Value::Bytes(cert_bytes) = cert_val
-            && let Ok(cert) = Certificate::from_der(cert_bytes)
-        {
-            candidates.push((idx, cert));
-        }
-    }
+    let mut candidates: Vec<(usize, Certificate)> = x5chain_certificates(x5chain_cbor)
+        .into_iter()
+        .enumerate()
+        .collect();
 
     // Iteratively find certificates signed by trusted certs
     let mut progress = true;
@@ -487,12 +589,99 @@ fn prepare_mdoc(pub_key: PublicKey) -> Result<isomdl::issuance::mdoc::Builder> {
         .device_key_info(device_key_info))
 }
 
-pub fn setup_certificate_chain(
-    iaca_cert_pem: String,
-    iaca_key_pem: String,
-) -> Result<(Certificate, Vec<Certificate>, p256::ecdsa::SigningKey)> {
-    let parts: Vec<&str> = iaca_cert_pem.split("-----BEGIN CERTIFICATE-----").collect();
-    let mut iaca_certs = Vec::new();
+/// Runs blocking work on a dedicated thread, so that async entry points don't block
+/// the thread polling them. Returns `None` if the work panicked.
+pub(crate) async fn run_blocking<T, F>(f: F) -> Option<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    let (sender, receiver) = futures_channel::oneshot::channel();
+    std::thread::spawn(move || {
+        let _ = sender.send(f());
+    });
+    receiver.await.ok()
+}
+
+/// Issuer signing key, on one of the curves supported for issuance.
+#[derive(Clone)]
+pub enum IssuerSigningKey {
+    /// ECDSA P-256, producing ES256 signatures.
+    P256(p256::ecdsa::SigningKey),
+    /// ECDSA P-384, producing ES384 signatures.
+    P384(p384::ecdsa::SigningKey),
+    /// Ed25519, producing EdDSA signatures.
+    Ed25519(ed25519_dalek::SigningKey),
+}
+
+impl IssuerSigningKey {
+    /// Parses a PKCS#8 PEM encoded private key, detecting its curve.
+    pub fn from_pkcs8_pem(key_pem: &str) -> Result<Self> {
+        if let Ok(key) = p256::ecdsa::SigningKey::from_pkcs8_pem(key_pem) {
+            return Ok(Self::P256(key));
+        }
+        if let Ok(key) = p384::ecdsa::SigningKey::from_pkcs8_pem(key_pem) {
+            return Ok(Self::P384(key));
+        }
+        let key = ed25519_dalek::SigningKey::from_pkcs8_pem(key_pem)
+            .context("issuer key is not a P-256, P-384 or Ed25519 PKCS#8 key")?;
+        Ok(Self::Ed25519(key))
+    }
+
+    /// The type of this key.
+    pub fn key_type(&self) -> IssuerKeyType {
+        match self {
+            Self::P256(_) => IssuerKeyType::P256,
+            Self::P384(_) => IssuerKeyType::P384,
+            Self::Ed25519(_) => IssuerKeyType::Ed25519,
+        }
+    }
+
+    /// Generates a new random key of the given type.
+    pub fn generate(key_type: IssuerKeyType) -> Self {
+        let mut rng = signature::rand_core::OsRng;
+        match key_type {
+            IssuerKeyType::P256 => Self::P256(p256::ecdsa::SigningKey::random(&mut rng)),
+            IssuerKeyType::P384 => Self::P384(p384::ecdsa::SigningKey::random(&mut rng)),
+            IssuerKeyType::Ed25519 => Self::Ed25519(ed25519_dalek::SigningKey::generate(&mut rng)),
+        }
+    }
+
+    /// Encodes the private key as a PKCS#8 PEM document.
+    pub fn to_pkcs8_pem(&self) -> Result<String> {
+        let line_ending = p256::pkcs8::LineEnding::LF;
+        Ok(match self {
+            Self::P256(key) => key.to_pkcs8_pem(line_ending)?.to_string(),
+            Self::P384(key) => key.to_pkcs8_pem(line_ending)?.to_string(),
+            Self::Ed25519(key) => key.to_pkcs8_pem(line_ending)?.to_string(),
+        })
+    }
+
+    /// Signs a message, returning the signature in its COSE encoding.
+    pub fn sign(&self, message: &[u8]) -> Vec<u8> {
+        use signature::Signer;
+
+        match self {
+            Self::P256(key) => Signer::<p256::ecdsa::Signature>::sign(key, message).to_vec(),
+            Self::P384(key) => Signer::<p384::ecdsa::Signature>::sign(key, message).to_vec(),
+            Self::Ed25519(key) => key.sign(message).to_vec(),
+        }
+    }
+
+    /// The SubjectPublicKeyInfo of the corresponding public key.
+    pub fn subject_public_key_info(&self) -> Result<SubjectPublicKeyInfoOwned> {
+        Ok(match self {
+            Self::P256(key) => SubjectPublicKeyInfoOwned::from_key(*key.verifying_key())?,
+            Self::P384(key) => SubjectPublicKeyInfoOwned::from_key(*key.verifying_key())?,
+            Self::Ed25519(key) => SubjectPublicKeyInfoOwned::from_key(key.verifying_key())?,
+        })
+    }
+}
+
+/// Parses one or more concatenated PEM encoded certificates, in order.
+pub fn parse_certificate_chain(certs_pem: &str) -> Result<Vec<Certificate>> {
+    let parts: Vec<&str> = certs_pem.split("-----BEGIN CERTIFICATE-----").collect();
+    let mut certs = Vec::new();
 
     for part in parts.iter().skip(1) {
         if part.trim().is_empty() {
@@ -500,24 +689,49 @@ pub fn setup_certificate_chain(
         }
         let full_pem = format!("-----BEGIN CERTIFICATE-----\n{}", part.trim_start());
         match Certificate::from_pem(&full_pem) {
-            Ok(cert) => iaca_certs.push(cert),
+            Ok(cert) => certs.push(cert),
             Err(_) => {
                 // Try parsing with pem crate as fallback
                 if let Ok(p) = pem::parse(&full_pem)
                     && let Ok(cert) = Certificate::from_der(p.contents())
                 {
-                    iaca_certs.push(cert);
+                    certs.push(cert);
                 }
             }
         }
     }
 
-    if iaca_certs.is_empty() {
-        let cert = Certificate::from_pem(&iaca_cert_pem)?;
-        iaca_certs.push(cert);
+    if certs.is_empty() {
+        let cert = Certificate::from_pem(certs_pem)?;
+        certs.push(cert);
     }
 
-    let iaca_key = p256::ecdsa::SigningKey::from_pkcs8_pem(&iaca_key_pem)?;
+    Ok(certs)
+}
+
+pub fn setup_certificate_chain(
+    iaca_cert_pem: String,
+    iaca_key_pem: String,
+) -> Result<(Certificate, Vec<Certificate>, p256::ecdsa::SigningKey)> {
+    match setup_issuer_certificate_chain(iaca_cert_pem, iaca_key_pem)? {
+        (certificate, iaca_certs, IssuerSigningKey::P256(key)) => {
+            Ok((certificate, iaca_certs, key))
+        }
+        _ => anyhow::bail!("expected a P-256 issuer key, use setup_issuer_certificate_chain"),
+    }
+}
+
+/// Same as [setup_certificate_chain], but accepting P-256, P-384 or Ed25519 issuer keys.
+///
+/// When the first certificate is a CA, the ephemeral document signer is generated on
+/// the same curve as the IACA key.
+pub fn setup_issuer_certificate_chain(
+    iaca_cert_pem: String,
+    iaca_key_pem: String,
+) -> Result<(Certificate, Vec<Certificate>, IssuerSigningKey)> {
+    let mut iaca_certs = parse_certificate_chain(&iaca_cert_pem)?;
+
+    let iaca_key = IssuerSigningKey::from_pkcs8_pem(&iaca_key_pem)?;
 
     // Check if the first certificate is a CA
     let is_ca = iaca_certs[0]
@@ -543,8 +757,22 @@ pub fn setup_certificate_chain(
     let iaca_cert = &iaca_certs[0];
     let iaca_name: Name = iaca_cert.tbs_certificate.subject.clone();
 
-    let issuer_ski = iaca_cert
-        .tbs_certificate
+    let issuer_ski = subject_key_identifier(iaca_cert);
+
+    let ds_key = IssuerSigningKey::generate(iaca_key.key_type());
+    let ds_certificate = issue_signer_certificate(
+        ds_key.subject_public_key_info()?,
+        &iaca_key,
+        iaca_name,
+        issuer_ski,
+        SignerCertificateProfile::test_ds()?,
+    )?;
+
+    Ok((ds_certificate, iaca_certs, ds_key))
+}
+
+fn subject_key_identifier(cert: &Certificate) -> Option<Vec<u8>> {
+    cert.tbs_certificate
         .extensions
         .as_ref()
         .and_then(|exts| {
@@ -552,29 +780,102 @@ pub fn setup_certificate_chain(
                 .find(|e| e.extn_id == ID_CE_SUBJECT_KEY_IDENTIFIER)
         })
         .and_then(|e| SubjectKeyIdentifier::from_der(e.extn_value.as_bytes()).ok())
-        .map(|ski| ski.0.as_bytes().to_vec());
+        .map(|ski| ski.0.as_bytes().to_vec())
+}
 
-    let ds_key = p256::ecdsa::SigningKey::random(&mut signature::rand_core::OsRng);
-    let mut prepared_ds_certificate =
-        prepare_signer_certificate(&ds_key, &iaca_key, iaca_name.clone(), issuer_ski)?;
-    let signature: p256::ecdsa::Signature = iaca_key.sign(&prepared_ds_certificate.finalize()?);
-    let ds_certificate: Certificate =
-        prepared_ds_certificate.assemble(signature.to_der().to_bitstring()?)?;
+/// Subject, validity and issuer-specific extension values of a document signer certificate.
+struct SignerCertificateProfile {
+    subject: Name,
+    validity: Validity,
+    issuer_alt_name: GeneralName,
+    crl_distribution_point: String,
+    /// The extended key usage, the mDL document signer (1.0.18013.5.1.2) unless the
+    /// certificate is issued for another purpose.
+    extended_key_usage: &'static str,
+}
 
-    Ok((ds_certificate, iaca_certs, ds_key))
+impl SignerCertificateProfile {
+    /// Profile of the ephemeral test document signer.
+    fn test_ds() -> Result<Self> {
+        Ok(Self {
+            subject: "CN=SpruceID Test DS,C=US,ST=NY,O=SpruceID".parse()?,
+            // Document signer certificate valid for sixty days.
+            validity: Validity::from_now(Duration::from_secs(60 * 60 * 24 * 60))?,
+            issuer_alt_name: GeneralName::Rfc822Name(
+                "isointerop@spruceid.com".to_string().try_into()?,
+            ),
+            crl_distribution_point: "https://interopevent.spruceid.com/interop.crl".to_string(),
+            extended_key_usage: MDL_DS_EXTENDED_KEY_USAGE,
+        })
+    }
+
+    /// Profile of a test mdoc reader authentication certificate.
+    #[cfg(test)]
+    fn test_reader() -> Result<Self> {
+        Ok(Self {
+            subject: "CN=Test Reader,C=US,ST=NY".parse()?,
+            validity: Validity::from_now(Duration::from_secs(60 * 60 * 24))?,
+            issuer_alt_name: GeneralName::Rfc822Name("reader@example.com".to_string().try_into()?),
+            crl_distribution_point: "https://example.com/reader.crl".to_string(),
+            extended_key_usage: "1.0.18013.5.1.6",
+        })
+    }
+}
+
+/// The extended key usage of mDL document signer certificates.
+const MDL_DS_EXTENDED_KEY_USAGE: &str = "1.0.18013.5.1.2";
+
+/// Issues a document signer certificate for `spki`, signed by the IACA key.
+fn issue_signer_certificate(
+    spki: SubjectPublicKeyInfoOwned,
+    iaca_key: &IssuerSigningKey,
+    iaca_name: Name,
+    issuer_ski: Option<Vec<u8>>,
+    profile: SignerCertificateProfile,
+) -> Result<Certificate> {
+    match iaca_key {
+        IssuerSigningKey::P256(iaca_key) => {
+            let builder =
+                prepare_signer_certificate(spki, iaca_key, iaca_name, issuer_ski, profile)?;
+            build_signed::<_, p256::ecdsa::DerSignature>(builder)
+        }
+        IssuerSigningKey::P384(iaca_key) => {
+            let builder =
+                prepare_signer_certificate(spki, iaca_key, iaca_name, issuer_ski, profile)?;
+            build_signed::<_, p384::ecdsa::DerSignature>(builder)
+        }
+        IssuerSigningKey::Ed25519(iaca_key) => {
+            let builder =
+                prepare_signer_certificate(spki, iaca_key, iaca_name, issuer_ski, profile)?;
+            build_signed::<_, ed25519_dalek::Signature>(builder)
+        }
+    }
+}
+
+/// Signs and assembles the certificate or request prepared by `builder`.
+fn build_signed<B, Sig>(mut builder: B) -> Result<B::Output>
+where
+    B: Builder,
+    B::Signer: signature::Signer<Sig>,
+    Sig: signature::SignatureEncoding,
+{
+    let blob = builder.finalize()?;
+    let signature: Sig = builder.signer().sign(&blob);
+    let signature = BitString::from_bytes(&signature.to_vec())?;
+    Ok(builder.assemble(signature)?)
 }
 
 fn prepare_signer_certificate<'s, S>(
-    signer_key: &'s S,
+    spki: SubjectPublicKeyInfoOwned,
     iaca_key: &'s S,
     iaca_name: Name,
     issuer_ski: Option<Vec<u8>>,
+    profile: SignerCertificateProfile,
 ) -> Result<CertificateBuilder<'s, S>>
 where
     S: signature::KeypairRef + DynSignatureAlgorithmIdentifier,
     S::VerifyingKey: EncodePublicKey,
 {
-    let spki = SubjectPublicKeyInfoOwned::from_key(signer_key.verifying_key())?;
     let ski_digest = Sha1::digest(spki.subject_public_key.raw_bytes());
     let ski_digest_octet = OctetString::new(ski_digest.to_vec())?;
 
@@ -591,9 +892,8 @@ where
             issuer: Some(iaca_name),
         },
         rand::random::<u64>().into(),
-        // Document signer certificate valid for sixty days.
-        Validity::from_now(Duration::from_secs(60 * 60 * 24 * 60))?,
-        "CN=SpruceID Test DS,C=US,ST=NY,O=SpruceID".parse()?,
+        profile.validity,
+        profile.subject,
         spki,
         iaca_key,
     )?;
@@ -607,36 +907,495 @@ where
 
     builder.add_extension(&KeyUsage(KeyUsages::DigitalSignature.into()))?;
 
-    builder.add_extension(&IssuerAltName(vec![GeneralName::Rfc822Name(
-        "isointerop@spruceid.com".to_string().try_into()?,
-    )]))?;
+    builder.add_extension(&IssuerAltName(vec![profile.issuer_alt_name]))?;
+
+    builder.add_extension(&CrlDistributionPoints(vec![DistributionPoint {
+        distribution_point: Some(DistributionPointName::FullName(vec![
+            GeneralName::UniformResourceIdentifier(profile.crl_distribution_point.try_into()?),
+        ])),
+        reasons: None,
+        crl_issuer: None,
+    }]))?;
+
+    builder.add_extension(&ExtendedKeyUsage(vec![ObjectIdentifier::new(
+        profile.extended_key_usage,
+    )?]))?;
+
+    Ok(builder)
+}
+
+// ============================================================================
+// Certificate Generation
+// ============================================================================
+
+/// Key type of a generated certificate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, uniffi::Enum)]
+pub enum IssuerKeyType {
+    P256,
+    P384,
+    Ed25519,
+}
+
+/// A generated certificate and its private key, both PEM encoded.
+#[derive(Debug, Clone, uniffi::Record)]
+pub struct GeneratedCertificate {
+    pub certificate_pem: String,
+    /// PKCS#8 encoded private key.
+    pub key_pem: String,
+}
+
+/// Parameters of an IACA root certificate, see ISO/IEC 18013-5 Annex B.1.2.
+#[derive(Debug, Clone, uniffi::Record)]
+pub struct IacaCertificateParams {
+    pub common_name: String,
+    /// ISO 3166-1 alpha-2 country code of the issuing authority.
+    pub country: String,
+    #[uniffi(default = None)]
+    pub state_or_province: Option<String>,
+    #[uniffi(default = None)]
+    pub organization: Option<String>,
+    /// Contact of the issuing authority, as an email address or a URI.
+    pub issuer_alt_name: String,
+    /// URI of the CRL published by the IACA.
+    pub crl_distribution_point: String,
+    /// Validity period, at most 20 years.
+    pub validity_days: u32,
+    /// Defaults to P-256.
+    #[uniffi(default = None)]
+    pub key_type: Option<IssuerKeyType>,
+}
+
+/// Maximum validity of an IACA certificate, twenty years including leap days.
+const IACA_MAX_VALIDITY_DAYS: u32 = 20 * 365 + 5;
+
+/// Generates a self-signed IACA root certificate and key following the ISO/IEC 18013-5
+/// Annex B profile: critical basic constraints with a path length of zero, critical key
+/// usage limited to certificate and CRL signing, subject key identifier, issuer
+/// alternative name and CRL distribution point.
+#[uniffi::export]
+pub fn generate_iaca_certificate(
+    params: IacaCertificateParams,
+) -> Result<GeneratedCertificate, MdlUtilError> {
+    if params.validity_days == 0 || params.validity_days > IACA_MAX_VALIDITY_DAYS {
+        return Err(MdlUtilError::General(format!(
+            "IACA validity must be between 1 and {IACA_MAX_VALIDITY_DAYS} days"
+        )));
+    }
+
+    let subject = subject_name(
+        &params.common_name,
+        &params.country,
+        params.state_or_province.as_deref(),
+        params.organization.as_deref(),
+    )?;
+    let validity = Validity::from_now(Duration::from_secs(
+        60 * 60 * 24 * u64::from(params.validity_days),
+    ))
+    .context("invalid validity")?;
+
+    let key = IssuerSigningKey::generate(params.key_type.unwrap_or(IssuerKeyType::P256));
+    let certificate = match &key {
+        IssuerSigningKey::P256(key) => {
+            build_iaca_certificate::<_, p256::ecdsa::DerSignature>(key, subject, validity, &params)?
+        }
+        IssuerSigningKey::P384(key) => {
+            build_iaca_certificate::<_, p384::ecdsa::DerSignature>(key, subject, validity, &params)?
+        }
+        IssuerSigningKey::Ed25519(key) => {
+            build_iaca_certificate::<_, ed25519_dalek::Signature>(key, subject, validity, &params)?
+        }
+    };
+
+    Ok(GeneratedCertificate {
+        certificate_pem: certificate
+            .to_pem(p256::pkcs8::LineEnding::LF)
+            .context("failed to encode certificate")?,
+        key_pem: key.to_pkcs8_pem()?,
+    })
+}
+
+fn build_iaca_certificate<S, Sig>(
+    key: &S,
+    subject: Name,
+    validity: Validity,
+    params: &IacaCertificateParams,
+) -> Result<Certificate>
+where
+    S: signature::KeypairRef + DynSignatureAlgorithmIdentifier + signature::Signer<Sig>,
+    S::VerifyingKey: EncodePublicKey,
+    Sig: signature::SignatureEncoding,
+{
+    let spki = SubjectPublicKeyInfoOwned::from_key(key.verifying_key())?;
+    let ski_digest = Sha1::digest(spki.subject_public_key.raw_bytes());
+
+    let mut builder = CertificateBuilder::new(
+        x509_cert::builder::Profile::Manual { issuer: None },
+        rand::random::<u64>().into(),
+        validity,
+        subject,
+        spki,
+        key,
+    )?;
+
+    builder.add_extension(&SubjectKeyIdentifier(OctetString::new(
+        ski_digest.to_vec(),
+    )?))?;
+
+    builder.add_extension(&KeyUsage(KeyUsages::KeyCertSign | KeyUsages::CRLSign))?;
+
+    builder.add_extension(&IssuerAltName(vec![general_name(&params.issuer_alt_name)?]))?;
+
+    builder.add_extension(&BasicConstraints {
+        ca: true,
+        path_len_constraint: Some(0),
+    })?;
 
     builder.add_extension(&CrlDistributionPoints(vec![DistributionPoint {
         distribution_point: Some(DistributionPointName::FullName(vec![
             GeneralName::UniformResourceIdentifier(
-                "https://interopevent.spruceid.com/interop.crl"
-                    .to_string()
-                    .try_into()?,
+                params.crl_distribution_point.clone().try_into()?,
             ),
         ])),
         reasons: None,
         crl_issuer: None,
     }]))?;
 
+    build_signed::<_, Sig>(builder)
+}
+
+/// Parameters of a document signer certificate, see ISO/IEC 18013-5 Annex B.1.4.
+///
+/// The country and state or province are taken from the IACA certificate, as required
+/// by the mDL validation rules.
+#[derive(Debug, Clone, uniffi::Record)]
+pub struct DsCertificateParams {
+    pub common_name: String,
+    #[uniffi(default = None)]
+    pub organization: Option<String>,
+    /// Contact of the issuing authority, as an email address or a URI. Defaults to
+    /// the issuer alternative name of the IACA certificate.
+    #[uniffi(default = None)]
+    pub issuer_alt_name: Option<String>,
+    /// URI of the CRL published by the IACA.
+    pub crl_distribution_point: String,
+    /// Validity period, at most 457 days and within the validity of the IACA.
+    pub validity_days: u32,
+    /// Defaults to the key type of the IACA.
+    #[uniffi(default = None)]
+    pub key_type: Option<IssuerKeyType>,
+}
+
+/// Maximum validity of a document signer certificate.
+const DS_MAX_VALIDITY_DAYS: u32 = 457;
+
+/// Generates a document signer certificate and key, signed by the given IACA.
+///
+/// The certificate carries the extensions checked by the mDL validation rules: the
+/// mDL document signer extended key usage (1.0.18013.5.1.2), critical digital
+/// signature key usage, authority and subject key identifiers, issuer alternative
+/// name and CRL distribution point.
+#[uniffi::export]
+pub fn generate_ds_certificate(
+    iaca_cert_pem: String,
+    iaca_key_pem: String,
+    params: DsCertificateParams,
+) -> Result<GeneratedCertificate, MdlUtilError> {
+    let iaca_cert = Certificate::from_pem(&iaca_cert_pem).context("invalid IACA certificate")?;
+    let iaca_key = IssuerSigningKey::from_pkcs8_pem(&iaca_key_pem)?;
+    if iaca_cert.tbs_certificate.subject_public_key_info != iaca_key.subject_public_key_info()? {
+        return Err(MdlUtilError::General(
+            "IACA certificate does not match the provided key".to_string(),
+        ));
+    }
+
+    if params.validity_days == 0 || params.validity_days > DS_MAX_VALIDITY_DAYS {
+        return Err(MdlUtilError::General(format!(
+            "document signer validity must be between 1 and {DS_MAX_VALIDITY_DAYS} days"
+        )));
+    }
+    let validity = Validity::from_now(Duration::from_secs(
+        60 * 60 * 24 * u64::from(params.validity_days),
+    ))
+    .context("invalid validity")?;
+    if validity.not_after.to_unix_duration()
+        > iaca_cert
+            .tbs_certificate
+            .validity
+            .not_after
+            .to_unix_duration()
+    {
+        return Err(MdlUtilError::General(
+            "document signer validity exceeds the IACA validity".to_string(),
+        ));
+    }
+
+    let issuer_alt_name = match &params.issuer_alt_name {
+        Some(issuer_alt_name) => general_name(issuer_alt_name)?,
+        None => iaca_issuer_alt_name(&iaca_cert)
+            .context("IACA has no issuer alternative name, one must be provided")?,
+    };
+
+    let iaca_name = &iaca_cert.tbs_certificate.subject;
+    let subject = certificate_name(
+        &params.common_name,
+        name_attribute(iaca_name, rfc4519::COUNTRY_NAME)
+            .context("IACA subject has no country name")?,
+        name_attribute(iaca_name, rfc4519::ST),
+        params.organization.as_deref(),
+    )?;
+
+    let key = IssuerSigningKey::generate(params.key_type.unwrap_or(iaca_key.key_type()));
+    let certificate = issue_signer_certificate(
+        key.subject_public_key_info()?,
+        &iaca_key,
+        iaca_name.clone(),
+        subject_key_identifier(&iaca_cert),
+        SignerCertificateProfile {
+            subject,
+            validity,
+            issuer_alt_name,
+            crl_distribution_point: params.crl_distribution_point,
+            extended_key_usage: MDL_DS_EXTENDED_KEY_USAGE,
+        },
+    )?;
+
+    Ok(GeneratedCertificate {
+        certificate_pem: certificate
+            .to_pem(p256::pkcs8::LineEnding::LF)
+            .context("failed to encode certificate")?,
+        key_pem: key.to_pkcs8_pem()?,
+    })
+}
+
+/// Issues a reader authentication certificate and key signed by the reader CA, for
+/// tests of reader authenticated requests.
+#[cfg(test)]
+pub(crate) fn generate_test_reader_certificate(
+    reader_ca: &GeneratedCertificate,
+) -> Result<GeneratedCertificate> {
+    let ca_certificate = Certificate::from_pem(&reader_ca.certificate_pem)?;
+    let ca_key = IssuerSigningKey::from_pkcs8_pem(&reader_ca.key_pem)?;
+    let key = IssuerSigningKey::generate(IssuerKeyType::P256);
+    let certificate = issue_signer_certificate(
+        key.subject_public_key_info()?,
+        &ca_key,
+        ca_certificate.tbs_certificate.subject.clone(),
+        subject_key_identifier(&ca_certificate),
+        SignerCertificateProfile::test_reader()?,
+    )?;
+    Ok(GeneratedCertificate {
+        certificate_pem: certificate.to_pem(p256::pkcs8::LineEnding::LF)?,
+        key_pem: key.to_pkcs8_pem()?,
+    })
+}
+
+/// A generated certificate signing request and its private key, both PEM encoded.
+#[derive(Debug, Clone, uniffi::Record)]
+pub struct GeneratedCsr {
+    /// PKCS#10 certificate signing request.
+    pub csr_pem: String,
+    /// PKCS#8 encoded private key.
+    pub key_pem: String,
+}
+
+/// Subject and key type of a document signer certificate signing request.
+///
+/// The country and state or province must match those of the IACA that will sign
+/// the certificate.
+#[derive(Debug, Clone, uniffi::Record)]
+pub struct DsCsrParams {
+    pub common_name: String,
+    /// ISO 3166-1 alpha-2 country code of the issuing authority.
+    pub country: String,
+    #[uniffi(default = None)]
+    pub state_or_province: Option<String>,
+    #[uniffi(default = None)]
+    pub organization: Option<String>,
+    /// Defaults to P-256.
+    #[uniffi(default = None)]
+    pub key_type: Option<IssuerKeyType>,
+}
+
+/// Generates a document signer key and a PKCS#10 certificate signing request for it,
+/// for IACAs operated by a third party.
+///
+/// The request asks for the digital signature key usage and the mDL document signer
+/// extended key usage (1.0.18013.5.1.2). The remaining extensions (authority key
+/// identifier, issuer alternative name, CRL distribution point) are set by the IACA.
+#[uniffi::export]
+pub fn generate_ds_csr(params: DsCsrParams) -> Result<GeneratedCsr, MdlUtilError> {
+    let subject = subject_name(
+        &params.common_name,
+        &params.country,
+        params.state_or_province.as_deref(),
+        params.organization.as_deref(),
+    )?;
+
+    let key = IssuerSigningKey::generate(params.key_type.unwrap_or(IssuerKeyType::P256));
+    let csr = match &key {
+        IssuerSigningKey::P256(key) => build_ds_csr::<_, p256::ecdsa::DerSignature>(key, subject)?,
+        IssuerSigningKey::P384(key) => build_ds_csr::<_, p384::ecdsa::DerSignature>(key, subject)?,
+        IssuerSigningKey::Ed25519(key) => {
+            build_ds_csr::<_, ed25519_dalek::Signature>(key, subject)?
+        }
+    };
+
+    Ok(GeneratedCsr {
+        csr_pem: csr
+            .to_pem(p256::pkcs8::LineEnding::LF)
+            .context("failed to encode certificate signing request")?,
+        key_pem: key.to_pkcs8_pem()?,
+    })
+}
+
+fn build_ds_csr<S, Sig>(key: &S, subject: Name) -> Result<CertReq>
+where
+    S: signature::Keypair + DynSignatureAlgorithmIdentifier + signature::Signer<Sig>,
+    S::VerifyingKey: EncodePublicKey,
+    Sig: signature::SignatureEncoding,
+{
+    let mut builder = RequestBuilder::new(subject, key)?;
+
+    builder.add_extension(&KeyUsage(KeyUsages::DigitalSignature.into()))?;
+
     builder.add_extension(&ExtendedKeyUsage(vec![ObjectIdentifier::new(
         "1.0.18013.5.1.2",
     )?]))?;
 
-    Ok(builder)
+    build_signed::<_, Sig>(builder)
+}
+
+fn iaca_issuer_alt_name(iaca_cert: &Certificate) -> Option<GeneralName> {
+    iaca_cert
+        .tbs_certificate
+        .extensions
+        .as_ref()?
+        .iter()
+        .find(|e| e.extn_id == IssuerAltName::OID)
+        .and_then(|e| IssuerAltName::from_der(e.extn_value.as_bytes()).ok())?
+        .0
+        .into_iter()
+        .next()
+}
+
+/// The first value of the given attribute in a distinguished name.
+fn name_attribute(name: &Name, oid: ObjectIdentifier) -> Option<Any> {
+    name.0
+        .iter()
+        .flat_map(|rdn| rdn.0.iter())
+        .find(|attribute| attribute.oid == oid)
+        .map(|attribute| attribute.value.clone())
+}
+
+/// The common name of a distinguished name, if it is a UTF8String or PrintableString.
+pub(crate) fn common_name(name: &Name) -> Option<String> {
+    name_text(name, rfc4519::COMMON_NAME)
+}
+
+/// The country name of a distinguished name, an ISO 3166-1 alpha-2 code.
+pub(crate) fn country_name(name: &Name) -> Option<String> {
+    name_text(name, rfc4519::COUNTRY_NAME)
+}
+
+/// The state or province name of a distinguished name.
+pub(crate) fn state_or_province_name(name: &Name) -> Option<String> {
+    name_text(name, rfc4519::ST)
+}
+
+/// An attribute of a distinguished name, if it is a UTF8String or PrintableString.
+fn name_text(name: &Name, oid: ObjectIdentifier) -> Option<String> {
+    let value = name_attribute(name, oid)?;
+    value
+        .decode_as::<Utf8StringRef>()
+        .map(|cn| cn.as_str().to_owned())
+        .or_else(|_| {
+            value
+                .decode_as::<PrintableStringRef>()
+                .map(|cn| cn.as_str().to_owned())
+        })
+        .ok()
+}
+
+/// Builds a distinguished name from its string attributes, validating the country code.
+fn subject_name(
+    common_name: &str,
+    country: &str,
+    state_or_province: Option<&str>,
+    organization: Option<&str>,
+) -> Result<Name, MdlUtilError> {
+    if country.len() != 2 || !country.bytes().all(|b| b.is_ascii_uppercase()) {
+        return Err(MdlUtilError::General(format!(
+            "country must be an ISO 3166-1 alpha-2 code, got {country:?}"
+        )));
+    }
+
+    Ok(certificate_name(
+        common_name,
+        Any::encode_from(&PrintableStringRef::new(country).context("invalid country")?)
+            .context("invalid country")?,
+        state_or_province
+            .map(|state| Any::encode_from(&Utf8StringRef::new(state)?))
+            .transpose()
+            .context("invalid state or province")?,
+        organization,
+    )?)
+}
+
+/// Builds a distinguished name. Attributes are encoded individually, so values may
+/// contain characters that would need escaping in a string representation.
+fn certificate_name(
+    common_name: &str,
+    country: Any,
+    state_or_province: Option<Any>,
+    organization: Option<&str>,
+) -> Result<Name> {
+    let mut attributes = vec![(rfc4519::COUNTRY_NAME, country)];
+    if let Some(state_or_province) = state_or_province {
+        attributes.push((rfc4519::ST, state_or_province));
+    }
+    if let Some(organization) = organization {
+        attributes.push((
+            rfc4519::ORGANIZATION_NAME,
+            Any::encode_from(&Utf8StringRef::new(organization)?)?,
+        ));
+    }
+    attributes.push((
+        rfc4519::COMMON_NAME,
+        Any::encode_from(&Utf8StringRef::new(common_name)?)?,
+    ));
+
+    let rdns = attributes
+        .into_iter()
+        .map(|(oid, value)| {
+            let attribute = AttributeTypeAndValue { oid, value };
+            Ok(RelativeDistinguishedName(SetOfVec::try_from(vec![
+                attribute,
+            ])?))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(RdnSequence(rdns))
+}
+
+/// Parses an email address or URI into a GeneralName.
+fn general_name(value: &str) -> Result<GeneralName> {
+    if value.contains("://") {
+        Ok(GeneralName::UniformResourceIdentifier(
+            value.to_string().try_into()?,
+        ))
+    } else {
+        Ok(GeneralName::Rfc822Name(value.to_string().try_into()?))
+    }
 }
 
 #[uniffi::export]
 pub fn iso1801351_from_json(json: String) -> Result<HashMap<String, Vec<u8>>, MdlUtilError> {
     let json_value: serde_json::Value = serde_json::from_str(&json)
         .map_err(|_e| MdlUtilError::General("Error decoding json".to_owned()))?;
-    let btree = OrgIso1801351::from_json(&json_value)
+    let mut btree = OrgIso1801351::from_json(&json_value)
         .map_err(|_e| MdlUtilError::General("Error deserializing ISO1801351".to_owned()))?
         .to_ns_map();
+    namespaces::MDL.tag_dates(&mut btree);
 
     convert_btree(btree)
 }
@@ -652,6 +1411,45 @@ pub fn iso1801351_aamva_from_json(json: String) -> Result<HashMap<String, Vec<u8
     convert_btree(btree)
 }
 
+/// Validates mDL and optional AAMVA JSON items before issuance with
+/// [Mdoc::create_and_sign_mdl], returning every data element that is missing or
+/// malformed. An empty list means the items are valid.
+#[uniffi::export(default(aamva_items = None))]
+pub fn validate_mdl_items(mdl_items: String, aamva_items: Option<String>) -> Vec<FieldError> {
+    super::mdoc::validate_mdl_items(&mdl_items, aamva_items.as_deref())
+}
+
+/// Converts JSON vaccination and test records to the `org.micov.vtr.1` namespace, for
+/// use with [Mdoc::create_and_sign] and the `org.micov.1` doc type.
+#[uniffi::export]
+pub fn micov_vtr_from_json(json: String) -> Result<HashMap<String, Vec<u8>>, MdlUtilError> {
+    typed_from_json(&namespaces::MICOV_VTR, &json)
+}
+
+/// Converts JSON vaccination attestations to the `org.micov.attestation.1` namespace, for
+/// use with [Mdoc::create_and_sign] and the `org.micov.1` doc type.
+#[uniffi::export]
+pub fn micov_attestation_from_json(json: String) -> Result<HashMap<String, Vec<u8>>, MdlUtilError> {
+    typed_from_json(&namespaces::MICOV_ATTESTATION, &json)
+}
+
+fn typed_from_json(
+    definition: &NamespaceDefinition,
+    json: &str,
+) -> Result<HashMap<String, Vec<u8>>, MdlUtilError> {
+    let btree = definition.elements_from_json(json).map_err(|errors| {
+        MdlUtilError::General(
+            errors
+                .iter()
+                .map(|e| format!("{}: {}", e.identifier, e.reason))
+                .collect::<Vec<_>>()
+                .join("; "),
+        )
+    })?;
+
+    convert_btree(btree)
+}
+
 fn convert_btree(
     input: BTreeMap<String, ciborium::Value>,
 ) -> Result<HashMap<String, Vec<u8>>, MdlUtilError> {
@@ -665,3 +1463,823 @@ fn convert_btree(
     }
     Ok(outer)
 }
+
+// ============================================================================
+// Holder Keys
+// ============================================================================
+
+/// A holder public key, in one of the encodings returned by platform key stores.
+#[derive(Debug, Clone, uniffi::Enum)]
+pub enum HolderKey {
+    /// A public JWK, as accepted by the issuance constructors.
+    Jwk { jwk: String },
+    /// A SEC1 encoded P-256, P-384 or P-521 point, compressed or uncompressed, as
+    /// returned by the Secure Enclave.
+    Sec1 { point: Vec<u8> },
+    /// A DER encoded SubjectPublicKeyInfo, as returned by `PublicKey.getEncoded()` on
+    /// Android Keystore keys.
+    SubjectPublicKeyInfo { der: Vec<u8> },
+    /// A CBOR encoded COSE_Key.
+    CoseKey { cbor: Vec<u8> },
+}
+
+/// Converts a holder key to the public JWK accepted as `holder_jwk` by the issuance
+/// constructors and [MdocIssuer](super::mdoc::MdocIssuer) methods.
+#[uniffi::export]
+pub fn holder_key_to_jwk(holder_key: HolderKey) -> Result<String, MdlUtilError> {
+    match holder_key {
+        HolderKey::Jwk { jwk } => Ok(jwk),
+        HolderKey::Sec1 { point } => sec1_to_jwk(&point),
+        HolderKey::SubjectPublicKeyInfo { der } => spki_to_jwk(&der),
+        HolderKey::CoseKey { cbor } => {
+            let key: CoseKey = isomdl::cbor::from_slice(&cbor)
+                .map_err(|_e| MdlUtilError::General("invalid COSE_Key".to_owned()))?;
+            cose_key_to_jwk(&key)
+        }
+    }
+}
+
+fn sec1_to_jwk(point: &[u8]) -> Result<String, MdlUtilError> {
+    let jwk = match point.len() {
+        33 | 65 => p256::PublicKey::from_sec1_bytes(point).map(|key| key.to_jwk_string()),
+        49 | 97 => p384::PublicKey::from_sec1_bytes(point).map(|key| key.to_jwk_string()),
+        67 | 133 => p521::PublicKey::from_sec1_bytes(point).map(|key| key.to_jwk_string()),
+        _ => {
+            return Err(MdlUtilError::General(
+                "SEC1 point is not a P-256, P-384 or P-521 point".to_owned(),
+            ));
+        }
+    };
+    jwk.map_err(|_e| MdlUtilError::General("invalid SEC1 point".to_owned()))
+}
+
+fn spki_to_jwk(der: &[u8]) -> Result<String, MdlUtilError> {
+    use p256::pkcs8::DecodePublicKey;
+
+    if let Ok(key) = p256::PublicKey::from_public_key_der(der) {
+        return Ok(key.to_jwk_string());
+    }
+    if let Ok(key) = p384::PublicKey::from_public_key_der(der) {
+        return Ok(key.to_jwk_string());
+    }
+    if let Ok(key) = p521::PublicKey::from_public_key_der(der) {
+        return Ok(key.to_jwk_string());
+    }
+    let key = ed25519_dalek::VerifyingKey::from_public_key_der(der).map_err(|_e| {
+        MdlUtilError::General(
+            "SubjectPublicKeyInfo is not a P-256, P-384, P-521 or Ed25519 key".to_owned(),
+        )
+    })?;
+    Ok(ed25519_jwk(key.as_bytes()))
+}
+
+/// Verifies a signature of `payload` by a device key: ECDSA with SHA-256, SHA-384 or
+/// SHA-512 for P-256, P-384 and P-521 keys respectively, raw `r || s` or DER encoded, or
+/// Ed25519.
+pub(crate) fn verify_device_key_signature(
+    key: &CoseKey,
+    payload: &[u8],
+    signature: &[u8],
+) -> Result<(), String> {
+    use signature::{Verifier, hazmat::PrehashVerifier};
+
+    let key_error = |e| format!("Invalid device key: {:?}", e);
+    let signature_error = |e| format!("Failed to parse signature: {:?}", e);
+    let verification_error = |e| format!("Signature verification failed: {:?}", e);
+
+    let (crv, point) = match key {
+        CoseKey::EC2 { crv, x, y } => (
+            crv,
+            match y {
+                EC2Y::Value(y) => [&[0x04], x.as_slice(), y.as_slice()].concat(),
+                EC2Y::SignBit(odd) => [&[0x02 | u8::from(*odd)], x.as_slice()].concat(),
+            },
+        ),
+        CoseKey::OKP {
+            crv: OKPCurve::Ed25519,
+            x,
+        } => {
+            let x: &[u8; 32] = x
+                .as_slice()
+                .try_into()
+                .map_err(|_| "Invalid Ed25519 device key length")?;
+            let verifying_key = ed25519_dalek::VerifyingKey::from_bytes(x).map_err(key_error)?;
+            let signature =
+                ed25519_dalek::Signature::from_slice(signature).map_err(signature_error)?;
+            return verifying_key
+                .verify(payload, &signature)
+                .map_err(verification_error);
+        }
+        CoseKey::OKP { .. } => return Err("Unsupported device key curve".to_string()),
+    };
+    match crv {
+        EC2Curve::P256 => p256::ecdsa::VerifyingKey::from_sec1_bytes(&point)
+            .map_err(key_error)?
+            .verify_prehash(
+                &sha2::Sha256::digest(payload),
+                &match signature.len() {
+                    64 => p256::ecdsa::Signature::from_slice(signature),
+                    _ => p256::ecdsa::Signature::from_der(signature),
+                }
+                .map_err(signature_error)?,
+            ),
+        EC2Curve::P384 => p384::ecdsa::VerifyingKey::from_sec1_bytes(&point)
+            .map_err(key_error)?
+            .verify_prehash(
+                &sha2::Sha384::digest(payload),
+                &match signature.len() {
+                    96 => p384::ecdsa::Signature::from_slice(signature),
+                    _ => p384::ecdsa::Signature::from_der(signature),
+                }
+                .map_err(signature_error)?,
+            ),
+        EC2Curve::P521 => p521::ecdsa::VerifyingKey::from_sec1_bytes(&point)
+            .map_err(key_error)?
+            .verify_prehash(
+                &sha2::Sha512::digest(payload),
+                &match signature.len() {
+                    132 => p521::ecdsa::Signature::from_slice(signature),
+                    _ => p521::ecdsa::Signature::from_der(signature),
+                }
+                .map_err(signature_error)?,
+            ),
+        _ => return Err("Unsupported device key curve".to_string()),
+    }
+    .map_err(verification_error)
+}
+
+/// The tagged CBOR encoded DeviceAuthentication of the session transcript, the docType
+/// and the tagged DeviceNameSpacesBytes, which the deviceSignature or deviceMac of a
+/// document is made over.
+fn device_authentication_bytes(
+    session_transcript: ciborium::Value,
+    doc_type: &str,
+    device_name_spaces_bytes: ciborium::Value,
+) -> Result<Vec<u8>, String> {
+    use ciborium::Value;
+
+    let encode = |value: &Value| {
+        let mut bytes = Vec::new();
+        ciborium::into_writer(value, &mut bytes)
+            .map(|_| bytes)
+            .map_err(|e| format!("Could not encode DeviceAuthentication: {e}"))
+    };
+    let device_authentication = Value::Array(vec![
+        Value::Text("DeviceAuthentication".to_string()),
+        session_transcript,
+        Value::Text(doc_type.to_string()),
+        device_name_spaces_bytes,
+    ]);
+    encode(&Value::Tag(
+        24,
+        Box::new(Value::Bytes(encode(&device_authentication)?)),
+    ))
+}
+
+/// Verifies a deviceSignature: a COSE_Sign1 by `device_key` over the DeviceAuthentication
+/// of the session transcript, the docType and the tagged DeviceNameSpacesBytes, with its
+/// payload detached or included, and the `alg` of the device key in its protected header.
+pub(crate) fn verify_device_auth_signature(
+    device_key: &CoseKey,
+    session_transcript: ciborium::Value,
+    doc_type: &str,
+    device_name_spaces_bytes: ciborium::Value,
+    device_signature: &coset::CoseSign1,
+) -> Result<(), String> {
+    let payload =
+        device_authentication_bytes(session_transcript, doc_type, device_name_spaces_bytes)?;
+
+    // The algorithm is that of the device key, so that a signature cannot be passed off
+    // as made with another algorithm over the same key
+    let algorithm = device_key
+        .signature_algorithm()
+        .ok_or("The device key has no signature algorithm")?;
+    if device_signature.protected.header.alg
+        != Some(coset::RegisteredLabelWithPrivate::Assigned(algorithm))
+    {
+        return Err(format!(
+            "The signature algorithm {:?} does not match the {algorithm:?} device key",
+            device_signature.protected.header.alg
+        ));
+    }
+
+    let verify =
+        |signature: &[u8], tbs: &[u8]| verify_device_key_signature(device_key, tbs, signature);
+    match &device_signature.payload {
+        Some(included) if *included != payload => {
+            Err("The signed payload is not the DeviceAuthentication of the transcript".to_string())
+        }
+        Some(_) => device_signature.verify_signature(b"", verify),
+        None => device_signature.verify_detached_signature(&payload, b"", verify),
+    }
+}
+
+/// Verifies a deviceMac: a COSE_Mac0 with HMAC 256/256, keyed with the EMacKey of the
+/// document, over the DeviceAuthentication as [verify_device_auth_signature] does.
+pub(crate) fn verify_device_auth_mac(
+    e_mac_key: &[u8],
+    session_transcript: ciborium::Value,
+    doc_type: &str,
+    device_name_spaces_bytes: ciborium::Value,
+    device_mac: &coset::CoseMac0,
+) -> Result<(), String> {
+    use hmac::{Hmac, Mac};
+
+    let payload =
+        device_authentication_bytes(session_transcript, doc_type, device_name_spaces_bytes)?;
+    if device_mac.protected.header.alg
+        != Some(coset::RegisteredLabelWithPrivate::Assigned(
+            coset::iana::Algorithm::HMAC_256_256,
+        ))
+    {
+        return Err(format!(
+            "The MAC algorithm {:?} is not HMAC 256/256",
+            device_mac.protected.header.alg
+        ));
+    }
+
+    if device_mac
+        .payload
+        .as_ref()
+        .is_some_and(|included| *included != payload)
+    {
+        return Err(
+            "The MACed payload is not the DeviceAuthentication of the transcript".to_string(),
+        );
+    }
+    // The payload is detached or included, the MAC structure is the same
+    let tbm = coset::mac_structure_data(
+        coset::MacContext::CoseMac0,
+        device_mac.protected.clone(),
+        b"",
+        &payload,
+    );
+    let mut mac = Hmac::<sha2::Sha256>::new_from_slice(e_mac_key)
+        .map_err(|e| format!("Invalid EMacKey: {e}"))?;
+    mac.update(&tbm);
+    mac.verify_slice(&device_mac.tag)
+        .map_err(|_| "The deviceMac does not authenticate the response".to_string())
+}
+
+/// The x5chain header of a COSE_Sign1, unprotected or protected.
+pub(crate) fn x5chain_header(sign1: &coset::CoseSign1) -> Option<&ciborium::Value> {
+    use isomdl::definitions::x509::x5chain::X5CHAIN_COSE_HEADER_LABEL;
+
+    sign1
+        .unprotected
+        .rest
+        .iter()
+        .chain(&sign1.protected.header.rest)
+        .find(|(label, _)| *label == coset::Label::Int(X5CHAIN_COSE_HEADER_LABEL))
+        .map(|(_, x5chain)| x5chain)
+}
+
+/// Verifies the ReaderAuth of a DocRequest per ISO 18013-5 9.1.4: a COSE_Sign1 by the key
+/// of the reader certificate, the first of its x5chain, over the ReaderAuthentication of
+/// the session transcript and the tagged ItemsRequestBytes, with its payload detached or
+/// included. Returns the reader certificate, whose chain is left to the caller to
+/// validate.
+pub(crate) fn verify_reader_auth_signature(
+    reader_auth: &coset::CoseSign1,
+    session_transcript: ciborium::Value,
+    items_request_bytes: &[u8],
+) -> Result<Certificate, String> {
+    use ciborium::Value;
+
+    let x5chain = x5chain_header(reader_auth).ok_or("The ReaderAuth has no x5chain")?;
+    let certificate = x5chain_certificates(x5chain)
+        .into_iter()
+        .next()
+        .ok_or("The x5chain of the ReaderAuth has no certificate")?;
+    let reader_key = certificate_cose_key(&certificate)?;
+
+    let encode = |value: &Value| {
+        let mut bytes = Vec::new();
+        ciborium::into_writer(value, &mut bytes)
+            .map(|_| bytes)
+            .map_err(|e| format!("Could not encode ReaderAuthentication: {e}"))
+    };
+    let reader_authentication = Value::Array(vec![
+        Value::Text("ReaderAuthentication".to_string()),
+        session_transcript,
+        Value::Tag(24, Box::new(Value::Bytes(items_request_bytes.to_vec()))),
+    ]);
+    let payload = encode(&Value::Tag(
+        24,
+        Box::new(Value::Bytes(encode(&reader_authentication)?)),
+    ))?;
+
+    let algorithm = reader_key
+        .signature_algorithm()
+        .ok_or("The reader key has no signature algorithm")?;
+    if reader_auth.protected.header.alg
+        != Some(coset::RegisteredLabelWithPrivate::Assigned(algorithm))
+    {
+        return Err(format!(
+            "The signature algorithm {:?} does not match the {algorithm:?} reader key",
+            reader_auth.protected.header.alg
+        ));
+    }
+    let verify =
+        |signature: &[u8], tbs: &[u8]| verify_device_key_signature(&reader_key, tbs, signature);
+    match &reader_auth.payload {
+        Some(included) if *included != payload => {
+            Err("The signed payload is not the ReaderAuthentication of the request".to_string())
+        }
+        Some(_) => reader_auth.verify_signature(b"", verify),
+        None => reader_auth.verify_detached_signature(&payload, b"", verify),
+    }?;
+    Ok(certificate)
+}
+
+/// The COSE_Key of the P-256, P-384, P-521 or Ed25519 key of a certificate.
+fn certificate_cose_key(certificate: &Certificate) -> Result<CoseKey, String> {
+    use p256::pkcs8::DecodePublicKey;
+
+    let der = certificate
+        .tbs_certificate
+        .subject_public_key_info
+        .to_der()
+        .map_err(|e| format!("Invalid certificate key: {e}"))?;
+    let ec2 = |crv, point: &[u8]| {
+        // The uncompressed SEC1 point 0x04 || x || y
+        let (x, y) = point[1..].split_at((point.len() - 1) / 2);
+        CoseKey::EC2 {
+            crv,
+            x: x.to_vec(),
+            y: EC2Y::Value(y.to_vec()),
+        }
+    };
+    if let Ok(key) = p256::PublicKey::from_public_key_der(&der) {
+        return Ok(ec2(EC2Curve::P256, key.to_encoded_point(false).as_bytes()));
+    }
+    if let Ok(key) = p384::PublicKey::from_public_key_der(&der) {
+        return Ok(ec2(EC2Curve::P384, key.to_encoded_point(false).as_bytes()));
+    }
+    if let Ok(key) = p521::PublicKey::from_public_key_der(&der) {
+        return Ok(ec2(EC2Curve::P521, key.to_encoded_point(false).as_bytes()));
+    }
+    let key = ed25519_dalek::VerifyingKey::from_public_key_der(&der)
+        .map_err(|_e| "The certificate key is not a P-256, P-384, P-521 or Ed25519 key")?;
+    Ok(CoseKey::OKP {
+        crv: OKPCurve::Ed25519,
+        x: key.as_bytes().to_vec(),
+    })
+}
+
+fn cose_key_to_jwk(key: &CoseKey) -> Result<String, MdlUtilError> {
+    match key {
+        CoseKey::EC2 { crv, x, y } => {
+            let coordinate_length = match crv {
+                EC2Curve::P256 => 32,
+                EC2Curve::P384 => 48,
+                EC2Curve::P521 => 66,
+                _ => {
+                    return Err(MdlUtilError::General(
+                        "unsupported COSE_Key curve".to_owned(),
+                    ));
+                }
+            };
+            if x.len() != coordinate_length {
+                return Err(MdlUtilError::General(
+                    "COSE_Key coordinates do not match its curve".to_owned(),
+                ));
+            }
+            let point = match y {
+                EC2Y::Value(y) => [&[0x04], x.as_slice(), y.as_slice()].concat(),
+                EC2Y::SignBit(odd) => [&[0x02 | u8::from(*odd)], x.as_slice()].concat(),
+            };
+            sec1_to_jwk(&point)
+        }
+        CoseKey::OKP {
+            crv: OKPCurve::Ed25519,
+            x,
+        } => {
+            let x: [u8; 32] = x
+                .as_slice()
+                .try_into()
+                .map_err(|_e| MdlUtilError::General("invalid Ed25519 COSE_Key".to_owned()))?;
+            ed25519_dalek::VerifyingKey::from_bytes(&x)
+                .map_err(|_e| MdlUtilError::General("invalid Ed25519 COSE_Key".to_owned()))?;
+            Ok(ed25519_jwk(&x))
+        }
+        CoseKey::OKP { .. } => Err(MdlUtilError::General(
+            "unsupported COSE_Key curve".to_owned(),
+        )),
+    }
+}
+
+/// The RFC 7638 JWK thumbprint of a COSE_Key: the base64url-encoded SHA-256 digest of
+/// the required members of its JWK, in lexicographic order.
+pub(crate) fn cose_key_thumbprint(key: &CoseKey) -> Option<String> {
+    let jwk: serde_json::Value = serde_json::from_str(&cose_key_to_jwk(key).ok()?).ok()?;
+    Some(URL_SAFE_NO_PAD.encode(jwk_thumbprint(&jwk)?))
+}
+
+/// The RFC 7638 thumbprint of an EC or OKP JWK: the SHA-256 digest of its required
+/// members, in lexicographic order.
+pub(crate) fn jwk_thumbprint(jwk: &serde_json::Value) -> Option<Vec<u8>> {
+    let member = |name: &str| jwk.get(name).and_then(serde_json::Value::as_str);
+
+    let crv = member("crv")?;
+    let x = member("x")?;
+    let canonical = match member("kty")? {
+        "EC" => format!(
+            r#"{{"crv":"{crv}","kty":"EC","x":"{x}","y":"{}"}}"#,
+            member("y")?
+        ),
+        "OKP" => format!(r#"{{"crv":"{crv}","kty":"OKP","x":"{x}"}}"#),
+        _ => return None,
+    };
+    Some(sha2::Sha256::digest(canonical).to_vec())
+}
+
+/// A CBOR value in the diagnostic notation of RFC 8949 section 8, for debugging.
+pub(crate) fn cbor_diagnostic(value: &ciborium::Value) -> String {
+    use ciborium::Value;
+
+    let join = |items: Vec<String>| items.join(", ");
+    match value {
+        Value::Integer(i) => i128::from(*i).to_string(),
+        Value::Bytes(bytes) => format!(
+            "h'{}'",
+            bytes.iter().map(|b| format!("{b:02x}")).collect::<String>()
+        ),
+        Value::Float(f) if f.is_nan() => "NaN".to_string(),
+        Value::Float(f) if f.is_infinite() => if f.is_sign_positive() {
+            "Infinity"
+        } else {
+            "-Infinity"
+        }
+        .to_string(),
+        Value::Float(f) => format!("{f:?}"),
+        Value::Text(text) => serde_json::Value::from(text.as_str()).to_string(),
+        Value::Bool(b) => b.to_string(),
+        Value::Null => "null".to_string(),
+        Value::Tag(tag, value) => format!("{tag}({})", cbor_diagnostic(value)),
+        Value::Array(items) => format!("[{}]", join(items.iter().map(cbor_diagnostic).collect())),
+        Value::Map(entries) => format!(
+            "{{{}}}",
+            join(
+                entries
+                    .iter()
+                    .map(|(k, v)| format!("{}: {}", cbor_diagnostic(k), cbor_diagnostic(v)))
+                    .collect()
+            )
+        ),
+        _ => "undefined".to_string(),
+    }
+}
+
+fn ed25519_jwk(x: &[u8; 32]) -> String {
+    json!({
+        "kty": "OKP",
+        "crv": "Ed25519",
+        "x": URL_SAFE_NO_PAD.encode(x),
+    })
+    .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn iaca_params() -> IacaCertificateParams {
+        IacaCertificateParams {
+            common_name: "Test IACA, Inc.".to_string(),
+            country: "US".to_string(),
+            state_or_province: Some("NY".to_string()),
+            organization: None,
+            issuer_alt_name: "iaca@example.com".to_string(),
+            crl_distribution_point: "https://example.com/iaca.crl".to_string(),
+            validity_days: 365,
+            key_type: None,
+        }
+    }
+
+    #[test]
+    fn test_generate_iaca_certificate() {
+        // 1. Generate a P-256 IACA
+        let generated = generate_iaca_certificate(iaca_params()).unwrap();
+        let cert = Certificate::from_pem(&generated.certificate_pem).unwrap();
+
+        // 2. It is a self-signed CA with a path length of zero
+        assert!(is_ca_certificate(&cert));
+        assert!(verify_certificate_signature(&cert, &cert).is_ok());
+        assert_eq!(cert.tbs_certificate.issuer, cert.tbs_certificate.subject);
+        let extensions = cert.tbs_certificate.extensions.as_ref().unwrap();
+        let basic_constraints = extensions
+            .iter()
+            .find(|e| e.extn_id == BasicConstraints::OID)
+            .unwrap();
+        assert!(basic_constraints.critical);
+        assert_eq!(
+            BasicConstraints::from_der(basic_constraints.extn_value.as_bytes())
+                .unwrap()
+                .path_len_constraint,
+            Some(0)
+        );
+        for oid in [
+            KeyUsage::OID,
+            SubjectKeyIdentifier::OID,
+            IssuerAltName::OID,
+            CrlDistributionPoints::OID,
+        ] {
+            assert!(extensions.iter().any(|e| e.extn_id == oid), "missing {oid}");
+        }
+
+        // 3. The generated material can be used to issue, for every key type
+        assert!(
+            setup_issuer_certificate_chain(generated.certificate_pem, generated.key_pem).is_ok()
+        );
+        for key_type in [IssuerKeyType::P384, IssuerKeyType::Ed25519] {
+            let generated = generate_iaca_certificate(IacaCertificateParams {
+                key_type: Some(key_type),
+                ..iaca_params()
+            })
+            .unwrap();
+            let cert = Certificate::from_pem(&generated.certificate_pem).unwrap();
+            assert!(
+                verify_certificate_signature(&cert, &cert).is_ok(),
+                "{key_type:?}"
+            );
+            assert!(
+                setup_issuer_certificate_chain(generated.certificate_pem, generated.key_pem)
+                    .is_ok(),
+                "{key_type:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_generate_ds_certificate() {
+        // 1. Generate an IACA and a document signer
+        let iaca = generate_iaca_certificate(iaca_params()).unwrap();
+        let params = DsCertificateParams {
+            common_name: "Test DS".to_string(),
+            organization: Some("Test DMV".to_string()),
+            issuer_alt_name: None,
+            crl_distribution_point: "https://example.com/iaca.crl".to_string(),
+            validity_days: 90,
+            key_type: None,
+        };
+        let ds = generate_ds_certificate(
+            iaca.certificate_pem.clone(),
+            iaca.key_pem.clone(),
+            params.clone(),
+        )
+        .unwrap();
+        let iaca_cert = Certificate::from_pem(&iaca.certificate_pem).unwrap();
+        let ds_cert = Certificate::from_pem(&ds.certificate_pem).unwrap();
+
+        // 2. It is signed by the IACA and inherits its country and state
+        assert!(verify_certificate_signature(&ds_cert, &iaca_cert).is_ok());
+        assert!(!is_ca_certificate(&ds_cert));
+        assert_eq!(
+            ds_cert.tbs_certificate.issuer,
+            iaca_cert.tbs_certificate.subject
+        );
+        let ds_name = &ds_cert.tbs_certificate.subject;
+        let iaca_name = &iaca_cert.tbs_certificate.subject;
+        for oid in [rfc4519::COUNTRY_NAME, rfc4519::ST] {
+            assert_eq!(name_attribute(ds_name, oid), name_attribute(iaca_name, oid));
+        }
+        assert_eq!(
+            iaca_issuer_alt_name(&ds_cert),
+            iaca_issuer_alt_name(&iaca_cert)
+        );
+        let extensions = ds_cert.tbs_certificate.extensions.as_ref().unwrap();
+        let eku = extensions
+            .iter()
+            .find(|e| e.extn_id == ExtendedKeyUsage::OID)
+            .unwrap();
+        assert_eq!(
+            ExtendedKeyUsage::from_der(eku.extn_value.as_bytes())
+                .unwrap()
+                .0,
+            vec![ObjectIdentifier::new_unwrap("1.0.18013.5.1.2")]
+        );
+
+        // 3. Validity is limited
+        assert!(
+            generate_ds_certificate(
+                iaca.certificate_pem,
+                iaca.key_pem,
+                DsCertificateParams {
+                    validity_days: 458,
+                    ..params
+                },
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_generate_ds_csr() {
+        // 1. Generate a CSR
+        let generated = generate_ds_csr(DsCsrParams {
+            common_name: "Test DS".to_string(),
+            country: "US".to_string(),
+            state_or_province: Some("NY".to_string()),
+            organization: None,
+            key_type: None,
+        })
+        .unwrap();
+        let csr = CertReq::from_pem(&generated.csr_pem).unwrap();
+
+        // 2. It is signed by the generated key
+        let key = IssuerSigningKey::from_pkcs8_pem(&generated.key_pem).unwrap();
+        assert_eq!(csr.info.public_key, key.subject_public_key_info().unwrap());
+        let IssuerSigningKey::P256(key) = key else {
+            panic!("expected a P-256 key");
+        };
+        let signature =
+            p256::ecdsa::Signature::from_der(csr.signature.as_bytes().unwrap()).unwrap();
+        use signature::Verifier;
+        assert!(
+            key.verifying_key()
+                .verify(&csr.info.to_der().unwrap(), &signature)
+                .is_ok()
+        );
+
+        // 3. It requests the document signer extensions
+        let extension_request = csr.info.attributes.iter().next().unwrap();
+        let extensions = x509_cert::request::ExtensionReq::from_der(
+            &extension_request
+                .values
+                .iter()
+                .next()
+                .unwrap()
+                .to_der()
+                .unwrap(),
+        )
+        .unwrap();
+        assert!(
+            extensions
+                .0
+                .iter()
+                .any(|e| e.extn_id == ExtendedKeyUsage::OID)
+        );
+    }
+
+    #[test]
+    fn test_generate_iaca_certificate_invalid_params() {
+        let mut params = iaca_params();
+        params.country = "USA".to_string();
+        assert!(generate_iaca_certificate(params).is_err());
+
+        let mut params = iaca_params();
+        params.validity_days = 21 * 365;
+        assert!(generate_iaca_certificate(params).is_err());
+    }
+
+    #[test]
+    fn test_holder_key_to_jwk() {
+        let key = p256::SecretKey::random(&mut signature::rand_core::OsRng).public_key();
+        let expected: serde_json::Value = serde_json::from_str(&key.to_jwk_string()).unwrap();
+        let jwk = |holder_key| -> serde_json::Value {
+            serde_json::from_str(&holder_key_to_jwk(holder_key).unwrap()).unwrap()
+        };
+
+        // 1. Compressed and uncompressed SEC1 points
+        for compress in [true, false] {
+            let point = key.to_encoded_point(compress).as_bytes().to_vec();
+            assert_eq!(jwk(HolderKey::Sec1 { point }), expected);
+        }
+
+        // 2. SubjectPublicKeyInfo
+        let der = key.to_public_key_der().unwrap().to_vec();
+        assert_eq!(jwk(HolderKey::SubjectPublicKeyInfo { der }), expected);
+
+        // 3. COSE_Key with a sign bit instead of the y coordinate
+        let point = key.to_encoded_point(true);
+        let cose_key = CoseKey::EC2 {
+            crv: EC2Curve::P256,
+            x: point.x().unwrap().to_vec(),
+            y: EC2Y::SignBit(point.tag() == p256::elliptic_curve::sec1::Tag::CompressedOddY),
+        };
+        let cbor = isomdl::cbor::to_vec(&cose_key).unwrap();
+        assert_eq!(jwk(HolderKey::CoseKey { cbor }), expected);
+
+        // 4. Ed25519 keys become OKP JWKs
+        let ed25519 = ed25519_dalek::SigningKey::generate(&mut signature::rand_core::OsRng);
+        let der = ed25519
+            .verifying_key()
+            .to_public_key_der()
+            .unwrap()
+            .to_vec();
+        let okp = jwk(HolderKey::SubjectPublicKeyInfo { der });
+        assert_eq!(okp["kty"], "OKP");
+        assert_eq!(
+            okp["x"],
+            URL_SAFE_NO_PAD.encode(ed25519.verifying_key().as_bytes())
+        );
+
+        // 5. Points of unknown size are rejected
+        assert!(holder_key_to_jwk(HolderKey::Sec1 { point: vec![4; 10] }).is_err());
+    }
+
+    #[test]
+    fn test_verify_device_auth_mac() {
+        use ciborium::Value;
+        use hmac::{Hmac, Mac};
+
+        let e_mac_key = [7; 32];
+        let session_transcript = Value::Array(vec![Value::Null, Value::Null, Value::Null]);
+        let device_name_spaces_bytes = Value::Tag(24, Box::new(Value::Bytes(vec![0xa0])));
+        let payload = device_authentication_bytes(
+            session_transcript.clone(),
+            "org.iso.18013.5.1.mDL",
+            device_name_spaces_bytes.clone(),
+        )
+        .unwrap();
+        let device_mac = |key: &[u8], algorithm| {
+            let mut device_mac = coset::CoseMac0Builder::new()
+                .protected(coset::HeaderBuilder::new().algorithm(algorithm).build())
+                .payload(payload.clone())
+                .create_tag(b"", |data| {
+                    let mut mac = Hmac::<sha2::Sha256>::new_from_slice(key).unwrap();
+                    mac.update(data);
+                    mac.finalize().into_bytes().to_vec()
+                })
+                .build();
+            device_mac.payload = None;
+            device_mac
+        };
+        let verify = |doc_type: &str, device_mac: &coset::CoseMac0| {
+            verify_device_auth_mac(
+                &e_mac_key,
+                session_transcript.clone(),
+                doc_type,
+                device_name_spaces_bytes.clone(),
+                device_mac,
+            )
+        };
+
+        // 1. A deviceMac with the EMacKey over the DeviceAuthentication is valid, with
+        // its payload detached or included
+        let valid = device_mac(&e_mac_key, coset::iana::Algorithm::HMAC_256_256);
+        assert!(verify("org.iso.18013.5.1.mDL", &valid).is_ok());
+        let mut included = valid.clone();
+        included.payload = Some(payload.clone());
+        assert!(verify("org.iso.18013.5.1.mDL", &included).is_ok());
+        included.payload = Some(vec![0]);
+        assert!(verify("org.iso.18013.5.1.mDL", &included).is_err());
+
+        // 2. It does not authenticate another docType, nor does a tag with another key
+        assert!(verify("eu.europa.ec.eudi.pid.1", &valid).is_err());
+        let other_key = device_mac(&[8; 32], coset::iana::Algorithm::HMAC_256_256);
+        assert!(verify("org.iso.18013.5.1.mDL", &other_key).is_err());
+
+        // 3. Only HMAC 256/256 is accepted
+        let truncated = device_mac(&e_mac_key, coset::iana::Algorithm::HMAC_256_64);
+        assert!(verify("org.iso.18013.5.1.mDL", &truncated).is_err());
+    }
+
+    #[test]
+    fn test_cbor_diagnostic() {
+        use ciborium::Value;
+
+        let value = Value::Map(vec![
+            (Value::Text("a\"b".to_string()), Value::Integer((-3).into())),
+            (
+                Value::Integer(1.into()),
+                Value::Array(vec![
+                    Value::Bytes(vec![0x01, 0xab]),
+                    Value::Tag(1004, Box::new(Value::Text("2000-01-01".to_string()))),
+                    Value::Float(1.0),
+                    Value::Bool(true),
+                    Value::Null,
+                ]),
+            ),
+        ]);
+        assert_eq!(
+            cbor_diagnostic(&value),
+            r#"{"a\"b": -3, 1: [h'01ab', 1004("2000-01-01"), 1.0, true, null]}"#
+        );
+    }
+
+    #[test]
+    fn test_pem_trust_anchor() {
+        let pem = generate_iaca_certificate(iaca_params())
+            .unwrap()
+            .certificate_pem;
+
+        // 1. A plain PEM takes the requested purpose
+        let anchor = pem_trust_anchor(&pem, TrustAnchorPurpose::ReaderCa).unwrap();
+        assert_eq!(anchor.certificate_pem, pem);
+        assert!(matches!(anchor.purpose, TrustPurpose::ReaderCa));
+
+        // 2. A PemTrustAnchor JSON keeps its own purpose
+        let json = serde_json::to_string(&PemTrustAnchor {
+            certificate_pem: pem.clone(),
+            purpose: TrustPurpose::Iaca,
+        })
+        .unwrap();
+        let anchor = pem_trust_anchor(&json, TrustAnchorPurpose::ReaderCa).unwrap();
+        assert!(matches!(anchor.purpose, TrustPurpose::Iaca));
+
+        // 3. Anything else is rejected
+        assert!(pem_trust_anchor("not_valid_json", TrustAnchorPurpose::Iaca).is_err());
+    }
+}
@@ -196,7 +196,7 @@ fn test_intermediate_chaining() {
     .unwrap();
 
     // We verify without trust anchors first to check the chain structure
-    let result = mdoc_wrapper.verify_issuer_signature(None, false);
+    let result = mdoc_wrapper.verify_issuer_signature(None, false, None);
     assert!(result.is_ok(), "Verification failed: {:?}", result);
 
     let verification = result.unwrap();